@@ -0,0 +1,307 @@
+//! The numbers of the game (HP of the enemies, damage of the towers, bombs, what can be pushed,
+//! etc.), from `assets/balance.toml` so that tuning them doesn't require recompiling. The file
+//! is also compiled into the game, as the defaults of the values missing from the files read at
+//! startup (`assets/balance.toml`, then the ones of the mods, then the one given with
+//! `--balance`, each overriding the previous ones).
+//!
+//! The balance in use is per thread, so that tests and simulations with different numbers can
+//! run side by side. It has to be set before levels are parsed, as enemies get their HP then.
+
+use crate::*;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Clone, Default)]
+pub struct Balance {
+	/// By enemy name (see `Enemy::name`), `protected` for all the protected enemies.
+	enemy_hp: HashMap<String, u32>,
+	/// By tower name, for the towers that shoot, multiplied by the tier of the tower.
+	tower_damage: HashMap<String, u32>,
+	/// By tower name, the shots of a full tower, for the towers that run out of ammo.
+	tower_ammo: HashMap<String, u32>,
+	pub sniper_cooldown: u32,
+	pub miner_cooldown: u32,
+	pub bomb_damage: u32,
+	pub bomb_countdown: u32,
+	pub mine_damage: u32,
+	/// What spike traps do to the enemies on them when they strike.
+	pub spike_damage: u32,
+	/// What lava does every turn to the enemies pushed into it.
+	pub lava_damage: u32,
+	/// Burning enemies take that damage for that many turns after the last flamethrower hit.
+	pub burn_damage: u32,
+	pub burn_turns: u32,
+	pub player_attack_damage: u32,
+	pub wall_hp: u32,
+	/// The shield of shielded enemies, back to full after `shield_regen_turns` turns in a row
+	/// without damage.
+	pub shield_hp: u32,
+	pub shield_regen_turns: u32,
+	/// The HP that the followers of a group have over their kind while their leader lives.
+	pub group_leader_hp_bonus: u32,
+	/// By boss behavior name, the HP at which the boss gets it.
+	boss_phases: HashMap<String, u32>,
+	/// Hits for the enemies to break a rock, tree or flower in their way.
+	pub obstacle_hits: u32,
+	push_rocks: bool,
+	push_towers: bool,
+	push_bombs: bool,
+	push_flowers: bool,
+	/// Pusher towers push enemies (the player never does).
+	pub push_enemies: bool,
+	/// Basic tower hits push the enemies back along the path (unless the level says otherwise).
+	pub knockback: bool,
+	/// By kind of object (see `PUSHED_OBJECTS`).
+	push_weight: HashMap<String, u32>,
+	/// The heaviest line of objects that the player, pusher towers (of tier 1, merged towers
+	/// multiply it by their tier) and enemies can push.
+	pub player_push_strength: u32,
+	pub pusher_push_strength: u32,
+	pub enemy_push_strength: u32,
+	/// The knockback of basic towers (see `knockback`).
+	pub knockback_push_strength: u32,
+	/// By recipe, the wood and stone it costs.
+	craft_costs: HashMap<String, (u32, u32)>,
+}
+
+/// The kinds of objects that can be pushed, named like in the `push_weight` section.
+const PUSHED_OBJECTS: [&str; 5] = ["rock", "tower", "bomb", "flower", "enemy"];
+
+impl Balance {
+	pub const DEFAULT_PATH: &'static str = "assets/balance.toml";
+
+	/// The balance compiled into the game.
+	pub fn compiled_in() -> Balance {
+		let mut balance = Balance::default();
+		if let Err(error) = balance.apply(include_str!("../assets/balance.toml")) {
+			panic!("invalid compiled-in balance: {error}");
+		}
+		balance
+	}
+
+	/// Override values with the ones of the given file, or change nothing if it is invalid.
+	pub fn apply_file(&mut self, path: &std::path::Path) -> Result<(), String> {
+		let in_file = |error: String| format!("{}: {error}", path.display());
+		let text = fs::read_to_string(path).map_err(|error| in_file(error.to_string()))?;
+		let mut balance = self.clone();
+		balance.apply(&text).map_err(in_file)?;
+		*self = balance;
+		Ok(())
+	}
+
+	/// Change one value, named like in the file (`enemy_hp.tank`).
+	pub fn set_value(&mut self, name: &str, value: &str) -> Result<(), String> {
+		let (section, key) = name
+			.split_once('.')
+			.ok_or(format!("{name} should be like section.key"))?;
+		self.apply(&format!("[{section}]\n{key} = {value}"))
+	}
+
+	/// Tables are flattened into dotted names, like in the sprite atlas.
+	fn apply(&mut self, text: &str) -> Result<(), String> {
+		let table: toml::Table = text
+			.parse()
+			.map_err(|error: toml::de::Error| error.to_string())?;
+		for (section, entries) in &table {
+			let entries = entries
+				.as_table()
+				.ok_or(format!("{section} should be a table"))?;
+			for (key, value) in entries {
+				let name = format!("{section}.{key}");
+				let number = || {
+					value
+						.as_integer()
+						.and_then(|number| u32::try_from(number).ok())
+						.ok_or(format!("{name} should be a positive number"))
+				};
+				let boolean = || {
+					value
+						.as_bool()
+						.ok_or(format!("{name} should be true or false"))
+				};
+				match (section.as_str(), key.as_str()) {
+					("enemy_hp", enemy) if enemy == "protected" || Enemy::from_name(enemy).is_some() => {
+						self.enemy_hp.insert(enemy.to_string(), number()?);
+					},
+					("tower_damage", tower) if Tower::from_name(tower).is_some() => {
+						self.tower_damage.insert(tower.to_string(), number()?);
+					},
+					("tower_ammo", tower) if Tower::from_name(tower).is_some() => {
+						self.tower_ammo.insert(tower.to_string(), number()?);
+					},
+					("tower_cooldown", "sniper") => self.sniper_cooldown = number()?,
+					("tower_cooldown", "miner") => self.miner_cooldown = number()?,
+					("bomb", "damage") => self.bomb_damage = number()?,
+					("bomb", "countdown") => {
+						// There are only bomb sprites for countdowns up to 3.
+						let countdown = number()?;
+						if countdown > 3 {
+							return Err(format!("{name} should be at most 3"));
+						}
+						self.bomb_countdown = countdown;
+					},
+					("mine", "damage") => self.mine_damage = number()?,
+					("spikes", "damage") => self.spike_damage = number()?,
+					("lava", "damage") => self.lava_damage = number()?,
+					("burn", "damage") => self.burn_damage = number()?,
+					("burn", "turns") => self.burn_turns = number()?,
+					("player", "attack_damage") => self.player_attack_damage = number()?,
+					("wall", "hp") => self.wall_hp = number()?,
+					("shield", "hp") => self.shield_hp = number()?,
+					("shield", "regen_turns") => self.shield_regen_turns = number()?,
+					("group", "leader_hp_bonus") => self.group_leader_hp_bonus = number()?,
+					("boss_phase", behavior) if BossBehavior::from_name(behavior).is_some() => {
+						self.boss_phases.insert(behavior.to_string(), number()?);
+					},
+					("obstacle", "hits") => self.obstacle_hits = number()?,
+					("push", "rocks") => self.push_rocks = boolean()?,
+					("push", "towers") => self.push_towers = boolean()?,
+					("push", "bombs") => self.push_bombs = boolean()?,
+					("push", "flowers") => self.push_flowers = boolean()?,
+					("push", "enemies") => self.push_enemies = boolean()?,
+					("push", "knockback") => self.knockback = boolean()?,
+					("push_weight", obj) if PUSHED_OBJECTS.contains(&obj) => {
+						self.push_weight.insert(obj.to_string(), number()?);
+					},
+					("push_strength", "player") => self.player_push_strength = number()?,
+					("push_strength", "pusher") => self.pusher_push_strength = number()?,
+					("push_strength", "enemy") => self.enemy_push_strength = number()?,
+					("push_strength", "knockback") => self.knockback_push_strength = number()?,
+					("craft_wood", recipe) if Recipe::from_name(recipe).is_some() => {
+						self.craft_costs.entry(recipe.to_string()).or_default().0 = number()?;
+					},
+					("craft_stone", recipe) if Recipe::from_name(recipe).is_some() => {
+						self.craft_costs.entry(recipe.to_string()).or_default().1 = number()?;
+					},
+					_ => return Err(format!("unknown balance value {name}")),
+				}
+			}
+		}
+		Ok(())
+	}
+
+	pub fn enemy_hp(&self, enemy: &Enemy) -> u32 {
+		let name = match enemy {
+			Enemy::Protected { .. } => "protected",
+			_ => enemy.name(),
+		};
+		self.enemy_hp.get(name).copied().unwrap_or(1)
+	}
+
+	/// The damage of a shot of a tower of tier 1.
+	pub fn tower_damage(&self, tower: &Tower) -> u32 {
+		self.tower_damage.get(tower.name()).copied().unwrap_or(0)
+	}
+
+	/// The shots of a full tower, `None` if it never runs out.
+	pub fn tower_ammo(&self, tower: &Tower) -> Option<u32> {
+		self.tower_ammo.get(tower.name()).copied()
+	}
+
+	/// The phases of bosses in the order they go through them, with the HP at which they enter
+	/// each one.
+	pub fn boss_phases(&self) -> Vec<(BossBehavior, u32)> {
+		let mut phases: Vec<(BossBehavior, u32)> = self
+			.boss_phases
+			.iter()
+			.map(|(name, &hp)| (BossBehavior::from_name(name).unwrap(), hp))
+			.collect();
+		phases.sort_by_key(|&(behavior, hp)| (std::cmp::Reverse(hp), behavior));
+		phases
+	}
+
+	/// Whether the object can be pushed (or pulled) by the player and pusher towers, enemies
+	/// aside.
+	pub fn can_push(&self, obj: &Obj) -> bool {
+		match obj {
+			Obj::Rock => self.push_rocks,
+			Obj::Tower { .. } => self.push_towers,
+			Obj::Bomb { .. } => self.push_bombs,
+			Obj::Flower { .. } => self.push_flowers,
+			_ => false,
+		}
+	}
+
+	/// How much the object weighs when it is pushed, a line of objects only moves if their
+	/// weights add up to no more than the strength of what pushes it.
+	pub fn push_weight(&self, obj: &Obj) -> u32 {
+		let name = match obj {
+			Obj::Rock => "rock",
+			Obj::Tower { .. } => "tower",
+			Obj::Bomb { .. } => "bomb",
+			Obj::Flower { .. } => "flower",
+			Obj::Enemy { .. } => "enemy",
+			_ => return 0,
+		};
+		self.push_weight.get(name).copied().unwrap_or(1)
+	}
+
+	/// The wood and the stone that crafting the recipe costs.
+	pub fn craft_cost(&self, recipe: Recipe) -> (u32, u32) {
+		self
+			.craft_costs
+			.get(recipe.name())
+			.copied()
+			.unwrap_or_default()
+	}
+}
+
+thread_local! {
+	static CURRENT: RefCell<Rc<Balance>> = RefCell::new(Rc::new(Balance::compiled_in()));
+}
+
+/// Use that balance (in the current thread).
+pub fn set(balance: Balance) {
+	CURRENT.with(|current| *current.borrow_mut() = Rc::new(balance));
+}
+
+/// The balance in use (in the current thread).
+pub fn get() -> Rc<Balance> {
+	CURRENT.with(|current| current.borrow().clone())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn every_enemy_has_hp_and_values_can_be_changed() {
+		let balance = Balance::compiled_in();
+		let names = [
+			"basic",
+			"tank",
+			"speeeeed",
+			"stun",
+			"eat",
+			"broodmother",
+			"necromancer",
+			"burrower",
+			"paver",
+			"shielded",
+			"boss",
+			"protected",
+		];
+		for name in names {
+			assert!(balance.enemy_hp[name] > 0, "{name}");
+		}
+
+		let mut balance = Balance::compiled_in();
+		balance.set_value("enemy_hp.tank", "20").unwrap();
+		balance.set_value("push.rocks", "false").unwrap();
+		assert_eq!(balance.enemy_hp(&Enemy::Tank), 20);
+		assert!(!balance.can_push(&Obj::Rock));
+		assert!(balance.set_value("enemy_hp.dragon", "20").is_err());
+		assert!(balance.set_value("wall.hp", "-1").is_err());
+		assert!(balance.set_value("push_weight.goal", "1").is_err());
+		assert!(balance.set_value("bomb.countdown", "4").is_err());
+
+		set(balance);
+		let level_data = parse_level("Op |W |g").unwrap();
+		assert!(matches!(
+			level_data.init_grid.get((1, 0).into()).unwrap().obj,
+			Obj::Enemy { hp: 20, .. }
+		));
+		set(Balance::compiled_in());
+	}
+}
@@ -0,0 +1,150 @@
+//! A built-in heuristic player that can play levels on its own,
+//! useful for balance testing and for demos.
+
+use crate::coords::*;
+use crate::rng::Rng;
+use crate::*;
+
+use std::collections::VecDeque;
+
+/// How good a tile is to place a (basic) tower on, `None` if a tower can't be placed there.
+/// Towers are better where they can see many path tiles (choke points).
+fn tower_spot_score(grid: &Grid<Cell>, coords: Coords) -> Option<usize> {
+	let cell = grid.get(coords)?;
+	if !matches!(cell.obj, Obj::Empty)
+		|| matches!(cell.groud, Ground::Water | Ground::Lava | Ground::Path(_))
+		|| cell.rocky_path
+	{
+		// Towers placed on the path get trampled by the enemies, we avoid that.
+		return None;
+	}
+	// Towers stay longer than the weather, which is not taken into account.
+	let seen_path_tiles =
+		tower_range_tiles(grid, coords, &Tower::Basic, Direction::East, Weather::Clear)
+			.into_iter()
+			.filter(|&coords| matches!(grid.get(coords).unwrap().groud, Ground::Path(_)))
+			.count();
+	(seen_path_tiles > 0).then_some(seen_path_tiles)
+}
+
+/// The bot doesn't walk on the path, where enemies would trample the player.
+fn can_walk_on(grid: &Grid<Cell>, coords: Coords) -> bool {
+	grid.get(coords).is_some_and(|cell| {
+		matches!(cell.obj, Obj::Empty)
+			&& !matches!(cell.groud, Ground::Water | Ground::Lava | Ground::Path(_))
+	})
+}
+
+/// The first step to take to walk (without pushing anything) from `start` to the closest
+/// tile that satisfies `is_target`, if any is reachable.
+/// Among the equally close tiles, the one that is chosen depends on `rng`.
+fn first_step_towards(
+	grid: &Grid<Cell>,
+	start: Coords,
+	is_target: impl Fn(Coords) -> bool,
+	rng: &mut Rng,
+) -> Option<DxDy> {
+	let mut first_steps: Grid<Option<DxDy>> = Grid::new(grid.dims, None);
+	let mut queue = VecDeque::new();
+	let mut directions: Vec<DxDy> = DxDy::the_4_directions().collect();
+	directions.rotate_left(rng.below(4) as usize);
+	for dd in directions {
+		if can_walk_on(grid, start + dd) {
+			*first_steps.get_mut(start + dd).unwrap() = Some(dd);
+			queue.push_back(start + dd);
+		}
+	}
+	while let Some(coords) = queue.pop_front() {
+		let first_step = first_steps.get(coords).unwrap().unwrap();
+		if is_target(coords) {
+			return Some(first_step);
+		}
+		for dd in DxDy::the_4_directions() {
+			let neighbor = coords + dd;
+			if neighbor != start
+				&& can_walk_on(grid, neighbor)
+				&& first_steps.get(neighbor).unwrap().is_none()
+			{
+				*first_steps.get_mut(neighbor).unwrap() = Some(first_step);
+				queue.push_back(neighbor);
+			}
+		}
+	}
+	None
+}
+
+/// Is the tile dangerous for the player to stand on (next to an enemy that eats players)?
+fn is_dangerous(grid: &Grid<Cell>, coords: Coords) -> bool {
+	DxDy::the_4_directions().any(|dd| {
+		grid
+			.get(coords + dd)
+			.is_some_and(|cell| matches!(cell.obj, Obj::Enemy { variant: Enemy::Eater, .. }))
+	})
+}
+
+/// Choose the next action of the player, in the form that `play_turn` expects.
+/// The bot hesitates a bit between good choices, based on `rng`.
+pub fn choose_action(level: &LevelState, rng: &mut Rng) -> (DxDy, PlayerAction) {
+	let skip = (DxDy::from((0, 0)), PlayerAction::SkipTurn);
+	let Some(player_coords) = level.player_coords() else {
+		return skip;
+	};
+
+	// Run away from danger first.
+	if is_dangerous(&level.grid, player_coords) {
+		if let Some(dd) = DxDy::the_4_directions().find(|&dd| {
+			can_walk_on(&level.grid, player_coords + dd)
+				&& !is_dangerous(&level.grid, player_coords + dd)
+		}) {
+			return (dd, PlayerAction::Move);
+		}
+	}
+
+	// Reload an adjacent tower that is out of ammo.
+	if let Some(dd) = DxDy::the_4_directions().find(|&dd| {
+		level
+			.grid
+			.get(player_coords + dd)
+			.is_some_and(|cell| matches!(cell.obj, Obj::Tower { ammo: Some(0), .. }))
+	}) {
+		return (dd, PlayerAction::Reload);
+	}
+
+	if level.remaining_towers.is_some_and(|count| count == 0) {
+		return skip;
+	}
+
+	// The best spot of the whole level, to walk towards it.
+	let best_score = level
+		.grid
+		.dims
+		.iter()
+		.filter_map(|coords| tower_spot_score(&level.grid, coords))
+		.max();
+	let Some(best_score) = best_score else {
+		return skip;
+	};
+
+	// Place a tower right now if one of the adjacent tiles is good enough.
+	let adjacent_good_spots: Vec<DxDy> = DxDy::the_4_directions()
+		.filter(|&dd| {
+			tower_spot_score(&level.grid, player_coords + dd)
+				.is_some_and(|score| score * 2 >= best_score)
+		})
+		.collect();
+	if !adjacent_good_spots.is_empty() {
+		let dd = adjacent_good_spots[rng.below(adjacent_good_spots.len() as u32) as usize];
+		return (dd, PlayerAction::PlaceTower { variant: Tower::Basic });
+	}
+
+	// Walk next to a good spot.
+	let next_to_good_spot = |coords: Coords| {
+		DxDy::the_4_directions().any(|dd| {
+			tower_spot_score(&level.grid, coords + dd).is_some_and(|score| score * 2 >= best_score)
+		}) && !is_dangerous(&level.grid, coords)
+	};
+	match first_step_towards(&level.grid, player_coords, next_to_good_spot, rng) {
+		Some(dd) => (dd, PlayerAction::Move),
+		None => skip,
+	}
+}
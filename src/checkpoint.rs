@@ -0,0 +1,64 @@
+//! Checkpoints: snapshots of the level taken automatically every few turns, so that after a
+//! defeat the player can go back to the last one (a limited number of times).
+//! Unlike an undo, this doesn't allow to go back one turn at a time.
+
+use crate::*;
+
+/// A checkpoint is taken every that many turns.
+const CHECKPOINT_PERIOD: u32 = 5;
+/// How many times the player can go back to a checkpoint in a level.
+const MAX_REWINDS: u32 = 3;
+
+pub struct Checkpoints {
+	last: LevelState,
+	pub rewinds_left: u32,
+}
+
+impl Checkpoints {
+	/// The start of the level is the first checkpoint.
+	pub fn new(level: &LevelState) -> Checkpoints {
+		Checkpoints { last: level.clone(), rewinds_left: MAX_REWINDS }
+	}
+
+	/// To call after every turn, takes a checkpoint when it is time to.
+	pub fn update(&mut self, level: &LevelState) {
+		if !level.game_joever
+			&& level.turn.is_multiple_of(CHECKPOINT_PERIOD)
+			&& level.turn != self.last.turn
+		{
+			self.last = level.clone();
+		}
+	}
+
+	/// Go back to the last checkpoint if the level is lost and there are rewinds left.
+	pub fn rewind(&mut self, level: &mut LevelState) -> bool {
+		if !level.game_joever || self.rewinds_left == 0 {
+			return false;
+		}
+		*level = self.last.clone();
+		self.rewinds_left -= 1;
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rewind_goes_back_to_the_last_checkpoint() {
+		let level_data =
+			parse_level("Op O- O- O- O- O- O- O- O- O-\n|e |- |- |- |- |- |- |- |- |g").unwrap();
+		let mut level = LevelState::new(&level_data);
+		let mut checkpoints = Checkpoints::new(&level);
+		assert!(!checkpoints.rewind(&mut level));
+		while !level.game_joever {
+			play_turn(&mut level, (0, 0).into(), PlayerAction::SkipTurn);
+			checkpoints.update(&level);
+		}
+		assert!(checkpoints.rewind(&mut level));
+		assert_eq!(level.turn, 5);
+		assert!(!level.game_joever);
+		assert_eq!(checkpoints.rewinds_left, MAX_REWINDS - 1);
+	}
+}
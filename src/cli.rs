@@ -0,0 +1,145 @@
+//! The command line interface. Without a subcommand, the options of `play` can be given
+//! directly (`prototype-07 <level> --bot` is `prototype-07 play <level> --bot`), and without
+//! any level the game opens on the title screen.
+
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(version, about = "A turn-based tower defense prototype")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+	#[command(subcommand)]
+	pub command: Option<Command>,
+	#[command(flatten)]
+	pub play: PlayArgs,
+	#[command(flatten)]
+	pub global: GlobalArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+	/// Play a level.
+	Play(PlayArgs),
+	/// Simulate games of a level played by the bot and report the results as CSV.
+	Simulate(SimulateArgs),
+	/// Check a level file and report what is wrong with it.
+	Validate { level: String },
+	/// Generate a random level (from `--seed`) and write it to stdout.
+	Generate(GenerateArgs),
+	/// Play the daily challenge, a level generated from the date.
+	Daily(DailyArgs),
+}
+
+#[derive(Args)]
+pub struct GlobalArgs {
+	/// Draw each pixel of the sprites as a square of that many pixels (overrides the zoom
+	/// of the config, the scale of the window in the settings still applies on top of it).
+	#[arg(long, global = true, value_parser = clap::value_parser!(i32).range(4..=12))]
+	pub zoom: Option<i32>,
+	/// The seed of the bot and of the simulations and generated levels.
+	#[arg(long, global = true)]
+	pub seed: Option<u64>,
+	/// Play without a window: the bot (or the replay) plays the level to the end and the
+	/// outcome is written to stdout.
+	#[arg(long, global = true)]
+	pub headless: bool,
+	/// The config file to use instead of the one in the config directory of the platform.
+	#[arg(long, global = true)]
+	pub config: Option<std::path::PathBuf>,
+	/// The balance file (HP, damage, etc.) to use instead of `assets/balance.toml`.
+	#[arg(long, global = true, value_name = "FILE")]
+	pub balance: Option<String>,
+}
+
+#[derive(Args, Default)]
+pub struct PlayArgs {
+	/// The level file.
+	pub level: Option<String>,
+	/// Let the built-in bot play the level.
+	#[arg(long)]
+	pub bot: bool,
+	/// Turns advance on a timer (adjustable with `+` and `-`), the last action requested is
+	/// played at the next tick.
+	#[arg(long)]
+	pub auto_turn: bool,
+	/// Play in the terminal instead of a window.
+	#[arg(long)]
+	pub tui: bool,
+	/// Let an external program play via stdin/stdout (see the `protocol` module).
+	#[arg(long)]
+	pub agent: bool,
+	/// Like `--agent`, over a TCP connection accepted on that address.
+	#[arg(long, value_name = "ADDRESS")]
+	pub agent_tcp: Option<String>,
+	/// Save the turns played in a replay file.
+	#[arg(long, value_name = "FILE")]
+	pub record: Option<String>,
+	/// Play back a replay file (which gives the level).
+	#[arg(long, value_name = "FILE")]
+	pub replay: Option<String>,
+	/// Play back a replay from its share code (see the `share` module).
+	#[arg(long, value_name = "CODE", conflicts_with = "replay")]
+	pub watch: Option<String>,
+	/// Log what happens during each turn as lines of JSON.
+	#[arg(long, value_name = "FILE")]
+	pub log_turns: Option<String>,
+}
+
+#[derive(Args)]
+pub struct SimulateArgs {
+	pub level: String,
+	/// How many games to simulate.
+	#[arg(long, default_value_t = 100)]
+	pub games: u32,
+	/// A file of metadata lines (like `@max_towers 5`) added to the level.
+	#[arg(long, value_name = "FILE")]
+	pub tweaks: Option<String>,
+	/// Simulate the games once per value of a balance value (like `enemy_hp.tank=6,9,12`),
+	/// with one line of report each.
+	#[arg(long, value_name = "NAME=VALUES")]
+	pub sweep: Option<String>,
+}
+
+#[derive(Args)]
+pub struct GenerateArgs {
+	#[arg(long, default_value_t = 14)]
+	pub width: i32,
+	#[arg(long, default_value_t = 9)]
+	pub height: i32,
+}
+
+#[derive(Args)]
+pub struct DailyArgs {
+	/// The day of the challenge (`YYYY-MM-DD`), today by default.
+	#[arg(long)]
+	pub date: Option<String>,
+	/// Print the best scores of the day instead of playing.
+	#[arg(long)]
+	pub scores: bool,
+	/// Play in the terminal instead of a window.
+	#[arg(long)]
+	pub tui: bool,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn play_options_can_be_given_without_the_subcommand() {
+		let cli =
+			Cli::try_parse_from(["prototype-07", "levels/level_A", "--bot", "--seed", "3"]).unwrap();
+		assert!(cli.command.is_none());
+		assert_eq!(cli.play.level.as_deref(), Some("levels/level_A"));
+		assert!(cli.play.bot);
+		assert_eq!(cli.global.seed, Some(3));
+
+		let cli = Cli::try_parse_from(["prototype-07", "simulate", "levels/level_A", "--games", "5"])
+			.unwrap();
+		assert!(matches!(
+			cli.command,
+			Some(Command::Simulate(SimulateArgs { games: 5, .. }))
+		));
+		assert!(Cli::try_parse_from(["prototype-07", "--zoom", "100"]).is_err());
+	}
+}
@@ -0,0 +1,436 @@
+//! The options of the player, saved in `config.toml` in the config directory of the platform
+//! (like `~/.config/prototype-07/` on Linux) every time they are changed in the settings menu,
+//! and loaded at startup. Options missing from the file keep their default value.
+
+use crate::coords::*;
+use crate::locale::Language;
+
+use winit::event::VirtualKeyCode;
+
+pub struct Config {
+	/// Out of 100. There are no sounds yet, the volume is kept for when there are.
+	pub volume: u32,
+	/// Each pixel of the sprites is drawn as a square of `zoom` pixels (`--zoom` overrides it).
+	pub zoom: i32,
+	/// The picture drawn is shown `scale` times bigger in the window (the biggest that fits on
+	/// the screen if 0), whatever the zoom it was drawn with.
+	pub scale: u32,
+	/// How fast the animations play, in percents.
+	pub animation_speed: u32,
+	/// Use colors that can be told apart without seeing red and green.
+	pub colorblind: bool,
+	pub key_bindings: KeyBindings,
+	/// Milliseconds between the moves while a movement key is held, 0 to move once per press.
+	pub key_repeat: u32,
+	pub show_enemy_intents: bool,
+	pub show_stuner_sight: bool,
+	/// Show which way the protected enemies face.
+	pub show_facing: bool,
+	pub language: Language,
+	/// Ask before quitting in the middle of a level.
+	pub confirm_quit: bool,
+	/// Where the window was when the game was last closed, `None` to center it.
+	pub window: Option<WindowPlacement>,
+}
+
+impl Default for Config {
+	fn default() -> Config {
+		Config {
+			volume: 80,
+			zoom: 8,
+			scale: 0,
+			animation_speed: 100,
+			colorblind: false,
+			key_bindings: KeyBindings::Arrows,
+			key_repeat: 150,
+			show_enemy_intents: true,
+			show_stuner_sight: true,
+			show_facing: true,
+			language: Language::English,
+			confirm_quit: true,
+			window: None,
+		}
+	}
+}
+
+/// Where the window is on the screens. Its size is not kept, it fits the level (and the zoom).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct WindowPlacement {
+	/// Of the top left corner of the window (with its decorations), in physical pixels. When
+	/// the window is fullscreen, where it was before going fullscreen.
+	pub position: Coords,
+	pub fullscreen: bool,
+}
+
+impl WindowPlacement {
+	/// Where to put a window of `window_dims` so that it is where it was, moved to be fully on
+	/// the screen it was on (which may have changed size since), `None` if it was on no screen
+	/// anymore (so that it is centered instead).
+	pub fn position_on(self, window_dims: Dimensions, monitors: &[Rect]) -> Option<Coords> {
+		let monitor = monitors
+			.iter()
+			.find(|monitor| monitor.contains(self.position))?;
+		let clamp = |position: i32, window_side: i32, start: i32, side: i32| {
+			position.min(start + side - window_side).max(start)
+		};
+		Some(Coords {
+			x: clamp(
+				self.position.x,
+				window_dims.w,
+				monitor.left(),
+				monitor.dims.w,
+			),
+			y: clamp(
+				self.position.y,
+				window_dims.h,
+				monitor.top(),
+				monitor.dims.h,
+			),
+		})
+	}
+}
+
+pub const ZOOM_RANGE: std::ops::RangeInclusive<i32> = 4..=12;
+/// The scale can also be automatic (0).
+pub const SCALE_RANGE: std::ops::RangeInclusive<u32> = 1..=8;
+pub const ANIMATION_SPEED_RANGE: std::ops::RangeInclusive<u32> = 25..=300;
+/// Key repeat can also be off (0).
+pub const KEY_REPEAT_RANGE: std::ops::RangeInclusive<u32> = 50..=500;
+
+/// The keys that move the player, the arrow keys always work.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum KeyBindings {
+	Arrows,
+	Wasd,
+	/// WASD on AZERTY keyboards.
+	Zqsd,
+}
+
+impl KeyBindings {
+	pub const ALL: [KeyBindings; 3] = [KeyBindings::Arrows, KeyBindings::Wasd, KeyBindings::Zqsd];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			KeyBindings::Arrows => "arrows",
+			KeyBindings::Wasd => "wasd",
+			KeyBindings::Zqsd => "zqsd",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<KeyBindings> {
+		KeyBindings::ALL
+			.into_iter()
+			.find(|key_bindings| key_bindings.name() == name)
+	}
+
+	/// The direction in which a key moves the player, if it is a movement key.
+	pub fn direction(self, key: VirtualKeyCode) -> Option<DxDy> {
+		use VirtualKeyCode::*;
+		let (dx, dy) = match (self, key) {
+			(_, Up) | (KeyBindings::Wasd, W) | (KeyBindings::Zqsd, Z) => (0, -1),
+			(_, Right) | (KeyBindings::Wasd | KeyBindings::Zqsd, D) => (1, 0),
+			(_, Down) | (KeyBindings::Wasd | KeyBindings::Zqsd, S) => (0, 1),
+			(_, Left) | (KeyBindings::Wasd, A) | (KeyBindings::Zqsd, Q) => (-1, 0),
+			_ => return None,
+		};
+		Some(DxDy { dx, dy })
+	}
+}
+
+/// Where the game keeps what is not the config (like the scores), in the data directory of the
+/// platform (like `~/.local/share/prototype-07/` on Linux).
+pub fn data_dir() -> Option<std::path::PathBuf> {
+	let dirs = directories::ProjectDirs::from("", "when-the-studio", "prototype-07")?;
+	Some(dirs.data_dir().to_path_buf())
+}
+
+/// Read a JSON file of the data directory (like the scores) with `parse`, the default value if
+/// there is none (or if it can't be read). `what` is what the file holds, for the logs.
+pub fn load_data_file<T: Default>(
+	file_name: &str,
+	what: &str,
+	parse: impl FnOnce(&serde_json::Value) -> Result<T, String>,
+) -> T {
+	let Some(path) = data_dir().map(|dir| dir.join(file_name)) else {
+		return T::default();
+	};
+	let Ok(text) = std::fs::read_to_string(&path) else {
+		return T::default();
+	};
+	serde_json::from_str(&text)
+		.map_err(|error| error.to_string())
+		.and_then(|json| parse(&json))
+		.unwrap_or_else(|error| {
+			log::warn!("Invalid {what} {}: {error}", path.display());
+			T::default()
+		})
+}
+
+/// Write a JSON file of the data directory, see `load_data_file`.
+pub fn save_data_file(file_name: &str, what: &str, json: &serde_json::Value) {
+	let Some(path) = data_dir().map(|dir| dir.join(file_name)) else {
+		log::warn!("No data directory, the {what} are not saved");
+		return;
+	};
+	let result = std::fs::create_dir_all(path.parent().unwrap())
+		.and_then(|()| std::fs::write(&path, json.to_string()));
+	if let Err(error) = result {
+		log::warn!("Failed to save the {what} {}: {error}", path.display());
+	}
+}
+
+/// The config file given on the command line, if any (see `Config::set_path`).
+static PATH: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+
+impl Config {
+	/// How many pixels of the window for a pixel of a picture of `picture_dims`, on a screen of
+	/// `screen_dims` (if it is known) with that scale factor (2 on most HiDPI screens, where the
+	/// scale of the settings counts for twice as many pixels). Always a whole number, so that
+	/// all the pixels of the sprites are squares of the same size.
+	pub fn window_scale(
+		&self,
+		picture_dims: Dimensions,
+		screen_dims: Option<Dimensions>,
+		scale_factor: f64,
+	) -> u32 {
+		if self.scale != 0 {
+			return ((self.scale as f64 * scale_factor).round() as u32).max(1);
+		}
+		let Some(screen_dims) = screen_dims else {
+			return 1;
+		};
+		// Leaving some room for the decorations of the window and the task bars.
+		let fits = |side: i32, screen_side: i32| screen_side * 9 / 10 / side.max(1);
+		let scale = fits(picture_dims.w, screen_dims.w).min(fits(picture_dims.h, screen_dims.h));
+		scale.clamp(*SCALE_RANGE.start() as i32, *SCALE_RANGE.end() as i32) as u32
+	}
+
+	/// Use that config file instead of the one in the config directory (to be called before
+	/// the config is loaded).
+	pub fn set_path(path: std::path::PathBuf) {
+		let _ = PATH.set(path);
+	}
+
+	fn path() -> Option<std::path::PathBuf> {
+		if let Some(path) = PATH.get() {
+			return Some(path.clone());
+		}
+		let dirs = directories::ProjectDirs::from("", "when-the-studio", "prototype-07")?;
+		Some(dirs.config_dir().join("config.toml"))
+	}
+
+	/// The saved config, or the default one if there is none (or if it can't be read).
+	pub fn load() -> Config {
+		let Some(path) = Config::path() else {
+			return Config::default();
+		};
+		match std::fs::read_to_string(&path) {
+			Ok(text) => Config::parse(&text).unwrap_or_else(|error| {
+				log::warn!("Invalid config {}: {error}", path.display());
+				Config::default()
+			}),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Config::default(),
+			Err(error) => {
+				log::warn!("Failed to read the config {}: {error}", path.display());
+				Config::default()
+			},
+		}
+	}
+
+	pub fn save(&self) {
+		let Some(path) = Config::path() else {
+			log::warn!("No config directory, the options are not saved");
+			return;
+		};
+		let result = std::fs::create_dir_all(path.parent().unwrap())
+			.and_then(|()| std::fs::write(&path, self.to_toml()));
+		if let Err(error) = result {
+			log::warn!("Failed to save the config {}: {error}", path.display());
+		}
+	}
+
+	fn parse(text: &str) -> Result<Config, String> {
+		let table: toml::Table = text
+			.parse()
+			.map_err(|error: toml::de::Error| error.to_string())?;
+		let mut config = Config::default();
+		for (key, value) in &table {
+			let integer = || {
+				value
+					.as_integer()
+					.ok_or(format!("{key} should be a number"))
+			};
+			let boolean = || {
+				value
+					.as_bool()
+					.ok_or(format!("{key} should be true or false"))
+			};
+			match key.as_str() {
+				"volume" => config.volume = (integer()?.clamp(0, 100)) as u32,
+				"zoom" => {
+					let (min, max) = (*ZOOM_RANGE.start() as i64, *ZOOM_RANGE.end() as i64);
+					config.zoom = integer()?.clamp(min, max) as i32;
+				},
+				"scale" => {
+					config.scale = integer()?.clamp(0, *SCALE_RANGE.end() as i64) as u32;
+				},
+				"animation_speed" => {
+					let (min, max) = (
+						*ANIMATION_SPEED_RANGE.start() as i64,
+						*ANIMATION_SPEED_RANGE.end() as i64,
+					);
+					config.animation_speed = integer()?.clamp(min, max) as u32;
+				},
+				"colorblind" => config.colorblind = boolean()?,
+				"key_bindings" => {
+					let name = value.as_str().unwrap_or_default();
+					config.key_bindings =
+						KeyBindings::from_name(name).ok_or(format!("unknown key bindings {value}"))?;
+				},
+				"key_repeat" => {
+					let (min, max) = (
+						*KEY_REPEAT_RANGE.start() as i64,
+						*KEY_REPEAT_RANGE.end() as i64,
+					);
+					config.key_repeat = match integer()? {
+						0 => 0,
+						key_repeat => key_repeat.clamp(min, max) as u32,
+					};
+				},
+				"show_enemy_intents" => config.show_enemy_intents = boolean()?,
+				"show_stuner_sight" => config.show_stuner_sight = boolean()?,
+				"show_facing" => config.show_facing = boolean()?,
+				"confirm_quit" => config.confirm_quit = boolean()?,
+				"window" => {
+					let table = value.as_table().ok_or(format!("{key} should be a table"))?;
+					let number = |key: &str| {
+						table
+							.get(key)
+							.and_then(toml::Value::as_integer)
+							.ok_or(format!("window.{key} should be a number"))
+					};
+					config.window = Some(WindowPlacement {
+						position: Coords { x: number("x")? as i32, y: number("y")? as i32 },
+						fullscreen: table
+							.get("fullscreen")
+							.and_then(toml::Value::as_bool)
+							.unwrap_or(false),
+					});
+				},
+				"language" => {
+					let name = value.as_str().unwrap_or_default();
+					config.language =
+						Language::from_name(name).ok_or(format!("unknown language {value}"))?;
+				},
+				_ => log::warn!("Unknown option {key} in the config"),
+			}
+		}
+		Ok(config)
+	}
+
+	fn to_toml(&self) -> String {
+		let mut table = toml::Table::new();
+		table.insert("volume".into(), (self.volume as i64).into());
+		table.insert("zoom".into(), (self.zoom as i64).into());
+		table.insert("scale".into(), (self.scale as i64).into());
+		table.insert(
+			"animation_speed".into(),
+			(self.animation_speed as i64).into(),
+		);
+		table.insert("colorblind".into(), self.colorblind.into());
+		table.insert("key_bindings".into(), self.key_bindings.name().into());
+		table.insert("key_repeat".into(), (self.key_repeat as i64).into());
+		table.insert("show_enemy_intents".into(), self.show_enemy_intents.into());
+		table.insert("show_stuner_sight".into(), self.show_stuner_sight.into());
+		table.insert("show_facing".into(), self.show_facing.into());
+		table.insert("language".into(), self.language.name().into());
+		table.insert("confirm_quit".into(), self.confirm_quit.into());
+		if let Some(window) = self.window {
+			let mut window_table = toml::Table::new();
+			window_table.insert("x".into(), (window.position.x as i64).into());
+			window_table.insert("y".into(), (window.position.y as i64).into());
+			window_table.insert("fullscreen".into(), window.fullscreen.into());
+			table.insert("window".into(), window_table.into());
+		}
+		table.to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn config_is_saved_and_loaded_back() {
+		let config = Config {
+			volume: 30,
+			zoom: 5,
+			scale: 3,
+			animation_speed: 150,
+			colorblind: true,
+			key_bindings: KeyBindings::Zqsd,
+			key_repeat: 0,
+			show_enemy_intents: false,
+			show_stuner_sight: true,
+			show_facing: false,
+			language: Language::French,
+			confirm_quit: false,
+			window: Some(WindowPlacement { position: (-1200, 40).into(), fullscreen: true }),
+		};
+		let loaded = Config::parse(&config.to_toml()).unwrap();
+		assert_eq!(loaded.to_toml(), config.to_toml());
+	}
+
+	#[test]
+	fn missing_options_keep_their_default_value() {
+		let config = Config::parse("zoom = 100\n").unwrap();
+		assert_eq!(config.zoom, *ZOOM_RANGE.end());
+		assert_eq!(config.volume, Config::default().volume);
+		assert_eq!(Config::parse("key_repeat = 10\n").unwrap().key_repeat, 50);
+		assert!(Config::parse("colorblind = 3\n").is_err());
+		assert!(Config::parse("key_bindings = \"dvorak\"\n").is_err());
+		assert!(Config::parse("[window]\nx = 3\n").is_err());
+	}
+
+	#[test]
+	fn the_window_is_scaled_by_a_whole_number() {
+		let mut config = Config::default();
+		let picture = Dimensions { w: 640, h: 400 };
+		let screen = Dimensions { w: 1920, h: 1080 };
+		assert_eq!(config.window_scale(picture, Some(screen), 1.0), 2);
+		assert_eq!(config.window_scale(picture, None, 1.0), 1);
+		assert_eq!(
+			config.window_scale(picture, Some(Dimensions { w: 800, h: 600 }), 1.0),
+			1
+		);
+		// The screen is measured in physical pixels, whatever its scale factor.
+		let hidpi_screen = Dimensions { w: 3840, h: 2160 };
+		assert_eq!(config.window_scale(picture, Some(hidpi_screen), 2.0), 4);
+		config.scale = 4;
+		assert_eq!(config.window_scale(picture, Some(screen), 1.0), 4);
+		config.scale = 1;
+		assert_eq!(config.window_scale(picture, Some(hidpi_screen), 2.0), 2);
+		assert_eq!(config.window_scale(picture, Some(screen), 1.5), 2);
+		assert_eq!(config.window_scale(picture, Some(screen), 1.25), 1);
+		assert_eq!(Config::parse("scale = 20\n").unwrap().scale, 8);
+	}
+
+	#[test]
+	fn the_window_is_put_back_on_its_screen() {
+		let monitors = [
+			Rect { top_left: (0, 0).into(), dims: Dimensions { w: 1920, h: 1080 } },
+			Rect { top_left: (-1280, 0).into(), dims: Dimensions { w: 1280, h: 720 } },
+		];
+		let window_dims = Dimensions { w: 400, h: 300 };
+		let position_on = |x, y| {
+			WindowPlacement { position: (x, y).into(), fullscreen: false }
+				.position_on(window_dims, &monitors)
+		};
+		assert_eq!(position_on(100, 200), Some((100, 200).into()));
+		// Sticking out of the screen it is on (here the one on the left).
+		assert_eq!(position_on(-100, 600), Some((-400, 420).into()));
+		// On a screen that is not there anymore.
+		assert_eq!(position_on(2500, 100), None);
+		assert_eq!(position_on(-100, 900), None);
+	}
+}
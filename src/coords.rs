@@ -1,6 +1,6 @@
 //! Everything purely related to coordinates, grids, and such stuff.
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Dimensions {
 	pub w: i32,
 	pub h: i32,
@@ -66,7 +66,7 @@ impl Iterator for IterCoordsRect {
 	}
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 pub struct Grid<T> {
 	pub dims: Dimensions,
 	content: Vec<T>,
@@ -76,9 +76,7 @@ impl<T: Clone> Grid<T> {
 	pub fn new(dims: Dimensions, value: T) -> Grid<T> {
 		Grid {
 			dims,
-			content: std::iter::repeat(value)
-				.take(dims.area() as usize)
-				.collect(),
+			content: std::iter::repeat_n(value, dims.area() as usize).collect(),
 		}
 	}
 }
@@ -100,13 +98,13 @@ impl<T> Grid<T> {
 	}
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct Coords {
 	pub x: i32,
 	pub y: i32,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct DxDy {
 	pub dx: i32,
 	pub dy: i32,
@@ -159,6 +157,22 @@ impl DxDy {
 			.into_iter()
 			.map(DxDy::from)
 	}
+
+	/// The 4 directions and the 4 diagonals.
+	pub fn the_8_directions() -> impl Iterator<Item = DxDy> {
+		[
+			(0, -1),
+			(1, -1),
+			(1, 0),
+			(1, 1),
+			(0, 1),
+			(-1, 1),
+			(-1, 0),
+			(-1, -1),
+		]
+		.into_iter()
+		.map(DxDy::from)
+	}
 }
 
 impl std::fmt::Display for Coords {
@@ -193,6 +207,12 @@ impl Rect {
 	pub fn right_excluded(self) -> i32 {
 		self.top_left.x + self.dims.w
 	}
+	pub fn center(self) -> Coords {
+		Coords {
+			x: self.top_left.x + self.dims.w / 2,
+			y: self.top_left.y + self.dims.h / 2,
+		}
+	}
 
 	pub fn contains(self, coords: Coords) -> bool {
 		self.left() <= coords.x
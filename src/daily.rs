@@ -0,0 +1,252 @@
+//! The daily challenge (`daily` subcommand): a level generated from the date, the same for
+//! everyone on a given day, played with the compiled-in balance (mods and `--balance` don't
+//! apply) so that scores can be compared. The best scores of each day are kept in
+//! `daily_scores.json` in the data directory of the platform.
+//!
+//! Runs are scored like any level (see `high_scores::score`).
+
+use crate::coords::*;
+use crate::*;
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The size of the daily levels.
+pub const DIMS: Dimensions = Dimensions { w: 16, h: 9 };
+/// The rules of the daily levels, added to the generated level.
+const RULES: &str = "@lives 2\n";
+/// How many scores are kept for each day.
+const SCORES_PER_DAY: usize = 10;
+const SCORES_FILE_NAME: &str = "daily_scores.json";
+
+/// A day (UTC).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+	pub year: i32,
+	pub month: u32,
+	pub day: u32,
+}
+
+impl Date {
+	pub fn today() -> Date {
+		let seconds = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.map_or(0, |duration| duration.as_secs());
+		Date::from_days(seconds as i64 / 86400)
+	}
+
+	/// The date that many days after 1970-01-01 (see
+	/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days).
+	fn from_days(days: i64) -> Date {
+		let days = days + 719468;
+		let era = days.div_euclid(146097);
+		let day_of_era = days.rem_euclid(146097);
+		let year_of_era =
+			(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+		let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+		let month_index = (5 * day_of_year + 2) / 153;
+		let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+		let month = if month_index < 10 {
+			month_index + 3
+		} else {
+			month_index - 9
+		} as u32;
+		let year = (year_of_era + era * 400 + i64::from(month <= 2)) as i32;
+		Date { year, month, day }
+	}
+
+	/// A date written `YYYY-MM-DD`.
+	pub fn parse(text: &str) -> Option<Date> {
+		let mut parts = text.split('-');
+		let date = Date {
+			year: parts.next()?.parse().ok()?,
+			month: parts.next()?.parse().ok()?,
+			day: parts.next()?.parse().ok()?,
+		};
+		let valid =
+			parts.next().is_none() && (1..=12).contains(&date.month) && (1..=31).contains(&date.day);
+		valid.then_some(date)
+	}
+
+	fn seed(self) -> u64 {
+		// Mixed so that the seeds of consecutive days are far apart.
+		let number = self.year as u64 * 10000 + self.month as u64 * 100 + self.day as u64;
+		number.wrapping_mul(0x9E3779B97F4A7C15)
+	}
+}
+
+impl std::fmt::Display for Date {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+	}
+}
+
+/// The level of that day, in the level format.
+pub fn level_text(date: Date) -> String {
+	generate::generate_level(date.seed(), DIMS) + RULES
+}
+
+/// Write the level of that day in the data directory (so that replays can refer to it), and
+/// return its path.
+pub fn write_level_file(date: Date) -> Result<PathBuf, String> {
+	let dir = config::data_dir().ok_or("no data directory")?.join("daily");
+	let path = dir.join(date.to_string());
+	std::fs::create_dir_all(&dir)
+		.and_then(|()| std::fs::write(&path, level_text(date)))
+		.map_err(|error| format!("{}: {error}", path.display()))?;
+	Ok(path)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Score {
+	pub score: u32,
+	pub turns: u32,
+	pub won: bool,
+}
+
+/// The best scores of each day, from the best to the worst.
+#[derive(Default)]
+pub struct Scores {
+	by_day: BTreeMap<String, Vec<Score>>,
+}
+
+impl Scores {
+	/// The saved daily scores, see `config::load_data_file`.
+	pub fn load() -> Scores {
+		config::load_data_file(SCORES_FILE_NAME, "daily scores", Scores::from_json)
+	}
+
+	pub fn save(&self) {
+		config::save_data_file(SCORES_FILE_NAME, "daily scores", &self.to_json());
+	}
+
+	fn from_json(json: &Value) -> Result<Scores, String> {
+		let days = json["days"].as_object().ok_or("days should be an object")?;
+		let mut scores = Scores::default();
+		for (day, day_scores) in days {
+			let day_scores = day_scores
+				.as_array()
+				.ok_or(format!("{day} should be a list of scores"))?;
+			for score in day_scores {
+				let number = |key: &str| {
+					score[key]
+						.as_u64()
+						.and_then(|number| u32::try_from(number).ok())
+						.ok_or(format!("a score of {day} has no {key}"))
+				};
+				let won = score["won"]
+					.as_bool()
+					.ok_or(format!("a score of {day} has no won"))?;
+				let score = Score { score: number("score")?, turns: number("turns")?, won };
+				scores.by_day.entry(day.clone()).or_default().push(score);
+			}
+		}
+		Ok(scores)
+	}
+
+	fn to_json(&self) -> Value {
+		let days: serde_json::Map<String, Value> = self
+			.by_day
+			.iter()
+			.map(|(day, day_scores)| {
+				let day_scores = day_scores
+					.iter()
+					.map(|score| json!({ "score": score.score, "turns": score.turns, "won": score.won }))
+					.collect();
+				(day.clone(), Value::Array(day_scores))
+			})
+			.collect();
+		json!({ "days": days })
+	}
+
+	/// Add a score to the ones of that day, returns its rank (from 1) if it is among the best.
+	pub fn add(&mut self, date: Date, score: Score) -> Option<usize> {
+		let day_scores = self.by_day.entry(date.to_string()).or_default();
+		high_scores::add_ranked(day_scores, score, SCORES_PER_DAY, |score| score.score)
+	}
+
+	pub fn of_day(&self, date: Date) -> &[Score] {
+		self
+			.by_day
+			.get(&date.to_string())
+			.map_or(&[], |day_scores| day_scores.as_slice())
+	}
+}
+
+/// A daily challenge being played, its score is saved once when the level is over.
+pub struct Run {
+	pub date: Date,
+	finished: bool,
+}
+
+impl Run {
+	pub fn new(date: Date) -> Run {
+		Run { date, finished: false }
+	}
+
+	/// To be called after each turn: once the level is over, the score is saved and shown.
+	pub fn update(&mut self, level: &mut LevelState) {
+		if self.finished || !(level.game_joever || level.is_won()) {
+			return;
+		}
+		self.finished = true;
+		let score = Score {
+			score: high_scores::score(level),
+			turns: level.turn,
+			won: level.is_won(),
+		};
+		let mut scores = Scores::load();
+		let rank = scores.add(self.date, score);
+		scores.save();
+		let best = scores.of_day(self.date)[0].score;
+		let message = match rank {
+			Some(rank) => tr_with(
+				"daily.result_ranked",
+				&[("score", &score.score), ("rank", &rank), ("best", &best)],
+			),
+			None => tr_with("daily.result", &[("score", &score.score), ("best", &best)]),
+		};
+		log::info!("Daily challenge {}: {message}", self.date);
+		level.message = Some(message);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn dates_give_levels_and_scores_are_ranked() {
+		assert!(Date::from_days(0) == Date { year: 1970, month: 1, day: 1 });
+		assert_eq!(Date::from_days(20743).to_string(), "2026-10-17");
+		assert_eq!(Date::from_days(11016).to_string(), "2000-02-29");
+		let date = Date::parse("2026-10-17").unwrap();
+		assert!(Date::parse("2026-13-01").is_none());
+		assert_eq!(level_text(date), level_text(date));
+		assert_ne!(
+			level_text(date),
+			level_text(Date::parse("2026-10-18").unwrap())
+		);
+		assert!(parse_level(&level_text(date)).is_ok());
+
+		let mut scores = Scores::default();
+		let score = |score| Score { score, turns: 40, won: true };
+		assert_eq!(scores.add(date, score(300)), Some(1));
+		assert_eq!(scores.add(date, score(800)), Some(1));
+		assert_eq!(scores.add(date, score(300)), Some(3));
+		for _ in 0..10 {
+			scores.add(date, score(900));
+		}
+		assert_eq!(scores.add(date, score(100)), None);
+		let json = serde_json::from_str(&scores.to_json().to_string()).unwrap();
+		let scores = Scores::from_json(&json).unwrap();
+		let day_scores: Vec<u32> = scores
+			.of_day(date)
+			.iter()
+			.map(|score| score.score)
+			.collect();
+		assert_eq!(day_scores, [900; 10]);
+		assert!(scores.of_day(Date::parse("2026-10-18").unwrap()).is_empty());
+	}
+}
@@ -0,0 +1,95 @@
+//! Procedural generation of levels (in the level format) from a seed: a winding path from the
+//! left side to a goal on the right side, with waves of enemies spawning at its start, some
+//! scenery, and the player somewhere off the path.
+
+use crate::coords::*;
+use crate::rng::Rng;
+
+/// A level of the given size (at least 5x3), the same for the same seed.
+pub fn generate_level(seed: u64, dims: Dimensions) -> String {
+	let mut rng = Rng::with_seed(seed);
+	let mut tiles = vec![vec![String::from("O-"); dims.w as usize]; dims.h as usize];
+	let mut is_path = vec![vec![false; dims.w as usize]; dims.h as usize];
+
+	// The path goes up or down in the even columns and right in the odd ones, so that no two
+	// parts of the path are next to each other (which would be a shortcut).
+	let mut y = rng.below(dims.h as u32) as i32;
+	let spawn = Coords { x: 0, y };
+	let last_x = dims.w - 1 - (dims.w - 1) % 2;
+	for x in 0..=last_x {
+		if x % 2 == 0 && x != 0 && x != last_x {
+			let target_y = rng.below(dims.h as u32) as i32;
+			while y != target_y {
+				is_path[y as usize][x as usize] = true;
+				y += (target_y - y).signum();
+			}
+		}
+		is_path[y as usize][x as usize] = true;
+	}
+	let goal = Coords { x: last_x, y };
+	for (y, row) in is_path.iter().enumerate() {
+		for (x, &path) in row.iter().enumerate() {
+			if path {
+				tiles[y][x] = String::from("|-");
+			} else if rng.below(100) < 10 {
+				tiles[y][x] = String::from(if rng.below(2) == 0 { "Or" } else { "OT" });
+			}
+		}
+	}
+	tiles[goal.y as usize][goal.x as usize] = String::from("|g");
+	tiles[spawn.y as usize][spawn.x as usize] = String::from("?A");
+
+	// The player starts on the grass, around the middle.
+	let middle = Coords { x: dims.w / 2, y: dims.h / 2 };
+	let player = dims
+		.iter()
+		.filter(|coords| !is_path[coords.y as usize][coords.x as usize])
+		.min_by_key(|&coords| {
+			let DxDy { dx, dy } = coords - middle;
+			dx.abs() + dy.abs()
+		})
+		.unwrap();
+	tiles[player.y as usize][player.x as usize] = String::from("Op");
+
+	let mut text: String = tiles.iter().map(|row| row.join(" ") + "\n").collect();
+	text.push_str("@tile A |-\n");
+	text.push_str(&format!("@max_towers {}\n", 3 + rng.below(3)));
+	let waves = 3 + rng.below(3);
+	for wave in 0..waves {
+		let first_turn = 3 + wave * 8;
+		for index in 0..2 + wave {
+			let enemy = ["basic", "basic", "tank", "speeeeed"][rng.below(4) as usize];
+			text.push_str(&format!(
+				"@event spawn {enemy} A {}\n",
+				first_turn + 2 * index
+			));
+		}
+	}
+	text
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::*;
+
+	#[test]
+	fn generated_levels_are_playable_and_reproducible() {
+		for seed in 0..20 {
+			let dims = Dimensions { w: 5 + seed as i32, h: 3 + seed as i32 % 5 };
+			let text = generate_level(seed, dims);
+			assert_eq!(text, generate_level(seed, dims));
+			let level_data = parse_level(&text).unwrap();
+			let mut grid = level_data.init_grid.clone();
+			compute_distance(&mut grid);
+			let spawn = level_data.named_tiles["A"];
+			assert!(matches!(grid.get(spawn).unwrap().groud, Ground::Path(dist) if dist > 0));
+			let players = grid
+				.dims
+				.iter()
+				.filter(|&coords| matches!(grid.get(coords).unwrap().obj, Obj::Player { .. }))
+				.count();
+			assert_eq!(players, 1);
+		}
+	}
+}
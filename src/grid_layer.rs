@@ -0,0 +1,173 @@
+//! The cells of the grid are kept rendered between frames, and only the cells that changed
+//! since the last frame (the dirty cells) are repainted. Everything drawn over the grid
+//! (highlights, menus, the HUD, etc.) is still drawn every frame, over a copy of the cells.
+//!
+//! Whether a cell changed is decided by comparing a hash of everything that its rendering
+//! depends on (see `CellView`).
+
+use crate::coords::*;
+use crate::*;
+
+use std::hash::{Hash, Hasher};
+
+pub struct GridLayer {
+	/// The pixels of the rendered cells, `None` when everything has to be redrawn.
+	pixels: Option<Vec<u8>>,
+	/// The dimensions of the pixel buffer that `pixels` was rendered for.
+	pixel_buffer_dims: Dimensions,
+	/// The key of every cell as it is rendered in `pixels`.
+	cell_keys: Vec<u64>,
+	cell_pixel_side: i32,
+	grid_dims: Dimensions,
+	/// What is visible around the grid.
+	clear_color: [u8; 4],
+}
+
+impl GridLayer {
+	pub fn new(clear_color: [u8; 4]) -> GridLayer {
+		GridLayer {
+			pixels: None,
+			pixel_buffer_dims: Dimensions { w: 0, h: 0 },
+			cell_keys: vec![],
+			cell_pixel_side: 0,
+			grid_dims: Dimensions { w: 0, h: 0 },
+			clear_color,
+		}
+	}
+
+	/// Draw the cells of a grid in the pixel buffer, only repainting the ones whose key
+	/// changed (`cell_keys` are in the order of `grid_dims.iter()`). If the pixel buffer,
+	/// the grid or the size of the cells changed, everything is redrawn.
+	pub fn draw(
+		&mut self,
+		pixel_buffer: &mut pixels::Pixels,
+		pixel_buffer_dims: Dimensions,
+		grid_dims: Dimensions,
+		cell_pixel_side: i32,
+		cell_keys: Vec<u64>,
+		mut draw_cell: impl FnMut(&mut pixels::Pixels, Coords),
+	) {
+		let full_redraw = self.pixel_buffer_dims != pixel_buffer_dims
+			|| self.cell_pixel_side != cell_pixel_side
+			|| self.grid_dims != grid_dims;
+		match (&self.pixels, full_redraw) {
+			(Some(pixels), false) => pixel_buffer.frame_mut().copy_from_slice(pixels),
+			_ => {
+				self.pixels = None;
+				let clear_color = self.clear_color;
+				pixel_buffer
+					.frame_mut()
+					.chunks_exact_mut(4)
+					.for_each(|pixel| pixel.copy_from_slice(&clear_color));
+			},
+		}
+		for (index, coords) in grid_dims.iter().enumerate() {
+			if self.pixels.is_some() && self.cell_keys[index] == cell_keys[index] {
+				continue;
+			}
+			draw_cell(pixel_buffer, coords);
+			if let Some(pixels) = &mut self.pixels {
+				let dst = Rect::tile(coords, cell_pixel_side);
+				copy_rect(pixel_buffer.frame(), pixels, pixel_buffer_dims, dst);
+			}
+		}
+		if self.pixels.is_none() {
+			self.pixels = Some(pixel_buffer.frame().to_vec());
+		}
+		self.pixel_buffer_dims = pixel_buffer_dims;
+		self.cell_pixel_side = cell_pixel_side;
+		self.grid_dims = grid_dims;
+		self.cell_keys = cell_keys;
+	}
+}
+
+/// Copy the pixels of `rect` (clipped to the buffers) from one frame buffer to another.
+fn copy_rect(src: &[u8], dst: &mut [u8], dims: Dimensions, rect: Rect) {
+	let left = rect.left().clamp(0, dims.w);
+	let right = rect.right_excluded().clamp(0, dims.w);
+	for y in rect.top().max(0)..rect.bottom_excluded().min(dims.h) {
+		let row_start = ((y * dims.w + left) * 4) as usize;
+		let row_end = ((y * dims.w + right) * 4) as usize;
+		dst[row_start..row_end].copy_from_slice(&src[row_start..row_end]);
+	}
+}
+
+/// Everything that the rendering of a cell depends on: `draw_cell` draws a cell from it alone
+/// (and `draw_obj` its object), so its hash changes whenever the cell has to be repainted.
+#[derive(Clone, Hash)]
+pub struct CellView {
+	pub cell: Cell,
+	/// Which neighbors have the same ground (see `autotile_mask`).
+	pub autotile_mask: usize,
+	pub is_visible: bool,
+	pub has_corpse: bool,
+	/// For goals.
+	pub goal_hp_max: Option<u32>,
+	/// For spikes.
+	pub spikes_armed: bool,
+	pub theme: Theme,
+	/// Applied by the drawing functions (see `set_scene_tint`).
+	pub scene_tint: Option<[u8; 3]>,
+	pub colorblind: bool,
+	/// How the object is drawn in the cell, `None` when it is drawn over the grid instead
+	/// (like while it slides).
+	pub object_effects: Option<SpriteEffects>,
+}
+
+impl CellView {
+	pub fn of(
+		level: &LevelState,
+		coords: Coords,
+		object_effects: Option<SpriteEffects>,
+	) -> CellView {
+		let cell = level.grid.get(coords).unwrap();
+		CellView {
+			cell: cell.clone(),
+			autotile_mask: autotile_mask(&level.grid, coords),
+			is_visible: level.is_visible(coords),
+			has_corpse: level
+				.stats
+				.corpses
+				.iter()
+				.any(|(corpse_coords, _)| *corpse_coords == coords),
+			goal_hp_max: matches!(cell.obj, Obj::Goal { .. }).then_some(level.goal_hp_max),
+			spikes_armed: cell.spikes && level.spikes_armed(),
+			theme: level.theme,
+			scene_tint: level.scene_tint(),
+			colorblind: COLORBLIND.load(Ordering::Relaxed),
+			object_effects,
+		}
+	}
+
+	pub fn render_key(&self) -> u64 {
+		let mut hasher = std::collections::hash_map::DefaultHasher::new();
+		self.hash(&mut hasher);
+		hasher.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn only_the_cells_that_changed_are_dirty() {
+		let mut scenario = scenario::Scenario::new("Op O- |e |- |- |g");
+		let keys = |level: &LevelState| -> Vec<u64> {
+			level
+				.grid
+				.dims
+				.iter()
+				.map(|coords| CellView::of(level, coords, Some(SpriteEffects::default())).render_key())
+				.collect()
+		};
+		let keys_before = keys(&scenario.level);
+		scenario.skip_turns(1);
+		let dirty: Vec<bool> = keys_before
+			.iter()
+			.zip(keys(&scenario.level))
+			.map(|(before, after)| *before != after)
+			.collect();
+		assert_eq!(dirty, [false, false, true, true, false, false]);
+	}
+}
@@ -0,0 +1,233 @@
+//! The best scores of each level, kept in `high_scores.json` in the data directory of the
+//! platform. When a level played in the window ends with one of its 10 best scores, the player
+//! enters a name for it, and the scores are shown on the results screen and in the level select.
+//!
+//! A run scores 10 points per enemy killed, and if the level is won 500 points plus 100 per
+//! life left and 20 per tower that was not placed.
+
+use crate::*;
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// How many scores are kept for each level.
+pub const SCORES_PER_LEVEL: usize = 10;
+/// The longest name that can be entered.
+pub const MAX_NAME_LENGTH: usize = 12;
+
+/// The score of a run so far, see the module documentation.
+pub fn score(level: &LevelState) -> u32 {
+	let stats = &level.stats;
+	let kills = stats.tower_kills.values().sum::<u32>()
+		+ stats.bomb_kills
+		+ stats.player_kills
+		+ stats.spike_kills
+		+ stats.lava_kills
+		+ stats.meteor_kills;
+	let mut score = 10 * kills;
+	if level.is_won() {
+		score += 500 + 100 * level.lives.unwrap_or(0) + 20 * level.remaining_towers.unwrap_or(0);
+	}
+	score
+}
+
+/// How a level ended, for the results screen.
+#[derive(Clone, Copy)]
+pub struct Outcome {
+	pub score: u32,
+	pub turns: u32,
+	pub won: bool,
+	/// The rank of the score among the best ones of the level (from 1), if it is one of them.
+	pub rank: Option<usize>,
+}
+
+impl Outcome {
+	pub fn of(level: &LevelState) -> Outcome {
+		Outcome {
+			score: score(level),
+			turns: level.turn,
+			won: level.is_won(),
+			rank: None,
+		}
+	}
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Entry {
+	pub name: String,
+	pub score: u32,
+	pub turns: u32,
+	/// `YYYY-MM-DD`.
+	pub date: String,
+}
+
+#[derive(Default)]
+pub struct HighScores {
+	/// By level (see `level_key`), from the best to the worst.
+	by_level: BTreeMap<String, Vec<Entry>>,
+	/// The name entered last, suggested for the next high score.
+	pub last_name: String,
+}
+
+/// Levels are told apart by their file, `./levels/a` being `levels/a`.
+pub fn level_key(level_file: &str) -> String {
+	level_file.trim_start_matches("./").to_string()
+}
+
+const FILE_NAME: &str = "high_scores.json";
+
+impl HighScores {
+	/// The saved high scores, see `config::load_data_file`.
+	pub fn load() -> HighScores {
+		config::load_data_file(FILE_NAME, "high scores", HighScores::from_json)
+	}
+
+	pub fn save(&self) {
+		config::save_data_file(FILE_NAME, "high scores", &self.to_json());
+	}
+
+	fn from_json(json: &Value) -> Result<HighScores, String> {
+		let mut high_scores = HighScores {
+			last_name: json["last_name"].as_str().unwrap_or_default().to_string(),
+			..HighScores::default()
+		};
+		let levels = json["levels"]
+			.as_object()
+			.ok_or("levels should be an object")?;
+		for (level, entries) in levels {
+			let entries = entries
+				.as_array()
+				.ok_or(format!("the scores of {level} should be a list"))?;
+			for entry in entries {
+				let number = |key: &str| {
+					entry[key]
+						.as_u64()
+						.and_then(|number| u32::try_from(number).ok())
+						.ok_or(format!("a score of {level} has no {key}"))
+				};
+				let text = |key: &str| {
+					entry[key]
+						.as_str()
+						.map(str::to_string)
+						.ok_or(format!("a score of {level} has no {key}"))
+				};
+				let entry = Entry {
+					name: text("name")?,
+					score: number("score")?,
+					turns: number("turns")?,
+					date: text("date")?,
+				};
+				high_scores
+					.by_level
+					.entry(level.clone())
+					.or_default()
+					.push(entry);
+			}
+		}
+		Ok(high_scores)
+	}
+
+	fn to_json(&self) -> Value {
+		let levels: serde_json::Map<String, Value> = self
+			.by_level
+			.iter()
+			.map(|(level, entries)| {
+				let entries = entries
+					.iter()
+					.map(|entry| {
+						json!({
+							"name": entry.name,
+							"score": entry.score,
+							"turns": entry.turns,
+							"date": entry.date,
+						})
+					})
+					.collect();
+				(level.clone(), Value::Array(entries))
+			})
+			.collect();
+		json!({ "last_name": self.last_name, "levels": levels })
+	}
+
+	pub fn of_level(&self, level_key: &str) -> &[Entry] {
+		self
+			.by_level
+			.get(level_key)
+			.map_or(&[], |entries| entries.as_slice())
+	}
+
+	/// Would that score be one of the best of the level?
+	pub fn qualifies(&self, level_key: &str, score: u32) -> bool {
+		let entries = self.of_level(level_key);
+		entries.len() < SCORES_PER_LEVEL || entries.iter().any(|entry| entry.score < score)
+	}
+
+	/// Add a score to the ones of the level, returns its rank (from 1) if it is among the best.
+	pub fn add(&mut self, level_key: &str, entry: Entry) -> Option<usize> {
+		let entries = self.by_level.entry(level_key.to_string()).or_default();
+		add_ranked(entries, entry, SCORES_PER_LEVEL, |entry| entry.score)
+	}
+}
+
+/// Add a score to a list of scores from the best to the worst that keeps the `max` best ones,
+/// returns its rank (from 1) if it is kept.
+pub fn add_ranked<T>(
+	ranked: &mut Vec<T>,
+	new: T,
+	max: usize,
+	score: impl Fn(&T) -> u32,
+) -> Option<usize> {
+	// After the scores that are as good, as they were there first.
+	let index = ranked
+		.iter()
+		.position(|other| score(other) < score(&new))
+		.unwrap_or(ranked.len());
+	ranked.insert(index, new);
+	ranked.truncate(max);
+	(index < max).then_some(index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn the_best_scores_of_each_level_are_kept() {
+		let entry = |name: &str, score| Entry {
+			name: name.to_string(),
+			score,
+			turns: 30,
+			date: "2026-10-17".to_string(),
+		};
+		let mut high_scores = HighScores::default();
+		let key = level_key("./levels/level_A");
+		assert_eq!(key, "levels/level_A");
+		assert!(high_scores.qualifies(&key, 0));
+		assert_eq!(high_scores.add(&key, entry("Ann", 500)), Some(1));
+		assert_eq!(high_scores.add(&key, entry("Bob", 700)), Some(1));
+		assert_eq!(high_scores.add(&key, entry("Cy", 500)), Some(3));
+		for _ in 0..7 {
+			high_scores.add(&key, entry("Dee", 900));
+		}
+		assert!(!high_scores.qualifies(&key, 500));
+		assert!(high_scores.qualifies(&key, 501));
+		assert_eq!(high_scores.add(&key, entry("Eve", 100)), None);
+		high_scores.last_name = "Cy".to_string();
+
+		let json = serde_json::from_str(&high_scores.to_json().to_string()).unwrap();
+		let high_scores = HighScores::from_json(&json).unwrap();
+		let names: Vec<&str> = high_scores
+			.of_level(&key)
+			.iter()
+			.map(|entry| entry.name.as_str())
+			.collect();
+		assert_eq!(names[7..], ["Bob", "Ann", "Cy"]);
+		assert_eq!(high_scores.last_name, "Cy");
+		assert!(high_scores.of_level("levels/level_B").is_empty());
+
+		let level_data = parse_level("Op |g\n@lives 2\n@max_towers 3").unwrap();
+		let level = LevelState::new(&level_data);
+		assert!(level.is_won());
+		assert_eq!(score(&level), 500 + 200 + 60);
+	}
+}
@@ -0,0 +1,150 @@
+//! Tutorial hints (`@hint <trigger> <text>`), to introduce things to new players when they
+//! happen rather than all at once. Each hint is shown once, the first time its trigger happens,
+//! in a text box that stays until it is dismissed (hints triggered meanwhile wait their turn).
+//! When the trigger is about a tile (like an enemy that appears), the box points at it.
+
+use crate::coords::*;
+use crate::*;
+
+#[derive(Clone)]
+pub enum Trigger {
+	/// At the start of that turn.
+	Turn(u32),
+	/// When the player (any unit) is on or next to that tile.
+	Near(Coords),
+	/// The first time an enemy of that kind is seen.
+	Enemy(Enemy),
+	/// The first time the player or a tower is stunned.
+	Stunned,
+	/// When there are no towers left to place.
+	TowersDepleted,
+	/// The first time an enemy reaches a goal.
+	GoalDamaged,
+}
+
+#[derive(Clone)]
+pub struct Hint {
+	pub trigger: Trigger,
+	pub text: String,
+}
+
+/// A hint to show, with the tile it is about if any.
+#[derive(Clone)]
+pub struct ShownHint {
+	pub text: String,
+	pub anchor: Option<Coords>,
+}
+
+impl Trigger {
+	/// Where the trigger happens (with the tile it is about, if any), `None` if it doesn't.
+	fn happens(&self, level: &LevelState) -> Option<Option<Coords>> {
+		let find =
+			|is_it: &dyn Fn(&Obj) -> bool| {
+				level.grid.dims.iter().find(|&coords| {
+					level.is_visible(coords) && is_it(&level.grid.get(coords).unwrap().obj)
+				})
+			};
+		match self {
+			Trigger::Turn(turn) => (level.turn >= *turn).then_some(None),
+			Trigger::Near(coords) => level
+				.unit_coords()
+				.into_iter()
+				.any(|unit_coords| {
+					let DxDy { dx, dy } = *coords - unit_coords;
+					dx.abs() + dy.abs() <= 1
+				})
+				.then_some(Some(*coords)),
+			Trigger::Enemy(enemy) => find(&|obj| {
+				matches!(obj, Obj::Enemy { variant, .. }
+					if std::mem::discriminant(variant) == std::mem::discriminant(enemy))
+			})
+			.map(Some),
+			Trigger::Stunned => find(&|obj| {
+				matches!(
+					obj,
+					Obj::Player { stunned: true } | Obj::Tower { stunned: true, .. }
+				)
+			})
+			.map(Some),
+			Trigger::TowersDepleted => (level.remaining_towers == Some(0)).then_some(None),
+			Trigger::GoalDamaged => level
+				.stats
+				.turn_events
+				.iter()
+				.find_map(|event| match *event {
+					TurnEvent::GoalDamaged(coords) => Some(Some(coords)),
+					_ => None,
+				}),
+		}
+	}
+}
+
+/// Queue the hints whose trigger happened, to be called whenever the level changes.
+pub fn check(level: &mut LevelState) {
+	let mut index = 0;
+	while index < level.hints.len() {
+		if let Some(anchor) = level.hints[index].trigger.happens(level) {
+			let hint = level.hints.remove(index);
+			level
+				.shown_hints
+				.push_back(ShownHint { text: hint.text, anchor });
+		} else {
+			index += 1;
+		}
+	}
+}
+
+/// Close the hint being shown, showing the next one if any.
+pub fn dismiss(level: &mut LevelState) {
+	level.shown_hints.pop_front();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn hints_are_shown_once_when_triggered() {
+		let level_data = parse_level(
+			"Op O- ?A O-\n\
+			|- |- |- |g\n\
+			@hint turn 0 Welcome!\n\
+			@hint near A This is a tile\n\
+			@hint enemy tank   Tanks have a lot of HP\n\
+			@hint towers_depleted No towers left\n\
+			@max_towers 1\n\
+			@name B 0 1\n\
+			@event spawn tank B 2",
+		)
+		.unwrap();
+		let mut level = LevelState::new(&level_data);
+		let texts = |level: &LevelState| -> Vec<String> {
+			level
+				.shown_hints
+				.iter()
+				.map(|hint| hint.text.clone())
+				.collect()
+		};
+		assert_eq!(texts(&level), ["Welcome!"]);
+		dismiss(&mut level);
+		play_turn(&mut level, (1, 0).into(), PlayerAction::Move);
+		assert_eq!(texts(&level), ["This is a tile"]);
+		assert!(level.shown_hints[0]
+			.anchor
+			.is_some_and(|anchor| anchor == (2, 0).into()));
+		play_turn(
+			&mut level,
+			(1, 0).into(),
+			PlayerAction::PlaceTower { variant: Tower::Basic },
+		);
+		assert_eq!(
+			texts(&level),
+			["This is a tile", "Tanks have a lot of HP", "No towers left"]
+		);
+		dismiss(&mut level);
+		play_turn(&mut level, (-1, 0).into(), PlayerAction::Move);
+		play_turn(&mut level, (1, 0).into(), PlayerAction::Move);
+		assert_eq!(texts(&level), ["Tanks have a lot of HP", "No towers left"]);
+		assert!(parse_level("Op |g\n@hint sometimes Hello").is_err());
+	}
+}
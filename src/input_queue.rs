@@ -0,0 +1,131 @@
+//! The actions of the player waiting to be played. The key presses are queued (a few at most)
+//! so that none is lost when several come between two frames, and one is played per turn.
+//! Holding a movement key moves again every `Config::key_repeat` milliseconds (after a longer
+//! first delay), the repeats of the keyboard itself are ignored so that the rate is the same
+//! everywhere.
+
+use crate::coords::*;
+use crate::*;
+
+use std::time::{Duration, Instant};
+use winit::event::VirtualKeyCode;
+
+/// Key presses beyond that many actions waiting are dropped, so that mashing a key doesn't
+/// leave the player moving for a while after it stopped.
+const MAX_QUEUED: usize = 3;
+/// How long a movement key is held before it starts to repeat, as a multiple of the rate.
+const REPEAT_DELAY_FACTOR: u32 = 2;
+
+/// A movement key being held.
+struct HeldKey {
+	key: VirtualKeyCode,
+	dd: DxDy,
+	/// `None` without key repeat.
+	next_repeat: Option<Instant>,
+}
+
+#[derive(Default)]
+pub struct InputQueue {
+	actions: VecDeque<(DxDy, PlayerAction)>,
+	held: Option<HeldKey>,
+}
+
+impl InputQueue {
+	pub fn push(&mut self, dd: DxDy, action: PlayerAction) {
+		if self.actions.len() < MAX_QUEUED {
+			self.actions.push_back((dd, action));
+		}
+	}
+
+	/// The next action to play.
+	pub fn front(&self) -> Option<&(DxDy, PlayerAction)> {
+		self.actions.front()
+	}
+
+	pub fn pop(&mut self) -> Option<(DxDy, PlayerAction)> {
+		self.actions.pop_front()
+	}
+
+	/// Forget the actions waiting (when the level changes), and the key held.
+	pub fn clear(&mut self) {
+		self.actions.clear();
+		self.held = None;
+	}
+
+	pub fn is_held(&self, key: VirtualKeyCode) -> bool {
+		self.held.as_ref().is_some_and(|held| held.key == key)
+	}
+
+	/// A movement key that moves the player `dd` was pressed, it repeats while it is held
+	/// (with `repeat` milliseconds between the moves, not at all if it is 0).
+	pub fn hold(&mut self, key: VirtualKeyCode, dd: DxDy, repeat: u32, now: Instant) {
+		let delay = Duration::from_millis((repeat * REPEAT_DELAY_FACTOR) as u64);
+		self.held = Some(HeldKey { key, dd, next_repeat: (repeat > 0).then_some(now + delay) });
+	}
+
+	pub fn release(&mut self, key: VirtualKeyCode) {
+		if self.is_held(key) {
+			self.held = None;
+		}
+	}
+
+	/// Queue the move of the key held if it is time (and nothing else is waiting).
+	pub fn repeat(&mut self, repeat: u32, now: Instant) {
+		let Some(held) = &mut self.held else {
+			return;
+		};
+		let is_due = held
+			.next_repeat
+			.is_some_and(|next_repeat| now >= next_repeat);
+		if !is_due || !self.actions.is_empty() {
+			return;
+		}
+		held.next_repeat = (repeat > 0).then(|| now + Duration::from_millis(repeat as u64));
+		let dd = held.dd;
+		self.actions.push_back((dd, PlayerAction::Move));
+	}
+
+	/// When the next move of the key held is due, to wake up for it.
+	pub fn next_repeat(&self) -> Option<Instant> {
+		self.held.as_ref().and_then(|held| held.next_repeat)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn presses_are_queued_and_held_keys_repeat() {
+		let mut queue = InputQueue::default();
+		for _ in 0..5 {
+			queue.push((1, 0).into(), PlayerAction::Move);
+		}
+		let mut played = 0;
+		while queue.pop().is_some() {
+			played += 1;
+		}
+		assert_eq!(played, MAX_QUEUED);
+
+		let start = Instant::now();
+		let at = |ms| start + Duration::from_millis(ms);
+		queue.hold(VirtualKeyCode::Left, (-1, 0).into(), 100, start);
+		assert!(queue.is_held(VirtualKeyCode::Left));
+		queue.repeat(100, at(150));
+		assert!(queue.front().is_none());
+		queue.repeat(100, at(200));
+		assert!(queue.pop().is_some_and(|(dd, _)| dd == (-1, 0).into()));
+		assert_eq!(queue.next_repeat(), Some(at(300)));
+		queue.release(VirtualKeyCode::Right);
+		queue.release(VirtualKeyCode::Left);
+		queue.repeat(100, at(1000));
+		assert!(queue.front().is_none());
+
+		// Without key repeat, holding a key does nothing more.
+		queue.hold(VirtualKeyCode::Left, (-1, 0).into(), 0, start);
+		assert!(queue.is_held(VirtualKeyCode::Left));
+		queue.repeat(0, at(1000));
+		assert!(queue.front().is_none());
+		assert_eq!(queue.next_repeat(), None);
+	}
+}
@@ -0,0 +1,157 @@
+//! The text shown to the player in every language, from the string tables in
+//! `assets/locales/<language>.toml` (embedded in the executable). Texts are looked up by
+//! dotted keys (like `"menu.play"`), and `{name}` placeholders are replaced by values.
+//! A text missing from a table falls back to English, and then to the key itself.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+	English,
+	French,
+}
+
+impl Language {
+	pub const ALL: [Language; 2] = [Language::English, Language::French];
+
+	/// The code used in the config.
+	pub fn name(self) -> &'static str {
+		match self {
+			Language::English => "en",
+			Language::French => "fr",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Language> {
+		Language::ALL
+			.into_iter()
+			.find(|language| language.name() == name)
+	}
+
+	/// The name of the language in that language, for the settings menu.
+	pub fn native_name(self) -> &'static str {
+		match self {
+			Language::English => "ENGLISH",
+			Language::French => "FRANCAIS",
+		}
+	}
+
+	fn table_text(self) -> &'static str {
+		match self {
+			Language::English => include_str!("../assets/locales/en.toml"),
+			Language::French => include_str!("../assets/locales/fr.toml"),
+		}
+	}
+
+	fn index(self) -> usize {
+		Language::ALL
+			.iter()
+			.position(|&language| language == self)
+			.unwrap()
+	}
+}
+
+/// The index in `Language::ALL` of the language of the texts (see `Config::language`).
+static LANGUAGE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_language(language: Language) {
+	LANGUAGE.store(language.index(), Ordering::Relaxed);
+}
+
+fn language() -> Language {
+	Language::ALL[LANGUAGE.load(Ordering::Relaxed)]
+}
+
+/// The string tables of all the languages, parsed the first time a text is needed.
+fn tables() -> &'static [HashMap<String, String>] {
+	static TABLES: OnceLock<Vec<HashMap<String, String>>> = OnceLock::new();
+	TABLES.get_or_init(|| {
+		Language::ALL
+			.into_iter()
+			.map(|language| match parse_table(language.table_text()) {
+				Ok(table) => table,
+				Err(error) => panic!("invalid string table {}: {error}", language.name()),
+			})
+			.collect()
+	})
+}
+
+/// Tables are flattened into dotted keys, like in the sprite atlas.
+fn parse_table(text: &str) -> Result<HashMap<String, String>, String> {
+	let table: toml::Table = text
+		.parse()
+		.map_err(|error: toml::de::Error| error.to_string())?;
+	let mut texts = HashMap::new();
+	for (section, entries) in &table {
+		let entries = entries
+			.as_table()
+			.ok_or(format!("{section} should be a table"))?;
+		for (key, text) in entries {
+			let text = text
+				.as_str()
+				.ok_or(format!("{section}.{key} should be a string"))?;
+			texts.insert(format!("{section}.{key}"), text.to_string());
+		}
+	}
+	Ok(texts)
+}
+
+fn lookup(language: Language, key: &str) -> Option<&'static str> {
+	tables()[language.index()].get(key).map(String::as_str)
+}
+
+/// The text with the given key in the current language.
+pub fn tr(key: &str) -> String {
+	match lookup(language(), key).or_else(|| lookup(Language::English, key)) {
+		Some(text) => text.to_string(),
+		None => {
+			log::warn!("No text for {key}");
+			key.to_string()
+		},
+	}
+}
+
+/// Like `tr`, with the `{name}` placeholders of the text replaced by the given values.
+pub fn tr_with(key: &str, values: &[(&str, &dyn std::fmt::Display)]) -> String {
+	let mut text = tr(key);
+	for (name, value) in values {
+		text = text.replace(&format!("{{{name}}}"), &value.to_string());
+	}
+	text
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn placeholders(text: &str) -> Vec<&str> {
+		let mut names: Vec<&str> = text
+			.split('{')
+			.skip(1)
+			.filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+			.collect();
+		names.sort();
+		names
+	}
+
+	#[test]
+	fn all_languages_have_the_same_texts_and_placeholders() {
+		let english = &tables()[Language::English.index()];
+		for language in Language::ALL {
+			let table = &tables()[language.index()];
+			assert_eq!(
+				table.len(),
+				english.len(),
+				"texts missing in {}",
+				language.name()
+			);
+			for (key, text) in english {
+				let translation = lookup(language, key)
+					.unwrap_or_else(|| panic!("{key} is missing in {}", language.name()));
+				assert_eq!(placeholders(translation), placeholders(text), "{key}");
+			}
+		}
+	}
+}
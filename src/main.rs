@@ -1,44 +1,201 @@
+mod balance;
+mod bot;
+mod checkpoint;
+mod cli;
+mod config;
 mod coords;
+mod daily;
+mod generate;
+mod grid_layer;
+mod high_scores;
+mod hints;
+mod input_queue;
+mod locale;
+mod mods;
+mod particles;
+mod protocol;
+mod replay;
+mod rng;
+#[cfg(test)]
+mod scenario;
+mod screen_effects;
+mod script;
+mod share;
+mod simulate;
+mod spectate;
+mod spritesheet;
+mod theme;
+mod transition;
+mod tui;
+mod turn_log;
+mod ui;
+mod validate;
+mod weather;
 
 use coords::*;
+use locale::{tr, tr_with};
+use spritesheet::Spritesheet;
+use theme::Theme;
+use weather::Weather;
 
-use core::panic;
-use image::GenericImageView;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
 use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-#[derive(Clone)]
+/// What is affecting an enemy over several turns.
+#[derive(Clone, Default, Hash)]
+struct StatusEffects {
+	/// Turns of burning left (see `burns_tick`), set by the hits of flamethrowers.
+	burn: u32,
+	/// The group the enemy spawned with, if any (see `@event group`).
+	group: Option<GroupMember>,
+	/// The HP that the leader of its group gives, lost when the leader dies.
+	group_bonus: u32,
+	/// Still coming out of the portal it spawned on: it can't be shot until the end of its
+	/// first turn (see `enemies_emerge`).
+	emerging: bool,
+}
+
+/// An enemy of a group, which spawned with the others around the same tile.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+struct GroupMember {
+	/// The groups are numbered in the order of their events.
+	id: u32,
+	leader: bool,
+}
+
+#[derive(Clone, Hash)]
 enum Obj {
 	Empty,
-	Player { stunned: bool },
-	Goal,
-	Enemy { variant: Enemy, hp: u32 },
-	Tower { variant: Tower, stunned: bool },
-	Bomb { countdown: u32 },
-	Flower { variant: Flower },
+	Player {
+		stunned: bool,
+	},
+	/// The level is lost when it is destroyed, which happens when an enemy reaches it
+	/// with its last hit point.
+	Goal {
+		hp: u32,
+	},
+	Enemy {
+		variant: Enemy,
+		hp: u32,
+		effects: StatusEffects,
+	},
+	Tower {
+		variant: Tower,
+		stunned: bool,
+		priority: TargetPriority,
+		/// Number of turns before the tower can shoot again (for towers that have to reload).
+		cooldown: u32,
+		/// Starts at 1, merging two identical towers gives a tower of the next tier.
+		tier: u32,
+		/// Shots left before the player has to reload the tower, `None` for the towers that
+		/// never run out.
+		ammo: Option<u32>,
+		/// The way the player faced when placing it (east for the towers of the level file),
+		/// cannons only shoot that way.
+		facing: Direction,
+	},
+	Bomb {
+		countdown: u32,
+	},
+	Flower {
+		variant: Flower,
+	},
 	Rock,
-	Tree,
+	/// Chopped down (into a bomb) when the player walks into it twice.
+	Tree {
+		/// Walked into once.
+		chopped: bool,
+	},
+	/// Placed by miner towers, explodes when an enemy steps on it.
+	Mine,
+	/// Placed by the player, blocks everything until the enemies break it.
+	Wall {
+		hp: u32,
+	},
+	/// Picked up by the player when walking on it.
+	Item {
+		item: Item,
+	},
+	/// Picked up by the player when walking on it, opens a door of its color.
+	Key {
+		color: KeyColor,
+	},
+	/// Blocks everything until the player walks into it with a key of its color, which opens
+	/// it (and is used up).
+	Door {
+		color: KeyColor,
+	},
 }
 
 impl Obj {
 	fn new_enemy(variant: Enemy) -> Obj {
 		let hp = variant.hp_max();
-		Obj::Enemy { variant, hp }
+		Obj::Enemy { variant, hp, effects: StatusEffects::default() }
 	}
 	fn new_tower(variant: Tower) -> Obj {
-		Obj::Tower { variant, stunned: false }
+		let ammo = balance::get().tower_ammo(&variant);
+		Obj::Tower {
+			variant,
+			stunned: false,
+			priority: TargetPriority::All,
+			cooldown: 0,
+			tier: 1,
+			ammo,
+			facing: Direction::East,
+		}
+	}
+
+	/// Underground enemies and the ones coming out of a portal can't be shot and don't block
+	/// the line of sight of towers.
+	fn is_out_of_reach(&self) -> bool {
+		matches!(
+			self,
+			Obj::Enemy { variant: Enemy::Burrower { underground: 1.. }, .. }
+				| Obj::Enemy { effects: StatusEffects { emerging: true, .. }, .. }
+		)
+	}
+
+	fn blocks_line_of_sight(&self) -> bool {
+		!matches!(self, Obj::Empty | Obj::Mine) && !self.is_out_of_reach()
 	}
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 enum Ground {
 	Grass,
 	Water,
+	/// Burns the enemies pushed into it, and destroys the other objects (see `lava_burns`).
+	Lava,
 	/// Contains distance (along the path) to the goal.
 	Path(i32),
 }
 
-#[derive(Clone, Copy)]
+impl Ground {
+	fn name(&self) -> &'static str {
+		match self {
+			Ground::Grass => "grass",
+			Ground::Water => "water",
+			Ground::Lava => "lava",
+			Ground::Path(_) => "path",
+		}
+	}
+
+	/// A path of unknown distance to the goal for `path`.
+	fn from_name(name: &str) -> Option<Ground> {
+		[Ground::Grass, Ground::Water, Ground::Lava, Ground::Path(-1)]
+			.into_iter()
+			.find(|ground| ground.name() == name)
+	}
+
+	/// Water and lava, where nothing can be placed and the player can't walk.
+	fn is_liquid(&self) -> bool {
+		matches!(self, Ground::Water | Ground::Lava)
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 enum Direction {
 	North,
 	South,
@@ -46,7 +203,52 @@ enum Direction {
 	West,
 }
 
-#[derive(Clone, Copy)]
+impl Direction {
+	const ALL: [Direction; 4] = [
+		Direction::North,
+		Direction::East,
+		Direction::South,
+		Direction::West,
+	];
+
+	/// The direction of a step to a neighboring tile, `None` for a diagonal or no step.
+	fn of_step(dd: DxDy) -> Option<Direction> {
+		match dd {
+			DxDy { dx: 0, dy: -1 } => Some(Direction::North),
+			DxDy { dx: 1, dy: 0 } => Some(Direction::East),
+			DxDy { dx: 0, dy: 1 } => Some(Direction::South),
+			DxDy { dx: -1, dy: 0 } => Some(Direction::West),
+			_ => None,
+		}
+	}
+
+	/// The inverse of `of_step`.
+	fn step(self) -> DxDy {
+		DxDy::from(match self {
+			Direction::North => (0, -1),
+			Direction::East => (1, 0),
+			Direction::South => (0, 1),
+			Direction::West => (-1, 0),
+		})
+	}
+
+	fn from_name(name: &str) -> Option<Direction> {
+		Direction::ALL
+			.into_iter()
+			.find(|direction| direction.name() == name)
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Direction::North => "north",
+			Direction::South => "south",
+			Direction::East => "east",
+			Direction::West => "west",
+		}
+	}
+}
+
+#[derive(Clone, Copy, Hash)]
 enum Protection {
 	Sides,
 	FullStack,
@@ -57,52 +259,51 @@ enum Protection {
 }
 
 impl Protection {
-	fn sprite(self, dir: Direction) -> (i32, i32) {
+	/// North, East, South, West
+	fn sides_protected(self, dir: Direction) -> [bool; 4] {
 		match (self, dir) {
 			(Protection::Sides, Direction::North | Direction::South)
-			| (Protection::FullStack, Direction::East | Direction::West) => (4, 3),
+			| (Protection::FullStack, Direction::East | Direction::West) => [false, true, false, true],
 			(Protection::Sides, Direction::East | Direction::West)
-			| (Protection::FullStack, Direction::North | Direction::South) => (5, 3),
+			| (Protection::FullStack, Direction::North | Direction::South) => [true, false, true, false],
 			(Protection::UniqueFront, Direction::West) | (Protection::UniqueBack, Direction::East) => {
-				(6, 3)
+				[false, false, false, true]
 			},
 			(Protection::UniqueFront, Direction::East) | (Protection::UniqueBack, Direction::West) => {
-				(7, 3)
+				[false, true, false, false]
 			},
 			(Protection::UniqueFront, Direction::North)
-			| (Protection::UniqueBack, Direction::South) => (8, 3),
+			| (Protection::UniqueBack, Direction::South) => [true, false, false, false],
 			(Protection::UniqueFront, Direction::South)
-			| (Protection::UniqueBack, Direction::North) => (9, 3),
+			| (Protection::UniqueBack, Direction::North) => [false, false, true, false],
 			(Protection::ThreeFront, Direction::West) | (Protection::ThreeBack, Direction::East) => {
-				(10, 3)
+				[true, false, true, true]
 			},
 			(Protection::ThreeFront, Direction::East) | (Protection::ThreeBack, Direction::West) => {
-				(11, 3)
+				[true, true, true, false]
 			},
 			(Protection::ThreeFront, Direction::North) | (Protection::ThreeBack, Direction::South) => {
-				(12, 3)
+				[true, true, false, true]
 			},
 			(Protection::ThreeFront, Direction::South) | (Protection::ThreeBack, Direction::North) => {
-				(13, 3)
+				[false, true, true, true]
 			},
 		}
 	}
 
+	/// Protected enemies sprites are named after the sides that are protected
+	/// (like `enemy.protected.nsw` for north, south and west).
+	fn sprite_name(self, dir: Direction) -> String {
+		let sides: String = "nesw"
+			.chars()
+			.zip(self.sides_protected(dir))
+			.filter_map(|(side, protected)| protected.then_some(side))
+			.collect();
+		format!("enemy.protected.{sides}")
+	}
+
 	fn is_hurt_by_shot(self, enemy_dir: Direction, shot_comming_from_dir: Direction) -> bool {
-		// North, East, South, West
-		let sides_protected = match self.sprite(enemy_dir) {
-			(4, 3) => [false, true, false, true],
-			(5, 3) => [true, false, true, false],
-			(6, 3) => [false, false, false, true],
-			(7, 3) => [false, true, false, false],
-			(8, 3) => [true, false, false, false],
-			(9, 3) => [false, false, true, false],
-			(10, 3) => [true, false, true, true],
-			(11, 3) => [true, true, true, false],
-			(12, 3) => [true, true, false, true],
-			(13, 3) => [false, true, true, true],
-			_ => panic!("yo"),
-		};
+		let sides_protected = self.sides_protected(enemy_dir);
 		let index = match shot_comming_from_dir {
 			Direction::North => 0,
 			Direction::East => 1,
@@ -113,134 +314,1445 @@ impl Protection {
 	}
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 enum Enemy {
 	Basic,
 	Tank,
-	Protected { direction: Direction, protection: Protection },
+	Protected {
+		direction: Direction,
+		protection: Protection,
+	},
 	Speeeeed,
 	Stuner,
 	Eater,
+	/// Moves every other turn and regularly spawns basic enemies next to itself,
+	/// `countdown` is the number of turns before the next spawn.
+	Broodmother {
+		countdown: u32,
+	},
+	/// Instead of moving, revives the closest corpse in range (at half its hp).
+	Necromancer,
+	/// When a tower or a rock is in its way, digs under it, `underground` is the number of turns
+	/// left before emerging on the other side (0 when on the surface).
+	Burrower {
+		underground: u32,
+	},
+	/// When its path doesn't lead to the goal, paves the grass towards the goal and walks on it.
+	Paver,
+	/// Hits take its shield before its hp (`shield_damage` is what the shield took), the shield
+	/// comes back to full once it has not been damaged for a while, `calm` being the number of
+	/// turns since it was.
+	Shielded {
+		shield_damage: u32,
+		calm: u32,
+	},
+	/// Goes through phases as it loses HP (see `Balance::boss_phases`), `phase` being the number
+	/// of phases it entered, each giving it a behavior for good. `countdown` is the number of
+	/// turns before it summons a minion, once it does.
+	Boss {
+		phase: u32,
+		countdown: u32,
+	},
+}
+
+/// What a boss does on top of walking, from the phase that gives it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum BossBehavior {
+	/// Moves two tiles per turn, like speeeeeds.
+	Fast,
+	/// Stuns what it sees, like stuners.
+	Stun,
+	/// Regularly spawns basic enemies behind itself, like broodmothers.
+	Summon,
+}
+
+impl BossBehavior {
+	fn name(self) -> &'static str {
+		match self {
+			BossBehavior::Fast => "fast",
+			BossBehavior::Stun => "stun",
+			BossBehavior::Summon => "summon",
+		}
+	}
+
+	fn from_name(name: &str) -> Option<BossBehavior> {
+		Some(match name {
+			"fast" => BossBehavior::Fast,
+			"stun" => BossBehavior::Stun,
+			"summon" => BossBehavior::Summon,
+			_ => return None,
+		})
+	}
 }
 
+/// Number of turns a burrower spends underground to get past an obstacle.
+const BURROW_TURNS: u32 = 2;
+
+/// How far (in Manhattan distance) a necromancer can revive corpses.
+const NECROMANCER_RANGE: i32 = 3;
+
+/// Number of turns between two spawns of a broodmother (its countdown is the number of turns
+/// left before the next one, which happens when it is 0).
+const BROODMOTHER_SPAWN_PERIOD: u32 = 4;
+
 impl Enemy {
+	/// See `assets/balance.toml`.
 	fn hp_max(&self) -> u32 {
+		balance::get().enemy_hp(self)
+	}
+
+	/// What is left of its shield, for shielded enemies.
+	fn shield(&self) -> Option<u32> {
+		match self {
+			Enemy::Shielded { shield_damage, .. } => {
+				Some(balance::get().shield_hp.saturating_sub(*shield_damage))
+			},
+			_ => None,
+		}
+	}
+
+	/// What the boss does in its current phase, nothing for other enemies.
+	fn boss_behaviors(&self) -> Vec<BossBehavior> {
+		let Enemy::Boss { phase, .. } = self else {
+			return vec![];
+		};
+		let phases = balance::get().boss_phases();
+		phases
+			.into_iter()
+			.take(*phase as usize)
+			.map(|(behavior, _)| behavior)
+			.collect()
+	}
+
+	fn sprite_name(&self) -> String {
+		match self {
+			Enemy::Burrower { underground: 1.. } => String::from("enemy.burrower_underground"),
+			Enemy::Protected { direction, protection } => protection.sprite_name(*direction),
+			_ => format!("enemy.{}", self.name()),
+		}
+	}
+
+	/// The name used in level files (and elsewhere) to refer to this kind of enemy.
+	fn name(&self) -> &'static str {
+		match self {
+			Enemy::Basic => "basic",
+			Enemy::Tank => "tank",
+			Enemy::Speeeeed => "speeeeed",
+			Enemy::Stuner => "stun",
+			Enemy::Eater => "eat",
+			Enemy::Broodmother { .. } => "broodmother",
+			Enemy::Necromancer => "necromancer",
+			Enemy::Burrower { .. } => "burrower",
+			Enemy::Paver => "paver",
+			Enemy::Shielded { .. } => "shielded",
+			Enemy::Boss { .. } => "boss",
+			Enemy::Protected { protection: Protection::Sides, .. } => "protected_sides",
+			Enemy::Protected { protection: Protection::FullStack, .. } => "protected_full_stack",
+			Enemy::Protected { protection: Protection::UniqueFront, .. } => "protected_front",
+			Enemy::Protected { protection: Protection::UniqueBack, .. } => "protected_back",
+			Enemy::Protected { protection: Protection::ThreeFront, .. } => "protected_three_front",
+			Enemy::Protected { protection: Protection::ThreeBack, .. } => "protected_three_back",
+		}
+	}
+
+	/// Shown on the enemy with the colorblind option, so that enemies that mostly differ by
+	/// their colors can be told apart.
+	fn marker(&self) -> char {
 		match self {
-			Enemy::Basic => 5,
-			Enemy::Tank => 9,
-			Enemy::Protected { .. } => 4,
-			Enemy::Speeeeed => 3,
-			Enemy::Stuner => 4,
-			Enemy::Eater => 4,
+			Enemy::Basic => 'B',
+			Enemy::Tank => 'T',
+			Enemy::Protected { .. } => 'P',
+			Enemy::Speeeeed => 'F',
+			Enemy::Stuner => 'S',
+			Enemy::Eater => 'E',
+			Enemy::Broodmother { .. } => 'M',
+			Enemy::Necromancer => 'N',
+			Enemy::Burrower { .. } => 'U',
+			Enemy::Paver => 'V',
+			Enemy::Shielded { .. } => 'D',
+			Enemy::Boss { .. } => 'X',
 		}
 	}
+
+	fn from_name(name: &str) -> Option<Enemy> {
+		let protected = |protection| Enemy::Protected { direction: Direction::East, protection };
+		Some(match name {
+			"basic" => Enemy::Basic,
+			"tank" => Enemy::Tank,
+			"speeeeed" => Enemy::Speeeeed,
+			"stun" => Enemy::Stuner,
+			"eat" => Enemy::Eater,
+			"broodmother" => Enemy::Broodmother { countdown: BROODMOTHER_SPAWN_PERIOD - 1 },
+			"necromancer" => Enemy::Necromancer,
+			"burrower" => Enemy::Burrower { underground: 0 },
+			"paver" => Enemy::Paver,
+			"shielded" => Enemy::Shielded { shield_damage: 0, calm: 0 },
+			"boss" => Enemy::Boss { phase: 0, countdown: 0 },
+			"protected_sides" => protected(Protection::Sides),
+			"protected_full_stack" => protected(Protection::FullStack),
+			"protected_front" => protected(Protection::UniqueFront),
+			"protected_back" => protected(Protection::UniqueBack),
+			"protected_three_front" => protected(Protection::ThreeFront),
+			"protected_three_back" => protected(Protection::ThreeBack),
+			_ => return None,
+		})
+	}
 }
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 enum Tower {
 	Basic,
 	Piercing,
 	TotalEnergy,
 	Unabomber,
 	Pusher,
+	/// Shoots hard at any distance, also diagonally, but has to reload between shots.
+	Sniper,
+	/// Regularly places mines on the path next to it.
+	Miner,
+	/// Pulls the closest enemy it sees one tile towards it.
+	Magnet,
+	/// Only shoots the way it faces, but hard.
+	Cannon,
+	/// Sets the enemies it hits on fire (see `StatusEffects::burn`), at short range.
+	Flamethrower,
 }
 
-#[derive(Clone)]
+/// Towers of this tier can't be merged any further.
+const MAX_TOWER_TIER: u32 = 2;
+
+impl Tower {
+	/// How many tiles away (in a straight line) the tower can shoot,
+	/// `i32::MAX` if there is no limit.
+	fn range(&self) -> i32 {
+		match self {
+			Tower::Basic => 5,
+			Tower::Piercing => 7,
+			Tower::TotalEnergy => 0,
+			Tower::Unabomber => 4,
+			Tower::Pusher => 3,
+			Tower::Sniper => i32::MAX,
+			Tower::Miner => 1,
+			Tower::Magnet => 4,
+			Tower::Cannon => 5,
+			Tower::Flamethrower => 2,
+		}
+	}
+
+	/// Only the towers that hurt enemies can be merged, as merging multiplies their damage.
+	fn can_merge(&self) -> bool {
+		matches!(
+			self,
+			Tower::Basic | Tower::Piercing | Tower::Pusher | Tower::Sniper | Tower::Cannon
+		)
+	}
+
+	/// The directions in which the tower can shoot.
+	fn directions(&self) -> Vec<DxDy> {
+		match self {
+			Tower::Sniper => DxDy::the_8_directions().collect(),
+			_ => DxDy::the_4_directions().collect(),
+		}
+	}
+
+	/// The directions in which the tower shoots once placed facing that way.
+	fn directions_facing(&self, facing: Direction) -> Vec<DxDy> {
+		match self {
+			Tower::Cannon => vec![facing.step()],
+			_ => self.directions(),
+		}
+	}
+
+	fn name(&self) -> &'static str {
+		match self {
+			Tower::Basic => "basic",
+			Tower::Piercing => "piercing",
+			Tower::TotalEnergy => "total_energy",
+			Tower::Unabomber => "unabomber",
+			Tower::Pusher => "pusher",
+			Tower::Sniper => "sniper",
+			Tower::Miner => "miner",
+			Tower::Magnet => "magnet",
+			Tower::Cannon => "cannon",
+			Tower::Flamethrower => "flamethrower",
+		}
+	}
+
+	/// Shown on the tower with the colorblind option (see `Enemy::marker`).
+	fn marker(&self) -> char {
+		match self {
+			Tower::Basic => 'B',
+			Tower::Piercing => 'P',
+			Tower::TotalEnergy => 'T',
+			Tower::Unabomber => 'U',
+			Tower::Pusher => '>',
+			Tower::Sniper => 'S',
+			Tower::Miner => 'M',
+			Tower::Magnet => '<',
+			Tower::Cannon => 'C',
+			Tower::Flamethrower => 'F',
+		}
+	}
+
+	fn from_name(name: &str) -> Option<Tower> {
+		Some(match name {
+			"basic" => Tower::Basic,
+			"piercing" => Tower::Piercing,
+			"total_energy" => Tower::TotalEnergy,
+			"unabomber" => Tower::Unabomber,
+			"pusher" => Tower::Pusher,
+			"sniper" => Tower::Sniper,
+			"miner" => Tower::Miner,
+			"magnet" => Tower::Magnet,
+			"cannon" => Tower::Cannon,
+			"flamethrower" => Tower::Flamethrower,
+			_ => return None,
+		})
+	}
+}
+
+/// Which enemies a tower shoots at when it could hit several of them.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum TargetPriority {
+	/// Shoot in every direction in which there is an enemy.
+	All,
+	/// Only shoot the enemy that is the closest to the goal (along the path).
+	FirstOnPath,
+	LowestHp,
+	HighestHp,
+}
+
+impl TargetPriority {
+	const ALL: [TargetPriority; 4] = [
+		TargetPriority::All,
+		TargetPriority::FirstOnPath,
+		TargetPriority::LowestHp,
+		TargetPriority::HighestHp,
+	];
+
+	fn sprite_name(self) -> &'static str {
+		match self {
+			TargetPriority::All => "priority.all",
+			TargetPriority::FirstOnPath => "priority.first_on_path",
+			TargetPriority::LowestHp => "priority.lowest_hp",
+			TargetPriority::HighestHp => "priority.highest_hp",
+		}
+	}
+}
+
+/// Things that the player can pick up and use later.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Item {
+	/// Placed like a tower.
+	Bomb,
+	/// Gives one more tower to place.
+	TowerVoucher,
+	/// Used automatically to bring the player back when they get killed.
+	Heart,
+	/// Resources, from the trees and rocks of crafting levels, spent to craft (see `Recipe`).
+	Wood,
+	Stone,
+}
+
+impl Item {
+	const ALL: [Item; 5] = [
+		Item::Bomb,
+		Item::TowerVoucher,
+		Item::Heart,
+		Item::Wood,
+		Item::Stone,
+	];
+
+	fn sprite_name(self) -> String {
+		format!("item.{}", self.name())
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Item::Bomb => "bomb",
+			Item::TowerVoucher => "voucher",
+			Item::Heart => "heart",
+			Item::Wood => "wood",
+			Item::Stone => "stone",
+		}
+	}
+
+	fn from_name(name: &str) -> Option<Item> {
+		Item::ALL.into_iter().find(|item| item.name() == name)
+	}
+}
+
+/// Keys open the doors of the same color (`@key <color> <tile>` and `@door <color> <tile>`,
+/// `K` and `=` in the grid are red).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum KeyColor {
+	Red,
+	Blue,
+	Green,
+	Yellow,
+}
+
+impl KeyColor {
+	const ALL: [KeyColor; 4] = [
+		KeyColor::Red,
+		KeyColor::Blue,
+		KeyColor::Green,
+		KeyColor::Yellow,
+	];
+
+	fn name(self) -> &'static str {
+		match self {
+			KeyColor::Red => "red",
+			KeyColor::Blue => "blue",
+			KeyColor::Green => "green",
+			KeyColor::Yellow => "yellow",
+		}
+	}
+
+	fn from_name(name: &str) -> Option<KeyColor> {
+		KeyColor::ALL.into_iter().find(|color| color.name() == name)
+	}
+
+	/// The tint of the keys and doors of that color.
+	fn tint(self) -> [u8; 3] {
+		match self {
+			KeyColor::Red => [255, 90, 90],
+			KeyColor::Blue => [110, 150, 255],
+			KeyColor::Green => [110, 230, 110],
+			KeyColor::Yellow => [255, 230, 80],
+		}
+	}
+}
+
+/// The items that the player has picked up.
+#[derive(Clone, Default, Hash)]
+struct Inventory {
+	bombs: u32,
+	tower_vouchers: u32,
+	hearts: u32,
+	wood: u32,
+	stone: u32,
+	/// In the order they were picked up.
+	keys: Vec<KeyColor>,
+}
+
+impl Inventory {
+	fn count_mut(&mut self, item: Item) -> &mut u32 {
+		match item {
+			Item::Bomb => &mut self.bombs,
+			Item::TowerVoucher => &mut self.tower_vouchers,
+			Item::Heart => &mut self.hearts,
+			Item::Wood => &mut self.wood,
+			Item::Stone => &mut self.stone,
+		}
+	}
+
+	fn count(&self, item: Item) -> u32 {
+		match item {
+			Item::Bomb => self.bombs,
+			Item::TowerVoucher => self.tower_vouchers,
+			Item::Heart => self.hearts,
+			Item::Wood => self.wood,
+			Item::Stone => self.stone,
+		}
+	}
+}
+
+/// What can be crafted from wood and stone in the levels with `@crafting` (see
+/// `Balance::craft_cost`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Recipe {
+	/// One more tower to place.
+	Tower,
+	Bomb,
+}
+
+impl Recipe {
+	const ALL: [Recipe; 2] = [Recipe::Tower, Recipe::Bomb];
+
+	fn name(self) -> &'static str {
+		match self {
+			Recipe::Tower => "tower",
+			Recipe::Bomb => "bomb",
+		}
+	}
+
+	fn from_name(name: &str) -> Option<Recipe> {
+		Recipe::ALL.into_iter().find(|recipe| recipe.name() == name)
+	}
+}
+
+#[derive(Clone, Hash)]
+#[allow(clippy::enum_variant_names)]
 enum Flower {
 	BlueFlower,
 	TheOther,
 	TheOtherOther,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 struct Cell {
 	obj: Obj,
 	groud: Ground,
 	rocky_path: bool,
+	/// A spike trap (on the path), which strikes what is on it every other turn (see
+	/// `spikes_strike`).
+	spikes: bool,
+	/// A conveyor belt (on the path), which moves what is on it one tile that way at the end of
+	/// every turn (see `conveyors_move`).
+	conveyor: Option<Direction>,
+	/// A spawn portal, which the enemies of the events come out of (see `mark_portals`).
+	portal: bool,
 }
 
 struct LevelData {
 	init_grid: Grid<Cell>,
 	max_towers: Option<u32>,
+	max_walls: Option<u32>,
 	init_events: Vec<GameEvent>,
+	/// If set, fog of war is enabled and only the tiles at most that far from the player
+	/// or a tower are visible.
+	fog_radius: Option<i32>,
+	/// Can bombs hurt the player, the towers and the walls?
+	friendly_fire: bool,
+	/// Do basic tower hits push the enemies back along the path? The balance says if not set.
+	knockback: Option<bool>,
+	/// The tiles named with `?name` in the grid or with `@name` in the level file.
+	named_tiles: HashMap<String, Coords>,
+	/// How many enemies can reach the goal before it is destroyed.
+	goal_hp: u32,
+	/// If set, how many times the goal can be destroyed or the player killed
+	/// before the level is lost.
+	lives: Option<u32>,
+	/// If set, the level is lost if it is not won after that many turns.
+	max_turns: Option<u32>,
+	/// If set, chopped trees grow back after that many turns.
+	tree_regrowth: Option<u32>,
+	/// Towers and bombs can be crafted from the resources of trees and rocks, the towers to
+	/// place start at `max_towers` (or none).
+	crafting: bool,
+	day_night: Option<DayNight>,
+	weather: weather::Forecast,
+	theme: Theme,
+	/// The `@transform`s to apply once the level is parsed.
+	transforms: Vec<Transform>,
+	script: Option<std::rc::Rc<script::Script>>,
+	hints: Vec<hints::Hint>,
+	/// The name of the level shown in the window title (`@title`), its file name if there is none.
+	title: Option<String>,
+}
+
+/// Days and nights alternate over the turns, which changes the tint of the scene
+/// and can make the enemies that spawn at night tougher.
+#[derive(Clone, Copy)]
+struct DayNight {
+	day_turns: u32,
+	night_turns: u32,
+	/// Extra HP of the enemies that spawn at night.
+	night_enemy_hp_bonus: u32,
+}
+
+impl DayNight {
+	const DAY_TINT: [u8; 3] = [255, 255, 255];
+	const DUSK_TINT: [u8; 3] = [230, 170, 150];
+	const NIGHT_TINT: [u8; 3] = [110, 120, 190];
+
+	/// Levels start at dawn.
+	fn is_night(self, turn: u32) -> bool {
+		turn % (self.day_turns + self.night_turns) >= self.day_turns
+	}
+
+	/// The first and last turns of the day are dawn and dusk, in between day and night.
+	fn tint(self, turn: u32) -> [u8; 3] {
+		let turn_of_cycle = turn % (self.day_turns + self.night_turns);
+		if self.is_night(turn) {
+			DayNight::NIGHT_TINT
+		} else if turn_of_cycle == 0 || turn_of_cycle + 1 == self.day_turns {
+			DayNight::DUSK_TINT
+		} else {
+			DayNight::DAY_TINT
+		}
+	}
 }
 
 impl LevelData {
 	fn new(grid: Grid<Cell>) -> LevelData {
-		LevelData { init_grid: grid, max_towers: None, init_events: vec![] }
+		LevelData {
+			init_grid: grid,
+			max_towers: None,
+			max_walls: None,
+			init_events: vec![],
+			fog_radius: None,
+			friendly_fire: true,
+			knockback: None,
+			named_tiles: HashMap::new(),
+			goal_hp: 1,
+			lives: None,
+			max_turns: None,
+			tree_regrowth: None,
+			crafting: false,
+			day_night: None,
+			weather: weather::Forecast::default(),
+			theme: Theme::default(),
+			transforms: vec![],
+			script: None,
+			hints: vec![],
+			title: None,
+		}
+	}
+
+	/// Mirror or rotate the level, with everything in it.
+	fn transform(&mut self, transform: Transform) {
+		let dims = self.init_grid.dims;
+		let mut grid = Grid::new(
+			transform.dims(dims),
+			Cell {
+				obj: Obj::Empty,
+				groud: Ground::Grass,
+				rocky_path: false,
+				spikes: false,
+				conveyor: None,
+				portal: false,
+			},
+		);
+		for coords in dims.iter() {
+			let mut cell = self.init_grid.get(coords).unwrap().clone();
+			if let Obj::Enemy { variant, .. } = &mut cell.obj {
+				transform.apply_to_enemy(variant);
+			}
+			if let Some(direction) = &mut cell.conveyor {
+				*direction = transform.direction(*direction);
+			}
+			if let Obj::Tower { facing, .. } = &mut cell.obj {
+				*facing = transform.direction(*facing);
+			}
+			*grid.get_mut(transform.coords(coords, dims)).unwrap() = cell;
+		}
+		self.init_grid = grid;
+		for coords in self.named_tiles.values_mut() {
+			*coords = transform.coords(*coords, dims);
+		}
+		for event in &mut self.init_events {
+			match &mut event.event_type {
+				GameEventType::EnemySpawn(coords, enemy, _) => {
+					*coords = transform.coords(*coords, dims);
+					transform.apply_to_enemy(enemy);
+				},
+				GameEventType::TerrainChange(coords, _) | GameEventType::Meteor(coords) => {
+					*coords = transform.coords(*coords, dims);
+				},
+				GameEventType::Grant(..) => {},
+			}
+		}
+		for hint in &mut self.hints {
+			if let hints::Trigger::Near(coords) = &mut hint.trigger {
+				*coords = transform.coords(*coords, dims);
+			}
+		}
+		if let weather::Forecast::Fixed(Weather::Wind(direction)) = &mut self.weather {
+			*direction = transform.direction(*direction);
+		}
+	}
+}
+
+/// A symmetry of a level (`@transform`), to get variants of a level without redrawing it.
+#[derive(Clone, Copy)]
+enum Transform {
+	/// Left becomes right.
+	MirrorX,
+	/// Top becomes bottom.
+	MirrorY,
+	/// A quarter turn clockwise.
+	Rot90,
+}
+
+impl Transform {
+	fn from_name(name: &str) -> Option<Transform> {
+		match name {
+			"mirror_x" => Some(Transform::MirrorX),
+			"mirror_y" => Some(Transform::MirrorY),
+			"rot90" => Some(Transform::Rot90),
+			_ => None,
+		}
+	}
+
+	fn dims(self, dims: Dimensions) -> Dimensions {
+		match self {
+			Transform::MirrorX | Transform::MirrorY => dims,
+			Transform::Rot90 => Dimensions { w: dims.h, h: dims.w },
+		}
+	}
+
+	/// Where the tile at `coords` of a grid of dimensions `dims` ends up.
+	fn coords(self, coords: Coords, dims: Dimensions) -> Coords {
+		match self {
+			Transform::MirrorX => Coords { x: dims.w - 1 - coords.x, y: coords.y },
+			Transform::MirrorY => Coords { x: coords.x, y: dims.h - 1 - coords.y },
+			Transform::Rot90 => Coords { x: dims.h - 1 - coords.y, y: coords.x },
+		}
+	}
+
+	fn direction(self, direction: Direction) -> Direction {
+		match (self, direction) {
+			(Transform::MirrorX, Direction::East) => Direction::West,
+			(Transform::MirrorX, Direction::West) => Direction::East,
+			(Transform::MirrorY, Direction::North) => Direction::South,
+			(Transform::MirrorY, Direction::South) => Direction::North,
+			(Transform::Rot90, Direction::North) => Direction::East,
+			(Transform::Rot90, Direction::East) => Direction::South,
+			(Transform::Rot90, Direction::South) => Direction::West,
+			(Transform::Rot90, Direction::West) => Direction::North,
+			(_, direction) => direction,
+		}
+	}
+
+	/// Protected enemies keep their protected sides facing the same way relative to the level.
+	fn apply_to_enemy(self, enemy: &mut Enemy) {
+		if let Enemy::Protected { direction, .. } = enemy {
+			*direction = self.direction(*direction);
+		}
 	}
 }
 
+#[derive(Clone)]
 struct LevelState {
 	grid: Grid<Cell>,
 	remaining_towers: Option<u32>,
+	remaining_walls: Option<u32>,
 	turn: u32,
 	events: Vec<GameEvent>,
 	game_joever: bool,
+	fog_radius: Option<i32>,
+	friendly_fire: bool,
+	knockback: bool,
+	goal_hp_max: u32,
+	lives: Option<u32>,
+	max_turns: Option<u32>,
+	tree_regrowth: Option<u32>,
+	/// Where trees were chopped down, and the turn when they grow back (with `tree_regrowth`).
+	stumps: Vec<(Coords, u32)>,
+	crafting: bool,
+	day_night: Option<DayNight>,
+	weather: weather::Forecast,
+	theme: Theme,
+	/// Which tiles are visible by the player (only relevant with fog of war).
+	visibility: Option<Grid<bool>>,
+	stats: Stats,
+	inventory: Inventory,
+	script: Option<std::rc::Rc<script::Script>>,
+	/// Shown over the level, set by the level script.
+	message: Option<String>,
+	/// The level script said the level is won.
+	won_by_script: bool,
+	/// The tutorial hints not shown yet.
+	hints: Vec<hints::Hint>,
+	/// The hints to show, the first one is shown until it is dismissed.
+	shown_hints: VecDeque<hints::ShownHint>,
+	/// With several units (players), which one of the units that have not acted yet this turn
+	/// is controlled (an index in `units_to_act`, cycled with `PlayerAction::SwitchUnit`).
+	active_unit: usize,
+	/// Where the units that already acted this turn are.
+	units_acted: Vec<Coords>,
+	/// How many actions were played, more than turns with several units.
+	actions: u32,
+}
+
+/// What happened during a level, for balancing purposes
+/// (and for the enemies that make use of the dead).
+#[derive(Clone, Default)]
+struct Stats {
+	/// How many enemies were killed by each kind of tower.
+	tower_kills: HashMap<Tower, u32>,
+	/// How many enemies were killed by bombs.
+	bomb_kills: u32,
+	/// How many enemies were killed by the player (in melee).
+	player_kills: u32,
+	/// How many enemies were killed by spike traps.
+	spike_kills: u32,
+	/// How many enemies were killed by lava.
+	lava_kills: u32,
+	/// How many enemies were killed by meteors.
+	meteor_kills: u32,
+	/// Where enemies died (and what they were), until they are revived.
+	corpses: Vec<(Coords, Enemy)>,
+	/// The steps made by the enemies during the last turn (from, to),
+	/// fast enemies make several steps.
+	enemy_moves: Vec<(Coords, Coords)>,
+	/// What happened during the last turn, for the renderer to show it.
+	turn_events: Vec<TurnEvent>,
+	/// The enemies killed during the last turn (and where), for the script hooks.
+	turn_kills: Vec<(Coords, Enemy)>,
+	/// How many enemies left each fork of the path (a tile with several equally short ways to
+	/// the goal), the next one takes the next way so that waves spread over the branches.
+	path_forks: HashMap<Coords, usize>,
+	/// The hits taken by the rocks, trees and flowers that block the way of the enemies.
+	obstacle_hits: HashMap<Coords, u32>,
+}
+
+/// Something noticeable that happened during a turn.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TurnEvent {
+	/// An enemy was damaged.
+	Hit(Coords),
+	EnemyDied(Coords),
+	Shot {
+		from: Coords,
+		to: Coords,
+	},
+	/// A bomb exploded, or a meteor fell.
+	Explosion(Coords),
+	/// An enemy reached the goal.
+	GoalDamaged(Coords),
+	/// An object was pushed or pulled to a neighboring cell (a chain push moves every object
+	/// of the chain by one cell, so it is one event per object).
+	Slid {
+		from: Coords,
+		to: Coords,
+	},
+	/// A boss entered a phase, which gave it that behavior.
+	BossPhase {
+		at: Coords,
+		behavior: BossBehavior,
+	},
+	/// The player was given that many towers or items by an event.
+	Granted(Grant, u32),
+}
+
+enum Killer {
+	Tower(Tower),
+	Bomb,
+	Player,
+	Spikes,
+	Lava,
+	Meteor,
+}
+
+impl Stats {
+	fn record_kill(&mut self, killer: Killer, coords: Coords, enemy: Enemy) {
+		let killer_name = match &killer {
+			Killer::Tower(variant) => variant.name(),
+			Killer::Bomb => "bomb",
+			Killer::Player => "player",
+			Killer::Spikes => "spikes",
+			Killer::Lava => "lava",
+			Killer::Meteor => "meteor",
+		};
+		log::debug!("{} killed at {coords} by {killer_name}", enemy.name());
+		match killer {
+			Killer::Tower(variant) => *self.tower_kills.entry(variant).or_default() += 1,
+			Killer::Bomb => self.bomb_kills += 1,
+			Killer::Player => self.player_kills += 1,
+			Killer::Spikes => self.spike_kills += 1,
+			Killer::Lava => self.lava_kills += 1,
+			Killer::Meteor => self.meteor_kills += 1,
+		}
+		self.turn_kills.push((coords, enemy.clone()));
+		self.corpses.push((coords, enemy));
+		self.turn_events.push(TurnEvent::EnemyDied(coords));
+	}
+
+	fn record_hit(&mut self, coords: Coords) {
+		self.turn_events.push(TurnEvent::Hit(coords));
+	}
+
+	fn was_hit(&self, coords: Coords) -> bool {
+		self.turn_events.contains(&TurnEvent::Hit(coords))
+	}
 }
 
 impl LevelState {
 	fn new(level_data: &LevelData) -> LevelState {
 		let mut grid = level_data.init_grid.clone();
+		for coords in grid.dims.iter() {
+			if let Obj::Goal { hp } = &mut grid.get_mut(coords).unwrap().obj {
+				*hp = level_data.goal_hp;
+			}
+		}
 		compute_distance(&mut grid);
-		LevelState {
+		let mut level = LevelState {
 			grid,
-			remaining_towers: level_data.max_towers,
+			remaining_towers: if level_data.crafting {
+				Some(level_data.max_towers.unwrap_or(0))
+			} else {
+				level_data.max_towers
+			},
+			remaining_walls: level_data.max_walls,
 			turn: 0,
 			events: level_data.init_events.clone(),
 			game_joever: false,
+			fog_radius: level_data.fog_radius,
+			friendly_fire: level_data.friendly_fire,
+			knockback: level_data.knockback.unwrap_or(balance::get().knockback),
+			goal_hp_max: level_data.goal_hp,
+			lives: level_data.lives,
+			max_turns: level_data.max_turns,
+			tree_regrowth: level_data.tree_regrowth,
+			stumps: vec![],
+			crafting: level_data.crafting,
+			day_night: level_data.day_night,
+			weather: level_data.weather,
+			theme: level_data.theme,
+			visibility: None,
+			stats: Stats::default(),
+			inventory: Inventory::default(),
+			script: level_data.script.clone(),
+			message: None,
+			won_by_script: false,
+			hints: level_data.hints.clone(),
+			shown_hints: VecDeque::new(),
+			active_unit: 0,
+			units_acted: vec![],
+			actions: 0,
+		};
+		for coords in level.grid.dims.iter() {
+			face_the_path(&mut level.grid, &level.stats, coords);
+		}
+		if let Some(script) = level.script.clone() {
+			script.on_turn_start(&mut level);
 		}
+		level.update_visibility();
+		hints::check(&mut level);
+		level
 	}
-}
 
-#[derive(Clone)]
-enum GameEventType {
-	EnemySpawn(Coords, Enemy),
-}
+	fn is_night(&self) -> bool {
+		self
+			.day_night
+			.is_some_and(|day_night| day_night.is_night(self.turn))
+	}
 
-#[derive(Clone)]
-struct GameEvent {
-	turn: u32,
-	event_type: GameEventType,
-}
+	/// Do the spike traps strike at the end of the current turn? They do every other turn.
+	fn spikes_armed(&self) -> bool {
+		self.turn % 2 == 1
+	}
 
-impl GameEvent {
-	fn new(turn: u32, event_type: GameEventType) -> GameEvent {
-		GameEvent { turn, event_type }
+	/// The weather during the current turn.
+	fn weather(&self) -> Weather {
+		self.weather.weather(self.turn)
 	}
-}
 
-/// Draw a sprite form the given spritesheet to the given pixel buffer.
-/// `dst` is the rectangle location of the pixel buffer to draw to,
-/// `sprite` is the rectangle location of the spritesheet to copy from.
-fn draw_sprite(
-	pixel_buffer: &mut pixels::Pixels,
-	pixel_buffer_dims: Dimensions,
-	dst: Rect,
-	spritesheet: &image::DynamicImage,
-	sprite: Rect,
-) {
-	// `coords_dst_dims` is a pixel in the dst rect but with (0, 0) being the top left corner.
-	for coords_dst_dims in dst.dims.iter() {
-		// `(sx, sy)` is the pixel to read from the spritesheet.
-		let sx = (sprite.top_left.x + coords_dst_dims.x * sprite.dims.w / dst.dims.w) as u32;
-		let sy = (sprite.top_left.y + coords_dst_dims.y * sprite.dims.h / dst.dims.h) as u32;
-		let color = spritesheet.get_pixel(sx, sy).0;
-		if color[3] == 0 {
-			// Skip transparent pixels.
-			continue;
-		}
-		// `coords_pixel_buffer` is the pixel to write to in the pixel buffer,
-		// each of which is visited once.
+	/// The tint applied to the whole scene (see `SCENE_TINT`), if any.
+	fn scene_tint(&self) -> Option<[u8; 3]> {
+		self
+			.day_night
+			.map(|day_night| day_night.tint(self.turn))
+			.filter(|&tint| tint != DayNight::DAY_TINT)
+	}
+
+	/// Where the units are, in the order of the grid.
+	fn unit_coords(&self) -> Vec<Coords> {
+		self
+			.grid
+			.dims
+			.iter()
+			.filter(|&coords| matches!(self.grid.get(coords).unwrap().obj, Obj::Player { .. }))
+			.collect()
+	}
+
+	/// The units that have not acted yet this turn, the rest of the level moves once there
+	/// are none left.
+	fn units_to_act(&self) -> Vec<Coords> {
+		let mut units = self.unit_coords();
+		units.retain(|coords| !self.units_acted.contains(coords));
+		units
+	}
+
+	/// Where the unit controlled by the player is.
+	fn player_coords(&self) -> Option<Coords> {
+		let units = self.units_to_act();
+		units.get(self.active_unit % units.len().max(1)).copied()
+	}
+
+	/// Recompute which tiles are in the fog of war, which changes every turn as the player
+	/// moves and towers get placed or destroyed.
+	fn update_visibility(&mut self) {
+		let Some(radius) = self.fog_radius else {
+			return;
+		};
+		let mut visibility = Grid::new(self.grid.dims, false);
+		for coords in self.grid.dims.iter() {
+			if !matches!(
+				self.grid.get(coords).unwrap().obj,
+				Obj::Player { .. } | Obj::Tower { .. }
+			) {
+				continue;
+			}
+			let around = Rect {
+				top_left: coords - DxDy::from((radius, radius)),
+				dims: Dimensions::square(radius * 2 + 1),
+			};
+			for coords_seen in around.iter() {
+				let DxDy { dx, dy } = coords_seen - coords;
+				if dx * dx + dy * dy <= radius * radius {
+					if let Some(visible) = visibility.get_mut(coords_seen) {
+						*visible = true;
+					}
+				}
+			}
+		}
+		self.visibility = Some(visibility);
+	}
+
+	/// The level is won when every enemy has spawned and has been killed.
+	fn is_won(&self) -> bool {
+		!self.game_joever && self.won_by_script
+			|| !self.game_joever
+				&& self
+					.events
+					.iter()
+					.filter(|event| matches!(event.event_type, GameEventType::EnemySpawn(..)))
+					// The enemies waiting for a condition that doesn't hold may never come.
+					.all(|event| {
+						event.turn <= self.turn
+							|| event
+								.condition
+								.is_some_and(|condition| !condition.holds(&self.grid, self.goal_hp_max))
+					}) && self
+				.grid
+				.dims
+				.iter()
+				.all(|coords| !matches!(self.grid.get(coords).unwrap().obj, Obj::Enemy { .. }))
+	}
+
+	/// How many turns are left to win the level, if it has a limit.
+	fn turns_left(&self) -> Option<u32> {
+		self
+			.max_turns
+			.map(|max_turns| max_turns.saturating_sub(self.turn))
+	}
+
+	fn is_visible(&self, coords: Coords) -> bool {
+		self
+			.visibility
+			.as_ref()
+			.is_none_or(|visibility| visibility.get(coords).is_some_and(|visible| *visible))
+	}
+
+	/// Events that will fire at the end of the next turn, so that the renderer can warn
+	/// the player about them one turn in advance.
+	fn pending_events(&self) -> impl Iterator<Item = &GameEvent> {
+		self
+			.events
+			.iter()
+			.filter(|event| event.turn == self.turn + 1)
+			.filter(|event| {
+				event
+					.condition
+					.is_none_or(|condition| condition.holds(&self.grid, self.goal_hp_max))
+			})
+	}
+}
+
+#[derive(Clone, Hash)]
+enum GameEventType {
+	EnemySpawn(Coords, Enemy, Option<GroupMember>),
+	/// The ground of the tile changes (`@event terrain <tile> <ground> <turn>`), to open or
+	/// close paths during the level.
+	TerrainChange(Coords, Ground),
+	/// A meteor falls on the tile (`@event meteor <turn> [seed]`, the tile being drawn from
+	/// the seed among the empty ones), see `meteor_falls`.
+	Meteor(Coords),
+	/// The player is given that many towers or items (`@event grant <what> [count] <turn>`).
+	Grant(Grant, u32),
+}
+
+/// What the player can be given by events.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Grant {
+	/// More towers to place (in levels where they are limited).
+	Towers,
+	Item(Item),
+}
+
+impl Grant {
+	/// `towers` or the name of an item.
+	fn from_name(name: &str) -> Option<Grant> {
+		match name {
+			"towers" => Some(Grant::Towers),
+			_ => Item::from_name(name).map(Grant::Item),
+		}
+	}
+
+	/// What the player is told when they are given `count` of it.
+	fn toast(self, count: u32) -> String {
+		match self {
+			Grant::Towers => tr_with("toast.towers", &[("count", &count)]),
+			Grant::Item(item) => {
+				let item = tr(&format!("item.{}", item.name()));
+				tr_with("toast.item", &[("count", &count), ("item", &item)])
+			},
+		}
+	}
+}
+
+#[derive(Clone, Hash)]
+struct GameEvent {
+	/// The turn it happens, or the first turn it can happen if it has a condition (then it is
+	/// put off to the next turn until the condition holds).
+	turn: u32,
+	event_type: GameEventType,
+	/// `@event when <condition> <event>...`, `None` once it held.
+	condition: Option<EventCondition>,
+}
+
+impl GameEvent {
+	fn new(turn: u32, event_type: GameEventType) -> GameEvent {
+		GameEvent { turn, event_type, condition: None }
+	}
+}
+
+/// What the events with a condition wait for, checked at the end of each turn before the
+/// events happen.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum EventCondition {
+	/// `enemies_below <count>`: fewer enemies than that are left on the level.
+	EnemiesBelow(u32),
+	/// `goal_hp_below <percent>`: a goal has less than that share of its HP left.
+	GoalHpBelow(u32),
+}
+
+impl EventCondition {
+	fn parse<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<EventCondition, String> {
+		let name = words.next().unwrap_or_default();
+		Ok(match name {
+			"enemies_below" => EventCondition::EnemiesBelow(parse_value(words.next(), name)?),
+			"goal_hp_below" => {
+				let percent = words.next().map(|word| word.trim_end_matches('%'));
+				EventCondition::GoalHpBelow(parse_value(percent, name)?)
+			},
+			_ => return Err(tr_with("error.unknown_condition", &[("name", &name)])),
+		})
+	}
+
+	fn holds(self, grid: &Grid<Cell>, goal_hp_max: u32) -> bool {
+		let objs = || {
+			grid
+				.dims
+				.iter()
+				.map(|coords| &grid.get(coords).unwrap().obj)
+		};
+		match self {
+			EventCondition::EnemiesBelow(count) => {
+				objs()
+					.filter(|obj| matches!(obj, Obj::Enemy { .. }))
+					.count() < count as usize
+			},
+			EventCondition::GoalHpBelow(percent) => {
+				objs().any(|obj| matches!(obj, Obj::Goal { hp } if hp * 100 < percent * goal_hp_max))
+			},
+		}
+	}
+}
+
+/// Ways to draw a sprite differently from how it is in the spritesheet,
+/// so that variations of a sprite don't need their own sprites.
+#[derive(Clone, Copy, Default, Hash)]
+struct SpriteEffects {
+	flip_horizontally: bool,
+	flip_vertically: bool,
+	/// Number of clockwise quarter turns, applied after the flips.
+	quarter_turns: u32,
+	/// Each color channel is multiplied by the corresponding tint channel.
+	tint: Option<[u8; 3]>,
+	/// The alpha of every pixel is multiplied by this (out of 255).
+	opacity: Option<u8>,
+	/// The colors are swapped for the ones of the theme, before the tint.
+	theme: Theme,
+}
+
+impl SpriteEffects {
+	fn rotated_towards(direction: Direction) -> SpriteEffects {
+		let quarter_turns = match direction {
+			Direction::North => 0,
+			Direction::East => 1,
+			Direction::South => 2,
+			Direction::West => 3,
+		};
+		SpriteEffects { quarter_turns, ..SpriteEffects::default() }
+	}
+
+	fn tinted(tint: [u8; 3]) -> SpriteEffects {
+		SpriteEffects { tint: Some(tint), ..SpriteEffects::default() }
+	}
+
+	/// The pixel of the sprite (relative to its top left corner) to draw at `coords` in a
+	/// destination of the given dimensions.
+	fn source_coords(self, coords: Coords, dst_dims: Dimensions, sprite_dims: Dimensions) -> Coords {
+		let (mut x, mut y) = (coords.x, coords.y);
+		let (mut w, mut h) = (dst_dims.w, dst_dims.h);
+		// Undo the rotations one quarter turn at a time.
+		for _ in 0..(self.quarter_turns % 4) {
+			(x, y) = (y, w - 1 - x);
+			(w, h) = (h, w);
+		}
+		if self.flip_horizontally {
+			x = w - 1 - x;
+		}
+		if self.flip_vertically {
+			y = h - 1 - y;
+		}
+		Coords { x: x * sprite_dims.w / w, y: y * sprite_dims.h / h }
+	}
+}
+
+#[test]
+fn sprite_effects_rotate_clockwise_and_flip() {
+	let dims = Dimensions { w: 8, h: 8 };
+	let source = |effects: SpriteEffects, x, y| {
+		let coords = effects.source_coords((x, y).into(), dims, dims);
+		(coords.x, coords.y)
+	};
+	// The top left corner of the sprite ends up in the top right corner.
+	assert_eq!(
+		source(SpriteEffects::rotated_towards(Direction::East), 7, 0),
+		(0, 0)
+	);
+	assert_eq!(
+		source(SpriteEffects::rotated_towards(Direction::South), 7, 7),
+		(0, 0)
+	);
+	let flipped = SpriteEffects { flip_horizontally: true, ..SpriteEffects::default() };
+	assert_eq!(source(flipped, 7, 2), (0, 2));
+}
+
+/// Draw a sprite form the given spritesheet to the given pixel buffer.
+/// `dst` is the rectangle location of the pixel buffer to draw to,
+/// `sprite` is the rectangle location of the spritesheet to copy from.
+fn draw_sprite(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	dst: Rect,
+	spritesheet: &Spritesheet,
+	sprite: Rect,
+) {
+	draw_sprite_with_effects(
+		pixel_buffer,
+		pixel_buffer_dims,
+		dst,
+		spritesheet,
+		sprite,
+		SpriteEffects::default(),
+	);
+}
+
+/// Same as `draw_sprite`, but flipped, rotated and/or tinted.
+fn draw_sprite_with_effects(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	dst: Rect,
+	spritesheet: &Spritesheet,
+	sprite: Rect,
+	effects: SpriteEffects,
+) {
+	// `coords_dst_dims` is a pixel in the dst rect but with (0, 0) being the top left corner.
+	for coords_dst_dims in dst.dims.iter() {
+		// `(sx, sy)` is the pixel to read from the spritesheet.
+		let coords_in_sprite = effects.source_coords(coords_dst_dims, dst.dims, sprite.dims);
+		let mut color = spritesheet.pixel(sprite.top_left + DxDy::from(coords_in_sprite));
+		if color[3] == 0 {
+			// Skip transparent pixels.
+			continue;
+		}
+		color = effects.theme.recolor(color);
+		if let Some(tint) = effects.tint {
+			for channel in 0..3 {
+				color[channel] = (color[channel] as u32 * tint[channel] as u32 / 255) as u8;
+			}
+		}
+		if let Some(opacity) = effects.opacity {
+			color[3] = (color[3] as u32 * opacity as u32 / 255) as u8;
+		}
+		// `coords_pixel_buffer` is the pixel to write to in the pixel buffer,
+		// each of which is visited once.
 		let coords_pixel_buffer = coords_dst_dims + dst.top_left.into();
 		if let Some(pixel_index) = pixel_buffer_dims.index_of_coords(coords_pixel_buffer) {
 			let pixel_byte_index = pixel_index * 4;
 			let pixel_bytes = pixel_byte_index..(pixel_byte_index + 4);
-			pixel_buffer.frame_mut()[pixel_bytes].copy_from_slice(&color);
+			blend_pixel(&mut pixel_buffer.frame_mut()[pixel_bytes], color);
+		}
+	}
+}
+
+/// The colorblind option of the config (see `Config::colorblind`), which changes how things are
+/// drawn all over the place.
+static COLORBLIND: AtomicBool = AtomicBool::new(false);
+
+/// The colors of the full and the empty parts of the life bars.
+fn life_bar_colors() -> ([u8; 4], [u8; 4]) {
+	if COLORBLIND.load(Ordering::Relaxed) {
+		([40, 140, 255, 255], [255, 160, 0, 255])
+	} else {
+		([0, 255, 0, 255], [255, 0, 0, 255])
+	}
+}
+
+/// The colors of the shield bars, which are blue (white with the colorblind option, as the life
+/// bars are blue then).
+fn shield_bar_colors() -> ([u8; 4], [u8; 4]) {
+	if COLORBLIND.load(Ordering::Relaxed) {
+		([240, 240, 240, 255], [70, 70, 70, 255])
+	} else {
+		([60, 140, 255, 255], [30, 40, 90, 255])
+	}
+}
+
+/// A bar filled (from the left) in proportion to `(value, max)`.
+fn draw_bar(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	dst: Rect,
+	(value, max): (u32, u32),
+	(full_color, empty_color): ([u8; 4], [u8; 4]),
+) {
+	draw_rect(pixel_buffer, pixel_buffer_dims, dst, empty_color);
+	let mut full = dst;
+	full.dims.w = dst.dims.w * value as i32 / max.max(1) as i32;
+	draw_rect(pixel_buffer, pixel_buffer_dims, full, full_color);
+}
+
+thread_local! {
+	/// The tint of the whole scene (like the night, see `LevelState::scene_tint`), every color
+	/// channel of what is drawn while it is set is scaled by the tint channel.
+	/// It is only set while the grid is drawn, not the HUD and menus.
+	static SCENE_TINT: std::cell::Cell<Option<[u8; 3]>> = const { std::cell::Cell::new(None) };
+}
+
+fn set_scene_tint(tint: Option<[u8; 3]>) {
+	SCENE_TINT.with(|scene_tint| scene_tint.set(tint));
+}
+
+/// Draw `color` over a pixel of the pixel buffer, mixing them according to the alpha of
+/// `color` (source-over blending, the pixel buffer is always opaque).
+fn blend_pixel(pixel: &mut [u8], mut color: [u8; 4]) {
+	if let Some(tint) = SCENE_TINT.with(|scene_tint| scene_tint.get()) {
+		for channel in 0..3 {
+			color[channel] = (color[channel] as u32 * tint[channel] as u32 / 255) as u8;
+		}
+	}
+	match color[3] {
+		0 => {},
+		255 => pixel.copy_from_slice(&color),
+		alpha => {
+			let alpha = alpha as u32;
+			for channel in 0..3 {
+				pixel[channel] = ((color[channel] as u32 * alpha
+					+ pixel[channel] as u32 * (255 - alpha))
+					/ 255) as u8;
+			}
+			pixel[3] = 255;
+		},
+	}
+}
+
+#[test]
+fn alpha_blending_mixes_the_color_with_what_is_under_it() {
+	let mut pixel = [200, 100, 0, 255];
+	blend_pixel(&mut pixel, [0, 0, 0, 0]);
+	assert_eq!(pixel, [200, 100, 0, 255]);
+	blend_pixel(&mut pixel, [0, 100, 255, 51]);
+	assert_eq!(pixel, [160, 100, 51, 255]);
+	blend_pixel(&mut pixel, [1, 2, 3, 255]);
+	assert_eq!(pixel, [1, 2, 3, 255]);
+}
+
+/// Draw a sprite half transparent and tinted (each color channel is scaled by the `tint`
+/// channel), to show something that is not really there.
+fn draw_ghost_sprite(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	dst: Rect,
+	spritesheet: &Spritesheet,
+	sprite: Rect,
+	tint: [u8; 3],
+) {
+	draw_sprite_with_effects(
+		pixel_buffer,
+		pixel_buffer_dims,
+		dst,
+		spritesheet,
+		sprite,
+		SpriteEffects { tint: Some(tint), opacity: Some(128), ..SpriteEffects::default() },
+	);
+}
+
+/// The characters of the `font.symbols` sprite, in order.
+const FONT_SYMBOLS: &str = ":.<>+?";
+
+/// Where the glyph of the given character is in the spritesheet (4x8 pixels, the character
+/// itself is 3x5 pixels with a margin of 1 pixel on the top and the left),
+/// `None` for spaces and characters that are not in the font.
+fn glyph_sprite(spritesheet: &Spritesheet, character: char) -> Option<Rect> {
+	// The font has no accents, accented letters are drawn without them.
+	let character = match character.to_uppercase().next()? {
+		'À' | 'Â' | 'Ä' => 'A',
+		'Ç' => 'C',
+		'É' | 'È' | 'Ê' | 'Ë' => 'E',
+		'Î' | 'Ï' => 'I',
+		'Ô' | 'Ö' => 'O',
+		'Ù' | 'Û' | 'Ü' => 'U',
+		character => character,
+	};
+	let (name, index) = match character {
+		'0'..='9' => ("font.digits", character as i32 - '0' as i32),
+		'-' => ("font.minus", 0),
+		'/' => ("font.slash", 0),
+		'A'..='Z' => ("font.letters", character as i32 - 'A' as i32),
+		_ => ("font.symbols", FONT_SYMBOLS.find(character)? as i32),
+	};
+	Some(spritesheet.sprite_part(name, index, 4))
+}
+
+/// Draw a line of text with the pixel font of the spritesheet, each pixel of the font
+/// being `scale` pixels wide, in the given color.
+fn draw_text(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	top_left: Coords,
+	scale: i32,
+	spritesheet: &Spritesheet,
+	text: &str,
+	color: [u8; 4],
+) {
+	for (index, character) in text.chars().enumerate() {
+		let Some(glyph) = glyph_sprite(spritesheet, character) else {
+			continue;
+		};
+		let glyph_top_left = top_left + DxDy::from((index as i32 * 4 * scale, 0));
+		for coords_in_glyph in glyph.dims.iter() {
+			if spritesheet.pixel(glyph.top_left + DxDy::from(coords_in_glyph))[3] == 0 {
+				continue;
+			}
+			let dst = Rect {
+				top_left: glyph_top_left
+					+ DxDy::from((coords_in_glyph.x * scale, coords_in_glyph.y * scale)),
+				dims: Dimensions::square(scale),
+			};
+			draw_rect(pixel_buffer, pixel_buffer_dims, dst, color);
 		}
 	}
 }
 
+/// Draw a number with the digits of the spritesheet, `dst` is where the first digit goes
+/// (and the size of every digit).
+fn draw_number(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	dst: Rect,
+	spritesheet: &Spritesheet,
+	number: u32,
+) {
+	for (index, digit) in number.to_string().chars().enumerate() {
+		let digit = digit.to_digit(10).unwrap() as i32;
+		let sprite = spritesheet.sprite_part("font.digits", digit, 4);
+		let dst = Rect {
+			top_left: Coords { x: dst.left() + index as i32 * dst.dims.w, y: dst.top() },
+			dims: dst.dims,
+		};
+		draw_sprite(pixel_buffer, pixel_buffer_dims, dst, spritesheet, sprite);
+	}
+}
+
+/// Fill the given rectangle, blended with what is under it if `color` is not opaque.
 fn draw_rect(
 	pixel_buffer: &mut pixels::Pixels,
 	pixel_buffer_dims: Dimensions,
@@ -251,180 +1763,791 @@ fn draw_rect(
 		if let Some(pixel_index) = pixel_buffer_dims.index_of_coords(coords) {
 			let pixel_byte_index = pixel_index * 4;
 			let pixel_bytes = pixel_byte_index..(pixel_byte_index + 4);
-			pixel_buffer.frame_mut()[pixel_bytes].copy_from_slice(&color);
+			blend_pixel(&mut pixel_buffer.frame_mut()[pixel_bytes], color);
 		}
 	}
 }
 
-fn try_push(grid: &mut Grid<Cell>, coords: Coords, dd: DxDy, can_push_enemies: bool) {
+/// Draw the border of the given rectangle, `thickness` pixels thick, inside of it.
+fn draw_rect_outline(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	dst: Rect,
+	thickness: i32,
+	color: [u8; 4],
+) {
+	for edge in [
+		Rect { dims: Dimensions { w: dst.dims.w, h: thickness }, ..dst },
+		Rect { dims: Dimensions { w: thickness, h: dst.dims.h }, ..dst },
+		Rect {
+			top_left: (dst.left(), dst.bottom_excluded() - thickness).into(),
+			dims: Dimensions { w: dst.dims.w, h: thickness },
+		},
+		Rect {
+			top_left: (dst.right_excluded() - thickness, dst.top()).into(),
+			dims: Dimensions { w: thickness, h: dst.dims.h },
+		},
+	] {
+		draw_rect(pixel_buffer, pixel_buffer_dims, edge, color);
+	}
+}
+
+/// Where to draw the `index`-th item of the targeting priority menu of the tower at the
+/// given coords, so that the menu is just under (or above) the tower and fits in the window.
+fn priority_menu_item_rect(
+	tower_coords: Coords,
+	index: i32,
+	cell_pixel_side: i32,
+	grid_dims: Dimensions,
+) -> Rect {
+	let item_side = cell_pixel_side / 2;
+	let menu_width = item_side * TargetPriority::ALL.len() as i32;
+	let tower_rect = Rect::tile(tower_coords, cell_pixel_side);
+	let y = if tower_coords.y + 1 < grid_dims.h {
+		tower_rect.bottom_excluded()
+	} else {
+		tower_rect.top() - item_side
+	};
+	let x = tower_rect
+		.left()
+		.min(grid_dims.w * cell_pixel_side - menu_width);
+	Rect {
+		top_left: (x + index * item_side, y).into(),
+		dims: Dimensions::square(item_side),
+	}
+}
+
+/// Darken what is already drawn in the given rectangle of the pixel buffer.
+fn darken_rect(pixel_buffer: &mut pixels::Pixels, pixel_buffer_dims: Dimensions, dst: Rect) {
+	// Black at two thirds of opacity leaves a third of the brightness.
+	draw_rect(pixel_buffer, pixel_buffer_dims, dst, [0, 0, 0, 170]);
+}
+
+fn try_push(
+	grid: &mut Grid<Cell>,
+	stats: &mut Stats,
+	coords: Coords,
+	dd: DxDy,
+	can_push_enemies: bool,
+	strength: u32,
+) {
 	if grid.get(coords).is_none() {
 		return;
 	}
 	let obj = grid.get(coords).unwrap().obj.clone();
-	if matches!(
-		obj,
-		Obj::Rock | Obj::Tower { .. } | Obj::Bomb { .. } | Obj::Flower { .. }
-	) {
+	// Too heavy, nothing in the line moves (what is behind is not reached, and what is before
+	// doesn't get room to move).
+	let Some(strength) = strength.checked_sub(balance::get().push_weight(&obj)) else {
+		return;
+	};
+	if balance::get().can_push(&obj) {
 		let dst_coords = coords + dd;
-		try_push(grid, dst_coords, dd, can_push_enemies);
+		try_push(grid, stats, dst_coords, dd, can_push_enemies, strength);
 		if grid
 			.get(dst_coords)
 			.is_some_and(|cell| matches!(cell.obj, Obj::Empty))
 			&& (!matches!(obj, Obj::Tower { .. }) || (!grid.get(dst_coords).unwrap().rocky_path))
 		{
-			if !matches!(grid.get(dst_coords).unwrap().groud, Ground::Water) {
-				grid.get_mut(dst_coords).unwrap().obj = obj;
-			}
+			land_obj(grid, dst_coords, obj);
 			grid.get_mut(coords).unwrap().obj = Obj::Empty;
+			stats
+				.turn_events
+				.push(TurnEvent::Slid { from: coords, to: dst_coords });
 		}
 	} else if can_push_enemies && matches!(obj, Obj::Enemy { .. }) {
 		let dst_coords = coords + dd;
+		// Enemies stay on the path, unless they are pushed into lava.
 		if grid
 			.get(dst_coords)
-			.is_some_and(|cell| matches!(cell.groud, Ground::Path(_)))
+			.is_some_and(|cell| matches!(cell.groud, Ground::Path(_) | Ground::Lava))
 		{
-			try_push(grid, dst_coords, dd, can_push_enemies);
+			try_push(grid, stats, dst_coords, dd, can_push_enemies, strength);
 			if grid
 				.get(dst_coords)
 				.is_some_and(|cell| matches!(cell.obj, Obj::Empty))
 			{
 				grid.get_mut(dst_coords).unwrap().obj = obj;
 				grid.get_mut(coords).unwrap().obj = Obj::Empty;
+				stats
+					.turn_events
+					.push(TurnEvent::Slid { from: coords, to: dst_coords });
 			}
 		}
 	}
 }
 
-#[derive(PartialEq, Eq)]
+/// Put an object that was pushed or pulled on the (empty) tile at `coords`. Objects sink in
+/// water, but rocks fill it up into a stepping stone: rocky grass, that can be walked on but
+/// where towers can't be placed. Lava destroys objects too, but bombs are set off by it (they
+/// explode by the end of the turn, see `lava_burns`).
+fn land_obj(grid: &mut Grid<Cell>, coords: Coords, obj: Obj) {
+	let cell = grid.get_mut(coords).unwrap();
+	match (&cell.groud, obj) {
+		(Ground::Water, Obj::Rock) => {
+			log::debug!("A rock sinks into the water at {coords}, making a stepping stone");
+			cell.groud = Ground::Grass;
+			cell.rocky_path = true;
+		},
+		(Ground::Water, _) => {},
+		(Ground::Lava, Obj::Bomb { .. }) => {
+			log::debug!("A bomb falls into the lava at {coords}");
+			cell.obj = Obj::Bomb { countdown: 0 };
+		},
+		(Ground::Lava, _) => log::debug!("Something burns in the lava at {coords}"),
+		(_, obj) => cell.obj = obj,
+	}
+}
+
+/// The counterpart of `try_push`: moves the object at `coords` by `dd` if there is room there,
+/// without dragging what is behind it.
+fn try_pull(
+	grid: &mut Grid<Cell>,
+	stats: &mut Stats,
+	coords: Coords,
+	dd: DxDy,
+	can_pull_enemies: bool,
+) {
+	let Some(obj) = grid.get(coords).map(|cell| cell.obj.clone()) else {
+		return;
+	};
+	let dst_coords = coords + dd;
+	let can_be_pulled = match obj {
+		// Towers are never pulled.
+		Obj::Tower { .. } => false,
+		// Enemies stay on the path, unless they are pulled into lava.
+		Obj::Enemy { .. } => {
+			can_pull_enemies
+				&& grid
+					.get(dst_coords)
+					.is_some_and(|cell| matches!(cell.groud, Ground::Path(_) | Ground::Lava))
+		},
+		_ => balance::get().can_push(&obj),
+	};
+	if can_be_pulled
+		&& grid
+			.get(dst_coords)
+			.is_some_and(|cell| matches!(cell.obj, Obj::Empty))
+	{
+		land_obj(grid, dst_coords, obj);
+		grid.get_mut(coords).unwrap().obj = Obj::Empty;
+		stats
+			.turn_events
+			.push(TurnEvent::Slid { from: coords, to: dst_coords });
+	}
+}
+
+#[derive(Clone, PartialEq, Eq)]
 enum PlayerAction {
 	Move,
-	PlaceTower { variant: Tower },
+	PlaceTower {
+		variant: Tower,
+	},
+	PlaceWall,
+	UseItem {
+		item: Item,
+	},
+	/// Hit the adjacent enemy.
+	Attack,
+	/// Take back the adjacent tower, to place it again somewhere else.
+	PickUpTower,
+	/// Merge the adjacent tower into an identical tower next to it.
+	MergeTower,
+	/// Refill the ammo of the adjacent tower.
+	Reload,
 	SkipTurn,
+	/// Control the next unit that has not acted yet this turn (which doesn't take a turn).
+	SwitchUnit,
+	/// Spend resources (which doesn't take a turn either).
+	Craft {
+		recipe: Recipe,
+	},
 }
 
-fn player_move(level: &mut LevelState, dd: DxDy, action: PlayerAction) {
-	for coords in level.grid.dims.iter() {
-		if level
-			.grid
-			.get(coords)
-			.is_some_and(|cell| matches!(cell.obj, Obj::Player { stunned: false }))
-		{
-			let dst_coords = coords + dd;
-			match action {
-				PlayerAction::Move => {
-					if level
-						.grid
-						.get(dst_coords)
-						.is_some_and(|cell| !matches!(cell.groud, Ground::Water))
-					{
-						if !matches!(level.grid.get(dst_coords).unwrap().obj, Obj::Empty) {
-							try_push(&mut level.grid, dst_coords, dd, false);
-						}
-						if matches!(level.grid.get(dst_coords).unwrap().obj, Obj::Empty) {
-							level.grid.get_mut(coords).unwrap().obj = Obj::Empty;
-							level.grid.get_mut(dst_coords).unwrap().obj = Obj::Player { stunned: false };
-						}
-					}
-				},
-				PlayerAction::PlaceTower { variant } => {
-					if level.remaining_towers.is_some_and(|count| count == 0) {
-						// We can't place a tower if we have no more towers to place.
-					} else if level.grid.get(dst_coords).is_some_and(|cell| {
-						matches!(cell.obj, Obj::Empty)
-							&& !matches!(cell.groud, Ground::Water)
-							&& !cell.rocky_path
-					}) {
-						level.grid.get_mut(dst_coords).unwrap().obj =
-							Obj::Tower { variant, stunned: false };
-						if let Some(count) = &mut level.remaining_towers {
-							*count -= 1;
-						}
-					}
-				},
-				PlayerAction::SkipTurn => {},
-			}
-			return;
-		} else if let Obj::Player { stunned: stunned @ true } =
-			&mut level.grid.get_mut(coords).unwrap().obj
-		{
-			*stunned = false;
-		}
+impl PlayerAction {
+	/// Some actions are played right away, without the rest of the level moving.
+	fn takes_a_turn(&self) -> bool {
+		!matches!(self, PlayerAction::SwitchUnit | PlayerAction::Craft { .. })
 	}
 }
 
-fn enemy_displacement(new_grid: &mut Grid<Cell>, coords: Coords) -> Coords {
-	// We may move. We try to find an adjacent path tile that will get us loser
-	// to the goal (so its distance to the goal should be smaller that our
-	// current distance) (these distances are stored in the path tiles).
-	let dist_to_goal = if let Ground::Path(dist) = new_grid.get(coords).unwrap().groud {
-		dist
+/// The tower that the tower at the given coords could be merged into, if any:
+/// an adjacent tower of the same variant and tier that can still be upgraded.
+fn merge_partner(grid: &Grid<Cell>, coords: Coords) -> Option<Coords> {
+	let Some(Obj::Tower { variant, tier, .. }) = grid.get(coords).map(|cell| &cell.obj) else {
+		return None;
+	};
+	if !variant.can_merge() || *tier >= MAX_TOWER_TIER {
+		return None;
+	}
+	DxDy::the_4_directions()
+		.map(|dd| coords + dd)
+		.find(|&neighbor| {
+			grid.get(neighbor).is_some_and(|cell| {
+				matches!(&cell.obj, Obj::Tower { variant: other_variant, tier: other_tier, .. }
+				if other_variant == variant && other_tier == tier)
+			})
+		})
+}
+
+/// Can the player place a tower at the given coords (if they stand next to it)?
+fn can_place_tower(level: &LevelState, coords: Coords) -> bool {
+	// We can't place a tower if we have no more towers to place.
+	level.remaining_towers.is_none_or(|count| count > 0)
+		&& level.grid.get(coords).is_some_and(|cell| {
+			matches!(cell.obj, Obj::Empty) && !cell.groud.is_liquid() && !cell.rocky_path
+		})
+}
+
+/// Walking into a tree chops it, the second time it falls and leaves a bomb (wood in crafting
+/// levels).
+fn chop_tree(level: &mut LevelState, coords: Coords) {
+	let Obj::Tree { chopped } = &mut level.grid.get_mut(coords).unwrap().obj else {
+		return;
+	};
+	if !*chopped {
+		*chopped = true;
+		return;
+	}
+	log::debug!("The tree at {coords} is chopped down");
+	let item = if level.crafting {
+		Item::Wood
 	} else {
-		panic!("Not a path?????")
+		Item::Bomb
 	};
-	for dd in DxDy::the_4_directions() {
-		let dst_coords = coords + dd;
-		if new_grid.get(dst_coords).is_some_and(|cell| {
-			matches!(
-				cell.groud,
-				Ground::Path(neighbor_dist) if neighbor_dist < dist_to_goal
-			) && matches!(
+	level.grid.get_mut(coords).unwrap().obj = Obj::Item { item };
+	if let Some(regrowth) = level.tree_regrowth {
+		level.stumps.push((coords, level.turn + regrowth));
+	}
+}
+
+/// Chopped trees grow back when it is time and nothing is in the way (or later otherwise).
+fn regrow_trees(level: &mut LevelState) {
+	let turn = level.turn;
+	let grid = &mut level.grid;
+	level.stumps.retain(|&(coords, regrowth_turn)| {
+		let cell = grid.get_mut(coords).unwrap();
+		if turn < regrowth_turn || !matches!(cell.obj, Obj::Empty) {
+			return true;
+		}
+		log::debug!("A tree grows back at {coords}");
+		cell.obj = Obj::Tree { chopped: false };
+		false
+	});
+}
+
+/// The player walks into a door, which opens (disappears) if they have a key of its color.
+fn open_door(level: &mut LevelState, coords: Coords, color: KeyColor) {
+	let keys = &mut level.inventory.keys;
+	let Some(index) = keys.iter().position(|&key| key == color) else {
+		return;
+	};
+	keys.remove(index);
+	level.grid.get_mut(coords).unwrap().obj = Obj::Empty;
+	log::debug!("The {} door at {coords} opens", color.name());
+}
+
+/// The unit controlled by the player does the given action (a stunned unit only gets over it),
+/// returns where it is after it (`None` if there is no unit left to act).
+fn player_move(level: &mut LevelState, dd: DxDy, action: PlayerAction) -> Option<Coords> {
+	let mut unit_coords = level.player_coords()?;
+	if let Obj::Player { stunned: stunned @ true } =
+		&mut level.grid.get_mut(unit_coords).unwrap().obj
+	{
+		*stunned = false;
+		return Some(unit_coords);
+	}
+	let coords = unit_coords;
+	let dst_coords = coords + dd;
+	match action {
+		PlayerAction::Move => {
+			if level
+				.grid
+				.get(dst_coords)
+				.is_some_and(|cell| !cell.groud.is_liquid())
+			{
+				if let Obj::Item { item } = level.grid.get(dst_coords).unwrap().obj {
+					*level.inventory.count_mut(item) += 1;
+					level.grid.get_mut(dst_coords).unwrap().obj = Obj::Empty;
+				}
+				if let Obj::Key { color } = level.grid.get(dst_coords).unwrap().obj {
+					level.inventory.keys.push(color);
+					level.grid.get_mut(dst_coords).unwrap().obj = Obj::Empty;
+				}
+				if let Obj::Door { color } = level.grid.get(dst_coords).unwrap().obj {
+					open_door(level, dst_coords, color);
+					return Some(unit_coords);
+				}
+				if let Obj::Tree { .. } = level.grid.get(dst_coords).unwrap().obj {
+					chop_tree(level, dst_coords);
+					return Some(unit_coords);
+				}
+				if !matches!(level.grid.get(dst_coords).unwrap().obj, Obj::Empty) {
+					let strength = balance::get().player_push_strength;
+					try_push(
+						&mut level.grid,
+						&mut level.stats,
+						dst_coords,
+						dd,
+						false,
+						strength,
+					);
+				}
+				if matches!(level.grid.get(dst_coords).unwrap().obj, Obj::Empty) {
+					level.grid.get_mut(coords).unwrap().obj = Obj::Empty;
+					level.grid.get_mut(dst_coords).unwrap().obj = Obj::Player { stunned: false };
+					unit_coords = dst_coords;
+				}
+			}
+		},
+		PlayerAction::PlaceTower { variant } => {
+			if can_place_tower(level, dst_coords) {
+				let mut tower = Obj::new_tower(variant);
+				if let (Obj::Tower { facing, .. }, Some(direction)) =
+					(&mut tower, Direction::of_step(dd))
+				{
+					*facing = direction;
+				}
+				level.grid.get_mut(dst_coords).unwrap().obj = tower;
+				if let Some(count) = &mut level.remaining_towers {
+					*count -= 1;
+				}
+			}
+		},
+		PlayerAction::PlaceWall => {
+			if level.remaining_walls.is_some_and(|count| count == 0) {
+				// No more walls to place.
+			} else if level
+				.grid
+				.get(dst_coords)
+				.is_some_and(|cell| matches!(cell.obj, Obj::Empty) && !cell.groud.is_liquid())
+			{
+				level.grid.get_mut(dst_coords).unwrap().obj = Obj::Wall { hp: balance::get().wall_hp };
+				if let Some(count) = &mut level.remaining_walls {
+					*count -= 1;
+				}
+			}
+		},
+		PlayerAction::PickUpTower => {
+			if level
+				.grid
+				.get(dst_coords)
+				.is_some_and(|cell| matches!(cell.obj, Obj::Tower { .. }))
+			{
+				level.grid.get_mut(dst_coords).unwrap().obj = Obj::Empty;
+				if let Some(count) = &mut level.remaining_towers {
+					*count += 1;
+				}
+			}
+		},
+		PlayerAction::MergeTower => {
+			if let Some(partner_coords) = merge_partner(&level.grid, dst_coords) {
+				let merged =
+					std::mem::replace(&mut level.grid.get_mut(dst_coords).unwrap().obj, Obj::Empty);
+				let Obj::Tower { ammo: merged_ammo, .. } = merged else {
+					unreachable!()
+				};
+				if let Obj::Tower { tier, ammo, .. } =
+					&mut level.grid.get_mut(partner_coords).unwrap().obj
+				{
+					*tier += 1;
+					// The merged tower keeps the most ammo of the two.
+					*ammo = (*ammo).max(merged_ammo);
+				}
+			}
+		},
+		PlayerAction::Reload => {
+			if let Some(Obj::Tower { variant, ammo: Some(ammo), .. }) =
+				level.grid.get_mut(dst_coords).map(|cell| &mut cell.obj)
+			{
+				*ammo = balance::get().tower_ammo(variant).unwrap_or(*ammo);
+			}
+		},
+		PlayerAction::UseItem { item } => {
+			if level.inventory.count(item) == 0 {
+				// Nothing to use.
+			} else if item == Item::Bomb {
+				if level
+					.grid
+					.get(dst_coords)
+					.is_some_and(|cell| matches!(cell.obj, Obj::Empty) && !cell.groud.is_liquid())
+				{
+					level.grid.get_mut(dst_coords).unwrap().obj =
+						Obj::Bomb { countdown: balance::get().bomb_countdown };
+					level.inventory.bombs -= 1;
+				}
+			} else if item == Item::TowerVoucher {
+				// Useless when the number of towers is not limited.
+				if let Some(count) = &mut level.remaining_towers {
+					*count += 1;
+					level.inventory.tower_vouchers -= 1;
+				}
+			}
+		},
+		PlayerAction::Attack => {
+			let can_be_hit = level
+				.grid
+				.get(dst_coords)
+				.is_some_and(|cell| match &cell.obj {
+					Obj::Enemy { variant, .. } => {
+						!cell.obj.is_out_of_reach() && !is_protected_from_shot(variant, dd)
+					},
+					_ => false,
+				});
+			// In crafting levels, rocks are broken into stone.
+			if level.crafting
+				&& level
+					.grid
+					.get(dst_coords)
+					.is_some_and(|cell| matches!(cell.obj, Obj::Rock))
+			{
+				level.grid.get_mut(dst_coords).unwrap().obj = Obj::Item { item: Item::Stone };
+			}
+			if can_be_hit {
+				damage_enemy(
+					&mut level.grid,
+					&mut level.stats,
+					dst_coords,
+					balance::get().player_attack_damage,
+					Killer::Player,
+				);
+			}
+		},
+		PlayerAction::SkipTurn | PlayerAction::SwitchUnit | PlayerAction::Craft { .. } => {},
+	}
+	Some(unit_coords)
+}
+
+/// Spend the resources that the recipe costs (if there are enough) for what it makes.
+fn craft(level: &mut LevelState, recipe: Recipe) {
+	let (wood, stone) = balance::get().craft_cost(recipe);
+	let inventory = &mut level.inventory;
+	if !level.crafting || inventory.wood < wood || inventory.stone < stone {
+		return;
+	}
+	inventory.wood -= wood;
+	inventory.stone -= stone;
+	log::debug!("Crafted a {}", recipe.name());
+	match recipe {
+		Recipe::Tower => *level.remaining_towers.get_or_insert(0) += 1,
+		Recipe::Bomb => inventory.bombs += 1,
+	}
+}
+
+/// The enemy at `coords` reaches the goal at `goal_coords` and damages it. If the goal survives
+/// the enemy disappears and `true` is returned, otherwise the enemy has to move onto the goal.
+fn enemy_hits_goal(
+	new_grid: &mut Grid<Cell>,
+	stats: &mut Stats,
+	coords: Coords,
+	goal_coords: Coords,
+) -> bool {
+	let Obj::Goal { hp } = &mut new_grid.get_mut(goal_coords).unwrap().obj else {
+		return false;
+	};
+	stats.turn_events.push(TurnEvent::GoalDamaged(goal_coords));
+	log::debug!("Enemy at {coords} hits the goal at {goal_coords} ({hp} hp)");
+	if *hp <= 1 {
+		return false;
+	}
+	*hp -= 1;
+	new_grid.get_mut(coords).unwrap().obj = Obj::Empty;
+	true
+}
+
+fn enemy_displacement(new_grid: &mut Grid<Cell>, stats: &mut Stats, coords: Coords) -> Coords {
+	if !matches!(new_grid.get(coords).unwrap().obj, Obj::Enemy { .. }) {
+		// Died on the way (on a mine).
+		return coords;
+	}
+	// We may move. We try to find an adjacent path tile that will get us loser
+	// to the goal (so its distance to the goal should be smaller that our
+	// current distance) (these distances are stored in the path tiles).
+	let dist_to_goal = match new_grid.get(coords).unwrap().groud {
+		Ground::Path(dist) => dist,
+		// Stuck in the lava it was pushed into.
+		Ground::Lava => return coords,
+		_ => panic!("Not a path?????"),
+	};
+	let directions = path_directions(new_grid, &stats.path_forks, coords);
+	if directions.len() > 1 {
+		*stats.path_forks.entry(coords).or_default() += 1;
+	}
+	// A rock, tree or flower that keeps us from moving, attacked if we can't move at all.
+	let mut obstacle = None;
+	for dd in directions {
+		let dst_coords = coords + dd;
+		// A wall in the way is attacked (walls make the distances longer, so this only happens
+		// when there is no way around them).
+		if new_grid.get(dst_coords).is_some_and(|cell| {
+			matches!(cell.groud, Ground::Path(neighbor_dist) if neighbor_dist < dist_to_goal)
+				&& matches!(cell.obj, Obj::Wall { .. })
+		}) {
+			let Obj::Wall { hp } = &mut new_grid.get_mut(dst_coords).unwrap().obj else {
+				unreachable!()
+			};
+			*hp -= 1;
+			log::trace!("Enemy at {coords} attacks the wall at {dst_coords} ({hp} hp left)");
+			if *hp == 0 {
+				new_grid.get_mut(dst_coords).unwrap().obj = Obj::Empty;
+			}
+			return coords;
+		}
+		if new_grid.get(dst_coords).is_some_and(|cell| {
+			matches!(
+				cell.groud,
+				Ground::Path(neighbor_dist) if neighbor_dist < dist_to_goal
+			) && matches!(
 				cell.obj,
 				Obj::Empty
-					| Obj::Goal | Obj::Tower { .. }
-					| Obj::Rock | Obj::Enemy { .. }
+					| Obj::Goal { .. }
+					| Obj::Tower { .. }
+					| Obj::Rock
+					| Obj::Enemy { .. }
 					| Obj::Bomb { .. }
 					| Obj::Player { .. }
+					| Obj::Mine
+					| Obj::Item { .. }
 			)
 		}) {
 			if matches!(
 				new_grid.get_mut(dst_coords).unwrap().obj,
 				Obj::Rock | Obj::Bomb { .. }
 			) {
-				try_push(new_grid, dst_coords, dd, false);
+				let strength = balance::get().enemy_push_strength;
+				try_push(new_grid, stats, dst_coords, dd, false, strength);
 			}
 			if matches!(new_grid.get_mut(dst_coords).unwrap().obj, Obj::Enemy { .. }) {
-				enemy_displacement(new_grid, dst_coords);
+				enemy_displacement(new_grid, stats, dst_coords);
 			}
 			if !matches!(
 				new_grid.get_mut(dst_coords).unwrap().obj,
 				Obj::Rock | Obj::Enemy { .. } | Obj::Bomb { .. }
 			) {
+				stats.enemy_moves.push((coords, dst_coords));
+				log::trace!("Enemy moves from {coords} to {dst_coords}");
+				if enemy_hits_goal(new_grid, stats, coords, dst_coords) {
+					return dst_coords;
+				}
+				// Enemies don't see the mines.
+				let steps_on_mine = matches!(new_grid.get(dst_coords).unwrap().obj, Obj::Mine);
 				new_grid.get_mut(dst_coords).unwrap().obj =
 					std::mem::replace(&mut new_grid.get_mut(coords).unwrap().obj, Obj::Empty);
+				if steps_on_mine {
+					let hp = damage_enemy(
+						new_grid,
+						stats,
+						dst_coords,
+						balance::get().mine_damage,
+						Killer::Tower(Tower::Miner),
+					);
+					log::debug!("Enemy steps on the mine at {dst_coords} ({hp} hp left)");
+				}
+				// Protected enemies face the way they last walked.
 				if let Obj::Enemy { variant: Enemy::Protected { direction, .. }, .. } =
 					&mut new_grid.get_mut(dst_coords).unwrap().obj
 				{
-					match dd {
-						DxDy { dx: 0, dy: -1 } => *direction = Direction::North,
-						DxDy { dx: 1, dy: 0 } => *direction = Direction::East,
-						DxDy { dx: 0, dy: 1 } => *direction = Direction::South,
-						DxDy { dx: -1, dy: 0 } => *direction = Direction::West,
-						DxDy { dx: 0, dy: 0 } => {},
-						_ => unimplemented!(),
-					}
+					*direction = Direction::of_step(dd).unwrap_or(*direction);
 				}
 				return dst_coords;
 			}
+			if matches!(new_grid.get(dst_coords).unwrap().obj, Obj::Rock) {
+				obstacle = Some(dst_coords);
+			}
 			break;
 		}
+		if new_grid
+			.get(dst_coords)
+			.is_some_and(|cell| matches!(cell.obj, Obj::Tree { .. } | Obj::Flower { .. }))
+		{
+			obstacle.get_or_insert(dst_coords);
+		}
+	}
+	if let Some(obstacle) = obstacle {
+		enemy_attacks_obstacle(new_grid, stats, coords, obstacle);
 	}
 	coords
 }
 
-fn enemies_move(grid: &mut Grid<Cell>) {
+/// An enemy that can't get past a rock (that it can't push), a tree or a flower on its way
+/// hits it, and breaks it after a few hits, so that blocking the path doesn't stop the enemies
+/// forever.
+fn enemy_attacks_obstacle(
+	new_grid: &mut Grid<Cell>,
+	stats: &mut Stats,
+	coords: Coords,
+	obstacle: Coords,
+) {
+	let hits = stats.obstacle_hits.entry(obstacle).or_default();
+	*hits += 1;
+	log::trace!("Enemy at {coords} attacks the obstacle at {obstacle} ({hits} hits)");
+	if *hits >= balance::get().obstacle_hits {
+		stats.obstacle_hits.remove(&obstacle);
+		new_grid.get_mut(obstacle).unwrap().obj = Obj::Empty;
+	}
+}
+
+/// The directions that get closer to the goal from a path tile, starting with the one the
+/// next enemy to leave it takes (see `Stats::path_forks`).
+fn path_directions(
+	grid: &Grid<Cell>,
+	path_forks: &HashMap<Coords, usize>,
+	coords: Coords,
+) -> Vec<DxDy> {
+	let Some(&Cell { groud: Ground::Path(dist_to_goal), .. }) = grid.get(coords) else {
+		return vec![];
+	};
+	let mut directions: Vec<DxDy> = DxDy::the_4_directions()
+		.filter(|&dd| {
+			grid
+				.get(coords + dd)
+				.is_some_and(|cell| matches!(cell.groud, Ground::Path(dist) if dist < dist_to_goal))
+		})
+		.collect();
+	if !directions.is_empty() {
+		let left = path_forks.get(&coords).copied().unwrap_or(0) % directions.len();
+		directions.rotate_left(left);
+	}
+	directions
+}
+
+/// Protected enemies appear facing their first step on the path (they then face the way they
+/// last walked).
+fn face_the_path(grid: &mut Grid<Cell>, stats: &Stats, coords: Coords) {
+	let Some(next) = next_path_step(grid, stats, coords) else {
+		return;
+	};
+	if let Obj::Enemy { variant: Enemy::Protected { direction, .. }, .. } =
+		&mut grid.get_mut(coords).unwrap().obj
+	{
+		*direction = Direction::of_step(next - coords).unwrap_or(*direction);
+	}
+}
+
+/// The next tile on the path towards the goal, whatever is on it.
+fn next_path_step(grid: &Grid<Cell>, stats: &Stats, coords: Coords) -> Option<Coords> {
+	path_directions(grid, &stats.path_forks, coords)
+		.first()
+		.map(|&dd| coords + dd)
+}
+
+/// Paves the grass tile next to the paver at `coords` in the direction of the goal
+/// (favoring the axis on which the goal is the farthest) and moves the paver on it.
+/// The distances to the goal are then recomputed, as the new path may reroute everyone.
+fn paver_paves(new_grid: &mut Grid<Cell>, coords: Coords) {
+	let Some(goal) = new_grid
+		.dims
+		.iter()
+		.find(|&coords| matches!(new_grid.get(coords).unwrap().obj, Obj::Goal { .. }))
+	else {
+		return;
+	};
+	let DxDy { dx, dy } = goal - coords;
+	let horizontal = DxDy::from((dx.signum(), 0));
+	let vertical = DxDy::from((0, dy.signum()));
+	let mut directions = if dx.abs() >= dy.abs() {
+		[horizontal, vertical]
+	} else {
+		[vertical, horizontal]
+	}
+	.into_iter()
+	.filter(|&dd| dd != DxDy::from((0, 0)));
+	let can_pave = |coords: Coords| {
+		new_grid
+			.get(coords)
+			.is_some_and(|cell| matches!(cell.groud, Ground::Grass) && matches!(cell.obj, Obj::Empty))
+	};
+	if let Some(dd) = directions.find(|&dd| can_pave(coords + dd)) {
+		let paver = std::mem::replace(&mut new_grid.get_mut(coords).unwrap().obj, Obj::Empty);
+		let paved = new_grid.get_mut(coords + dd).unwrap();
+		paved.groud = Ground::Path(-1);
+		paved.obj = paver;
+		compute_distance(new_grid);
+	}
+}
+
+/// What a stuner at `coords` sees in each direction: the tiles in its line of sight, and the
+/// player or tower at the end of it (that gets stunned) if any.
+fn stuner_sight(grid: &Grid<Cell>, coords: Coords) -> Vec<(Vec<Coords>, Option<Coords>)> {
+	let mut sight = vec![];
+	for dd in DxDy::the_4_directions() {
+		let mut tiles = vec![];
+		let mut target = None;
+		let mut coords_possible_target = coords;
+		loop {
+			coords_possible_target += dd;
+			let Some(cell) = grid.get(coords_possible_target) else {
+				break;
+			};
+			if matches!(cell.obj, Obj::Player { .. } | Obj::Tower { .. }) {
+				// An thing is in a straight line of sight, we shoot it.
+				target = Some(coords_possible_target);
+				break;
+			}
+			if cell.obj.blocks_line_of_sight() {
+				// View is blocked by some non-targettable object.
+				break;
+			}
+			tiles.push(coords_possible_target);
+		}
+		sight.push((tiles, target));
+	}
+	sight
+}
+
+/// Where the enemies would step if the next turn was played now (from, to),
+/// without changing anything.
+fn predict_enemy_moves(level: &LevelState) -> Vec<(Coords, Coords)> {
+	let mut grid = level.grid.clone();
+	let mut stats = level.stats.clone();
+	enemies_move(&mut grid, &mut stats);
+	stats.enemy_moves
+}
+
+fn enemies_move(grid: &mut Grid<Cell>, stats: &mut Stats) {
+	stats.enemy_moves.clear();
+	log::trace!("Enemies move");
+	// Walls may have been placed or destroyed since the last turn.
+	compute_distance(grid);
+	// Obstacles may have been pushed away.
+	stats.obstacle_hits.retain(|&coords, _| {
+		matches!(
+			grid.get(coords).unwrap().obj,
+			Obj::Rock | Obj::Tree { .. } | Obj::Flower { .. }
+		)
+	});
 	let mut new_grid = grid.clone();
+	// Pavers on a path that doesn't lead to the goal (which have no distance to the goal, so
+	// they are not moved below) pave their way.
+	for coords in grid.dims.iter() {
+		let cell = grid.get(coords).unwrap();
+		if matches!(cell.obj, Obj::Enemy { variant: Enemy::Paver, .. })
+			&& matches!(cell.groud, Ground::Path(-1))
+		{
+			paver_paves(&mut new_grid, coords);
+		}
+	}
 	// In order for enemies to try to move in an efficient way, enemies closer to the goal
 	// (in distance on the path) move in priority (so that two adjacent enemies one before the
 	// other may both move during one turn, instead of the enemy behind trying to move first but
 	// being blocked by the other enemy just in front of it).
-	// One way to do that is to iterate in increasing order over all the possible distances
-	// that enemies can be to the goal, and for each possible distance we move all the enemies
+	// One way to do that is to iterate in increasing order over all the distances
+	// that enemies are at from the goal, and for each distance we move all the enemies
 	// that are at that distance. This is what we do here.
-	for dist in 0..grid.dims.area() {
-		let mut found_one = false;
+	let mut enemy_dists: Vec<i32> = grid
+		.dims
+		.iter()
+		.filter_map(|coords| match grid.get(coords).unwrap() {
+			Cell { obj: Obj::Enemy { .. }, groud: Ground::Path(dist), .. } if *dist >= 0 => {
+				Some(*dist)
+			},
+			_ => None,
+		})
+		.collect();
+	enemy_dists.sort();
+	enemy_dists.dedup();
+	for dist in enemy_dists {
 		for coords in grid.dims.iter() {
 			let dist_to_goal = if let Ground::Path(dist) = grid.get(coords).unwrap().groud {
-				found_one = true;
 				Some(dist)
 			} else {
 				None
@@ -439,44 +2562,19 @@ fn enemies_move(grid: &mut Grid<Cell>) {
 				}
 				match &mut grid.get_mut(coords).unwrap().obj {
 					Obj::Enemy {
-						variant: Enemy::Basic | Enemy::Tank | Enemy::Protected { .. }, ..
+						variant:
+							Enemy::Basic | Enemy::Tank | Enemy::Protected { .. } | Enemy::Shielded { .. },
+						..
 					} => {
-						enemy_displacement(&mut new_grid, coords);
+						enemy_displacement(&mut new_grid, stats, coords);
 					},
 					Obj::Enemy { variant: Enemy::Speeeeed, .. } => {
-						let new_coords = enemy_displacement(&mut new_grid, coords);
-						enemy_displacement(&mut new_grid, new_coords);
+						let new_coords = enemy_displacement(&mut new_grid, stats, coords);
+						enemy_displacement(&mut new_grid, stats, new_coords);
 					},
 					Obj::Enemy { variant: Enemy::Stuner, .. } => {
-						//stun
-						for dd in DxDy::the_4_directions() {
-							let mut coords_possible_target = coords;
-							loop {
-								coords_possible_target += dd;
-								if grid.get(coords_possible_target).is_some_and(|cell| {
-									matches!(cell.obj, Obj::Player { .. } | Obj::Tower { .. })
-								}) {
-									// An thing is in a straight line of sight, we shoot it.
-									if let Obj::Player { stunned } | Obj::Tower { stunned, .. } =
-										&mut new_grid.get_mut(coords_possible_target).unwrap().obj
-									{
-										*stunned = true;
-									} else {
-										unreachable!()
-									};
-									break;
-								}
-								if grid.get(coords_possible_target).is_none()
-									|| grid
-										.get(coords_possible_target)
-										.is_some_and(|cell| !matches!(cell.obj, Obj::Empty))
-								{
-									// View is blocked by some non-targettable object.
-									break;
-								}
-							}
-						}
-						enemy_displacement(&mut new_grid, coords);
+						stun_in_sight(grid, &mut new_grid, coords);
+						enemy_displacement(&mut new_grid, stats, coords);
 					},
 					Obj::Enemy { variant: Enemy::Eater, .. } => {
 						let eat = |new_grid: &mut Grid<Cell>, coords: Coords| {
@@ -492,53 +2590,366 @@ fn enemies_move(grid: &mut Grid<Cell>) {
 							}
 						};
 						eat(&mut new_grid, coords);
-						let new_coords = enemy_displacement(&mut new_grid, coords);
+						let new_coords = enemy_displacement(&mut new_grid, stats, coords);
 						eat(&mut new_grid, new_coords);
 					},
+					Obj::Enemy { variant: Enemy::Broodmother { countdown }, .. } => {
+						let countdown = *countdown;
+						if let Obj::Enemy {
+							variant: Enemy::Broodmother { countdown: new_countdown },
+							..
+						} = &mut new_grid.get_mut(coords).unwrap().obj
+						{
+							*new_countdown = countdown
+								.checked_sub(1)
+								.unwrap_or(BROODMOTHER_SPAWN_PERIOD - 1);
+						}
+						// It moves every other turn, starting on the turn after a spawn.
+						if countdown == 0 {
+							spawn_minion(grid, &mut new_grid, coords);
+						} else if (BROODMOTHER_SPAWN_PERIOD - 1 - countdown).is_multiple_of(2) {
+							enemy_displacement(&mut new_grid, stats, coords);
+						}
+					},
+					Obj::Enemy { variant: boss @ Enemy::Boss { .. }, .. } => {
+						let boss = boss.clone();
+						let Enemy::Boss { countdown, .. } = boss else {
+							unreachable!()
+						};
+						let behaviors = boss.boss_behaviors();
+						if behaviors.contains(&BossBehavior::Stun) {
+							stun_in_sight(grid, &mut new_grid, coords);
+						}
+						if behaviors.contains(&BossBehavior::Summon) {
+							if let Obj::Enemy {
+								variant: Enemy::Boss { countdown: new_countdown, .. },
+								..
+							} = &mut new_grid.get_mut(coords).unwrap().obj
+							{
+								*new_countdown = countdown
+									.checked_sub(1)
+									.unwrap_or(BROODMOTHER_SPAWN_PERIOD - 1);
+							}
+							if countdown == 0 {
+								spawn_minion(grid, &mut new_grid, coords);
+							}
+						}
+						let new_coords = enemy_displacement(&mut new_grid, stats, coords);
+						if behaviors.contains(&BossBehavior::Fast) {
+							enemy_displacement(&mut new_grid, stats, new_coords);
+						}
+					},
+					Obj::Enemy { variant: Enemy::Necromancer, .. } => {
+						// Like the minions of broodmothers, the revived enemy must not be found
+						// by this loop, so it has to be on a tile that is free in both grids.
+						let is_free = |grid: &Grid<Cell>, corpse_coords: Coords| {
+							grid.get(corpse_coords).is_some_and(|cell| {
+								matches!(cell.obj, Obj::Empty) && matches!(cell.groud, Ground::Path(_))
+							})
+						};
+						let distance = |corpse_coords: Coords| {
+							let DxDy { dx, dy } = corpse_coords - coords;
+							dx.abs() + dy.abs()
+						};
+						let closest_corpse = stats
+							.corpses
+							.iter()
+							.enumerate()
+							.filter(|(_, (corpse_coords, _))| {
+								distance(*corpse_coords) <= NECROMANCER_RANGE
+									&& is_free(grid, *corpse_coords)
+									&& is_free(&new_grid, *corpse_coords)
+							})
+							.min_by_key(|(_, (corpse_coords, _))| distance(*corpse_coords))
+							.map(|(index, _)| index);
+						if let Some(index) = closest_corpse {
+							let (corpse_coords, variant) = stats.corpses.remove(index);
+							let hp = (variant.hp_max() / 2).max(1);
+							log::debug!(
+								"Necromancer at {coords} revives a {} at {corpse_coords}",
+								variant.name()
+							);
+							new_grid.get_mut(corpse_coords).unwrap().obj =
+								Obj::Enemy { variant, hp, effects: StatusEffects::default() };
+						} else {
+							enemy_displacement(&mut new_grid, stats, coords);
+						}
+					},
+					Obj::Enemy { variant: Enemy::Burrower { underground }, .. } => {
+						let underground = *underground;
+						let set_underground = |new_grid: &mut Grid<Cell>, coords: Coords, turns| {
+							if let Obj::Enemy { variant: Enemy::Burrower { underground }, .. } =
+								&mut new_grid.get_mut(coords).unwrap().obj
+							{
+								*underground = turns;
+							}
+						};
+						let next = next_path_step(&new_grid, stats, coords);
+						if underground == 0 {
+							let blocked = next.is_some_and(|next| {
+								matches!(
+									new_grid.get(next).unwrap().obj,
+									Obj::Tower { .. } | Obj::Rock | Obj::Wall { .. }
+								)
+							});
+							if blocked {
+								log::debug!("Burrower at {coords} goes underground");
+								set_underground(&mut new_grid, coords, BURROW_TURNS);
+							} else {
+								enemy_displacement(&mut new_grid, stats, coords);
+							}
+						} else if underground > 1 {
+							set_underground(&mut new_grid, coords, underground - 1);
+						} else {
+							// Emerge on the other side of the obstacle (if there is room there,
+							// otherwise wait underground).
+							let far_side = next.and_then(|next| next_path_step(&new_grid, stats, next));
+							if let Some(far_side) = far_side.filter(|&far_side| {
+								matches!(
+									new_grid.get(far_side).unwrap().obj,
+									Obj::Empty | Obj::Goal { .. } | Obj::Player { .. }
+								)
+							}) {
+								set_underground(&mut new_grid, coords, 0);
+								stats.enemy_moves.push((coords, far_side));
+								if !enemy_hits_goal(&mut new_grid, stats, coords, far_side) {
+									new_grid.get_mut(far_side).unwrap().obj = std::mem::replace(
+										&mut new_grid.get_mut(coords).unwrap().obj,
+										Obj::Empty,
+									);
+								}
+							}
+						}
+					},
 					_ => {
-						enemy_displacement(&mut new_grid, coords);
+						enemy_displacement(&mut new_grid, stats, coords);
 					},
 				}
 			}
 		}
-		// Didn't find any tile with distance `dist` (so there wont be at any greater distance either),
-		// thus we stop iterating.
-		if !found_one {
-			break;
-		}
 	}
 	*grid = new_grid;
 }
 
-fn bomb_move(grid: &mut Grid<Cell>) {
+/// A stuner (or a boss that stuns) at `coords` stuns the player or tower it sees at the end
+/// of each of its lines of sight.
+fn stun_in_sight(grid: &Grid<Cell>, new_grid: &mut Grid<Cell>, coords: Coords) {
+	for (_, target) in stuner_sight(grid, coords) {
+		if let Some(Obj::Player { stunned } | Obj::Tower { stunned, .. }) =
+			target.map(|target| &mut new_grid.get_mut(target).unwrap().obj)
+		{
+			*stunned = true;
+		}
+	}
+}
+
+/// A broodmother (or a boss that summons) at `coords` spawns a basic enemy behind itself.
+fn spawn_minion(grid: &Grid<Cell>, new_grid: &mut Grid<Cell>, coords: Coords) {
+	// The minion spawns behind (farther from the goal), on a tile that is free in both grids so
+	// that `enemies_move` (that looks for enemies in the old grid) doesn't make it move during
+	// the turn it is born.
+	let spawn_coords = DxDy::the_4_directions()
+		.map(|dd| coords + dd)
+		.filter_map(|neighbor| {
+			let is_free = |grid: &Grid<Cell>| {
+				grid
+					.get(neighbor)
+					.is_some_and(|cell| matches!(cell.obj, Obj::Empty))
+			};
+			match grid.get(neighbor)?.groud {
+				Ground::Path(dist) if is_free(grid) && is_free(new_grid) => Some((dist, neighbor)),
+				_ => None,
+			}
+		})
+		.max_by_key(|&(dist, _)| dist);
+	if let Some((_, spawn_coords)) = spawn_coords {
+		log::debug!("Enemy at {coords} spawns a minion at {spawn_coords}");
+		new_grid.get_mut(spawn_coords).unwrap().obj = Obj::new_enemy(Enemy::Basic);
+	}
+}
+
+/// The enemies that came out of a portal last turn are out of it now, and can be shot.
+fn enemies_emerge(grid: &mut Grid<Cell>) {
 	for coords in grid.dims.iter() {
-		if let Obj::Bomb { countdown: 0 } = grid.get(coords).unwrap().obj {
-			grid.get_mut(coords).unwrap().obj = Obj::Empty;
-			for dd in DxDy::the_4_directions() {
-				let coords_explodes = coords + dd;
-				if !grid.dims.contains(coords_explodes) {
-					continue;
-				}
-				let is_dead =
-					if let Obj::Enemy { hp, .. } = &mut grid.get_mut(coords_explodes).unwrap().obj {
-						*hp = hp.saturating_sub(4);
-						*hp == 0
+		if let Obj::Enemy { effects, .. } = &mut grid.get_mut(coords).unwrap().obj {
+			effects.emerging = false;
+		}
+	}
+}
+
+/// Bosses whose HP dropped to the threshold of their next phases (see `Balance::boss_phases`)
+/// enter them.
+fn bosses_change_phase(grid: &mut Grid<Cell>, stats: &mut Stats) {
+	let phases = balance::get().boss_phases();
+	for coords in grid.dims.iter() {
+		let Obj::Enemy { variant: Enemy::Boss { phase, .. }, hp, .. } =
+			&mut grid.get_mut(coords).unwrap().obj
+		else {
+			continue;
+		};
+		let reached = phases
+			.iter()
+			.filter(|&&(_, threshold)| *hp <= threshold)
+			.count() as u32;
+		while *phase < reached {
+			let behavior = phases[*phase as usize].0;
+			log::debug!("Boss at {coords} enters its {} phase", behavior.name());
+			stats
+				.turn_events
+				.push(TurnEvent::BossPhase { at: coords, behavior });
+			*phase += 1;
+		}
+	}
+}
+
+/// Conveyor belts move what is on them (but goals) one tile their way, onto empty tiles that
+/// are not water nor lava (enemies only onto the path or into lava). Whatever is in the grid
+/// order moves first, and what was blocked tries again once the others moved, so that a line of
+/// objects on a belt moves as one. Returns the moves (from, to).
+fn conveyors_move(grid: &mut Grid<Cell>, stats: &mut Stats) -> Vec<(Coords, Coords)> {
+	let mut waiting: Vec<(Coords, Direction)> = grid
+		.dims
+		.iter()
+		.filter_map(|coords| {
+			let cell = grid.get(coords).unwrap();
+			let direction = cell.conveyor?;
+			(!matches!(cell.obj, Obj::Empty | Obj::Goal { .. })).then_some((coords, direction))
+		})
+		.collect();
+	let mut moves = vec![];
+	loop {
+		let waiting_before = waiting.len();
+		waiting.retain(|&(coords, direction)| {
+			let dst_coords = coords + direction.step();
+			let is_enemy = matches!(grid.get(coords).unwrap().obj, Obj::Enemy { .. });
+			let can_move = grid.get(dst_coords).is_some_and(|cell| {
+				matches!(cell.obj, Obj::Empty)
+					&& if is_enemy {
+						// Enemies stay on the path, unless they are carried into lava.
+						matches!(cell.groud, Ground::Path(_) | Ground::Lava)
 					} else {
-						matches!(
-							grid.get(coords_explodes).unwrap().obj,
-							Obj::Player { .. } | Obj::Tower { .. } | Obj::Flower { .. }
-						)
-					};
-				if is_dead {
-					grid.get_mut(coords_explodes).unwrap().obj = Obj::Empty;
-				}
+						!cell.groud.is_liquid()
+					}
+			});
+			if can_move {
+				grid.get_mut(dst_coords).unwrap().obj =
+					std::mem::replace(&mut grid.get_mut(coords).unwrap().obj, Obj::Empty);
+				stats
+					.turn_events
+					.push(TurnEvent::Slid { from: coords, to: dst_coords });
+				moves.push((coords, dst_coords));
 			}
-		} else if let Obj::Bomb { countdown } = &mut grid.get_mut(coords).unwrap().obj {
+			!can_move
+		});
+		if waiting.len() == waiting_before {
+			return moves;
+		}
+	}
+}
+
+/// Spike traps hurt the enemies on them and destroy anything else (the player, and what was
+/// pushed onto them), but not the goals.
+fn spikes_strike(grid: &mut Grid<Cell>, stats: &mut Stats) {
+	let damage = balance::get().spike_damage;
+	for coords in grid.dims.iter() {
+		let cell = grid.get_mut(coords).unwrap();
+		if !cell.spikes {
+			continue;
+		}
+		match &mut cell.obj {
+			Obj::Enemy { .. } => {
+				let hp = damage_enemy(grid, stats, coords, damage, Killer::Spikes);
+				log::debug!("Spikes at {coords} hit an enemy ({hp} hp left)");
+			},
+			Obj::Empty | Obj::Goal { .. } => {},
+			_ => {
+				log::debug!("Spikes at {coords} destroy what was on them");
+				cell.obj = Obj::Empty;
+			},
+		}
+	}
+}
+
+/// Bombs at the end of their countdown explode, and make the bombs next to them explode too.
+fn bomb_move(grid: &mut Grid<Cell>, stats: &mut Stats, friendly_fire: bool) {
+	let exploding: Vec<Coords> = grid
+		.dims
+		.iter()
+		.filter(|&coords| matches!(grid.get(coords).unwrap().obj, Obj::Bomb { countdown: 0 }))
+		.collect();
+	explode_bombs(grid, stats, friendly_fire, exploding);
+	for coords in grid.dims.iter() {
+		if let Obj::Bomb { countdown } = &mut grid.get_mut(coords).unwrap().obj {
 			*countdown -= 1;
 		}
 	}
 }
 
+/// The bombs at `exploding` explode, and make the bombs next to them explode too. Without
+/// `friendly_fire`, explosions don't hurt the player nor their towers and walls.
+fn explode_bombs(
+	grid: &mut Grid<Cell>,
+	stats: &mut Stats,
+	friendly_fire: bool,
+	mut exploding: Vec<Coords>,
+) {
+	while let Some(coords) = exploding.pop() {
+		if !matches!(grid.get(coords).unwrap().obj, Obj::Bomb { .. }) {
+			// Already exploded in the chain reaction.
+			continue;
+		}
+		grid.get_mut(coords).unwrap().obj = Obj::Empty;
+		stats.turn_events.push(TurnEvent::Explosion(coords));
+		log::debug!("Bomb explodes at {coords}");
+		for dd in DxDy::the_4_directions() {
+			let coords_explodes = coords + dd;
+			if !grid.dims.contains(coords_explodes) {
+				continue;
+			}
+			let is_destroyed = match &grid.get(coords_explodes).unwrap().obj {
+				Obj::Enemy { .. } => {
+					let damage = balance::get().bomb_damage;
+					let hp = damage_enemy(grid, stats, coords_explodes, damage, Killer::Bomb);
+					log::trace!("Explosion hits the enemy at {coords_explodes} ({hp} hp left)");
+					false
+				},
+				Obj::Bomb { .. } => {
+					exploding.push(coords_explodes);
+					false
+				},
+				Obj::Player { .. } | Obj::Tower { .. } | Obj::Wall { .. } => friendly_fire,
+				Obj::Flower { .. } => true,
+				_ => false,
+			};
+			if is_destroyed {
+				grid.get_mut(coords_explodes).unwrap().obj = Obj::Empty;
+			}
+		}
+	}
+}
+
+/// Lava hurts the enemies pushed into it every turn (see `Balance::lava_damage`), and the
+/// bombs that fell into it explode.
+fn lava_burns(grid: &mut Grid<Cell>, stats: &mut Stats, friendly_fire: bool) {
+	let damage = balance::get().lava_damage;
+	let mut exploding = vec![];
+	for coords in grid.dims.iter() {
+		let cell = grid.get_mut(coords).unwrap();
+		if !matches!(cell.groud, Ground::Lava) {
+			continue;
+		}
+		match &mut cell.obj {
+			Obj::Enemy { .. } => {
+				let hp = damage_enemy(grid, stats, coords, damage, Killer::Lava);
+				log::debug!("Lava at {coords} burns an enemy ({hp} hp left)");
+			},
+			Obj::Bomb { .. } => exploding.push(coords),
+			_ => {},
+		}
+	}
+	explode_bombs(grid, stats, friendly_fire, exploding);
+}
+
 fn flowers_move(grid: &mut Grid<Cell>) {
 	for coords in grid.dims.iter() {
 		if grid
@@ -612,11 +3023,14 @@ fn flowers_move(grid: &mut Grid<Cell>) {
 	}
 }
 
-fn towers_move(grid: &mut Grid<Cell>) {
+/// With `knockback`, the enemies hit by basic towers are pushed back along the path.
+fn towers_move(grid: &mut Grid<Cell>, stats: &mut Stats, knockback: bool, weather: Weather) {
+	log::trace!("Towers move");
 	for coords in grid.dims.iter() {
 		if grid.get(coords).is_some_and(|cell| {
 			matches!(cell.obj, Obj::Tower { stunned: false, .. })
 				&& !matches!(cell.obj, Obj::Tower { variant: Tower::TotalEnergy, .. })
+				&& !matches!(cell.obj, Obj::Tower { ammo: Some(0), .. })
 		}) {
 			let piercing = grid
 				.get(coords)
@@ -642,57 +3056,115 @@ fn towers_move(grid: &mut Grid<Cell>) {
 			let bombing = grid
 				.get(coords)
 				.is_some_and(|cell| matches!(cell.obj, Obj::Tower { variant: Tower::Unabomber, .. }));
-			for dd in DxDy::the_4_directions() {
-				let mut coords_possible_target = coords;
-				loop {
-					coords_possible_target += dd;
-					if grid
-						.get(coords_possible_target)
-						.is_some_and(|cell| matches!(cell.obj, Obj::Enemy { .. }))
-					{
-						// An enemy is in a straight line of sight, we shoot it.
-						let is_protected = if let Obj::Enemy {
-							variant: Enemy::Protected { direction, protection },
-							..
-						} = grid.get(coords_possible_target).unwrap().obj
-						{
-							let shot_comming_from_dir = match dd {
-								DxDy { dx: 0, dy: -1 } => Direction::South,
-								DxDy { dx: 1, dy: 0 } => Direction::West,
-								DxDy { dx: 0, dy: 1 } => Direction::North,
-								DxDy { dx: -1, dy: 0 } => Direction::East,
-								_ => panic!("aa help"),
-							};
-							!protection.is_hurt_by_shot(direction, shot_comming_from_dir)
+			let (variant, priority, tier, facing) =
+				if let Obj::Tower { variant, priority, tier, facing, .. } =
+					&grid.get(coords).unwrap().obj
+				{
+					(variant.clone(), *priority, *tier, *facing)
+				} else {
+					unreachable!()
+				};
+			if variant == Tower::Sniper {
+				sniper_move(
+					grid,
+					coords,
+					stats,
+					weather.tower_range(Tower::Sniper.range()),
+				);
+				continue;
+			}
+			if variant == Tower::Miner {
+				miner_move(grid, coords);
+				continue;
+			}
+			if variant == Tower::Magnet {
+				magnet_move(
+					grid,
+					stats,
+					coords,
+					weather.tower_range(Tower::Magnet.range()),
+				);
+				continue;
+			}
+			let range = weather.tower_range(variant.range());
+			let mut fired = false;
+			let directions: Vec<DxDy> = if variant == Tower::Cannon {
+				variant.directions_facing(facing)
+			} else if priority == TargetPriority::All {
+				DxDy::the_4_directions().collect()
+			} else {
+				prioritized_target_direction(grid, coords, range, piercing, priority)
+					.into_iter()
+					.collect()
+			};
+			for dd in directions {
+				let mut coords_possible_target = coords;
+				for _ in 0..range {
+					coords_possible_target += dd;
+					if grid.get(coords_possible_target).is_some_and(|cell| {
+						matches!(cell.obj, Obj::Enemy { .. }) && !cell.obj.is_out_of_reach()
+					}) {
+						// An enemy is in a straight line of sight, we shoot it.
+						let is_protected = if let Obj::Enemy { variant, .. } =
+							&grid.get(coords_possible_target).unwrap().obj
+						{
+							is_protected_from_shot(variant, dd)
 						} else {
-							false
+							unreachable!()
 						};
 						if !is_protected {
+							fired = true;
 							if !bombing {
-								let is_dead = if let Obj::Enemy { hp, .. } =
-									&mut grid.get_mut(coords_possible_target).unwrap().obj
-								{
-									*hp -= 1;
-									*hp == 0
-								} else {
-									unreachable!()
-								};
-								if is_dead {
-									grid.get_mut(coords_possible_target).unwrap().obj = Obj::Empty;
+								if variant == Tower::Flamethrower {
+									if let Obj::Enemy { effects, .. } =
+										&mut grid.get_mut(coords_possible_target).unwrap().obj
+									{
+										// Burns don't stack, a new one only rekindles the fire.
+										effects.burn = effects.burn.max(balance::get().burn_turns);
+									}
+								}
+								stats
+									.turn_events
+									.push(TurnEvent::Shot { from: coords, to: coords_possible_target });
+								let damage = balance::get().tower_damage(&variant) * tier;
+								let hp = damage_enemy(
+									grid,
+									stats,
+									coords_possible_target,
+									damage,
+									Killer::Tower(variant.clone()),
+								);
+								log::debug!(
+									"{} tower at {coords} shoots {coords_possible_target} \
+									for {damage} damage ({hp} hp left)",
+									variant.name()
+								);
+								if hp > 0 && knockback && variant == Tower::Basic {
+									knock_back(grid, stats, coords_possible_target, dd, tier);
 								}
 							}
 							if pushing {
 								for dd in DxDy::the_4_directions() {
 									let coords_pushed = coords_possible_target + dd;
-									try_push(grid, coords_pushed, dd, true);
+									let balance = balance::get();
+									let strength = balance.pusher_push_strength * tier;
+									try_push(
+										grid,
+										stats,
+										coords_pushed,
+										dd,
+										balance.push_enemies,
+										strength,
+									);
 								}
 							}
 							if bombing {
 								let bomb_coords = coords_possible_target - dd;
 								if matches!(grid.get(bomb_coords).unwrap().obj, Obj::Empty)
-									&& !matches!(grid.get(bomb_coords).unwrap().groud, Ground::Water)
+									&& !grid.get(bomb_coords).unwrap().groud.is_liquid()
 								{
-									grid.get_mut(bomb_coords).unwrap().obj = Obj::Bomb { countdown: 3 };
+									grid.get_mut(bomb_coords).unwrap().obj =
+										Obj::Bomb { countdown: balance::get().bomb_countdown };
 								}
 							}
 							if !piercing {
@@ -702,13 +3174,16 @@ fn towers_move(grid: &mut Grid<Cell>) {
 					} else if grid.get(coords_possible_target).is_none()
 						|| grid
 							.get(coords_possible_target)
-							.is_some_and(|cell| !matches!(cell.obj, Obj::Empty))
+							.is_some_and(|cell| cell.obj.blocks_line_of_sight())
 					{
 						// View is blocked by some non-targettable object.
 						break;
 					}
 				}
 			}
+			if fired {
+				spend_ammo(grid, coords);
+			}
 		} else if let Obj::Tower { stunned: stunned @ true, .. } =
 			&mut grid.get_mut(coords).unwrap().obj
 		{
@@ -717,34 +3192,501 @@ fn towers_move(grid: &mut Grid<Cell>) {
 	}
 }
 
+/// Damage the enemy at `coords` (see `hurt_enemy`), which dies if that was its last HP (see
+/// `enemy_dies`). Every source of damage goes through here. Returns the HP it has left.
+fn damage_enemy(
+	grid: &mut Grid<Cell>,
+	stats: &mut Stats,
+	coords: Coords,
+	damage: u32,
+	killer: Killer,
+) -> u32 {
+	let Obj::Enemy { variant, hp, .. } = &mut grid.get_mut(coords).unwrap().obj else {
+		return 0;
+	};
+	hurt_enemy(variant, hp, damage);
+	let hp = *hp;
+	stats.record_hit(coords);
+	if hp == 0 {
+		enemy_dies(grid, stats, coords, killer);
+	}
+	hp
+}
+
+/// Remove the enemy at `coords`, killed by `killer`, with everything that happens when an
+/// enemy dies: the kill is counted and leaves a corpse (see `Stats::record_kill`), and the
+/// `TurnEvent::EnemyDied` that it records is what the particles, the screen effects, the turn
+/// log and the scripts react to.
+fn enemy_dies(grid: &mut Grid<Cell>, stats: &mut Stats, coords: Coords, killer: Killer) {
+	let obj = std::mem::replace(&mut grid.get_mut(coords).unwrap().obj, Obj::Empty);
+	if let Obj::Enemy { variant, .. } = obj {
+		stats.record_kill(killer, coords, variant);
+	}
+}
+
+/// Damage an enemy, its shield (if it has one) taking what it can first.
+fn hurt_enemy(variant: &mut Enemy, hp: &mut u32, damage: u32) {
+	let mut damage = damage;
+	let shield = variant.shield();
+	if let (Enemy::Shielded { shield_damage, calm }, Some(shield)) = (variant, shield) {
+		if damage > 0 {
+			*calm = 0;
+		}
+		let absorbed = damage.min(shield);
+		*shield_damage += absorbed;
+		damage -= absorbed;
+	}
+	*hp = hp.saturating_sub(damage);
+}
+
+/// Shields come back to full at the end of the second turn in a row that their enemy was not
+/// damaged (see `Balance::shield_regen_turns`).
+fn shields_regenerate(grid: &mut Grid<Cell>) {
+	let balance = balance::get();
+	for coords in grid.dims.iter() {
+		if let Obj::Enemy { variant: Enemy::Shielded { shield_damage, calm }, .. } =
+			&mut grid.get_mut(coords).unwrap().obj
+		{
+			if *calm >= balance.shield_regen_turns {
+				*shield_damage = 0;
+			}
+			*calm += 1;
+		}
+	}
+}
+
+fn group_leader_lives(grid: &Grid<Cell>, id: u32) -> bool {
+	grid.dims.iter().any(|coords| {
+		matches!(&grid.get(coords).unwrap().obj, Obj::Enemy { effects, .. }
+			if effects.group == Some(GroupMember { id, leader: true }))
+	})
+}
+
+/// The enemies of a group whose leader died lose the HP it gave them (but not their last one).
+fn groups_lose_leaders(grid: &mut Grid<Cell>) {
+	for coords in grid.dims.iter() {
+		let Obj::Enemy { effects, .. } = &grid.get(coords).unwrap().obj else {
+			continue;
+		};
+		let Some(member) = effects.group else {
+			continue;
+		};
+		if effects.group_bonus == 0 || group_leader_lives(grid, member.id) {
+			continue;
+		}
+		if let Obj::Enemy { hp, effects, .. } = &mut grid.get_mut(coords).unwrap().obj {
+			*hp = hp.saturating_sub(effects.group_bonus).max(1);
+			effects.group_bonus = 0;
+			log::debug!("Enemy at {coords} lost its group leader ({hp} hp left)");
+		}
+	}
+}
+
+/// Burning enemies take damage, at the start of the enemies' turn.
+fn burns_tick(grid: &mut Grid<Cell>, stats: &mut Stats) {
+	let damage = balance::get().burn_damage;
+	for coords in grid.dims.iter() {
+		let Obj::Enemy { effects, .. } = &mut grid.get_mut(coords).unwrap().obj else {
+			continue;
+		};
+		if effects.burn == 0 {
+			continue;
+		}
+		effects.burn -= 1;
+		let hp = damage_enemy(
+			grid,
+			stats,
+			coords,
+			damage,
+			Killer::Tower(Tower::Flamethrower),
+		);
+		log::debug!("Enemy at {coords} burns for {damage} damage ({hp} hp left)");
+	}
+}
+
+/// Push the enemy at `coords` (just hit by a shot going `dd`) one tile back along the path,
+/// away from the goal (the way of the shot if it goes back). It pushes like a pusher tower,
+/// dragging the line behind it if it is not too heavy, and then faces the path again from
+/// there, so that a protected enemy turns its protected sides back towards the goal.
+fn knock_back(grid: &mut Grid<Cell>, stats: &mut Stats, coords: Coords, dd: DxDy, tier: u32) {
+	let Some(&Cell { groud: Ground::Path(distance), .. }) = grid.get(coords) else {
+		return;
+	};
+	let is_back = |dd: DxDy| {
+		grid
+			.get(coords + dd)
+			.is_some_and(|cell| matches!(cell.groud, Ground::Path(other) if other > distance))
+	};
+	let back = if is_back(dd) {
+		Some(dd)
+	} else {
+		DxDy::the_4_directions().find(|&dd| is_back(dd))
+	};
+	let Some(back) = back else {
+		return;
+	};
+	let strength = balance::get().knockback_push_strength * tier;
+	try_push(grid, stats, coords, back, true, strength);
+	if grid
+		.get(coords)
+		.is_some_and(|cell| matches!(cell.obj, Obj::Empty))
+	{
+		log::debug!("Enemy at {coords} knocked back to {}", coords + back);
+		face_the_path(grid, stats, coords + back);
+	}
+}
+
+/// A turn of shooting (in any number of directions) takes a shot from the tower's ammo.
+fn spend_ammo(grid: &mut Grid<Cell>, coords: Coords) {
+	if let Obj::Tower { ammo: Some(ammo), .. } = &mut grid.get_mut(coords).unwrap().obj {
+		*ammo = ammo.saturating_sub(1);
+	}
+}
+
+/// Is the given enemy protected from a shot traveling in the `dd` direction?
+fn is_protected_from_shot(enemy: &Enemy, dd: DxDy) -> bool {
+	if let Enemy::Protected { direction, protection } = enemy {
+		// A diagonal shot comes from two sides, it gets through if one of them is not protected.
+		[DxDy::from((dd.dx, 0)), DxDy::from((0, dd.dy))]
+			.into_iter()
+			.filter(|&dd| dd != DxDy::from((0, 0)))
+			.all(|dd| {
+				let shot_comming_from_dir = match dd {
+					DxDy { dx: 0, dy: -1 } => Direction::South,
+					DxDy { dx: 1, dy: 0 } => Direction::West,
+					DxDy { dx: 0, dy: 1 } => Direction::North,
+					DxDy { dx: -1, dy: 0 } => Direction::East,
+					_ => panic!("aa help"),
+				};
+				!protection.is_hurt_by_shot(*direction, shot_comming_from_dir)
+			})
+	} else {
+		false
+	}
+}
+
+/// The first enemy that can be seen from `coords` in the direction `dd` (which may be a
+/// diagonal) up to `range` tiles away, other objects block the line of sight.
+fn first_enemy_in_line(grid: &Grid<Cell>, coords: Coords, dd: DxDy, range: i32) -> Option<Coords> {
+	let mut coords_in_line = coords;
+	for _ in 0..range {
+		coords_in_line += dd;
+		let cell = grid.get(coords_in_line)?;
+		if !cell.obj.blocks_line_of_sight() {
+			continue;
+		}
+		return matches!(cell.obj, Obj::Enemy { .. }).then_some(coords_in_line);
+	}
+	None
+}
+
+/// How much a tower with the given priority wants to shoot the enemy in the given cell,
+/// the enemy with the smallest score is the preferred one.
+fn target_score(priority: TargetPriority, cell: &Cell) -> i32 {
+	let Obj::Enemy { hp, .. } = &cell.obj else {
+		return i32::MAX;
+	};
+	match (priority, &cell.groud) {
+		(TargetPriority::FirstOnPath, Ground::Path(dist)) => *dist,
+		(TargetPriority::FirstOnPath, _) => i32::MAX,
+		(TargetPriority::LowestHp, _) => *hp as i32,
+		(TargetPriority::HighestHp, _) => -(*hp as i32),
+		(TargetPriority::All, _) => 0,
+	}
+}
+
+/// Magnets pull the closest enemy they see (whatever its protection) one tile towards them.
+fn magnet_move(grid: &mut Grid<Cell>, stats: &mut Stats, coords: Coords, range: i32) {
+	let target = Tower::Magnet
+		.directions()
+		.into_iter()
+		.filter_map(|dd| Some((dd, first_enemy_in_line(grid, coords, dd, range)?)))
+		.min_by_key(|&(_, target)| {
+			let DxDy { dx, dy } = target - coords;
+			dx.abs() + dy.abs()
+		});
+	if let Some((dd, target)) = target {
+		try_pull(grid, stats, target, DxDy::from((-dd.dx, -dd.dy)), true);
+	}
+}
+
+/// Miners place a mine on an empty path tile next to them, and then wait a bit.
+fn miner_move(grid: &mut Grid<Cell>, coords: Coords) {
+	let Obj::Tower { cooldown, .. } = &mut grid.get_mut(coords).unwrap().obj else {
+		unreachable!()
+	};
+	*cooldown = cooldown.saturating_sub(1);
+	if *cooldown > 0 {
+		return;
+	}
+	let mine_coords = Tower::Miner
+		.directions()
+		.into_iter()
+		.map(|dd| coords + dd)
+		.find(|&neighbor| {
+			grid.get(neighbor).is_some_and(|cell| {
+				matches!(cell.groud, Ground::Path(_)) && matches!(cell.obj, Obj::Empty)
+			})
+		});
+	if let Some(mine_coords) = mine_coords {
+		grid.get_mut(mine_coords).unwrap().obj = Obj::Mine;
+		if let Obj::Tower { cooldown, .. } = &mut grid.get_mut(coords).unwrap().obj {
+			*cooldown = balance::get().miner_cooldown;
+		}
+	}
+}
+
+/// Snipers shoot only one enemy (in any of the 8 directions), and then have to reload.
+fn sniper_move(grid: &mut Grid<Cell>, coords: Coords, stats: &mut Stats, range: i32) {
+	let Obj::Tower { cooldown, priority, tier, .. } = &mut grid.get_mut(coords).unwrap().obj else {
+		unreachable!()
+	};
+	*cooldown = cooldown.saturating_sub(1);
+	if *cooldown > 0 {
+		return;
+	}
+	let (priority, tier) = (*priority, *tier);
+	let target = Tower::Sniper
+		.directions()
+		.into_iter()
+		.filter_map(|dd| {
+			let target = first_enemy_in_line(grid, coords, dd, range)?;
+			let Obj::Enemy { variant, .. } = &grid.get(target).unwrap().obj else {
+				unreachable!()
+			};
+			(!is_protected_from_shot(variant, dd)).then_some(target)
+		})
+		.min_by_key(|&target| target_score(priority, grid.get(target).unwrap()));
+	let Some(target) = target else {
+		return;
+	};
+	if let Obj::Tower { cooldown, .. } = &mut grid.get_mut(coords).unwrap().obj {
+		*cooldown = balance::get().sniper_cooldown;
+	}
+	spend_ammo(grid, coords);
+	let damage = balance::get().tower_damage(&Tower::Sniper) * tier;
+	stats
+		.turn_events
+		.push(TurnEvent::Shot { from: coords, to: target });
+	let hp = damage_enemy(grid, stats, target, damage, Killer::Tower(Tower::Sniper));
+	log::debug!("Sniper at {coords} shoots {target} for {damage} damage ({hp} hp left)");
+}
+
+/// The direction in which a tower at the given coords should shoot to hit the enemy
+/// it prefers the most according to its targeting priority (if any enemy can be hit).
+fn prioritized_target_direction(
+	grid: &Grid<Cell>,
+	coords: Coords,
+	range: i32,
+	piercing: bool,
+	priority: TargetPriority,
+) -> Option<DxDy> {
+	let mut best: Option<(DxDy, i32)> = None;
+	for dd in DxDy::the_4_directions() {
+		let mut coords_possible_target = coords;
+		for _ in 0..range {
+			coords_possible_target += dd;
+			let Some(cell) = grid.get(coords_possible_target) else {
+				break;
+			};
+			if cell.obj.is_out_of_reach() {
+				continue;
+			}
+			if let Obj::Enemy { variant, .. } = &cell.obj {
+				if is_protected_from_shot(variant, dd) {
+					continue;
+				}
+				let score = target_score(priority, cell);
+				if best.is_none_or(|(_, best_score)| score < best_score) {
+					best = Some((dd, score));
+				}
+				if !piercing {
+					break;
+				}
+			} else if cell.obj.blocks_line_of_sight() {
+				// View is blocked by some non-targettable object.
+				break;
+			}
+		}
+	}
+	best.map(|(dd, _)| dd)
+}
+
+/// The tiles that a tower at the given coords could shoot at, taking into account its range
+/// (in that weather) and the objects blocking its line of sight.
+fn tower_range_tiles(
+	grid: &Grid<Cell>,
+	coords: Coords,
+	variant: &Tower,
+	facing: Direction,
+	weather: Weather,
+) -> Vec<Coords> {
+	let mut tiles = vec![];
+	for dd in variant.directions_facing(facing) {
+		let mut coords_in_range = coords;
+		for _ in 0..weather.tower_range(variant.range()) {
+			coords_in_range += dd;
+			let Some(cell) = grid.get(coords_in_range) else {
+				break;
+			};
+			tiles.push(coords_in_range);
+			if cell.obj.blocks_line_of_sight() && !matches!(cell.obj, Obj::Enemy { .. }) {
+				break;
+			}
+		}
+	}
+	tiles
+}
+
+/// A meteor destroys whatever is on the tile (but goals), and cracks its ground: it becomes
+/// rocky, without its spikes or belt.
+fn meteor_falls(grid: &mut Grid<Cell>, stats: &mut Stats, coords: Coords) {
+	let Some(cell) = grid.get_mut(coords) else {
+		return;
+	};
+	log::debug!("A meteor falls at {coords}");
+	stats.turn_events.push(TurnEvent::Explosion(coords));
+	match cell.obj {
+		Obj::Goal { .. } => {},
+		Obj::Enemy { .. } => enemy_dies(grid, stats, coords, Killer::Meteor),
+		_ => cell.obj = Obj::Empty,
+	}
+	let cell = grid.get_mut(coords).unwrap();
+	if !cell.groud.is_liquid() {
+		cell.rocky_path = true;
+		cell.spikes = false;
+		cell.conveyor = None;
+	}
+}
+
 fn apply_events(level: &mut LevelState) {
+	let night_enemy_hp_bonus = match level.day_night {
+		Some(day_night) if level.is_night() => day_night.night_enemy_hp_bonus,
+		_ => 0,
+	};
+	// The events waiting for their condition happen now if it holds (events of turn 0 included,
+	// which have been waiting since the start).
+	for event in level.events.iter_mut().filter(|e| e.turn <= level.turn) {
+		if let Some(condition) = event.condition {
+			if condition.holds(&level.grid, level.goal_hp_max) {
+				event.condition = None;
+				event.turn = level.turn;
+			} else {
+				event.turn = level.turn + 1;
+			}
+		}
+	}
+	let mut terrain_changed = false;
 	for event in level.events.iter_mut().filter(|e| e.turn == level.turn) {
 		match &event.event_type {
-			GameEventType::EnemySpawn(coords, enemy) => {
+			GameEventType::EnemySpawn(coords, enemy, group) => {
+				let group_bonus = match group {
+					Some(member) if !member.leader && group_leader_lives(&level.grid, member.id) => {
+						balance::get().group_leader_hp_bonus
+					},
+					_ => 0,
+				};
 				if let Some(tile) = level.grid.get_mut(*coords) {
 					match tile.obj {
-						Obj::Empty | Obj::Player { .. } => tile.obj = Obj::new_enemy(enemy.clone()),
+						Obj::Empty | Obj::Player { .. } => {
+							let hp = enemy.hp_max() + night_enemy_hp_bonus + group_bonus;
+							log::debug!("{} spawns at {coords} with {hp} hp", enemy.name());
+							tile.obj = Obj::Enemy {
+								variant: enemy.clone(),
+								hp,
+								effects: StatusEffects {
+									group: *group,
+									group_bonus,
+									emerging: true,
+									..StatusEffects::default()
+								},
+							};
+							face_the_path(&mut level.grid, &level.stats, *coords);
+						},
 						// Can't place enemy
-						_ => event.turn += 1,
+						_ => {
+							log::debug!("{} can't spawn at {coords}, postponed", enemy.name());
+							event.turn += 1;
+						},
 					}
 				}
 			},
+			GameEventType::TerrainChange(coords, ground) => {
+				let Some(tile) = level.grid.get_mut(*coords) else {
+					continue;
+				};
+				// Enemies only stand on the path, and objects would be lost in water or lava.
+				let blocked = match tile.obj {
+					Obj::Empty => false,
+					Obj::Enemy { .. } => true,
+					_ => ground.is_liquid(),
+				};
+				if blocked {
+					log::debug!("The ground at {coords} can't change, postponed");
+					event.turn += 1;
+					continue;
+				}
+				log::debug!("The ground at {coords} becomes {}", ground.name());
+				// Without the spikes, belts and rocks of the ground it was.
+				tile.groud = ground.clone();
+				tile.rocky_path = false;
+				tile.spikes = false;
+				tile.conveyor = None;
+				terrain_changed = true;
+			},
+			GameEventType::Meteor(coords) => {
+				meteor_falls(&mut level.grid, &mut level.stats, *coords);
+				terrain_changed = true;
+			},
+			&GameEventType::Grant(grant, count) => {
+				match grant {
+					Grant::Towers => {
+						// Nothing to give if the towers are not limited.
+						if let Some(remaining_towers) = &mut level.remaining_towers {
+							*remaining_towers += count;
+						}
+					},
+					Grant::Item(item) => *level.inventory.count_mut(item) += count,
+				}
+				level
+					.stats
+					.turn_events
+					.push(TurnEvent::Granted(grant, count));
+			},
 		}
 	}
+	if terrain_changed {
+		compute_distance(&mut level.grid);
+	}
 }
 
-fn parse_tile(tile_string: [char; 2]) -> Cell {
-	let mut cell = Cell { obj: Obj::Empty, groud: Ground::Grass, rocky_path: false };
+fn parse_tile(tile_string: [char; 2]) -> Result<Cell, String> {
+	let tile = || tile_string.iter().collect::<String>();
+	let mut cell = Cell {
+		obj: Obj::Empty,
+		groud: Ground::Grass,
+		rocky_path: false,
+		spikes: false,
+		conveyor: None,
+		portal: false,
+	};
 	(cell.groud, cell.rocky_path) = match tile_string[0] {
 		'O' => (Ground::Grass, false),
 		'0' => (Ground::Grass, true),
 		'x' => (Ground::Water, false),
-		'|' => (Ground::Path(-1), false),
+		'%' => (Ground::Lava, false),
+		'|' | '^' | '>' | '<' | 'A' | 'v' | '*' => (Ground::Path(-1), false),
 		'/' => (Ground::Path(-1), true),
-		_ => panic!(
-			"Gwound fowmat '{}{}' incowect >w<",
-			tile_string[0], tile_string[1]
-		),
+		_ => return Err(tr_with("error.unknown_ground", &[("tile", &tile())])),
+	};
+	cell.spikes = tile_string[0] == '^';
+	cell.portal = tile_string[0] == '*';
+	cell.conveyor = match tile_string[0] {
+		'>' => Some(Direction::East),
+		'<' => Some(Direction::West),
+		'A' => Some(Direction::North),
+		'v' => Some(Direction::South),
+		_ => None,
 	};
 	cell.obj = match tile_string[1] {
 		'-' => Obj::Empty,
@@ -754,11 +3696,22 @@ fn parse_tile(tile_string: [char; 2]) -> Cell {
 		'k' => Obj::new_tower(Tower::TotalEnergy),
 		'd' => Obj::new_tower(Tower::Unabomber),
 		'y' => Obj::new_tower(Tower::Pusher),
+		's' => Obj::new_tower(Tower::Sniper),
+		'm' => Obj::new_tower(Tower::Miner),
+		'n' => Obj::new_tower(Tower::Magnet),
+		'v' => Obj::new_tower(Tower::Cannon),
+		'h' => Obj::new_tower(Tower::Flamethrower),
 		'e' => Obj::new_enemy(Enemy::Basic),
 		'W' => Obj::new_enemy(Enemy::Tank),
 		'Z' => Obj::new_enemy(Enemy::Speeeeed),
 		'L' => Obj::new_enemy(Enemy::Stuner),
 		'H' => Obj::new_enemy(Enemy::Eater),
+		'M' => Obj::new_enemy(Enemy::Broodmother { countdown: BROODMOTHER_SPAWN_PERIOD - 1 }),
+		'N' => Obj::new_enemy(Enemy::Necromancer),
+		'B' => Obj::new_enemy(Enemy::Burrower { underground: 0 }),
+		'P' => Obj::new_enemy(Enemy::Paver),
+		'D' => Obj::new_enemy(Enemy::from_name("shielded").unwrap()),
+		'X' => Obj::new_enemy(Enemy::from_name("boss").unwrap()),
 		'{' => Obj::new_enemy(Enemy::Protected {
 			direction: Direction::East,
 			protection: Protection::Sides,
@@ -783,436 +3736,4519 @@ fn parse_tile(tile_string: [char; 2]) -> Cell {
 			direction: Direction::East,
 			protection: Protection::ThreeBack,
 		}),
-		'g' => Obj::Goal,
+		'g' => Obj::Goal { hp: 1 },
 		'r' => Obj::Rock,
-		'T' => Obj::Tree,
+		'*' => Obj::Mine,
+		'#' => Obj::Wall { hp: balance::get().wall_hp },
+		'o' => Obj::Item { item: Item::Bomb },
+		'$' => Obj::Item { item: Item::TowerVoucher },
+		'+' => Obj::Item { item: Item::Heart },
+		'w' => Obj::Item { item: Item::Wood },
+		'c' => Obj::Item { item: Item::Stone },
+		'T' => Obj::Tree { chopped: false },
 		'^' => Obj::Flower { variant: Flower::BlueFlower },
 		'!' => Obj::Flower { variant: Flower::TheOther },
 		'f' => Obj::Flower { variant: Flower::TheOtherOther },
-		_ => panic!(
-			"Obwect fowmat '{}{}' incowect >w<",
-			tile_string[0], tile_string[1]
-		),
+		'b' => Obj::Bomb { countdown: balance::get().bomb_countdown },
+		'K' => Obj::Key { color: KeyColor::Red },
+		'=' => Obj::Door { color: KeyColor::Red },
+		_ => return Err(tr_with("error.unknown_object", &[("tile", &tile())])),
 	};
-	cell
+	Ok(cell)
 }
 
-fn load_level(level_file: &str) -> std::io::Result<LevelData> {
-	let level_raw_data = fs::read_to_string(level_file)?;
-	let filt = |x: &&str| !x.is_empty() && !x.starts_with('@') && !x.starts_with('~');
-	let grid_h = level_raw_data.split('\n').filter(filt).count();
-	let grid_w = level_raw_data
-		.split('\n')
-		.find(filt)
-		.unwrap()
-		.split(char::is_whitespace)
-		.count();
-	let dims = Dimensions { w: grid_w as i32, h: grid_h as i32 };
-	let mut grid: Grid<Cell> = Grid::new(
-		dims,
-		Cell { obj: Obj::Empty, groud: Ground::Grass, rocky_path: false },
+/// The inverse of `parse_tile` (the state that is not in the level format is lost,
+/// like the facing of protected enemies, the hp of enemies or the color of keys and doors).
+fn tile_string(cell: &Cell) -> [char; 2] {
+	let ground = match (&cell.groud, cell.rocky_path) {
+		(Ground::Path(_), _) if cell.spikes => '^',
+		(Ground::Path(_), _) if cell.portal => '*',
+		(Ground::Path(_), _) if cell.conveyor.is_some() => match cell.conveyor.unwrap() {
+			Direction::East => '>',
+			Direction::West => '<',
+			Direction::North => 'A',
+			Direction::South => 'v',
+		},
+		(Ground::Grass, false) => 'O',
+		(Ground::Grass, true) => '0',
+		(Ground::Water, _) => 'x',
+		(Ground::Lava, _) => '%',
+		(Ground::Path(_), false) => '|',
+		(Ground::Path(_), true) => '/',
+	};
+	let obj = match &cell.obj {
+		Obj::Empty => '-',
+		Obj::Player { .. } => 'p',
+		Obj::Tower { variant: Tower::Basic, .. } => 't',
+		Obj::Tower { variant: Tower::Piercing, .. } => 'u',
+		Obj::Tower { variant: Tower::TotalEnergy, .. } => 'k',
+		Obj::Tower { variant: Tower::Unabomber, .. } => 'd',
+		Obj::Tower { variant: Tower::Pusher, .. } => 'y',
+		Obj::Tower { variant: Tower::Sniper, .. } => 's',
+		Obj::Tower { variant: Tower::Miner, .. } => 'm',
+		Obj::Tower { variant: Tower::Magnet, .. } => 'n',
+		Obj::Tower { variant: Tower::Cannon, .. } => 'v',
+		Obj::Tower { variant: Tower::Flamethrower, .. } => 'h',
+		Obj::Enemy { variant: Enemy::Basic, .. } => 'e',
+		Obj::Enemy { variant: Enemy::Tank, .. } => 'W',
+		Obj::Enemy { variant: Enemy::Speeeeed, .. } => 'Z',
+		Obj::Enemy { variant: Enemy::Stuner, .. } => 'L',
+		Obj::Enemy { variant: Enemy::Eater, .. } => 'H',
+		Obj::Enemy { variant: Enemy::Broodmother { .. }, .. } => 'M',
+		Obj::Enemy { variant: Enemy::Necromancer, .. } => 'N',
+		Obj::Enemy { variant: Enemy::Burrower { .. }, .. } => 'B',
+		Obj::Enemy { variant: Enemy::Paver, .. } => 'P',
+		Obj::Enemy { variant: Enemy::Shielded { .. }, .. } => 'D',
+		Obj::Enemy { variant: Enemy::Boss { .. }, .. } => 'X',
+		Obj::Enemy { variant: Enemy::Protected { protection, .. }, .. } => match protection {
+			Protection::Sides => '{',
+			Protection::FullStack => '}',
+			Protection::UniqueFront => ')',
+			Protection::UniqueBack => '(',
+			Protection::ThreeFront => ']',
+			Protection::ThreeBack => '[',
+		},
+		Obj::Goal { .. } => 'g',
+		Obj::Rock => 'r',
+		Obj::Mine => '*',
+		Obj::Wall { .. } => '#',
+		Obj::Item { item: Item::Bomb } => 'o',
+		Obj::Item { item: Item::TowerVoucher } => '$',
+		Obj::Item { item: Item::Heart } => '+',
+		Obj::Item { item: Item::Wood } => 'w',
+		Obj::Item { item: Item::Stone } => 'c',
+		Obj::Key { .. } => 'K',
+		Obj::Door { .. } => '=',
+		Obj::Tree { .. } => 'T',
+		Obj::Flower { variant: Flower::BlueFlower } => '^',
+		Obj::Flower { variant: Flower::TheOther } => '!',
+		Obj::Flower { variant: Flower::TheOtherOther } => 'f',
+		Obj::Bomb { .. } => 'b',
+	};
+	[ground, obj]
+}
+
+/// Bitmask of the neighbors of a water or path tile that are of the same ground kind
+/// (north = 1, east = 2, south = 4, west = 8), used to pick the sprite variant with the
+/// right edges. Outside of the grid counts as the same kind, so that paths leaving the
+/// level stay open.
+fn autotile_mask(grid: &Grid<Cell>, coords: Coords) -> usize {
+	let kind = |coords: Coords| {
+		grid
+			.get(coords)
+			.map(|cell| std::mem::discriminant(&cell.groud))
+	};
+	let own_kind = kind(coords);
+	[(0, -1), (1, 0), (0, 1), (-1, 0)]
+		.into_iter()
+		.enumerate()
+		.filter(|(_, dd)| {
+			let neighbor_kind = kind(coords + DxDy::from(*dd));
+			neighbor_kind.is_none() || neighbor_kind == own_kind
+		})
+		.map(|(bit, _)| 1 << bit)
+		.sum()
+}
+
+#[test]
+fn path_turns_and_shores_get_edges_from_their_neighbors() {
+	let scenario = scenario::Scenario::new(
+		"
+		|- |- Op
+		O- |- x-
+		O- |g x-
+		",
 	);
-	let mut cells_info = level_raw_data.split(char::is_whitespace);
-	let mut h: HashMap<char, Coords> = HashMap::new();
-	for coords in grid.dims.iter() {
-		let current_tile = cells_info.next().unwrap();
-		if current_tile.is_empty() {
-			panic!("Tile empty, may have a blank space at the end of line or two spaces");
-		}
-		let cell = grid.get_mut(coords).unwrap();
-		if current_tile.starts_with('?') {
-			h.insert(current_tile.chars().nth(1).unwrap(), coords);
-		} else {
-			let mut tile = current_tile.chars();
-			let c1 = tile.next().unwrap();
-			let c2 = tile.next().unwrap();
-			*cell = parse_tile([c1, c2]);
-		}
-	}
-	let mut level_data = LevelData::new(grid);
-	let meta_data = level_raw_data
-		.split('\n')
-		.filter_map(|x| x.strip_prefix('@'));
-	for line in meta_data {
-		let mut line = line.split(char::is_whitespace);
-		match line.next().unwrap() {
-			"max_towers" => level_data.max_towers = Some(line.next().unwrap().parse().unwrap()),
-			"tile" => {
-				let name = line.next().unwrap();
-				let coords = h.get(&name.chars().next().unwrap()).unwrap();
-				let mut tile = line.next().unwrap().chars();
-				let c1 = tile.next().unwrap();
-				let c2 = tile.next().unwrap();
-				*level_data.init_grid.get_mut(*coords).unwrap() = parse_tile([c1, c2]);
-			},
-			"event" => match line.next().unwrap() {
-				"spawn" => {
-					let enemy = match line.next().unwrap() {
-						"basic" => Enemy::Basic,
-						"tank" => Enemy::Tank,
-						"speeeeed" => Enemy::Speeeeed,
-						"stun" => Enemy::Stuner,
-						"eat" => Enemy::Eater,
-						"protected_sides" => {
-							Enemy::Protected { direction: Direction::East, protection: Protection::Sides }
-						},
-						"protected_full_stack" => Enemy::Protected {
-							direction: Direction::East,
-							protection: Protection::FullStack,
-						},
-						"protected_front" => Enemy::Protected {
-							direction: Direction::East,
-							protection: Protection::UniqueFront,
-						},
-						"protected_back" => Enemy::Protected {
-							direction: Direction::East,
-							protection: Protection::UniqueBack,
-						},
-						"protected_three_front" => Enemy::Protected {
-							direction: Direction::East,
-							protection: Protection::ThreeFront,
-						},
-						"protected_three_back" => Enemy::Protected {
-							direction: Direction::East,
-							protection: Protection::ThreeBack,
-						},
-						creature => panic!("UwU, trying to spawn {creature} but it doesn't exist"),
-					};
-					let tile_name = line.next().unwrap().chars().next().unwrap();
-					let tile_coords = h.get(&tile_name).unwrap();
-					let turn: u32 = line.next().unwrap().parse().unwrap();
-					level_data.init_events.push(GameEvent::new(
-						turn,
-						GameEventType::EnemySpawn(*tile_coords, enemy),
-					));
-					// println!("OH THE MISERY Everybody wants to be my enemy");
-				},
-				other_event => panic!("Nyoooo unknown event {other_event}"),
-			},
-			unknown_meta_data_name => panic!("Jaaj {unknown_meta_data_name}??"),
-		}
-	}
-	println!("max_towers: {x:?}", x = level_data.max_towers);
-	Ok(level_data)
+	// Outside of the grid counts as the same ground kind.
+	assert_eq!(
+		autotile_mask(&scenario.level.grid, (0, 0).into()),
+		1 | 2 | 8
+	);
+	assert_eq!(
+		autotile_mask(&scenario.level.grid, (1, 0).into()),
+		1 | 4 | 8
+	);
+	assert_eq!(autotile_mask(&scenario.level.grid, (1, 2).into()), 1 | 4);
+	assert_eq!(autotile_mask(&scenario.level.grid, (2, 1).into()), 2 | 4);
 }
 
-fn compute_distance(grid: &mut Grid<Cell>) {
-	let goal = 'goal_find: {
-		for coords in grid.dims.iter() {
-			if matches!(grid.get(coords).unwrap().obj, Obj::Goal) {
-				break 'goal_find coords;
-			}
-		}
-		println!("Didn't find a goal on the level");
-		return;
+/// The ground sprite of a tile, chosen among the autotile variants for water and paths (lava
+/// is water drawn over in `draw_cell`).
+/// The 16 variants of a ground kind are side by side in its sprite, indexed by `autotile_mask`.
+fn ground_sprite(spritesheet: &Spritesheet, ground: &Ground, autotile_mask: usize) -> Rect {
+	let name = match ground {
+		Ground::Grass => return spritesheet.sprite("ground.grass"),
+		Ground::Water | Ground::Lava => "ground.water",
+		Ground::Path(_) => "ground.path",
 	};
-	fn update_dist(grid: &mut Grid<Cell>, start: Coords, depth: i32) {
-		grid.get_mut(start).unwrap().groud = Ground::Path(depth);
-		for dd in DxDy::the_4_directions() {
-			let dst = start + dd;
-			if grid.get(dst).is_none() {
-				continue;
-			}
-			if let Ground::Path(dist) = grid.get(dst).unwrap().groud {
-				if dist == -1 || dist > depth {
-					update_dist(grid, dst, depth + 1);
-				}
-			}
-		}
+	spritesheet.sprite_part(name, autotile_mask as i32, 8)
+}
+
+/// Errors are messages for the player. The files that the level refers to are looked for next
+/// to it.
+fn load_level(level_file: &str) -> Result<LevelData, String> {
+	let level_raw_data = fs::read_to_string(level_file).map_err(|error| match error.kind() {
+		std::io::ErrorKind::NotFound => tr_with("error.level_not_found", &[("file", &level_file)]),
+		_ => tr_with(
+			"error.level_unreadable",
+			&[("file", &level_file), ("error", &error)],
+		),
+	})?;
+	parse_level_in(&level_raw_data, level_dir(level_file))
+}
+
+/// The title of the window, with the level and the turn while a level is played.
+fn window_title(
+	level_data: &LevelData,
+	level_file: &str,
+	level: &LevelState,
+	screen: Screen,
+) -> String {
+	if screen == Screen::Title {
+		return "Prototype 7".to_string();
 	}
-	update_dist(grid, goal, 0);
+	let level_name = level_data.title.clone().unwrap_or_else(|| {
+		std::path::Path::new(level_file)
+			.file_stem()
+			.map_or(level_file.to_string(), |stem| {
+				stem.to_string_lossy().into_owned()
+			})
+	});
+	tr_with(
+		"window.title",
+		&[("level", &level_name), ("turn", &level.turn)],
+	)
 }
 
-fn _print_dist(grid: &Grid<Cell>) {
-	for y in 0..grid.dims.h {
-		for x in 0..grid.dims.w {
-			match grid.get((x, y).into()).unwrap().groud {
-				Ground::Path(d) => print!("{d:2} "),
-				_ => print!(" - "),
+/// The directory of a level file, where the files it includes are.
+fn level_dir(level_file: &str) -> &std::path::Path {
+	std::path::Path::new(level_file)
+		.parent()
+		.unwrap_or(std::path::Path::new(""))
+}
+
+/// Parse a tile of the level format (two characters).
+fn parse_tile_text(text: &str) -> Result<Cell, String> {
+	let mut chars = text.chars();
+	match (chars.next(), chars.next()) {
+		(Some(c1), Some(c2)) => parse_tile([c1, c2]),
+		_ => Err(tr_with("error.bad_tile", &[("tile", &text)])),
+	}
+}
+
+/// Parse the next word of a metadata line (`what` is the name of the metadata).
+fn parse_value<T: std::str::FromStr>(word: Option<&str>, what: &str) -> Result<T, String> {
+	let word = word.ok_or_else(|| tr_with("error.missing_value", &[("name", &what)]))?;
+	word
+		.parse()
+		.map_err(|_| tr_with("error.bad_number", &[("value", &word)]))
+}
+
+/// What the metadata lines of a level file are parsed with.
+struct MetadataContext<'a> {
+	named_tiles: &'a HashMap<String, Coords>,
+	/// The directory of the file, where `@include` and `@prefab` look for files.
+	dir: &'a std::path::Path,
+	/// How many `@include` deep the line is, to stop include cycles.
+	include_depth: u32,
+}
+
+impl MetadataContext<'_> {
+	const MAX_INCLUDE_DEPTH: u32 = 8;
+}
+
+/// Read a prefab (a grid in the level format where `..` keeps the tile it is stamped on) and
+/// stamp it with its top left corner on the given tile.
+fn stamp_prefab(
+	grid: &mut Grid<Cell>,
+	prefab_file: &std::path::Path,
+	corner: Coords,
+) -> Result<(), String> {
+	let prefab_raw_data = fs::read_to_string(prefab_file).map_err(|error| {
+		tr_with(
+			"error.file_unreadable",
+			&[("file", &prefab_file.display()), ("error", &error)],
+		)
+	})?;
+	for (dy, (line_index, line)) in grid_lines(&prefab_raw_data).enumerate() {
+		for (dx, tile) in line.split(char::is_whitespace).enumerate() {
+			if tile == ".." {
+				continue;
 			}
+			let coords = corner + DxDy { dx: dx as i32, dy: dy as i32 };
+			let cell = parse_tile_text(tile).map_err(|error| {
+				let error = at_line(line_index, error);
+				tr_with(
+					"error.in_file",
+					&[("file", &prefab_file.display()), ("error", &error)],
+				)
+			})?;
+			*grid
+				.get_mut(coords)
+				.ok_or_else(|| tr_with("error.outside_grid", &[("coords", &coords)]))? = cell;
 		}
-		println!();
 	}
-	println!();
+	Ok(())
 }
 
-fn is_game_joever(grid: &Grid<Cell>) -> bool {
-	for coords in grid.dims.iter() {
-		if matches!(grid.get(coords).unwrap().obj, Obj::Goal) {
-			return false;
+/// Apply the metadata lines of an included file to the level, the tiles it names are only
+/// known in that file.
+fn include_level_file(
+	level_data: &mut LevelData,
+	file: &std::path::Path,
+	context: &MetadataContext,
+) -> Result<(), String> {
+	if context.include_depth >= MetadataContext::MAX_INCLUDE_DEPTH {
+		return Err(tr("error.include_depth"));
+	}
+	let in_file = |error: String| {
+		tr_with(
+			"error.in_file",
+			&[("file", &file.display()), ("error", &error)],
+		)
+	};
+	let raw_data = fs::read_to_string(file).map_err(|error| {
+		tr_with(
+			"error.file_unreadable",
+			&[("file", &file.display()), ("error", &error)],
+		)
+	})?;
+	if let Some((line_index, _)) = grid_lines(&raw_data).next() {
+		return Err(in_file(at_line(line_index, tr("error.tiles_in_include"))));
+	}
+	let mut named_tiles = context.named_tiles.clone();
+	if let Some(error) = parse_tile_names(&raw_data, level_data.init_grid.dims, &mut named_tiles)
+		.into_iter()
+		.next()
+	{
+		return Err(in_file(error));
+	}
+	let context = MetadataContext {
+		named_tiles: &named_tiles,
+		dir: file.parent().unwrap_or(context.dir),
+		include_depth: context.include_depth + 1,
+	};
+	for (line_index, line) in raw_data.split('\n').enumerate() {
+		if let Some(line) = line.strip_prefix('@') {
+			parse_metadata(level_data, line, &context)
+				.map_err(|error| in_file(at_line(line_index, error)))?;
 		}
 	}
-	true
+	Ok(())
 }
-fn main() {
-	env_logger::init();
-	let event_loop = winit::event_loop::EventLoop::new();
 
-	let level_file = if let Some(file_path) = std::env::args().nth(1) {
-		file_path
-	} else {
-		String::from("./levels/test")
+/// Apply a metadata line of a level file (without its `@`) to the level.
+fn parse_metadata(
+	level_data: &mut LevelData,
+	line: &str,
+	context: &MetadataContext,
+) -> Result<(), String> {
+	// Tiles can be referred to by their name, or as they are named in the grid (`?name`).
+	let named_tile = |name: Option<&str>| {
+		let name = name.unwrap_or_default();
+		let name = name.strip_prefix('?').unwrap_or(name);
+		context
+			.named_tiles
+			.get(name)
+			.copied()
+			.ok_or_else(|| tr_with("error.unknown_tile_name", &[("name", &name)]))
 	};
-	let level_data = match load_level(level_file.as_str()) {
-		Ok(grid) => grid,
-		Err(jaaj) => match jaaj.kind() {
-			std::io::ErrorKind::NotFound => panic!("File not found at {level_file}"),
-			_ => panic!("Error while reading level file"),
+	let mut line = line.split(char::is_whitespace);
+	let name = line.next().unwrap_or_default();
+	match name {
+		// Names are given before the other metadata is parsed (see `parse_tile_names`).
+		"name" => {},
+		"max_towers" => level_data.max_towers = Some(parse_value(line.next(), name)?),
+		"max_walls" => level_data.max_walls = Some(parse_value(line.next(), name)?),
+		"goal_hp" => level_data.goal_hp = parse_value(line.next(), name)?,
+		"lives" => level_data.lives = Some(parse_value(line.next(), name)?),
+		"max_turns" => level_data.max_turns = Some(parse_value(line.next(), name)?),
+		"tree_regrowth" => level_data.tree_regrowth = Some(parse_value(line.next(), name)?),
+		"crafting" => level_data.crafting = true,
+		"theme" => {
+			let theme = line.next().unwrap_or_default();
+			level_data.theme = Theme::from_name(theme)
+				.ok_or_else(|| tr_with("error.unknown_theme", &[("name", &theme)]))?;
 		},
-	};
+		"day_night" => {
+			level_data.day_night = Some(DayNight {
+				day_turns: parse_value(line.next(), name)?,
+				night_turns: parse_value(line.next(), name)?,
+				night_enemy_hp_bonus: match line.next() {
+					Some(bonus) => parse_value(Some(bonus), name)?,
+					None => 0,
+				},
+			})
+		},
+		"hint" => {
+			let trigger = match line.next().unwrap_or_default() {
+				"turn" => hints::Trigger::Turn(parse_value(line.next(), "hint turn")?),
+				"near" => hints::Trigger::Near(named_tile(line.next())?),
+				"enemy" => {
+					let enemy = line.next().unwrap_or_default();
+					hints::Trigger::Enemy(
+						Enemy::from_name(enemy)
+							.ok_or_else(|| tr_with("error.unknown_hint_enemy", &[("name", &enemy)]))?,
+					)
+				},
+				"stunned" => hints::Trigger::Stunned,
+				"towers_depleted" => hints::Trigger::TowersDepleted,
+				"goal_damaged" => hints::Trigger::GoalDamaged,
+				other => return Err(tr_with("error.unknown_hint_trigger", &[("name", &other)])),
+			};
+			let text = line.collect::<Vec<&str>>().join(" ").trim().to_string();
+			level_data.hints.push(hints::Hint { trigger, text });
+		},
+		"weather" => level_data.weather = weather::Forecast::parse(line)?,
+		"title" => {
+			let title = line.collect::<Vec<&str>>().join(" ").trim().to_string();
+			level_data.title = (!title.is_empty()).then_some(title);
+		},
+		"fog" => {
+			level_data.fog_radius = Some(match line.next() {
+				Some(radius) => parse_value(Some(radius), name)?,
+				None => 3,
+			})
+		},
+		"friendly_fire" => {
+			level_data.friendly_fire = match line.next() {
+				Some("on") => true,
+				Some("off") => false,
+				other => {
+					return Err(tr_with(
+						"error.friendly_fire",
+						&[("value", &format!("{other:?}"))],
+					))
+				},
+			}
+		},
+		"knockback" => {
+			level_data.knockback = match line.next() {
+				Some("on") => Some(true),
+				Some("off") => Some(false),
+				other => {
+					return Err(tr_with(
+						"error.knockback",
+						&[("value", &format!("{other:?}"))],
+					))
+				},
+			}
+		},
+		"transform" => {
+			let transform = line.next().unwrap_or_default();
+			level_data.transforms.push(
+				Transform::from_name(transform)
+					.ok_or_else(|| tr_with("error.unknown_transform", &[("name", &transform)]))?,
+			);
+		},
+		"script" => {
+			let file = context.dir.join(line.next().unwrap_or_default());
+			let text = fs::read_to_string(&file).map_err(|error| {
+				tr_with(
+					"error.file_unreadable",
+					&[("file", &file.display()), ("error", &error)],
+				)
+			})?;
+			let script = script::Script::compile(&text).map_err(|error| {
+				tr_with(
+					"error.script",
+					&[("file", &file.display()), ("error", &error)],
+				)
+			})?;
+			level_data.script = Some(std::rc::Rc::new(script));
+		},
+		"include" => {
+			let file = line.next().unwrap_or_default();
+			include_level_file(level_data, &context.dir.join(file), context)?;
+		},
+		"prefab" => {
+			let prefab = line.next().unwrap_or_default();
+			let corner = named_tile(line.next())?;
+			let prefab_file = context.dir.join("prefabs").join(prefab);
+			stamp_prefab(&mut level_data.init_grid, &prefab_file, corner)?;
+		},
+		"tile" => {
+			let coords = named_tile(line.next())?;
+			let tile = line.next().unwrap_or_default();
+			*level_data.init_grid.get_mut(coords).unwrap() = parse_tile_text(tile)?;
+		},
+		"key" | "door" => {
+			let color = line.next().unwrap_or_default();
+			let color = KeyColor::from_name(color)
+				.ok_or_else(|| tr_with("error.unknown_key_color", &[("name", &color)]))?;
+			let coords = named_tile(line.next())?;
+			level_data.init_grid.get_mut(coords).unwrap().obj = if name == "key" {
+				Obj::Key { color }
+			} else {
+				Obj::Door { color }
+			};
+		},
+		// The condition applies to the events of the rest of the line (all the enemies of a group).
+		"event" if line.clone().next() == Some("when") => {
+			line.next();
+			let condition = EventCondition::parse(&mut line)?;
+			let events_before = level_data.init_events.len();
+			let event_line = std::iter::once("event")
+				.chain(line)
+				.collect::<Vec<_>>()
+				.join(" ");
+			parse_metadata(level_data, &event_line, context)?;
+			for event in &mut level_data.init_events[events_before..] {
+				event.condition = Some(condition);
+			}
+		},
+		"event" => match line.next().unwrap_or_default() {
+			"grant" => {
+				let what = line.next().unwrap_or_default();
+				let grant = Grant::from_name(what)
+					.ok_or_else(|| tr_with("error.unknown_grant", &[("name", &what)]))?;
+				// The count is optional, the turn is the last number.
+				let numbers: Vec<&str> = line.collect();
+				let (count, turn) = match numbers[..] {
+					[turn] => (1, parse_value(Some(turn), "event grant")?),
+					[count, turn] => (
+						parse_value(Some(count), "event grant")?,
+						parse_value(Some(turn), "event grant")?,
+					),
+					_ => return Err(tr_with("error.missing_value", &[("name", &"event grant")])),
+				};
+				level_data
+					.init_events
+					.push(GameEvent::new(turn, GameEventType::Grant(grant, count)));
+			},
+			"meteor" => {
+				let turn: u32 = parse_value(line.next(), "event meteor")?;
+				let seed: u64 = match line.next() {
+					Some(seed) => parse_value(Some(seed), "event meteor")?,
+					None => 0,
+				};
+				let grid = &level_data.init_grid;
+				let targets: Vec<Coords> = grid
+					.dims
+					.iter()
+					.filter(|&coords| {
+						let cell = grid.get(coords).unwrap();
+						matches!(cell.obj, Obj::Empty) && !cell.groud.is_liquid()
+					})
+					.collect();
+				if targets.is_empty() {
+					return Err(tr("error.no_meteor_target"));
+				}
+				// Not the same tile for the meteors of a level that have the same seed.
+				let mut rng = rng::Rng::with_seed(
+					seed ^ turn as u64 ^ (level_data.init_events.len() as u64) << 32,
+				);
+				let target = targets[rng.below(targets.len() as u32) as usize];
+				level_data
+					.init_events
+					.push(GameEvent::new(turn, GameEventType::Meteor(target)));
+			},
+			"terrain" => {
+				let tile_coords = named_tile(line.next())?;
+				let ground = line.next().unwrap_or_default();
+				let ground = Ground::from_name(ground)
+					.ok_or_else(|| tr_with("error.unknown_ground_name", &[("name", &ground)]))?;
+				let turn: u32 = parse_value(line.next(), "event terrain")?;
+				level_data.init_events.push(GameEvent::new(
+					turn,
+					GameEventType::TerrainChange(tile_coords, ground),
+				));
+			},
+			"spawn" => {
+				let creature = line.next().unwrap_or_default();
+				let enemy = Enemy::from_name(creature)
+					.ok_or_else(|| tr_with("error.unknown_enemy", &[("name", &creature)]))?;
+				let tile_coords = named_tile(line.next())?;
+				let turn: u32 = parse_value(line.next(), "event spawn")?;
+				level_data.init_events.push(GameEvent::new(
+					turn,
+					GameEventType::EnemySpawn(tile_coords, enemy, None),
+				));
+			},
+			"group" => {
+				let tile_coords = named_tile(line.next())?;
+				let turn: u32 = parse_value(line.next(), "event group")?;
+				let id = level_data
+					.init_events
+					.iter()
+					.filter_map(|event| match &event.event_type {
+						GameEventType::EnemySpawn(_, _, group) => group.map(|member| member.id + 1),
+						_ => None,
+					})
+					.max()
+					.unwrap_or(0);
+				let mut count = 0;
+				for (index, creature) in line.enumerate() {
+					let enemy = Enemy::from_name(creature)
+						.ok_or_else(|| tr_with("error.unknown_enemy", &[("name", &creature)]))?;
+					// In a column: one enemy per turn on the tile, the leader first.
+					let member = GroupMember { id, leader: index == 0 };
+					level_data.init_events.push(GameEvent::new(
+						turn + index as u32,
+						GameEventType::EnemySpawn(tile_coords, enemy, Some(member)),
+					));
+					count += 1;
+				}
+				if count == 0 {
+					return Err(tr("error.empty_group"));
+				}
+			},
+			other_event => return Err(tr_with("error.unknown_event", &[("name", &other_event)])),
+		},
+		unknown_meta_data_name => {
+			return Err(tr_with(
+				"error.unknown_metadata",
+				&[("name", &unknown_meta_data_name)],
+			))
+		},
+	}
+	Ok(())
+}
+
+/// The lines of a level file that are rows of the grid, with their index.
+fn grid_lines(level_raw_data: &str) -> impl Iterator<Item = (usize, &str)> {
+	level_raw_data
+		.split('\n')
+		.enumerate()
+		.filter(|(_, line)| !line.is_empty() && !line.starts_with('@') && !line.starts_with('~'))
+}
+
+fn at_line(line_index: usize, error: String) -> String {
+	tr_with(
+		"error.at_line",
+		&[("line", &(line_index + 1)), ("error", &error)],
+	)
+}
+
+/// Parse the grid of a level file, with the coordinates of the tiles named with `?name`.
+fn parse_grid(level_raw_data: &str) -> Result<(Grid<Cell>, HashMap<String, Coords>), String> {
+	let rows: Vec<(usize, &str)> = grid_lines(level_raw_data).collect();
+	let grid_w = rows
+		.first()
+		.ok_or_else(|| tr("error.empty_level"))?
+		.1
+		.split(char::is_whitespace)
+		.count();
+	let dims = Dimensions { w: grid_w as i32, h: rows.len() as i32 };
+	let mut grid: Grid<Cell> = Grid::new(
+		dims,
+		Cell {
+			obj: Obj::Empty,
+			groud: Ground::Grass,
+			rocky_path: false,
+			spikes: false,
+			conveyor: None,
+			portal: false,
+		},
+	);
+	let mut h: HashMap<String, Coords> = HashMap::new();
+	for (y, &(line_index, line)) in rows.iter().enumerate() {
+		let at_line = |error: String| at_line(line_index, error);
+		let tiles: Vec<&str> = line.split(char::is_whitespace).collect();
+		if tiles.len() != grid_w {
+			return Err(at_line(tr_with(
+				"error.row_width",
+				&[("count", &tiles.len()), ("width", &grid_w)],
+			)));
+		}
+		for (x, &tile) in tiles.iter().enumerate() {
+			let coords = Coords { x: x as i32, y: y as i32 };
+			if tile.is_empty() {
+				return Err(at_line(tr("error.empty_tile")));
+			}
+			if let Some(name) = tile.strip_prefix('?') {
+				if name.is_empty() {
+					return Err(at_line(tr_with("error.bad_tile", &[("tile", &tile)])));
+				}
+				if h.insert(name.to_string(), coords).is_some() {
+					return Err(at_line(tr_with(
+						"error.duplicate_tile_name",
+						&[("name", &name)],
+					)));
+				}
+			} else {
+				*grid.get_mut(coords).unwrap() = parse_tile_text(tile).map_err(at_line)?;
+			}
+		}
+	}
+	Ok((grid, h))
+}
+
+/// Add the tiles named by coordinates with `@name <name> <x> <y>` to the named tiles, so that
+/// the other metadata can refer to them wherever they are in the file. Returns the errors.
+fn parse_tile_names(
+	level_raw_data: &str,
+	dims: Dimensions,
+	named_tiles: &mut HashMap<String, Coords>,
+) -> Vec<String> {
+	let mut errors = vec![];
+	for (line_index, line) in level_raw_data.split('\n').enumerate() {
+		let Some(line) = line.strip_prefix("@name") else {
+			continue;
+		};
+		let mut words = line.split_whitespace();
+		let result = (|| {
+			let name = words
+				.next()
+				.ok_or_else(|| tr_with("error.missing_value", &[("name", &"name")]))?;
+			let coords = Coords {
+				x: parse_value(words.next(), "name")?,
+				y: parse_value(words.next(), "name")?,
+			};
+			if !dims.contains(coords) {
+				return Err(tr_with("error.outside_grid", &[("coords", &coords)]));
+			}
+			if named_tiles.insert(name.to_string(), coords).is_some() {
+				return Err(tr_with("error.duplicate_tile_name", &[("name", &name)]));
+			}
+			Ok(())
+		})();
+		if let Err(error) = result {
+			errors.push(at_line(line_index, error));
+		}
+	}
+	errors
+}
+
+/// Errors are messages for the player. The files that the level refers to are looked for in
+/// the `levels` directory.
+#[cfg(test)]
+fn parse_level(level_raw_data: &str) -> Result<LevelData, String> {
+	parse_level_in(level_raw_data, std::path::Path::new("levels"))
+}
+
+/// Like `parse_level`, with the files that the level refers to looked for in `dir`.
+fn parse_level_in(level_raw_data: &str, dir: &std::path::Path) -> Result<LevelData, String> {
+	parse_level_at_depth(level_raw_data, dir, 0)
+}
+
+/// The first `@include` of a level without tiles, which makes it a variant of the included
+/// level (that gives its tiles and metadata).
+fn variant_base(level_raw_data: &str) -> Option<(usize, &str)> {
+	if grid_lines(level_raw_data).next().is_some() {
+		return None;
+	}
+	level_raw_data
+		.split('\n')
+		.enumerate()
+		.find_map(|(line_index, line)| Some((line_index, line.strip_prefix("@include ")?.trim())))
+}
+
+fn parse_level_at_depth(
+	level_raw_data: &str,
+	dir: &std::path::Path,
+	include_depth: u32,
+) -> Result<LevelData, String> {
+	let (mut level_data, mut h, base_line) = match variant_base(level_raw_data) {
+		Some((line_index, base)) => {
+			let base_file = dir.join(base);
+			let base_data = (|| {
+				if include_depth >= MetadataContext::MAX_INCLUDE_DEPTH {
+					return Err(tr("error.include_depth"));
+				}
+				let base_raw_data = fs::read_to_string(&base_file).map_err(|error| {
+					tr_with(
+						"error.file_unreadable",
+						&[("file", &base_file.display()), ("error", &error)],
+					)
+				})?;
+				let base_dir = base_file.parent().unwrap_or(dir);
+				parse_level_at_depth(&base_raw_data, base_dir, include_depth + 1).map_err(|error| {
+					tr_with(
+						"error.in_file",
+						&[("file", &base_file.display()), ("error", &error)],
+					)
+				})
+			})()
+			.map_err(|error| at_line(line_index, error))?;
+			let h = base_data.named_tiles.clone();
+			(base_data, h, Some(line_index))
+		},
+		None => {
+			let (grid, h) = parse_grid(level_raw_data)?;
+			(LevelData::new(grid), h, None)
+		},
+	};
+	if let Some(error) = parse_tile_names(level_raw_data, level_data.init_grid.dims, &mut h)
+		.into_iter()
+		.next()
+	{
+		return Err(error);
+	}
+	for (line_index, line) in level_raw_data.split('\n').enumerate() {
+		if Some(line_index) == base_line {
+			continue;
+		}
+		if let Some(line) = line.strip_prefix('@') {
+			let context = MetadataContext { named_tiles: &h, dir, include_depth };
+			parse_metadata(&mut level_data, line, &context)
+				.map_err(|error| at_line(line_index, error))?;
+		}
+	}
+	log::info!("max_towers: {x:?}", x = level_data.max_towers);
+	level_data.named_tiles = h;
+	for transform in std::mem::take(&mut level_data.transforms) {
+		level_data.transform(transform);
+	}
+	mark_portals(&mut level_data);
+	Ok(level_data)
+}
+
+/// The enemies of the events come out of portals, so the tiles they spawn on get one (on top
+/// of the ones of the grid).
+fn mark_portals(level_data: &mut LevelData) {
+	for event in &level_data.init_events {
+		if let GameEventType::EnemySpawn(coords, ..) = event.event_type {
+			if let Some(cell) = level_data.init_grid.get_mut(coords) {
+				cell.portal = true;
+			}
+		}
+	}
+}
+
+/// Sets the distances (along the path) to the goal in the path tiles, walls count as very long
+/// so that enemies go around them when they can, and closed doors can't be gone through at all.
+fn compute_distance(grid: &mut Grid<Cell>) {
+	let goal = 'goal_find: {
+		for coords in grid.dims.iter() {
+			if matches!(grid.get(coords).unwrap().obj, Obj::Goal { .. }) {
+				break 'goal_find coords;
+			}
+		}
+		log::warn!("Didn't find a goal on the level");
+		return;
+	};
+	for coords in grid.dims.iter() {
+		let cell = grid.get_mut(coords).unwrap();
+		if let Ground::Path(dist) = &mut cell.groud {
+			*dist = -1;
+		}
+	}
+	let wall_penalty = grid.dims.area();
+	grid.get_mut(goal).unwrap().groud = Ground::Path(0);
+	let mut queue = BinaryHeap::new();
+	queue.push(Reverse((0, goal.x, goal.y)));
+	while let Some(Reverse((depth, x, y))) = queue.pop() {
+		let coords = Coords::from((x, y));
+		if matches!(grid.get(coords).unwrap().groud, Ground::Path(dist) if dist < depth) {
+			continue;
+		}
+		for dd in DxDy::the_4_directions() {
+			let dst = coords + dd;
+			let Some(cell) = grid.get_mut(dst) else {
+				continue;
+			};
+			if matches!(cell.obj, Obj::Door { .. }) {
+				continue;
+			}
+			let dst_depth = depth
+				+ 1 + if matches!(cell.obj, Obj::Wall { .. }) {
+				wall_penalty
+			} else {
+				0
+			};
+			if let Ground::Path(dist) = &mut cell.groud {
+				if *dist == -1 || *dist > dst_depth {
+					*dist = dst_depth;
+					queue.push(Reverse((dst_depth, dst.x, dst.y)));
+				}
+			}
+		}
+	}
+}
+
+fn _print_dist(grid: &Grid<Cell>) {
+	for y in 0..grid.dims.h {
+		for x in 0..grid.dims.w {
+			match grid.get((x, y).into()).unwrap().groud {
+				Ground::Path(d) => print!("{d:2} "),
+				_ => print!(" - "),
+			}
+		}
+		println!();
+	}
+	println!();
+}
+
+/// The player does the given action, and then everything else in the level moves.
+/// Run `f`, turning a panic into an error message for the error screen (the panic itself is
+/// still reported by the panic hook), so that a bug doesn't bring the whole game down.
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+	std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|payload| {
+		let message = match payload.downcast::<String>() {
+			Ok(message) => *message,
+			Err(payload) => payload
+				.downcast_ref::<&str>()
+				.map(|message| message.to_string())
+				.unwrap_or_default(),
+		};
+		tr_with("error.crash", &[("error", &message)])
+	})
+}
+
+fn play_turn(level: &mut LevelState, dd: DxDy, action: PlayerAction) {
+	level.stats.turn_events.clear();
+	level.stats.turn_kills.clear();
+	level.actions += 1;
+	match action {
+		PlayerAction::SwitchUnit => {
+			let units = level.units_to_act().len();
+			level.active_unit = (level.active_unit + 1) % units.max(1);
+			return;
+		},
+		PlayerAction::Craft { recipe } => {
+			craft(level, recipe);
+			return;
+		},
+		_ => {},
+	}
+	let goals: Vec<Coords> = level
+		.grid
+		.dims
+		.iter()
+		.filter(|&coords| matches!(level.grid.get(coords).unwrap().obj, Obj::Goal { .. }))
+		.collect();
+	log::debug!(
+		"Turn {}: {}",
+		level.turn,
+		protocol::action_line(dd, &action)
+	);
+	if let Some(unit_coords) = player_move(level, dd, action) {
+		level.units_acted.push(unit_coords);
+	}
+	// The rest of the level waits for all the units to act.
+	if level.player_coords().is_some() {
+		if let Some(script) = level.script.clone() {
+			script.after_turn(level);
+		}
+		level.update_visibility();
+		hints::check(level);
+		return;
+	}
+	let mut units = std::mem::take(&mut level.units_acted);
+	level.active_unit = 0;
+	level.advance_turn(&goals, &mut units);
+	for unit_coords in units {
+		use_heart_if_killed(level, unit_coords);
+		lose_life_if_killed(level, unit_coords);
+	}
+	if let Some(script) = level.script.clone() {
+		script.after_turn(level);
+	}
+	if level.turns_left() == Some(0) && !level.is_won() {
+		log::debug!("Out of turns");
+		level.game_joever = true;
+	}
+	level.update_visibility();
+	hints::check(level);
+}
+
+/// What happens once the player (all the units) acted, in the order of `TURN_PHASES`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TurnPhase {
+	/// See `burns_tick`.
+	Burns,
+	/// Unless the weather keeps them from it (see `Weather::enemies_move`).
+	EnemiesMove,
+	/// The goals destroyed cost lives (see `lose_lives_for_destroyed_goals`), the level ends
+	/// here if it is lost.
+	GoalsDamaged,
+	/// See `bomb_move`.
+	Bombs,
+	/// See `weather::wind_blows`.
+	Wind,
+	Flowers,
+	/// See `towers_move`.
+	Towers,
+	/// See `conveyors_move`.
+	Conveyors,
+	/// See `spikes_strike`, every other turn.
+	Spikes,
+	/// See `lava_burns`.
+	Lava,
+	/// See `shields_regenerate`.
+	Shields,
+	/// See `groups_lose_leaders`.
+	GroupLeaders,
+	/// See `bosses_change_phase`.
+	BossPhases,
+	/// See `enemies_emerge`.
+	EnemiesEmerge,
+	/// The turn number goes up, the phases after this one are the start of the next turn.
+	NextTurn,
+	/// See `apply_events`.
+	Events,
+	/// See `regrow_trees`.
+	TreesRegrow,
+}
+
+/// The phases of a turn, in order. A new phase goes in there (and in `TurnPhase::play`).
+const TURN_PHASES: [TurnPhase; 17] = [
+	TurnPhase::Burns,
+	TurnPhase::EnemiesMove,
+	TurnPhase::GoalsDamaged,
+	TurnPhase::Bombs,
+	TurnPhase::Wind,
+	TurnPhase::Flowers,
+	TurnPhase::Towers,
+	TurnPhase::Conveyors,
+	TurnPhase::Spikes,
+	TurnPhase::Lava,
+	TurnPhase::Shields,
+	TurnPhase::GroupLeaders,
+	TurnPhase::BossPhases,
+	TurnPhase::EnemiesEmerge,
+	TurnPhase::NextTurn,
+	TurnPhase::Events,
+	TurnPhase::TreesRegrow,
+];
+
+impl TurnPhase {
+	/// `goals` are where the goals were at the start of the turn, and `units` where the units
+	/// are (the phases that move them update it).
+	fn play(self, level: &mut LevelState, goals: &[Coords], units: &mut [Coords]) {
+		let weather = level.weather();
+		let (grid, stats) = (&mut level.grid, &mut level.stats);
+		match self {
+			TurnPhase::Burns => burns_tick(grid, stats),
+			TurnPhase::EnemiesMove => {
+				if weather.enemies_move(level.turn) {
+					enemies_move(grid, stats);
+				}
+			},
+			TurnPhase::GoalsDamaged => {
+				lose_lives_for_destroyed_goals(level, goals);
+				level.game_joever = is_game_joever(&level.grid);
+			},
+			TurnPhase::Bombs => bomb_move(grid, stats, level.friendly_fire),
+			TurnPhase::Wind => {
+				if let Weather::Wind(direction) = weather {
+					weather::wind_blows(grid, stats, direction);
+				}
+			},
+			TurnPhase::Flowers => flowers_move(grid),
+			TurnPhase::Towers => towers_move(grid, stats, level.knockback, weather),
+			TurnPhase::Conveyors => {
+				for (from, to) in conveyors_move(grid, stats) {
+					// The units carried away are not dead.
+					if let Some(unit) = units.iter_mut().find(|unit| **unit == from) {
+						*unit = to;
+					}
+				}
+			},
+			TurnPhase::Spikes => {
+				if level.spikes_armed() {
+					spikes_strike(&mut level.grid, &mut level.stats);
+				}
+			},
+			TurnPhase::Lava => lava_burns(grid, stats, level.friendly_fire),
+			TurnPhase::Shields => shields_regenerate(grid),
+			TurnPhase::GroupLeaders => groups_lose_leaders(grid),
+			TurnPhase::BossPhases => bosses_change_phase(grid, stats),
+			TurnPhase::EnemiesEmerge => enemies_emerge(grid),
+			TurnPhase::NextTurn => level.turn += 1,
+			TurnPhase::Events => apply_events(level),
+			TurnPhase::TreesRegrow => regrow_trees(level),
+		}
+	}
+}
+
+impl LevelState {
+	/// Play the phases of the turn (see `TURN_PHASES`) once the units acted, until the level
+	/// ends.
+	fn advance_turn(&mut self, goals: &[Coords], units: &mut [Coords]) {
+		for phase in TURN_PHASES {
+			if self.game_joever {
+				break;
+			}
+			phase.play(self, goals, units);
+		}
+	}
+}
+
+/// Bring the player back where they were (or next to it if there is something there now),
+/// returns `false` if there is no room for them.
+fn respawn_player(level: &mut LevelState, player_coords: Coords) -> bool {
+	let respawn_coords = std::iter::once(player_coords)
+		.chain(DxDy::the_4_directions().map(|dd| player_coords + dd))
+		.find(|&coords| {
+			level
+				.grid
+				.get(coords)
+				.is_some_and(|cell| matches!(cell.obj, Obj::Empty) && !cell.groud.is_liquid())
+		});
+	if let Some(respawn_coords) = respawn_coords {
+		level.grid.get_mut(respawn_coords).unwrap().obj = Obj::Player { stunned: false };
+	}
+	respawn_coords.is_some()
+}
+
+fn is_unit_at(level: &LevelState, coords: Coords) -> bool {
+	matches!(level.grid.get(coords).unwrap().obj, Obj::Player { .. })
+}
+
+/// If the unit that was there got killed this turn, a heart (if any) brings it back.
+fn use_heart_if_killed(level: &mut LevelState, player_coords: Coords) {
+	if is_unit_at(level, player_coords) || level.inventory.hearts == 0 {
+		return;
+	}
+	if respawn_player(level, player_coords) {
+		level.inventory.hearts -= 1;
+	}
+}
+
+/// If the unit that was there got killed this turn (and no heart saved it), a life is lost,
+/// it comes back if there are lives left and the level is lost otherwise.
+fn lose_life_if_killed(level: &mut LevelState, player_coords: Coords) {
+	if level.lives.is_none_or(|lives| lives == 0) || is_unit_at(level, player_coords) {
+		return;
+	}
+	let lives = level.lives.as_mut().unwrap();
+	*lives -= 1;
+	if *lives == 0 {
+		level.game_joever = true;
+	} else {
+		respawn_player(level, player_coords);
+	}
+}
+
+/// Each goal that got destroyed this turn costs a life, it is then rebuilt
+/// (getting rid of the enemy that destroyed it) if there are lives left.
+fn lose_lives_for_destroyed_goals(level: &mut LevelState, goals: &[Coords]) {
+	for &coords in goals {
+		let Some(lives) = &mut level.lives else {
+			return;
+		};
+		if *lives == 0 || matches!(level.grid.get(coords).unwrap().obj, Obj::Goal { .. }) {
+			continue;
+		}
+		*lives -= 1;
+		if *lives > 0 {
+			level.grid.get_mut(coords).unwrap().obj = Obj::Goal { hp: level.goal_hp_max };
+		}
+	}
+}
+
+fn is_game_joever(grid: &Grid<Cell>) -> bool {
+	for coords in grid.dims.iter() {
+		if matches!(grid.get(coords).unwrap().obj, Obj::Goal { .. }) {
+			return false;
+		}
+	}
+	true
+}
+
+/// Lines of text that describe what is in the given cell, for the player to inspect it.
+fn cell_description(level: &LevelState, coords: Coords) -> Vec<String> {
+	let cell = level.grid.get(coords).unwrap();
+	let mut lines = vec![];
+	let ground = match cell.groud {
+		Ground::Grass => tr("inspect.grass"),
+		Ground::Water => tr("inspect.water"),
+		Ground::Lava => tr("inspect.lava"),
+		Ground::Path(-1) => tr("inspect.path_nowhere"),
+		Ground::Path(dist) => tr_with("inspect.path", &[("dist", &dist)]),
+	};
+	lines.push(if cell.rocky_path {
+		tr_with("inspect.rocky", &[("ground", &ground)])
+	} else {
+		ground
+	});
+	for event in level.events.iter().filter(|event| event.turn > level.turn) {
+		match &event.event_type {
+			GameEventType::TerrainChange(event_coords, ground) if *event_coords == coords => {
+				let ground = tr(&format!("ground.{}", ground.name()));
+				lines.push(tr_with(
+					"inspect.terrain_change",
+					&[("ground", &ground), ("turn", &event.turn)],
+				));
+			},
+			GameEventType::Meteor(event_coords) if *event_coords == coords => {
+				lines.push(tr_with("inspect.meteor", &[("turn", &event.turn)]));
+			},
+			_ => {},
+		}
+	}
+	if let Some(direction) = cell.conveyor {
+		let direction = tr(&format!("direction.{}", direction.name()));
+		lines.push(tr_with("inspect.conveyor", &[("direction", &direction)]));
+	}
+	if cell.portal {
+		lines.push(tr("inspect.portal"));
+	}
+	if cell.spikes {
+		lines.push(tr(if level.spikes_armed() {
+			"inspect.spikes_armed"
+		} else {
+			"inspect.spikes_down"
+		}));
+	}
+	if !level.is_visible(coords) {
+		lines.push(tr("inspect.fog"));
+		return lines;
+	}
+	match &cell.obj {
+		Obj::Empty => {},
+		Obj::Player { stunned } => lines.push(tr(if *stunned {
+			"inspect.player_stunned"
+		} else {
+			"inspect.player"
+		})),
+		Obj::Goal { hp } => lines.push(tr_with(
+			"inspect.goal",
+			&[("hp", hp), ("hp_max", &level.goal_hp_max)],
+		)),
+		Obj::Enemy { variant, hp, effects } => {
+			let name = tr(&format!("enemy.{}", variant.name()));
+			lines.push(tr_with(
+				"inspect.enemy",
+				&[("name", &name), ("hp", hp), ("hp_max", &variant.hp_max())],
+			));
+			if let Enemy::Protected { direction, protection } = variant {
+				let protected_sides: Vec<String> = Direction::ALL
+					.into_iter()
+					.filter(|&side| !protection.is_hurt_by_shot(*direction, side))
+					.map(|side| tr(&format!("direction.{}", side.name())))
+					.collect();
+				let direction = tr(&format!("direction.{}", direction.name()));
+				lines.push(tr_with("inspect.facing", &[("direction", &direction)]));
+				lines.push(tr_with(
+					"inspect.protected_from",
+					&[("sides", &protected_sides.join(" "))],
+				));
+			}
+			if let Enemy::Burrower { underground: 1.. } = variant {
+				lines.push(tr("inspect.underground"));
+			}
+			if effects.emerging {
+				lines.push(tr("inspect.emerging"));
+			}
+			if let Some(shield) = variant.shield() {
+				lines.push(tr_with(
+					"inspect.shield",
+					&[
+						("shield", &shield),
+						("shield_max", &balance::get().shield_hp),
+					],
+				));
+			}
+			let behaviors = variant.boss_behaviors();
+			if !behaviors.is_empty() {
+				let behaviors: Vec<String> = behaviors
+					.into_iter()
+					.map(|behavior| tr(&format!("boss_behavior.{}", behavior.name())))
+					.collect();
+				lines.push(tr_with(
+					"inspect.boss_phase",
+					&[("behaviors", &behaviors.join(", "))],
+				));
+			}
+			if effects.burn > 0 {
+				lines.push(tr_with("inspect.burning", &[("turns", &effects.burn)]));
+			}
+		},
+		Obj::Tower { variant, stunned, cooldown, tier, ammo, facing, .. } => {
+			let name = tr(&format!("tower.{}", variant.name()));
+			lines.push(tr_with("inspect.tower", &[("name", &name), ("tier", tier)]));
+			let range = level.weather().tower_range(variant.range());
+			if range != i32::MAX {
+				lines.push(tr_with("inspect.range", &[("range", &range)]));
+			}
+			if *variant == Tower::Cannon {
+				let direction = tr(&format!("direction.{}", facing.name()));
+				lines.push(tr_with("inspect.facing", &[("direction", &direction)]));
+			}
+			if let (Some(ammo), Some(ammo_max)) = (ammo, balance::get().tower_ammo(variant)) {
+				lines.push(tr_with(
+					"inspect.ammo",
+					&[("ammo", ammo), ("ammo_max", &ammo_max)],
+				));
+			}
+			if *cooldown > 0 {
+				lines.push(tr_with("inspect.reloading", &[("cooldown", cooldown)]));
+			}
+			if *stunned {
+				lines.push(tr("inspect.stunned"));
+			}
+		},
+		Obj::Bomb { countdown } => lines.push(tr_with("inspect.bomb", &[("countdown", countdown)])),
+		Obj::Flower { .. } => lines.push(tr("inspect.flower")),
+		Obj::Rock => lines.push(tr("inspect.rock")),
+		Obj::Tree { chopped: false } => lines.push(tr("inspect.tree")),
+		Obj::Tree { chopped: true } => lines.push(tr("inspect.tree_chopped")),
+		Obj::Mine => lines.push(tr("inspect.mine")),
+		Obj::Wall { hp } => lines.push(tr_with(
+			"inspect.wall",
+			&[("hp", hp), ("hp_max", &balance::get().wall_hp)],
+		)),
+		Obj::Item { item } => lines.push(tr(&format!("item.{}", item.name()))),
+		Obj::Key { color } => {
+			let color = tr(&format!("key_color.{}", color.name()));
+			lines.push(tr_with("inspect.key", &[("color", &color)]));
+		},
+		Obj::Door { color } => {
+			let color = tr(&format!("key_color.{}", color.name()));
+			lines.push(tr_with("inspect.door", &[("color", &color)]));
+		},
+	}
+	lines
+}
+
+/// The menus that can be opened over the level.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Screen {
+	/// The main menu, shown when the game is launched without arguments.
+	Title,
+	Playing,
+	Paused,
+	LevelSelect,
+	Settings,
+	/// Something went wrong (see `catch_panic`), the player can go back to the title screen.
+	Error,
+	/// The level ended with a high score, the player enters a name for it.
+	NameEntry,
+	/// How the level ended, with its high scores.
+	Results,
+	/// The player pastes the share code of a replay to watch it.
+	WatchReplay,
+	/// The recipes of a crafting level, to spend its resources.
+	Crafting,
+	/// Quitting in the middle of a level, which loses it, is confirmed first (see
+	/// `quit_is_confirmed`).
+	ConfirmQuit,
+}
+
+/// Quitting from that screen asks for a confirmation first: in the middle of a level that the
+/// player plays (which would be lost), unless the option is off.
+fn quit_is_confirmed(
+	config: &config::Config,
+	level: &LevelState,
+	screen: Screen,
+	by_hand: bool,
+) -> bool {
+	config.confirm_quit
+		&& by_hand
+		&& matches!(screen, Screen::Playing | Screen::Paused)
+		&& level.actions > 0
+		&& !level.game_joever
+		&& !level.is_won()
+}
+
+/// Draw everything that is in a cell of the grid (its ground, object, life bar, fog, etc.),
+/// `object_effects` apply to the object, which is not drawn if it is `None` (when it is
+/// drawn elsewhere, like while it slides, see `slides`).
+fn draw_cell(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	spritesheet: &Spritesheet,
+	view: &grid_layer::CellView,
+	coords: Coords,
+	cell_pixel_side: i32,
+) {
+	let dst = Rect::tile(coords, cell_pixel_side);
+	let ground_effects = SpriteEffects { theme: view.theme, ..SpriteEffects::default() };
+	let sprite_rect = ground_sprite(spritesheet, &view.cell.groud, view.autotile_mask);
+	draw_sprite_with_effects(
+		pixel_buffer,
+		pixel_buffer_dims,
+		dst,
+		spritesheet,
+		sprite_rect,
+		ground_effects,
+	);
+	if matches!(view.cell.groud, Ground::Lava) {
+		draw_rect(pixel_buffer, pixel_buffer_dims, dst, [235, 90, 20, 200]);
+	}
+	if view.cell.rocky_path {
+		let sprite_rect = spritesheet.sprite("ground.rocky");
+		draw_sprite_with_effects(
+			pixel_buffer,
+			pixel_buffer_dims,
+			dst,
+			spritesheet,
+			sprite_rect,
+			ground_effects,
+		);
+	}
+	if let Some(direction) = view.cell.conveyor {
+		// A belt with an arrow the way it goes.
+		draw_rect(pixel_buffer, pixel_buffer_dims, dst, [70, 70, 80, 160]);
+		let mut arrow = dst;
+		arrow.top_left += DxDy::from((dst.dims.w / 4, dst.dims.h / 4));
+		arrow.dims = Dimensions::square(dst.dims.w / 2);
+		draw_sprite_with_effects(
+			pixel_buffer,
+			pixel_buffer_dims,
+			arrow,
+			spritesheet,
+			spritesheet.sprite("ui.intent"),
+			SpriteEffects::rotated_towards(direction),
+		);
+	}
+	if view.cell.portal {
+		draw_sprite_with_effects(
+			pixel_buffer,
+			pixel_buffer_dims,
+			dst,
+			spritesheet,
+			spritesheet.sprite("ground.portal"),
+			ground_effects,
+		);
+	}
+	if view.cell.spikes {
+		// Spikes come out of the holes when the trap is about to strike.
+		let sprite_name = if view.spikes_armed {
+			"ground.spikes_armed"
+		} else {
+			"ground.spikes"
+		};
+		draw_sprite_with_effects(
+			pixel_buffer,
+			pixel_buffer_dims,
+			dst,
+			spritesheet,
+			spritesheet.sprite(sprite_name),
+			ground_effects,
+		);
+	}
+	if view.is_visible && view.has_corpse {
+		let sprite_rect = spritesheet.sprite("ground.corpse");
+		draw_sprite(
+			pixel_buffer,
+			pixel_buffer_dims,
+			dst,
+			spritesheet,
+			sprite_rect,
+		);
+	}
+	if let Some(object_effects) = view.object_effects {
+		draw_obj(
+			pixel_buffer,
+			pixel_buffer_dims,
+			spritesheet,
+			view,
+			dst,
+			object_effects,
+		);
+	}
+	if !view.is_visible {
+		darken_rect(pixel_buffer, pixel_buffer_dims, dst);
+	}
+}
+
+/// Towers show at most that many ammo pips.
+const AMMO_PIPS: u32 = 5;
+
+/// Draw the object of a cell (with its life bar, chevron, etc.) at `dst`, which is usually
+/// the tile of the cell but not while the object slides.
+fn draw_obj(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	spritesheet: &Spritesheet,
+	view: &grid_layer::CellView,
+	dst: Rect,
+	mut effects: SpriteEffects,
+) {
+	let cell_pixel_side = dst.dims.w;
+	if let Obj::Rock | Obj::Tree { .. } | Obj::Flower { .. } = view.cell.obj {
+		// The scenery follows the theme of the level, like the ground.
+		effects.theme = view.theme;
+	}
+	if let Obj::Tree { chopped: true } = view.cell.obj {
+		effects.opacity = Some(effects.opacity.unwrap_or(255) / 3 * 2);
+	}
+	if let Obj::Key { color } | Obj::Door { color } = view.cell.obj {
+		// Their sprites are grey, tinted with their color.
+		effects.tint = Some(effects.tint.unwrap_or(color.tint()));
+	}
+	if let Obj::Tower { variant: Tower::Cannon, facing, .. } = view.cell.obj {
+		// The sprite faces north, it is turned the way the cannon shoots.
+		effects.quarter_turns = SpriteEffects::rotated_towards(facing).quarter_turns;
+	}
+	let sprite_name = match view.cell.obj {
+		Obj::Empty => None,
+		Obj::Player { .. } => Some(String::from("player.player")),
+		Obj::Goal { .. } => Some(String::from("player.goal")),
+		Obj::Enemy { ref variant, .. } => Some(variant.sprite_name()),
+		Obj::Tower { ref variant, .. } => Some(format!("tower.{}", variant.name())),
+		Obj::Bomb { countdown: countdown @ 0..=3 } => Some(format!("bomb.{countdown}")),
+		Obj::Bomb { .. } => unimplemented!(),
+		Obj::Flower { variant: Flower::BlueFlower } => Some(String::from("flower.blue")),
+		Obj::Flower { variant: Flower::TheOther } => Some(String::from("flower.the_other")),
+		Obj::Flower { variant: Flower::TheOtherOther } => {
+			Some(String::from("flower.the_other_other"))
+		},
+		Obj::Rock => Some(String::from("obj.rock")),
+		Obj::Mine => Some(String::from("obj.mine")),
+		Obj::Wall { .. } => Some(String::from("obj.wall")),
+		Obj::Item { item } => Some(item.sprite_name()),
+		Obj::Key { .. } => Some(String::from("obj.key")),
+		Obj::Door { .. } => Some(String::from("obj.door")),
+		Obj::Tree { .. } => Some(String::from("obj.tree")),
+	};
+	// Enemies in the fog of war are hidden.
+	let hidden = !view.is_visible && matches!(view.cell.obj, Obj::Enemy { .. });
+	if let Some(sprite_name) = sprite_name.filter(|_| !hidden) {
+		let sprite_rect = spritesheet.sprite(&sprite_name);
+		draw_sprite_with_effects(
+			pixel_buffer,
+			pixel_buffer_dims,
+			dst,
+			spritesheet,
+			sprite_rect,
+			effects,
+		);
+	}
+	if let (false, Obj::Enemy { effects: StatusEffects { burn: 1.., .. }, .. }) =
+		(hidden, &view.cell.obj)
+	{
+		// Flames in the bottom left corner of burning enemies.
+		let flame = Rect {
+			top_left: dst.top_left + DxDy::from((0, cell_pixel_side / 2)),
+			dims: Dimensions::square(cell_pixel_side / 2),
+		};
+		draw_sprite(
+			pixel_buffer,
+			pixel_buffer_dims,
+			flame,
+			spritesheet,
+			spritesheet.sprite("ui.flame"),
+		);
+	}
+	let marker = match &view.cell.obj {
+		Obj::Enemy { variant, .. } if !hidden => Some(variant.marker()),
+		Obj::Tower { variant, .. } => Some(variant.marker()),
+		_ => None,
+	};
+	if let Some(marker) = marker.filter(|_| view.colorblind) {
+		// In the bottom right corner, on a dark background to be readable over any sprite.
+		let scale = (cell_pixel_side / 32).max(1);
+		let background = Rect {
+			top_left: dst.top_left
+				+ DxDy::from((cell_pixel_side - 5 * scale, cell_pixel_side - 7 * scale)),
+			dims: Dimensions { w: 5 * scale, h: 7 * scale },
+		};
+		draw_rect(pixel_buffer, pixel_buffer_dims, background, [0, 0, 0, 200]);
+		draw_text(
+			pixel_buffer,
+			pixel_buffer_dims,
+			background.top_left,
+			scale,
+			spritesheet,
+			&marker.to_string(),
+			[255, 255, 255, 255],
+		);
+	}
+	if let Obj::Tower { tier: 2.., .. } = view.cell.obj {
+		// Merged towers wear a chevron.
+		draw_sprite(
+			pixel_buffer,
+			pixel_buffer_dims,
+			dst,
+			spritesheet,
+			spritesheet.sprite("tower.tier"),
+		);
+	}
+	if let Obj::Tower { variant, ammo: Some(ammo), .. } = &view.cell.obj {
+		// Ammo pips under the sprite, each one standing for a share of a full tower's shots.
+		let ammo_max = balance::get().tower_ammo(variant).unwrap_or(*ammo).max(1);
+		let pips = ammo_max.min(AMMO_PIPS);
+		let full_pips = (ammo * pips).div_ceil(ammo_max);
+		let side = (cell_pixel_side / 10).max(1);
+		let gap = side / 2;
+		let width = pips as i32 * (side + gap) - gap;
+		for pip in 0..pips {
+			let pip_rect = Rect {
+				top_left: dst.top_left
+					+ DxDy::from((
+						(cell_pixel_side - width) / 2 + pip as i32 * (side + gap),
+						cell_pixel_side - side - 1,
+					)),
+				dims: Dimensions { w: side, h: side },
+			};
+			let color = if pip < full_pips {
+				[255, 220, 60, 255]
+			} else {
+				[40, 40, 40, 200]
+			};
+			draw_rect(pixel_buffer, pixel_buffer_dims, pip_rect, color);
+		}
+	}
+	if let (false, Obj::Enemy { variant, hp, .. }) = (hidden, &view.cell.obj) {
+		// Draw a life bar, with the shield bar over it for shielded enemies.
+		let mut bar = dst;
+		bar.top_left.y += cell_pixel_side / 8;
+		bar.dims.h = cell_pixel_side / 8;
+		bar.top_left.x += cell_pixel_side / 8;
+		bar.dims.w = cell_pixel_side * 6 / 8;
+		// Enemies that spawned at night can have more than their max HP.
+		let hp = (*hp).min(variant.hp_max());
+		draw_bar(
+			pixel_buffer,
+			pixel_buffer_dims,
+			bar,
+			(hp, variant.hp_max()),
+			life_bar_colors(),
+		);
+		if let Some(shield) = variant.shield() {
+			bar.top_left.y -= bar.dims.h;
+			draw_bar(
+				pixel_buffer,
+				pixel_buffer_dims,
+				bar,
+				(shield, balance::get().shield_hp),
+				shield_bar_colors(),
+			);
+		}
+	}
+	if let (Obj::Goal { hp }, Some(goal_hp_max @ 2..)) = (&view.cell.obj, view.goal_hp_max) {
+		// Draw a big life bar over the goal.
+		let mut bar = dst;
+		bar.dims.h = cell_pixel_side / 4;
+		draw_bar(
+			pixel_buffer,
+			pixel_buffer_dims,
+			bar,
+			(*hp, goal_hp_max),
+			life_bar_colors(),
+		);
+	}
+	if let Obj::Player { stunned: true } | Obj::Tower { stunned: true, .. } = &view.cell.obj {
+		let mut dst = dst;
+		dst.dims.w /= 4;
+		dst.dims.h /= 4;
+		dst.top_left.x += 6 * cell_pixel_side / 8;
+		draw_rect(pixel_buffer, pixel_buffer_dims, dst, [255, 255, 0, 255]);
+	}
+}
+
+/// How long the toasts of what the player was given during a turn are shown.
+const TOAST_DURATION: std::time::Duration = std::time::Duration::from_millis(2500);
+
+/// The toasts of what the player was given during the last turn.
+fn toasts(level: &LevelState) -> Vec<String> {
+	level
+		.stats
+		.turn_events
+		.iter()
+		.filter_map(|event| match *event {
+			TurnEvent::Granted(grant, count) => Some(grant.toast(count)),
+			_ => None,
+		})
+		.collect()
+}
+
+/// How long pushed or pulled objects take to slide to their new cell.
+const SLIDE_DURATION: std::time::Duration = std::time::Duration::from_millis(120);
+
+/// The objects that were pushed or pulled during the last turn, as `(from, to)` pairs, so
+/// that they can be drawn sliding from a cell to the other instead of teleporting. An object
+/// pushed several times in a turn only slides for its last push, and the objects that are no
+/// longer there (like the ones that sank in water) or are hidden in the fog don't slide.
+fn slides(level: &LevelState) -> Vec<(Coords, Coords)> {
+	let mut slides: Vec<(Coords, Coords)> = vec![];
+	for event in &level.stats.turn_events {
+		if let TurnEvent::Slid { from, to } = *event {
+			slides.retain(|&(_, other_to)| other_to != from && other_to != to);
+			slides.push((from, to));
+		}
+	}
+	slides.retain(|&(_, to)| {
+		let obj = &level.grid.get(to).unwrap().obj;
+		!matches!(obj, Obj::Empty) && (level.is_visible(to) || !matches!(obj, Obj::Enemy { .. }))
+	});
+	slides
+}
+
+/// The enemies that just came out of a portal, which rise from it at the start of the turn
+/// (they are drawn over the grid, like the slides).
+fn rises(level: &LevelState) -> Vec<Coords> {
+	level
+		.grid
+		.dims
+		.iter()
+		.filter(|&coords| {
+			level.is_visible(coords)
+				&& matches!(
+					level.grid.get(coords).unwrap().obj,
+					Obj::Enemy { effects: StatusEffects { emerging: true, .. }, .. }
+				)
+		})
+		.collect()
+}
+
+/// Stunned things blink in yellow, enemies that were just hit flash red, and the ones still
+/// coming out of a portal are see-through.
+fn cell_effects(
+	level: &LevelState,
+	coords: Coords,
+	blink_on: bool,
+	hit_flash_on: bool,
+) -> SpriteEffects {
+	match level.grid.get(coords).unwrap().obj {
+		Obj::Player { stunned: true } | Obj::Tower { stunned: true, .. } if blink_on => {
+			SpriteEffects::tinted([255, 255, 80])
+		},
+		Obj::Enemy { .. } if hit_flash_on && level.stats.was_hit(coords) => {
+			SpriteEffects::tinted([255, 60, 60])
+		},
+		Obj::Enemy { effects: StatusEffects { emerging: true, .. }, .. } => {
+			SpriteEffects { opacity: Some(170), ..SpriteEffects::default() }
+		},
+		_ => SpriteEffects::default(),
+	}
+}
+
+/// The files in the `levels` directory (sorted) and then the levels of the mods, as
+/// `(name, path)`.
+fn list_level_files(mods: &mods::Mods) -> Vec<(String, String)> {
+	let mut names: Vec<String> = fs::read_dir("./levels")
+		.map(|entries| {
+			entries
+				.filter_map(|entry| entry.ok())
+				.filter(|entry| entry.path().is_file())
+				.filter_map(|entry| entry.file_name().into_string().ok())
+				.collect()
+		})
+		.unwrap_or_default();
+	names.sort();
+	let mut level_files: Vec<(String, String)> = names
+		.into_iter()
+		.map(|name| {
+			let path = format!("./levels/{name}");
+			(name, path)
+		})
+		.collect();
+	level_files.extend(mods.level_files());
+	level_files
+}
+
+/// Resize the window and the pixel buffer so that the whole level is visible (the window
+/// being `Config::window_scale` times bigger), returns the new dimensions of the pixel buffer.
+fn fit_window_to_level(
+	window: &winit::window::Window,
+	pixel_buffer: &mut pixels::Pixels,
+	grid_dims: Dimensions,
+	cell_pixel_side: i32,
+	config: &config::Config,
+) -> Dimensions {
+	let dims = Dimensions {
+		w: grid_dims.w * cell_pixel_side,
+		h: grid_dims.h * cell_pixel_side,
+	};
+	// A fullscreen window keeps its size, the level is scaled up into it.
+	if window.fullscreen().is_none() {
+		let screen_dims = window
+			.current_monitor()
+			.map(|monitor| monitor.size().into());
+		let scale = config.window_scale(dims, screen_dims, window.scale_factor()) as i32;
+		let (w, h) = ((dims.w * scale) as u32, (dims.h * scale) as u32);
+		window.set_inner_size(winit::dpi::PhysicalSize::new(w, h));
+		if let Err(error) = pixel_buffer.resize_surface(w, h) {
+			log::error!("Failed to resize the surface: {error}");
+		}
+	}
+	if let Err(error) = pixel_buffer.resize_buffer(dims.w as u32, dims.h as u32) {
+		log::error!("Failed to resize the pixel buffer: {error}");
+	}
+	dims
+}
+
+/// Where the window is, to put it back there next time (see `Config::window`).
+fn window_placement(
+	window: &winit::window::Window,
+	last: Option<config::WindowPlacement>,
+) -> Option<config::WindowPlacement> {
+	let fullscreen = window.fullscreen().is_some();
+	let position = match last {
+		// A fullscreen window is where its screen is, it goes back where it was before.
+		Some(last) if fullscreen => last.position,
+		_ => {
+			let position = window.outer_position().ok()?;
+			Coords { x: position.x, y: position.y }
+		},
+	};
+	Some(config::WindowPlacement { position, fullscreen })
+}
+
+fn main() {
+	env_logger::init();
+	let cli = <cli::Cli as clap::Parser>::parse();
+	if let Some(path) = cli.global.config.clone() {
+		config::Config::set_path(path);
+	}
+	let mut config = config::Config::load();
+	if let Some(zoom) = cli.global.zoom {
+		config.zoom = zoom;
+	}
+	locale::set_language(config.language);
+	let seed = cli.global.seed.unwrap_or(0);
+	let mods = mods::Mods::scan(std::path::Path::new(mods::Mods::DIR));
+	// Each balance file overrides the values of the previous ones (see the `mods` module).
+	let mut game_balance = balance::Balance::compiled_in();
+	let default_balance_file = std::path::PathBuf::from(balance::Balance::DEFAULT_PATH);
+	let balance_files = std::iter::once(default_balance_file)
+		.filter(|path| path.exists())
+		.chain(mods.balance_files());
+	for path in balance_files {
+		if let Err(error) = game_balance.apply_file(&path) {
+			log::warn!("Invalid balance, ignored: {error}");
+		}
+	}
+	if let Some(path) = &cli.global.balance {
+		if let Err(error) = game_balance.apply_file(std::path::Path::new(path)) {
+			eprintln!("{error}");
+			std::process::exit(1);
+		}
+	}
+	balance::set(game_balance);
+
+	// The daily challenge being played, if it is one.
+	let mut daily_run: Option<daily::Run> = None;
+	let play = match cli.command {
+		None => cli.play,
+		Some(cli::Command::Play(play)) => play,
+		Some(cli::Command::Simulate(simulate)) => {
+			let level_raw_data = fs::read_to_string(&simulate.level)
+				.map_err(|error| error.to_string())
+				.and_then(|mut level_raw_data| {
+					if let Some(tweaks_file) = &simulate.tweaks {
+						level_raw_data.push('\n');
+						level_raw_data.push_str(
+							&fs::read_to_string(tweaks_file).map_err(|error| error.to_string())?,
+						);
+					}
+					Ok(level_raw_data)
+				});
+			// Without a sweep, a single run with the balance as it is.
+			let sweeps: Vec<Option<(&str, &str)>> = match &simulate.sweep {
+				Some(sweep) => {
+					let (name, values) = sweep.split_once('=').unwrap_or((sweep, ""));
+					values.split(',').map(|value| Some((name, value))).collect()
+				},
+				None => vec![None],
+			};
+			let base_balance = balance::get();
+			let result = level_raw_data.and_then(|level_raw_data| {
+				simulate::write_header(std::io::stdout().lock()).unwrap();
+				for sweep in sweeps {
+					let mut label = simulate.level.clone();
+					if let Some((name, value)) = sweep {
+						let mut swept = (*base_balance).clone();
+						swept.set_value(name, value)?;
+						balance::set(swept);
+						label = format!("{label} {name}={value}");
+					}
+					// Parsed again for each run, as the HP of the enemies come from the balance.
+					let level_data = parse_level_in(&level_raw_data, level_dir(&simulate.level))?;
+					simulate::run(
+						&label,
+						&level_data,
+						simulate.games,
+						seed,
+						std::io::stdout().lock(),
+					)
+					.unwrap();
+				}
+				Ok(())
+			});
+			if let Err(error) = result {
+				eprintln!("{error}");
+				std::process::exit(1);
+			}
+			return;
+		},
+		Some(cli::Command::Validate { level }) => {
+			let problems = match fs::read_to_string(&level) {
+				Ok(level_raw_data) => validate::validate_level(&level_raw_data, level_dir(&level)),
+				Err(error) => vec![error.to_string()],
+			};
+			if problems.is_empty() {
+				println!("{level}: ok");
+				return;
+			}
+			for problem in problems {
+				println!("{level}: {problem}");
+			}
+			std::process::exit(1);
+		},
+		Some(cli::Command::Generate(generate)) => {
+			let dims = Dimensions { w: generate.width.max(5), h: generate.height.max(3) };
+			print!("{}", generate::generate_level(seed, dims));
+			return;
+		},
+		Some(cli::Command::Daily(daily_args)) => {
+			let date = match &daily_args.date {
+				Some(date) => daily::Date::parse(date).unwrap_or_else(|| {
+					eprintln!("{}", tr_with("daily.invalid_date", &[("date", date)]));
+					std::process::exit(1);
+				}),
+				None => daily::Date::today(),
+			};
+			if daily_args.scores {
+				let scores = daily::Scores::load();
+				println!("{}", tr_with("daily.scores", &[("date", &date)]));
+				for (index, score) in scores.of_day(date).iter().enumerate() {
+					let outcome = tr(if score.won { "daily.won" } else { "daily.lost" });
+					println!(
+						"{:>2}. {:>6}  {outcome}, {}",
+						index + 1,
+						score.score,
+						tr_with("daily.turns", &[("turns", &score.turns)])
+					);
+				}
+				return;
+			}
+			// The same rules for everyone.
+			balance::set(balance::Balance::compiled_in());
+			let level_file = daily::write_level_file(date).unwrap_or_else(|error| {
+				eprintln!("{error}");
+				std::process::exit(1);
+			});
+			daily_run = Some(daily::Run::new(date));
+			cli::PlayArgs {
+				level: Some(level_file.to_string_lossy().into_owned()),
+				tui: daily_args.tui,
+				..Default::default()
+			}
+		},
+	};
+	// Without a level, the game starts on the title screen.
+	let show_title = play.level.is_none() && play.replay.is_none() && play.watch.is_none();
+	let mut level_file = play.level.unwrap_or_else(|| String::from("./levels/test"));
+	// With `--bot`, the built-in bot plays the level by itself.
+	let bot_mode = play.bot;
+	// With `--agent` (or `--agent-tcp <address>`) there is no window and an external program
+	// plays the level via stdin/stdout (or a TCP connection), see the `protocol` module.
+	let agent_mode = play.agent || play.agent_tcp.is_some();
+	let agent_tcp_address = play.agent_tcp;
+	// With `--record <file>`, the turns played are saved in a replay file that can then be
+	// played back with `--replay <file>` (see the `replay` module).
+	let record_file = play.record;
+	let replay_file = play.replay;
+	// With `--log-turns <file>`, what happens during each turn is logged in the file as a line
+	// of JSON (see the `turn_log` module).
+	let turn_log_file = play.log_turns;
+	// With `--auto-turn`, turns advance on a timer (adjustable with `+` and `-`) and the last
+	// action requested by the player is played at the next tick.
+	let auto_turn_mode = play.auto_turn;
+	// With `--tui`, there is no window and the level is played in the terminal
+	// (see the `tui` module).
+	let tui_mode = play.tui;
+
+	let mut spectator = replay_file.map(|replay_file| {
+		let replay = fs::read_to_string(&replay_file)
+			.map_err(|error| error.to_string())
+			.and_then(|replay_text| replay::Replay::parse(&replay_text))
+			.unwrap_or_else(|error| {
+				eprintln!("{replay_file}: {error}");
+				std::process::exit(1);
+			});
+		level_file = replay.level_file.clone();
+		spectate::Spectator::new(replay)
+	});
+	if let Some(code) = play.watch {
+		let replay =
+			share::replay_from_code(&code, &list_level_files(&mods)).unwrap_or_else(|error| {
+				eprintln!("{error}");
+				std::process::exit(1);
+			});
+		level_file = replay.level_file.clone();
+		spectator = Some(spectate::Spectator::new(replay));
+	}
+	let mut recorder = record_file
+		.as_ref()
+		.map(|record_file| replay::Recorder::new(&level_file, record_file));
+	let mut turn_log = turn_log_file.map(|turn_log_file| {
+		turn_log::TurnLog::create(&turn_log_file)
+			.unwrap_or_else(|error| panic!("Failed to create the turn log {turn_log_file}: {error}"))
+	});
+
+	// A level that can't be loaded is reported on the error screen, over an empty field.
+	let mut startup_error: Option<String> = None;
+	let mut level_data = load_level(level_file.as_str()).unwrap_or_else(|error| {
+		log::error!("{error}");
+		startup_error = Some(error);
+		LevelData::new(Grid::new(
+			Dimensions { w: 12, h: 8 },
+			Cell {
+				obj: Obj::Empty,
+				groud: Ground::Grass,
+				rocky_path: false,
+				spikes: false,
+				conveyor: None,
+				portal: false,
+			},
+		))
+	});
+	if let Some(error) = startup_error.as_ref().filter(|_| agent_mode || tui_mode) {
+		eprintln!("{error}");
+		return;
+	}
 	let mut level = LevelState::new(&level_data);
+	// Going back in time would make the recorded replay wrong.
+	let mut checkpoints =
+		(!bot_mode && spectator.is_none() && recorder.is_none() && daily_run.is_none())
+			.then(|| checkpoint::Checkpoints::new(&level));
+
+	if agent_mode {
+		let result = if let Some(address) = agent_tcp_address {
+			let listener = std::net::TcpListener::bind(&address).unwrap();
+			log::info!("Waiting for an agent to connect to {address}");
+			let (stream, _) = listener.accept().unwrap();
+			protocol::run(
+				&mut level,
+				std::io::BufReader::new(stream.try_clone().unwrap()),
+				stream,
+			)
+		} else {
+			protocol::run(
+				&mut level,
+				std::io::stdin().lock(),
+				std::io::stdout().lock(),
+			)
+		};
+		if let Err(error) = result {
+			log::error!("Agent connection error: {error}");
+		}
+		return;
+	}
+	if tui_mode {
+		if let Err(error) = tui::run(&mut level) {
+			log::error!("Terminal error: {error}");
+		}
+		if let Some(daily_run) = &mut daily_run {
+			daily_run.update(&mut level);
+			if let Some(message) = &level.message {
+				println!("{message}");
+			}
+		}
+		return;
+	}
+	if cli.global.headless {
+		if let Some(error) = startup_error {
+			eprintln!("{error}");
+			std::process::exit(1);
+		}
+		let mut bot_rng = rng::Rng::with_seed(seed);
+		while !level.game_joever && !level.is_won() && level.turn < simulate::MAX_TURNS {
+			let turn = level.turn;
+			let (dd, action) = match &mut spectator {
+				Some(spectator) => match spectator.step_forward(&mut level) {
+					Ok(Some(turn)) => turn,
+					Ok(None) => break,
+					Err(error) => {
+						eprintln!("{error}");
+						std::process::exit(1);
+					},
+				},
+				None => {
+					let (dd, action) = bot::choose_action(&level, &mut bot_rng);
+					match &mut recorder {
+						Some(recorder) => recorder.play_and_record(&mut level, dd, action.clone()),
+						None => play_turn(&mut level, dd, action.clone()),
+					}
+					(dd, action)
+				},
+			};
+			if let Some(turn_log) = &mut turn_log {
+				turn_log.log(&level, turn, dd, &action);
+			}
+		}
+		let outcome = if level.is_won() {
+			"won"
+		} else if level.game_joever {
+			"lost"
+		} else {
+			"unfinished"
+		};
+		println!(
+			"{level_file}: {outcome} after {} turns, state hash {:016x}",
+			level.turn,
+			level.state_hash()
+		);
+		return;
+	}
 	_print_dist(&level.grid);
 
-	let cell_pixel_side = 8 * 8;
+	let event_loop = winit::event_loop::EventLoop::new();
+	COLORBLIND.store(config.colorblind, Ordering::Relaxed);
+	let mut cell_pixel_side = 8 * config.zoom;
+
+	let mut pixel_buffer_dims = Dimensions {
+		w: level.grid.dims.w * cell_pixel_side,
+		h: level.grid.dims.h * cell_pixel_side,
+	};
+	let window_scale = {
+		let screen = event_loop
+			.primary_monitor()
+			.or_else(|| event_loop.available_monitors().next());
+		let scale_factor = screen.as_ref().map_or(1.0, |screen| screen.scale_factor());
+		let screen_dims = screen.map(|screen| screen.size().into());
+		config.window_scale(pixel_buffer_dims, screen_dims, scale_factor) as i32
+	};
+	let window = winit::window::WindowBuilder::new()
+		.with_title("Prototype 7")
+		.with_inner_size(winit::dpi::PhysicalSize::new(
+			(pixel_buffer_dims.w * window_scale) as u32,
+			(pixel_buffer_dims.h * window_scale) as u32,
+		))
+		.build(&event_loop)
+		.unwrap();
+
+	// Put the window back where it was when the game was closed, or center it (on the first
+	// screen) if it was on a screen that is not there anymore.
+	let monitors: Vec<Rect> = window
+		.available_monitors()
+		.map(|monitor| Rect {
+			top_left: (monitor.position().x, monitor.position().y).into(),
+			dims: monitor.size().into(),
+		})
+		.collect();
+	let window_dims: Dimensions = window.outer_size().into();
+	let window_position = config
+		.window
+		.and_then(|placement| placement.position_on(window_dims, &monitors))
+		.or_else(|| {
+			let screen = monitors.first()?;
+			Some(screen.center() - DxDy::from((window_dims.w / 2, window_dims.h / 2)))
+		});
+	if let Some(position) = window_position {
+		window.set_outer_position(winit::dpi::PhysicalPosition::new(position.x, position.y));
+	}
+
+	// Set background and edge color
+	let clear_color = [0, 50, 50, 255];
+	let clear_color_wgpu = {
+		fn conv_srgb_to_linear(x: f64) -> f64 {
+			// See https://github.com/gfx-rs/wgpu/issues/2326
+			// Stolen from https://github.com/three-rs/three/blob/07e47da5e0673aa9a16526719e16debd59040eec/src/color.rs#L42
+			// (licensed MIT, not a substancial portion so not concerned by license obligations)
+			// Basically the brightness is adjusted somewhere by wgpu or something due to sRGB stuff,
+			// color is hard.
+			if x > 0.04045 {
+				((x + 0.055) / 1.055).powf(2.4)
+			} else {
+				x / 12.92
+			}
+		}
+		pixels::wgpu::Color {
+			r: conv_srgb_to_linear(clear_color[0] as f64 / 255.0),
+			g: conv_srgb_to_linear(clear_color[1] as f64 / 255.0),
+			b: conv_srgb_to_linear(clear_color[2] as f64 / 255.0),
+			a: conv_srgb_to_linear(clear_color[3] as f64 / 255.0),
+		}
+	};
+
+	// The pixel buffer is scaled up to the size of the window (see `Config::window_scale`), by
+	// a whole number with the clear color around it if the window is bigger.
+	let mut pixel_buffer = {
+		let dims = pixel_buffer_dims;
+		let window_size = window.inner_size();
+		let surface_texture =
+			pixels::SurfaceTexture::new(window_size.width, window_size.height, &window);
+		pixels::PixelsBuilder::new(dims.w as u32, dims.h as u32, surface_texture)
+			.clear_color(clear_color_wgpu)
+			.build()
+			.unwrap()
+	};
+
+	let spritesheet = match mods.spritesheet() {
+		Some((image, atlas)) => {
+			Spritesheet::load_files(&image, atlas.as_deref()).unwrap_or_else(|error| {
+				log::warn!("Invalid spritesheet in a mod, using the game's one: {error}");
+				Spritesheet::load()
+			})
+		},
+		None => Spritesheet::load(),
+	};
+	// Going fullscreen once the pixel buffer fits the level, it is then scaled up as much as it
+	// fits on the screen.
+	if config.window.is_some_and(|placement| placement.fullscreen) {
+		window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
+	}
+	let (icon, icon_dims) = spritesheet.sprite_pixels("player.player", 4);
+	window.set_window_icon(
+		winit::window::Icon::from_rgba(icon, icon_dims.w as u32, icon_dims.h as u32).ok(),
+	);
+	let mut shown_window_title = "Prototype 7".to_string();
+
+	// Which menu is open, if any.
+	let mut screen = if startup_error.is_some() {
+		Screen::Error
+	} else if show_title {
+		Screen::Title
+	} else {
+		Screen::Playing
+	};
+	// The message of the error screen.
+	let mut error_message = startup_error.unwrap_or_default();
+	// The menu that the level select and the settings go back to.
+	let mut main_menu = if matches!(screen, Screen::Title | Screen::Error) {
+		Screen::Title
+	} else {
+		Screen::Paused
+	};
+	// The screen that the quit confirmation goes back to.
+	let mut screen_before_quit = Screen::Playing;
+	// After a few seconds without input on the title screen, the bot plays the level behind
+	// the title (attract mode) until there is some input.
+	let attract_mode_delay = std::time::Duration::from_secs(5);
+	let mut title_idle_since = std::time::Instant::now();
+	let mut ui_input = ui::UiInput::default();
+	// The selected item of the list of the open menu.
+	let mut menu_selection = 0;
+	// The levels of the level select, as `(name, path)`.
+	let mut level_files: Vec<(String, String)> = vec![];
+	let mut high_scores = high_scores::HighScores::load();
+	// How the level ended, once it did (for the results screen).
+	let mut outcome: Option<high_scores::Outcome> = None;
+	// The name being entered for a high score.
+	let mut name_entry = String::new();
+	// The turns played since the level started, to share them as a replay code.
+	let mut played = replay::Replay::new(&level_file);
+	// The share code being pasted, and what happened to the last code copied or pasted.
+	let mut code_entry = String::new();
+	let mut share_status = String::new();
+	// The cell being inspected, moved with the mouse or IJKL.
+	let mut inspected_cell: Option<Coords> = None;
+
+	let mut is_ctrl_pressed = false;
+	let mut is_shift_pressed = false;
+	let mut is_alt_pressed = false;
+	// Where the player last attacked and when, to show the attack for a short time.
+	let mut attack_flash: Option<(Coords, std::time::Instant)> = None;
+	// A tower that the player asked to pick up, which will be picked up
+	// if asked again (to avoid picking up towers by accident).
+	let mut pickup_to_confirm: Option<Coords> = None;
+	// The direction (from the player) of a tower that was just placed or pushed next to an
+	// identical one, the player is offered to merge them.
+	let mut merge_offer: Option<DxDy> = None;
+	// An item that will be used in the direction of the next arrow key.
+	let mut selected_item: Option<Item> = None;
+	let mut bot_rng = rng::Rng::with_seed(seed);
+	// The actions requested by the player, waiting to be played.
+	let mut input_queue = input_queue::InputQueue::default();
+	// Time between turns when the turns are not triggered by the player
+	// (auto-turn mode, bot, replay playback).
+	let mut turn_interval = std::time::Duration::from_millis(if auto_turn_mode { 800 } else { 300 });
+	let mut last_timed_turn = std::time::Instant::now();
+	// The last turn that was drawn and when it was first drawn, to flash what just happened.
+	let mut last_turn_drawn = (level.turn, std::time::Instant::now());
+	let start_time = std::time::Instant::now();
+	// Whether something may have changed since the last frame was drawn.
+	let mut needs_redraw = true;
+	// Time between frames while something is animated (or turns are fast-forwarded).
+	let animation_frame_duration = std::time::Duration::from_millis(16);
+	let mut particles = particles::Particles::new();
+	let mut screen_effects = screen_effects::ScreenEffects::new();
+	let mut last_frame_time = std::time::Instant::now();
+	let mut grid_layer = grid_layer::GridLayer::new(clear_color);
+	// How many times a level was (re)started, and the state of the game in the last frame
+	// drawn, to fade between states when it changes.
+	let mut level_starts = 0;
+	let mut drawn_state = (screen, level_starts, level.game_joever);
+	let mut transition: Option<transition::Transition> = None;
+	// While fast-forwarding, turns are played without waiting, a few of them per frame.
+	let mut fast_forward = false;
+	let fast_forward_turns_per_frame = 4;
+	// A turn of the replay to step through, forward (`true`) or back, at the next frame.
+	let mut spectate_step: Option<bool> = None;
+	// Position of the mouse cursor in the window, in pixels.
+	let mut cursor_position: Option<Coords> = None;
+	// Coords of the tower whose targeting priority menu is open (if any).
+	let mut priority_menu: Option<Coords> = None;
+	let hovered_tile = |cursor_position: Option<Coords>, dims: Dimensions, cell_pixel_side: i32| {
+		cursor_position
+			.map(|position| Coords {
+				x: position.x / cell_pixel_side,
+				y: position.y / cell_pixel_side,
+			})
+			.filter(|&coords| dims.contains(coords))
+	};
+
+	use winit::event::*;
+	event_loop.run(move |event, _, control_flow| {
+		// Frames are only drawn after input, window events, or when it is time for a timed turn
+		// or for the next frame of an animation (see the end of `MainEventsCleared`).
+		if let Event::WindowEvent { .. } | Event::NewEvents(StartCause::ResumeTimeReached { .. }) =
+			event
+		{
+			needs_redraw = true;
+		}
+		match event {
+			// Moved to a screen with another scale factor (or it changed), the window keeps the
+			// size of the level scaled by a whole number.
+			Event::WindowEvent {
+				event: WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size },
+				window_id,
+			} if window_id == window.id() => {
+				if window.fullscreen().is_none() {
+					let screen_dims = window
+						.current_monitor()
+						.map(|monitor| monitor.size().into());
+					let scale = config.window_scale(pixel_buffer_dims, screen_dims, scale_factor);
+					*new_inner_size = winit::dpi::PhysicalSize::new(
+						pixel_buffer_dims.w as u32 * scale,
+						pixel_buffer_dims.h as u32 * scale,
+					);
+				}
+				if let Err(error) =
+					pixel_buffer.resize_surface(new_inner_size.width, new_inner_size.height)
+				{
+					log::error!("Failed to resize the surface: {error}");
+				}
+			},
+			Event::WindowEvent { ref event, window_id } if window_id == window.id() => match event {
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::F11),
+							..
+						},
+					..
+				} => {
+					config.window = window_placement(&window, config.window).or(config.window);
+					window.set_fullscreen(match window.fullscreen() {
+						Some(_) => None,
+						None => Some(winit::window::Fullscreen::Borderless(None)),
+					});
+				},
+				WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+					if let Err(error) = pixel_buffer.resize_surface(size.width, size.height) {
+						log::error!("Failed to resize the surface: {error}");
+					}
+				},
+
+				// While a menu is open, the keyboard and the mouse are for the menu.
+				WindowEvent::KeyboardInput {
+					input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+					..
+				} if screen != Screen::Playing => {
+					ui_input.press_key(*key);
+					if is_ctrl_pressed {
+						ui_input.press_shortcut(*key);
+					}
+					// Pasting in the text fields.
+					if ui_input.paste && matches!(screen, Screen::NameEntry | Screen::WatchReplay) {
+						match share::paste_from_clipboard() {
+							Ok(text) => ui_input.typed.push_str(text.trim()),
+							Err(error) => log::warn!("Failed to paste: {error}"),
+						}
+					}
+				},
+				WindowEvent::MouseInput {
+					state: ElementState::Pressed,
+					button: MouseButton::Left,
+					..
+				} if screen != Screen::Playing => {
+					ui_input.clicked = true;
+				},
+				WindowEvent::ReceivedCharacter(character)
+					if matches!(screen, Screen::NameEntry | Screen::WatchReplay)
+						&& !character.is_control() =>
+				{
+					ui_input.typed.push(*character);
+				},
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::P),
+							..
+						},
+					..
+				} => {
+					screen = Screen::Paused;
+					menu_selection = 0;
+					ui_input.clear();
+				},
+
+				WindowEvent::CloseRequested
+				| WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::Escape),
+							..
+						},
+					..
+				} => {
+					if quit_is_confirmed(&config, &level, screen, !bot_mode && spectator.is_none()) {
+						screen_before_quit = screen;
+						screen = Screen::ConfirmQuit;
+						menu_selection = 0;
+						ui_input.clear();
+					} else {
+						*control_flow = winit::event_loop::ControlFlow::Exit;
+					}
+				},
+
+				WindowEvent::CursorMoved { position, .. } => {
+					// The pixel buffer may be scaled up in the window (when it is fullscreen).
+					cursor_position = pixel_buffer
+						.window_pos_to_pixel((position.x as f32, position.y as f32))
+						.ok()
+						.map(|(x, y)| (x as i32, y as i32).into());
+					ui_input.cursor_moved = true;
+					if let Some(coords) = hovered_tile(cursor_position, level.grid.dims, cell_pixel_side)
+					{
+						inspected_cell = Some(coords);
+					}
+				},
+				WindowEvent::CursorLeft { .. } => {
+					cursor_position = None;
+				},
+
+				WindowEvent::MouseInput {
+					state: ElementState::Pressed,
+					button: MouseButton::Left,
+					..
+				} => {
+					if let Some(tower_coords) = priority_menu.take() {
+						// Clicking on one of the menu items selects it, clicking anywhere
+						// else just closes the menu.
+						for (index, new_priority) in TargetPriority::ALL.into_iter().enumerate() {
+							let item_rect = priority_menu_item_rect(
+								tower_coords,
+								index as i32,
+								cell_pixel_side,
+								level.grid.dims,
+							);
+							if cursor_position.is_some_and(|position| item_rect.contains(position)) {
+								if let Obj::Tower { priority, .. } =
+									&mut level.grid.get_mut(tower_coords).unwrap().obj
+								{
+									*priority = new_priority;
+								}
+							}
+						}
+					} else if let Some(coords) =
+						hovered_tile(cursor_position, level.grid.dims, cell_pixel_side)
+					{
+						if matches!(level.grid.get(coords).unwrap().obj, Obj::Tower { .. }) {
+							priority_menu = Some(coords);
+						}
+					}
+				},
+
+				WindowEvent::KeyboardInput {
+					input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+					..
+				} if matches!(
+					key,
+					VirtualKeyCode::Plus
+						| VirtualKeyCode::Equals
+						| VirtualKeyCode::NumpadAdd
+						| VirtualKeyCode::Minus
+						| VirtualKeyCode::NumpadSubtract
+				) =>
+				{
+					// Adjust the speed of the timed turns.
+					let step = std::time::Duration::from_millis(100);
+					if matches!(key, VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract) {
+						turn_interval = (turn_interval + step).min(std::time::Duration::from_secs(3));
+					} else {
+						turn_interval = turn_interval.saturating_sub(step).max(step);
+					}
+				},
+
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode:
+								Some(
+									key @ (VirtualKeyCode::Space
+									| VirtualKeyCode::Comma
+									| VirtualKeyCode::Period),
+								),
+							..
+						},
+					..
+				} if spectator.is_some() => {
+					// The playback controls of the replay.
+					match key {
+						VirtualKeyCode::Space => {
+							if let Some(spectator) = &mut spectator {
+								spectator.paused = !spectator.paused;
+							}
+						},
+						VirtualKeyCode::Comma => spectate_step = Some(false),
+						_ => spectate_step = Some(true),
+					}
+				},
+
+				WindowEvent::KeyboardInput {
+					input: KeyboardInput { state, virtual_keycode: Some(key), .. },
+					..
+				} if *key == VirtualKeyCode::F || (*key == VirtualKeyCode::Space && bot_mode) => {
+					fast_forward = *state == ElementState::Pressed;
+				},
+
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::Tab),
+							..
+						},
+					..
+				} if !bot_mode && spectator.is_none() => {
+					merge_offer = None;
+					pickup_to_confirm = None;
+					input_queue.push(DxDy::from((0, 0)), PlayerAction::SwitchUnit);
+				},
+
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::C),
+							..
+						},
+					..
+				} if screen == Screen::Playing
+					&& level.crafting
+					&& !bot_mode
+					&& spectator.is_none() =>
+				{
+					merge_offer = None;
+					screen = Screen::Crafting;
+					menu_selection = 0;
+					ui_input.clear();
+				},
+
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(key @ (VirtualKeyCode::B | VirtualKeyCode::V)),
+							..
+						},
+					..
+				} if !bot_mode && spectator.is_none() => {
+					merge_offer = None;
+					if *key == VirtualKeyCode::B {
+						selected_item = Some(Item::Bomb);
+					} else {
+						input_queue.push(
+							DxDy::from((0, 0)),
+							PlayerAction::UseItem { item: Item::TowerVoucher },
+						);
+					}
+				},
+
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::Return),
+							..
+						},
+					..
+				} => {
+					hints::dismiss(&mut level);
+				},
+
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::M),
+							..
+						},
+					..
+				} => {
+					if let Some(dd) = merge_offer.take() {
+						input_queue.push(dd, PlayerAction::MergeTower);
+					}
+				},
+
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::R),
+							..
+						},
+					..
+				} => {
+					if let Some(checkpoints) = &mut checkpoints {
+						if checkpoints.rewind(&mut level) {
+							played.turns.truncate(level.actions as usize);
+							input_queue.clear();
+							merge_offer = None;
+							pickup_to_confirm = None;
+						}
+					}
+				},
+
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode:
+								Some(
+									key @ (VirtualKeyCode::I
+									| VirtualKeyCode::J
+									| VirtualKeyCode::K
+									| VirtualKeyCode::L),
+								),
+							..
+						},
+					..
+				} => {
+					let dd: DxDy = match key {
+						VirtualKeyCode::I => (0, -1),
+						VirtualKeyCode::J => (-1, 0),
+						VirtualKeyCode::K => (0, 1),
+						_ => (1, 0),
+					}
+					.into();
+					// The cursor starts on the player.
+					let start = inspected_cell.or(level.player_coords());
+					inspected_cell = start
+						.map(|coords| coords + dd)
+						.filter(|&coords| level.grid.dims.contains(coords))
+						.or(start);
+				},
+
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput { state: ElementState::Released, virtual_keycode: Some(key), .. },
+					..
+				} => input_queue.release(*key),
+				WindowEvent::Focused(false) => input_queue.clear(),
+
+				WindowEvent::ModifiersChanged(modifiers) => {
+					is_ctrl_pressed = (*modifiers & ModifiersState::CTRL) == ModifiersState::CTRL;
+					is_shift_pressed = (*modifiers & ModifiersState::SHIFT) == ModifiersState::SHIFT;
+					is_alt_pressed = (*modifiers & ModifiersState::ALT) == ModifiersState::ALT;
+				},
+
+				WindowEvent::KeyboardInput {
+					input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+					..
+				} if config.key_bindings.direction(*key).is_some() || *key == VirtualKeyCode::Space => {
+					// The repeats of the keyboard, the queue repeats the moves at its own rate.
+					if input_queue.is_held(*key) {
+						return;
+					}
+					// Hitting one's own tower reloads it.
+					let aims_at_tower = config
+						.key_bindings
+						.direction(*key)
+						.zip(level.player_coords())
+						.and_then(|(dd, coords)| level.grid.get(coords + dd))
+						.is_some_and(|cell| matches!(cell.obj, Obj::Tower { .. }));
+					let mut action = if let Some(item) = selected_item.take() {
+						PlayerAction::UseItem { item }
+					} else if is_ctrl_pressed && is_shift_pressed {
+						PlayerAction::PickUpTower
+					} else if is_ctrl_pressed && is_alt_pressed {
+						PlayerAction::PlaceTower { variant: Tower::Cannon }
+					} else if is_alt_pressed && aims_at_tower {
+						PlayerAction::Reload
+					} else if is_alt_pressed {
+						PlayerAction::Attack
+					} else if is_ctrl_pressed {
+						PlayerAction::PlaceTower { variant: Tower::Basic }
+					} else if is_shift_pressed {
+						PlayerAction::PlaceWall
+					} else {
+						PlayerAction::Move
+					};
+					let dxdy = match config.key_bindings.direction(*key) {
+						Some(dxdy) => dxdy,
+						None => {
+							action = PlayerAction::SkipTurn;
+							DxDy::from((0, 0))
+						},
+					};
+					if action == PlayerAction::PickUpTower {
+						let tower_coords = level.player_coords().map(|coords| coords + dxdy);
+						if tower_coords != pickup_to_confirm.take() {
+							pickup_to_confirm = tower_coords;
+							return;
+						}
+					} else {
+						pickup_to_confirm = None;
+					}
+					merge_offer = None;
+					if !bot_mode && spectator.is_none() {
+						if action == PlayerAction::Move {
+							input_queue.hold(*key, dxdy, config.key_repeat, std::time::Instant::now());
+						}
+						input_queue.push(dxdy, action);
+					}
+				},
+
+				_ => {},
+			},
+
+			Event::MainEventsCleared => {
+				if !needs_redraw {
+					return;
+				}
+				needs_redraw = false;
+
+				// Play a turn if there is one to play, when and how depends on who is playing.
+				let attract_mode =
+					screen == Screen::Title && title_idle_since.elapsed() >= attract_mode_delay;
+				let turns_this_frame = if attract_mode {
+					1
+				} else if screen != Screen::Playing {
+					0
+				} else if fast_forward {
+					fast_forward_turns_per_frame
+				} else {
+					1
+				};
+				if screen == Screen::Playing {
+					input_queue.repeat(config.key_repeat, std::time::Instant::now());
+				}
+				for _ in 0..turns_this_frame {
+					let timer_ticked = !level.game_joever
+						&& (fast_forward || last_timed_turn.elapsed() >= turn_interval);
+					if timer_ticked {
+						last_timed_turn = std::time::Instant::now();
+					}
+					let turn_to_play = if bot_mode || attract_mode {
+						timer_ticked.then(|| bot::choose_action(&level, &mut bot_rng))
+					} else if spectator.is_some() {
+						None
+					} else if (auto_turn_mode || fast_forward)
+						&& input_queue
+							.front()
+							.is_none_or(|(_, action)| action.takes_a_turn())
+					{
+						// Switching units and crafting don't wait for the timer, they don't take
+						// a turn.
+						timer_ticked.then(|| {
+							input_queue
+								.pop()
+								.unwrap_or(((0, 0).into(), PlayerAction::SkipTurn))
+						})
+					} else {
+						input_queue.pop()
+					};
+					if let Some((dd, action)) = turn_to_play {
+						if action == PlayerAction::Attack {
+							if let Some(player_coords) = level.player_coords() {
+								attack_flash = Some((player_coords + dd, std::time::Instant::now()));
+							}
+						}
+						let moves_a_tower = match action {
+							PlayerAction::PlaceTower { .. } => true,
+							PlayerAction::Move => level.player_coords().is_some_and(|coords| {
+								matches!(level.grid.get(coords + dd).unwrap().obj, Obj::Tower { .. })
+							}),
+							_ => false,
+						};
+						let turn = level.turn;
+						let result = catch_panic(|| match &mut recorder {
+							Some(recorder) => recorder.play_and_record(&mut level, dd, action.clone()),
+							None => play_turn(&mut level, dd, action.clone()),
+						});
+						if let Err(error) = result {
+							error_message = error;
+							screen = Screen::Error;
+							break;
+						}
+						if let Some(turn_log) = &mut turn_log {
+							turn_log.log(&level, turn, dd, &action);
+						}
+						played.turns.push(replay::ReplayTurn {
+							dd,
+							action: action.clone(),
+							state_hash: level.state_hash(),
+						});
+						particles.emit_for_turn(&level.stats.turn_events, cell_pixel_side);
+						screen_effects.react_to_turn(&level.stats.turn_events, std::time::Instant::now());
+						if let Some(checkpoints) = &mut checkpoints {
+							checkpoints.update(&level);
+						}
+						if let Some(daily_run) = &mut daily_run {
+							daily_run.update(&mut level);
+						}
+						// The results are shown once the level is over for good (lost with no
+						// checkpoint left to go back to).
+						let is_over = level.is_won()
+							|| level.game_joever
+								&& checkpoints
+									.as_ref()
+									.is_none_or(|checkpoints| checkpoints.rewinds_left == 0);
+						if !attract_mode && !bot_mode && outcome.is_none() && is_over {
+							let level_outcome = high_scores::Outcome::of(&level);
+							outcome = Some(level_outcome);
+							menu_selection = 0;
+							let key = high_scores::level_key(&level_file);
+							if high_scores.qualifies(&key, level_outcome.score) {
+								name_entry = high_scores.last_name.clone();
+								screen = Screen::NameEntry;
+							} else {
+								screen = Screen::Results;
+							}
+						}
+						let tower_coords = level.player_coords().map(|coords| coords + dd);
+						if !bot_mode
+							&& moves_a_tower
+							&& tower_coords
+								.is_some_and(|coords| merge_partner(&level.grid, coords).is_some())
+						{
+							merge_offer = Some(dd);
+						}
+					}
+
+					if let Some(spectating) = &mut spectator {
+						let step = spectate_step.take();
+						if step == Some(false) {
+							spectating.paused = true;
+							if spectating.step_back(&mut level) {
+								played.turns.truncate(level.actions as usize);
+							}
+						} else if step == Some(true) || (timer_ticked && !spectating.paused) {
+							if step.is_some() {
+								spectating.paused = true;
+							}
+							let turn_number = level.turn;
+							match spectating.step_forward(&mut level) {
+								Ok(Some((dd, action))) => {
+									if let Some(turn_log) = &mut turn_log {
+										turn_log.log(&level, turn_number, dd, &action);
+									}
+									played.turns.push(replay::ReplayTurn {
+										dd,
+										action,
+										state_hash: level.state_hash(),
+									});
+									particles.emit_for_turn(&level.stats.turn_events, cell_pixel_side);
+									screen_effects
+										.react_to_turn(&level.stats.turn_events, std::time::Instant::now());
+								},
+								Ok(None) => {},
+								Err(error) => {
+									error_message = error;
+									screen = Screen::Error;
+									spectator = None;
+								},
+							}
+						}
+					}
+				}
+				// The demo starts over a moment after it is over.
+				if attract_mode && level.game_joever && last_timed_turn.elapsed() >= 3 * turn_interval {
+					level = LevelState::new(&level_data);
+					level_starts += 1;
+					played = replay::Replay::new(&level_file);
+				}
+
+				let title = window_title(&level_data, &level_file, &level, screen);
+				if title != shown_window_title {
+					window.set_title(&title);
+					shown_window_title = title;
+				}
+
+				// Animations are sped up or slowed down by the option.
+				let animation_speed = config.animation_speed as f32 / 100.0;
+
+				let state = (screen, level_starts, level.game_joever);
+				if state != drawn_state {
+					// Opening and closing menus is quicker.
+					let only_the_menu_changed = (state.1, state.2) == (drawn_state.1, drawn_state.2);
+					let duration =
+						std::time::Duration::from_millis(if only_the_menu_changed { 200 } else { 500 })
+							.div_f32(animation_speed);
+					transition = Some(transition::Transition::start(
+						&pixel_buffer,
+						duration,
+						std::time::Instant::now(),
+					));
+					drawn_state = state;
+				}
+
+				if level.turn != last_turn_drawn.0 {
+					last_turn_drawn = (level.turn, std::time::Instant::now());
+				}
+				let animation_time_since_turn = last_turn_drawn.1.elapsed().mul_f32(animation_speed);
+				let hit_flash_on = animation_time_since_turn < std::time::Duration::from_millis(200);
+				let blink_on = (start_time.elapsed().as_millis() / 250).is_multiple_of(2);
+				// Pushed objects slide to their new cell at the start of the turn, they are drawn
+				// over the grid (and not in their cell) while they slide.
+				let slide_progress =
+					animation_time_since_turn.as_secs_f32() / SLIDE_DURATION.as_secs_f32();
+				let (slides, rises) = if slide_progress < 1.0 {
+					(slides(&level), rises(&level))
+				} else {
+					(vec![], vec![])
+				};
+				let cell_effects_of = |coords| {
+					let is_sliding = slides.iter().any(|&(_, to)| to == coords);
+					let is_rising = rises.contains(&coords);
+					(!is_sliding && !is_rising)
+						.then(|| cell_effects(&level, coords, blink_on, hit_flash_on))
+				};
+				// The level is not drawn anymore once something went wrong, in case drawing it is
+				// what went wrong.
+				let drawn = if screen == Screen::Error {
+					Ok(())
+				} else {
+					catch_panic(|| {
+						set_scene_tint(level.scene_tint());
+						let cell_views: Vec<grid_layer::CellView> = level
+							.grid
+							.dims
+							.iter()
+							.map(|coords| {
+								grid_layer::CellView::of(&level, coords, cell_effects_of(coords))
+							})
+							.collect();
+						grid_layer.draw(
+							&mut pixel_buffer,
+							pixel_buffer_dims,
+							level.grid.dims,
+							cell_pixel_side,
+							cell_views
+								.iter()
+								.map(grid_layer::CellView::render_key)
+								.collect(),
+							|pixel_buffer, coords| {
+								let index = level.grid.dims.index_of_coords(coords).unwrap();
+								draw_cell(
+									pixel_buffer,
+									pixel_buffer_dims,
+									&spritesheet,
+									&cell_views[index],
+									coords,
+									cell_pixel_side,
+								)
+							},
+						);
+
+						for &(from, to) in &slides {
+							let DxDy { dx, dy } = from - to;
+							let left = 1.0 - slide_progress;
+							let mut dst = Rect::tile(to, cell_pixel_side);
+							dst.top_left.x += (dx as f32 * left * cell_pixel_side as f32) as i32;
+							dst.top_left.y += (dy as f32 * left * cell_pixel_side as f32) as i32;
+							draw_obj(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								&spritesheet,
+								&grid_layer::CellView::of(&level, to, None),
+								dst,
+								cell_effects(&level, to, blink_on, hit_flash_on),
+							);
+						}
+						for &coords in &rises {
+							// Only the top of the enemy is out of the portal at first.
+							let below = ((1.0 - slide_progress) * cell_pixel_side as f32) as i32;
+							let mut dst = Rect::tile(coords, cell_pixel_side);
+							dst.top_left.y += below;
+							dst.dims.h -= below;
+							draw_obj(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								&spritesheet,
+								&grid_layer::CellView::of(&level, coords, None),
+								dst,
+								cell_effects(&level, coords, blink_on, hit_flash_on),
+							);
+						}
+						// Particles are lights, they don't get darker at night.
+						set_scene_tint(None);
+
+						let now = std::time::Instant::now();
+						if !screen_effects.is_hit_stopped(now) {
+							particles.update((now - last_frame_time).as_secs_f32() * animation_speed);
+						}
+						last_frame_time = now;
+						particles.draw(&mut pixel_buffer, pixel_buffer_dims);
+						weather::draw_overlay(
+							&mut pixel_buffer,
+							pixel_buffer_dims,
+							Dimensions {
+								w: level.grid.dims.w * cell_pixel_side,
+								h: level.grid.dims.h * cell_pixel_side,
+							},
+							cell_pixel_side,
+							level.weather(),
+							start_time.elapsed().as_secs_f32() * animation_speed,
+						);
+
+						// Highlight the tiles in range of the towers, either all of them while the
+						// place-tower modifier is held or just the hovered one.
+						for coords in level.grid.dims.iter() {
+							let Obj::Tower { variant, facing, .. } = &level.grid.get(coords).unwrap().obj
+							else {
+								continue;
+							};
+							let hovered_coords =
+								hovered_tile(cursor_position, level.grid.dims, cell_pixel_side);
+							if !is_ctrl_pressed && !hovered_coords.is_some_and(|hovered| hovered == coords)
+							{
+								continue;
+							}
+							for coords_in_range in
+								tower_range_tiles(&level.grid, coords, variant, *facing, level.weather())
+							{
+								draw_rect_outline(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									Rect::tile(coords_in_range, cell_pixel_side),
+									cell_pixel_side / 16,
+									[255, 255, 160, 255],
+								);
+							}
+						}
+
+						if let Some(dd) = merge_offer {
+							let tower_coords = level.player_coords().map(|coords| coords + dd);
+							if let Some(tower_coords) = tower_coords {
+								if let Some(partner_coords) = merge_partner(&level.grid, tower_coords) {
+									for coords in [tower_coords, partner_coords] {
+										draw_rect_outline(
+											&mut pixel_buffer,
+											pixel_buffer_dims,
+											Rect::tile(coords, cell_pixel_side),
+											cell_pixel_side / 8,
+											[255, 220, 60, 255],
+										);
+									}
+								}
+							}
+						}
+
+						if let Some(coords) = pickup_to_confirm {
+							if matches!(
+								level.grid.get(coords).map(|cell| &cell.obj),
+								Some(Obj::Tower { .. })
+							) {
+								draw_rect_outline(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									Rect::tile(coords, cell_pixel_side),
+									cell_pixel_side / 8,
+									[255, 60, 60, 255],
+								);
+							}
+						}
 
-	let window = winit::window::WindowBuilder::new()
-		.with_title("Prototype 7")
-		.with_inner_size(winit::dpi::PhysicalSize::new(
-			(level.grid.dims.w * cell_pixel_side) as u32,
-			(level.grid.dims.h * cell_pixel_side) as u32,
-		))
-		.build(&event_loop)
-		.unwrap();
+						// While the place-tower modifier is held, show ghosts of the towers that would be
+						// placed around the player (in red where it is not possible) and what they would see.
+						let placing_tower = is_ctrl_pressed && !is_shift_pressed;
+						let variant = if is_alt_pressed {
+							Tower::Cannon
+						} else {
+							Tower::Basic
+						};
+						if placing_tower && !bot_mode && spectator.is_none() {
+							if let Some(player_coords) = level.player_coords() {
+								for dd in DxDy::the_4_directions() {
+									let ghost_coords = player_coords + dd;
+									if !level.grid.dims.contains(ghost_coords) {
+										continue;
+									}
+									let valid = can_place_tower(&level, ghost_coords);
+									if valid {
+										let facing = Direction::of_step(dd).unwrap();
+										for coords_in_range in tower_range_tiles(
+											&level.grid,
+											ghost_coords,
+											&variant,
+											facing,
+											level.weather(),
+										) {
+											draw_rect_outline(
+												&mut pixel_buffer,
+												pixel_buffer_dims,
+												Rect::tile(coords_in_range, cell_pixel_side),
+												cell_pixel_side / 16,
+												[160, 200, 255, 255],
+											);
+										}
+									}
+									draw_ghost_sprite(
+										&mut pixel_buffer,
+										pixel_buffer_dims,
+										Rect::tile(ghost_coords, cell_pixel_side),
+										&spritesheet,
+										spritesheet.sprite(&format!("tower.{}", variant.name())),
+										if valid {
+											[255, 255, 255]
+										} else {
+											[255, 40, 40]
+										},
+									);
+								}
+							}
+						}
 
-	// Center the window
-	let screen_size = window.available_monitors().next().unwrap().size();
-	let window_outer_size = window.outer_size();
-	window.set_outer_position(winit::dpi::PhysicalPosition::new(
-		screen_size.width / 2 - window_outer_size.width / 2,
-		screen_size.height / 2 - window_outer_size.height / 2,
-	));
+						// Show what the stuners see, and what they would stun.
+						for coords in level.grid.dims.iter().filter(|_| config.show_stuner_sight) {
+							let is_stuner = matches!(
+								level.grid.get(coords).unwrap().obj,
+								Obj::Enemy { variant: Enemy::Stuner, .. }
+							);
+							if !is_stuner || !level.is_visible(coords) {
+								continue;
+							}
+							for (tiles, target) in stuner_sight(&level.grid, coords) {
+								for coords_seen in tiles {
+									let tile = Rect::tile(coords_seen, cell_pixel_side);
+									let dot_side = cell_pixel_side / 8;
+									let dot = Rect {
+										top_left: tile.center() - DxDy::from((dot_side / 2, dot_side / 2)),
+										dims: Dimensions::square(dot_side),
+									};
+									draw_rect(
+										&mut pixel_buffer,
+										pixel_buffer_dims,
+										dot,
+										[200, 200, 90, 255],
+									);
+								}
+								if let Some(target) = target {
+									draw_rect_outline(
+										&mut pixel_buffer,
+										pixel_buffer_dims,
+										Rect::tile(target, cell_pixel_side),
+										cell_pixel_side / 16,
+										[255, 255, 0, 255],
+									);
+								}
+							}
+						}
 
-	// Set background and edge color
-	let clear_color = [0, 50, 50, 255];
-	let clear_color_wgpu = {
-		fn conv_srgb_to_linear(x: f64) -> f64 {
-			// See https://github.com/gfx-rs/wgpu/issues/2326
-			// Stolen from https://github.com/three-rs/three/blob/07e47da5e0673aa9a16526719e16debd59040eec/src/color.rs#L42
-			// (licensed MIT, not a substancial portion so not concerned by license obligations)
-			// Basically the brightness is adjusted somewhere by wgpu or something due to sRGB stuff,
-			// color is hard.
-			if x > 0.04045 {
-				((x + 0.055) / 1.055).powf(2.4)
-			} else {
-				x / 12.92
-			}
-		}
-		pixels::wgpu::Color {
-			r: conv_srgb_to_linear(clear_color[0] as f64 / 255.0),
-			g: conv_srgb_to_linear(clear_color[1] as f64 / 255.0),
-			b: conv_srgb_to_linear(clear_color[2] as f64 / 255.0),
-			a: conv_srgb_to_linear(clear_color[3] as f64 / 255.0),
-		}
-	};
+						// Show where the enemies will step next turn.
+						let enemy_moves = if config.show_enemy_intents {
+							predict_enemy_moves(&level)
+						} else {
+							vec![]
+						};
+						for (from, to) in enemy_moves {
+							if !level.is_visible(from) {
+								continue;
+							}
+							let direction = match to - from {
+								DxDy { dx: 0, dy: ..=-1 } => Direction::North,
+								DxDy { dx: 1.., dy: 0 } => Direction::East,
+								DxDy { dx: 0, dy: 1.. } => Direction::South,
+								DxDy { dx: ..=-1, dy: 0 } => Direction::West,
+								_ => continue,
+							};
+							// The arrow is on the border between the two tiles.
+							let from_center = Rect::tile(from, cell_pixel_side).center();
+							let to_center = Rect::tile(to, cell_pixel_side).center();
+							let arrow_side = cell_pixel_side / 2;
+							let dst = Rect {
+								top_left: Coords {
+									x: (from_center.x + to_center.x) / 2 - arrow_side / 2,
+									y: (from_center.y + to_center.y) / 2 - arrow_side / 2,
+								},
+								dims: Dimensions::square(arrow_side),
+							};
+							draw_sprite_with_effects(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								dst,
+								&spritesheet,
+								spritesheet.sprite("ui.intent"),
+								SpriteEffects::rotated_towards(direction),
+							);
+						}
 
-	let pixel_buffer_dims: Dimensions = window.inner_size().into();
-	let mut pixel_buffer = {
-		let dims = pixel_buffer_dims;
-		let surface_texture = pixels::SurfaceTexture::new(dims.w as u32, dims.h as u32, &window);
-		pixels::PixelsBuilder::new(dims.w as u32, dims.h as u32, surface_texture)
-			.clear_color(clear_color_wgpu)
-			.build()
-			.unwrap()
-	};
+						// Show which way the protected enemies face (their protected sides turn with
+						// them), with a small arrow inside the tile on the side they face.
+						if config.show_facing {
+							for coords in level.grid.dims.iter() {
+								let Obj::Enemy { variant: Enemy::Protected { direction, .. }, .. } =
+									level.grid.get(coords).unwrap().obj
+								else {
+									continue;
+								};
+								if !level.is_visible(coords) {
+									continue;
+								}
+								let tile = Rect::tile(coords, cell_pixel_side);
+								let arrow_side = cell_pixel_side / 3;
+								let center = tile.center();
+								let reach = cell_pixel_side / 2 - arrow_side / 2;
+								let offset = DxDy::from(match direction {
+									Direction::North => (0, -reach),
+									Direction::East => (reach, 0),
+									Direction::South => (0, reach),
+									Direction::West => (-reach, 0),
+								});
+								let dst = Rect {
+									top_left: center + offset - DxDy::from((arrow_side / 2, arrow_side / 2)),
+									dims: Dimensions::square(arrow_side),
+								};
+								draw_sprite_with_effects(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									dst,
+									&spritesheet,
+									spritesheet.sprite("ui.intent"),
+									SpriteEffects::rotated_towards(direction),
+								);
+							}
+						}
 
-	let spritesheet = image::load_from_memory(include_bytes!("../assets/spritesheet.png")).unwrap();
+						// Telegraph the enemies that will spawn at the end of the next turn, and
+						// the tiles whose ground will change.
+						for event in level.pending_events() {
+							let (coords, enemy) = match &event.event_type {
+								GameEventType::EnemySpawn(coords, enemy, _) => (coords, enemy),
+								GameEventType::TerrainChange(coords, _) | GameEventType::Meteor(coords) => {
+									draw_sprite(
+										&mut pixel_buffer,
+										pixel_buffer_dims,
+										Rect::tile(*coords, cell_pixel_side),
+										&spritesheet,
+										spritesheet.sprite("ui.spawn_warning"),
+									);
+									continue;
+								},
+								GameEventType::Grant(..) => continue,
+							};
+							let dst = Rect::tile(*coords, cell_pixel_side);
+							let mut dst_enemy = dst;
+							dst_enemy.dims.w /= 2;
+							dst_enemy.dims.h /= 2;
+							dst_enemy.top_left.x += cell_pixel_side / 2;
+							dst_enemy.top_left.y += cell_pixel_side / 2;
+							draw_sprite(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								dst_enemy,
+								&spritesheet,
+								spritesheet.sprite(&enemy.sprite_name()),
+							);
+							let mut dst_warning = dst;
+							dst_warning.dims.w /= 2;
+							dst_warning.dims.h /= 2;
+							draw_sprite(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								dst_warning,
+								&spritesheet,
+								spritesheet.sprite("ui.spawn_warning"),
+							);
+						}
 
-	let mut is_ctrl_pressed = false;
+						// The targeting priority menu of a tower.
+						if let Some(tower_coords) = priority_menu {
+							if let Obj::Tower { priority: current_priority, .. } =
+								level.grid.get(tower_coords).unwrap().obj
+							{
+								for (index, priority) in TargetPriority::ALL.into_iter().enumerate() {
+									let dst = priority_menu_item_rect(
+										tower_coords,
+										index as i32,
+										cell_pixel_side,
+										level.grid.dims,
+									);
+									let background = if priority == current_priority {
+										[255, 255, 160, 255]
+									} else {
+										[40, 40, 40, 255]
+									};
+									draw_rect(&mut pixel_buffer, pixel_buffer_dims, dst, background);
+									draw_sprite(
+										&mut pixel_buffer,
+										pixel_buffer_dims,
+										dst,
+										&spritesheet,
+										spritesheet.sprite(priority.sprite_name()),
+									);
+								}
+							} else {
+								// The tower is gone (eaten, exploded, etc.).
+								priority_menu = None;
+							}
+						}
 
-	use winit::event::*;
-	event_loop.run(move |event, _, control_flow| match event {
-		Event::WindowEvent { ref event, window_id } if window_id == window.id() => match event {
-			WindowEvent::CloseRequested
-			| WindowEvent::KeyboardInput {
-				input:
-					KeyboardInput {
-						state: ElementState::Pressed,
-						virtual_keycode: Some(VirtualKeyCode::Escape),
-						..
-					},
-				..
-			} => {
-				*control_flow = winit::event_loop::ControlFlow::Exit;
-			},
+						if let Some((coords, time)) = attack_flash {
+							if time.elapsed() < std::time::Duration::from_millis(150) {
+								draw_sprite(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									Rect::tile(coords, cell_pixel_side),
+									&spritesheet,
+									spritesheet.sprite("ui.slash"),
+								);
+							} else {
+								attack_flash = None;
+							}
+						}
 
-			WindowEvent::ModifiersChanged(modifiers) => {
-				is_ctrl_pressed = (*modifiers & ModifiersState::CTRL) == ModifiersState::CTRL;
-			},
+						// With several units, the one controlled is outlined and the ones that are done
+						// with the turn are darkened.
+						if level.unit_coords().len() > 1 {
+							for &coords in &level.units_acted {
+								draw_rect(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									Rect::tile(coords, cell_pixel_side),
+									[0, 0, 0, 110],
+								);
+							}
+							if let Some(coords) = level.player_coords() {
+								draw_rect_outline(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									Rect::tile(coords, cell_pixel_side),
+									cell_pixel_side / 16,
+									[255, 230, 0, 255],
+								);
+							}
+						}
 
-			WindowEvent::KeyboardInput {
-				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
-				..
-			} if matches!(
-				key,
-				VirtualKeyCode::Up
-					| VirtualKeyCode::Right
-					| VirtualKeyCode::Down
-					| VirtualKeyCode::Left
-					| VirtualKeyCode::Space
-			) =>
-			{
-				let mut action = if is_ctrl_pressed {
-					PlayerAction::PlaceTower { variant: Tower::Basic }
-				} else {
-					PlayerAction::Move
-				};
-				let dxdy = match key {
-					VirtualKeyCode::Up => (0, -1),
-					VirtualKeyCode::Right => (1, 0),
-					VirtualKeyCode::Down => (0, 1),
-					VirtualKeyCode::Left => (-1, 0),
-					VirtualKeyCode::Space => {
-						action = PlayerAction::SkipTurn;
-						(0, 0)
-					},
-					_ => unreachable!(),
-				}
-				.into();
-				player_move(&mut level, dxdy, action);
-				if !level.game_joever {
-					enemies_move(&mut level.grid);
-					level.game_joever = is_game_joever(&level.grid);
-					if level.game_joever {
-						return;
-					}
-					bomb_move(&mut level.grid);
-					flowers_move(&mut level.grid);
-					towers_move(&mut level.grid);
-					level.turn += 1;
-					apply_events(&mut level);
-				}
-			},
+						// Shake the game (but not the HUD).
+						let shake_offset = screen_effects.shake_offset(now, cell_pixel_side);
+						screen_effects::shift_frame(
+							&mut pixel_buffer,
+							pixel_buffer_dims,
+							shake_offset,
+							clear_color,
+						);
 
-			_ => {},
-		},
+						// The inventory, in the top left corner.
+						let hud_side = cell_pixel_side / 2;
+						let mut hud_x = 0;
+						for item in Item::ALL {
+							let count = level.inventory.count(item);
+							if count == 0 {
+								continue;
+							}
+							let icon = Rect {
+								top_left: Coords { x: hud_x, y: 0 },
+								dims: Dimensions::square(hud_side),
+							};
+							let digit = Rect {
+								top_left: Coords { x: hud_x + hud_side, y: 0 },
+								dims: Dimensions { w: hud_side / 2, h: hud_side },
+							};
+							let digits = count.to_string().len() as i32;
+							let background = Rect {
+								top_left: icon.top_left,
+								dims: Dimensions { w: hud_side + digits * digit.dims.w, h: hud_side },
+							};
+							draw_rect(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								background,
+								[40, 40, 40, 255],
+							);
+							draw_sprite(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								icon,
+								&spritesheet,
+								spritesheet.sprite(&item.sprite_name()),
+							);
+							draw_number(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								digit,
+								&spritesheet,
+								count,
+							);
+							hud_x += background.dims.w + hud_side / 4;
+						}
+						// Then the keys, one by one.
+						for &color in &level.inventory.keys {
+							let icon = Rect {
+								top_left: Coords { x: hud_x, y: 0 },
+								dims: Dimensions::square(hud_side),
+							};
+							draw_rect(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								icon,
+								[40, 40, 40, 255],
+							);
+							draw_sprite_with_effects(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								icon,
+								&spritesheet,
+								spritesheet.sprite("obj.key"),
+								SpriteEffects::tinted(color.tint()),
+							);
+							hud_x += hud_side + hud_side / 4;
+						}
+						// The turns left to win, after the inventory (in red at the end).
+						if let Some(turns_left) = level.turns_left() {
+							let text = tr_with("hud.turns_left", &[("count", &turns_left)]);
+							let scale = hud_side / 8;
+							let background = Rect {
+								top_left: Coords { x: hud_x, y: 0 },
+								dims: Dimensions {
+									w: (text.chars().count() as i32 * 4 + 1) * scale,
+									h: hud_side,
+								},
+							};
+							draw_rect(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								background,
+								[40, 40, 40, 255],
+							);
+							let color = if turns_left <= 3 {
+								[255, 90, 90, 255]
+							} else {
+								[255, 255, 255, 255]
+							};
+							draw_text(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								background.top_left + DxDy::from((scale, scale)),
+								scale,
+								&spritesheet,
+								&text,
+								color,
+							);
+						}
+
+						// The inspected cell, and its description in the bottom left corner.
+						if let Some(coords) =
+							inspected_cell.filter(|&coords| level.grid.dims.contains(coords))
+						{
+							draw_rect_outline(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								Rect::tile(coords, cell_pixel_side),
+								cell_pixel_side / 16,
+								[80, 220, 255, 255],
+							);
+							let lines = cell_description(&level, coords);
+							let no_input = ui::UiInput::default();
+							let mut ui = ui::Ui::new(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								&spritesheet,
+								&no_input,
+								None,
+							);
+							let line_height = ui.line_height();
+							let width = lines
+								.iter()
+								.map(|line| ui.text_width(line))
+								.max()
+								.unwrap_or(0);
+							let panel = Rect {
+								top_left: (
+									0,
+									ui.screen_dims().h - line_height * lines.len() as i32 - line_height / 2,
+								)
+									.into(),
+								dims: Dimensions {
+									w: width + line_height / 2,
+									h: line_height * lines.len() as i32 + line_height / 2,
+								},
+							};
+							ui.panel(panel);
+							for (index, line) in lines.iter().enumerate() {
+								ui.label(
+									panel.top_left
+										+ DxDy::from((
+											line_height / 4,
+											line_height / 4 + index as i32 * line_height,
+										)),
+									line,
+								);
+							}
+						}
+
+						// The lives, in the top right corner.
+						if let Some(lives) = level.lives {
+							let hud_side = cell_pixel_side / 2;
+							let width = hud_side * lives as i32;
+							let background = Rect {
+								top_left: Coords { x: pixel_buffer_dims.w - width, y: 0 },
+								dims: Dimensions { w: width, h: hud_side },
+							};
+							if lives > 0 {
+								draw_rect(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									background,
+									[40, 40, 40, 255],
+								);
+							}
+							for index in 0..lives as i32 {
+								let icon = Rect {
+									top_left: Coords { x: background.left() + index * hud_side, y: 0 },
+									dims: Dimensions::square(hud_side),
+								};
+								draw_sprite(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									icon,
+									&spritesheet,
+									spritesheet.sprite("player.heart"),
+								);
+							}
+						}
+
+						// The message of the level script, at the bottom in the middle.
+						if let Some(message) = &level.message {
+							let no_input = ui::UiInput::default();
+							let mut ui = ui::Ui::new(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								&spritesheet,
+								&no_input,
+								None,
+							);
+							let line_height = ui.line_height();
+							let lines = ui.wrap_text(message, ui.screen_dims().w / 2);
+							let width = lines
+								.iter()
+								.map(|line| ui.text_width(line))
+								.max()
+								.unwrap_or(0);
+							let dims = Dimensions {
+								w: width + line_height / 2,
+								h: line_height * lines.len() as i32 + line_height / 2,
+							};
+							let panel = Rect {
+								top_left: (
+									(ui.screen_dims().w - dims.w) / 2,
+									ui.screen_dims().h - dims.h,
+								)
+									.into(),
+								dims,
+							};
+							ui.panel(panel);
+							for (index, line) in lines.iter().enumerate() {
+								ui.label(
+									panel.top_left
+										+ DxDy::from((
+											line_height / 4,
+											line_height / 4 + index as i32 * line_height,
+										)),
+									line,
+								);
+							}
+						}
 
-		Event::MainEventsCleared => {
-			std::thread::sleep(std::time::Duration::from_millis(7));
+						// What the player was just given, at the top in the middle for a while.
+						let toasts = toasts(&level);
+						if !toasts.is_empty() && animation_time_since_turn < TOAST_DURATION {
+							let no_input = ui::UiInput::default();
+							let mut ui = ui::Ui::new(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								&spritesheet,
+								&no_input,
+								None,
+							);
+							let line_height = ui.line_height();
+							let width = toasts
+								.iter()
+								.map(|toast| ui.text_width(toast))
+								.max()
+								.unwrap_or(0);
+							let dims = Dimensions {
+								w: width + line_height / 2,
+								h: line_height * toasts.len() as i32 + line_height / 2,
+							};
+							let panel = Rect {
+								top_left: ((ui.screen_dims().w - dims.w) / 2, line_height).into(),
+								dims,
+							};
+							ui.panel(panel);
+							for (index, toast) in toasts.iter().enumerate() {
+								ui.label(
+									panel.top_left
+										+ DxDy::from((
+											line_height / 4,
+											line_height / 4 + index as i32 * line_height,
+										)),
+									toast,
+								);
+							}
+						}
+
+						// The playback of the replay and its last turns, at the top right.
+						if let Some(spectator) = &spectator {
+							let no_input = ui::UiInput::default();
+							let mut ui = ui::Ui::new(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								&spritesheet,
+								&no_input,
+								None,
+							);
+							let line_height = ui.line_height();
+							let status = tr_with(
+								if spectator.paused {
+									"spectate.paused"
+								} else {
+									"spectate.playing"
+								},
+								&[
+									("turn", &spectator.next_turn),
+									("turns", &spectator.replay.turns.len()),
+									("ms", &turn_interval.as_millis()),
+								],
+							);
+							let skipped = spectator.log.len().saturating_sub(spectate::LOG_LINES);
+							let lines: Vec<String> = [status, tr("spectate.controls")]
+								.into_iter()
+								.chain(spectator.log[skipped..].iter().cloned())
+								.collect();
+							let width = lines
+								.iter()
+								.map(|line| ui.text_width(line))
+								.max()
+								.unwrap_or(0);
+							let dims = Dimensions {
+								w: width + line_height / 2,
+								h: line_height * lines.len() as i32 + line_height / 2,
+							};
+							let panel = Rect { top_left: (ui.screen_dims().w - dims.w, 0).into(), dims };
+							ui.panel(panel);
+							for (index, line) in lines.iter().enumerate() {
+								ui.label(
+									panel.top_left
+										+ DxDy::from((
+											line_height / 4,
+											line_height / 4 + index as i32 * line_height,
+										)),
+									line,
+								);
+							}
+						}
 
-			pixel_buffer
-				.frame_mut()
-				.chunks_exact_mut(4)
-				.for_each(|pixel| pixel.copy_from_slice(&clear_color));
+						// The tutorial hint, next to the tile it is about or at the top in the middle.
+						if let Some(hint) = level.shown_hints.front() {
+							let anchor = hint
+								.anchor
+								.map(|coords| Rect::tile(coords, cell_pixel_side));
+							if let Some(anchor) = anchor {
+								draw_rect_outline(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									anchor,
+									cell_pixel_side / 16,
+									[255, 230, 80, 255],
+								);
+							}
+							let no_input = ui::UiInput::default();
+							let mut ui = ui::Ui::new(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								&spritesheet,
+								&no_input,
+								None,
+							);
+							let line_height = ui.line_height();
+							let screen_dims = ui.screen_dims();
+							let mut lines = ui.wrap_text(&hint.text, screen_dims.w / 3);
+							lines.push(tr("hint.dismiss"));
+							let width = lines
+								.iter()
+								.map(|line| ui.text_width(line))
+								.max()
+								.unwrap_or(0);
+							let dims = Dimensions {
+								w: width + line_height / 2,
+								h: line_height * lines.len() as i32 + line_height / 2,
+							};
+							let top_left: Coords = match anchor {
+								// Below the tile if there is room for it, above it otherwise.
+								Some(anchor) => (
+									anchor.left().clamp(0, (screen_dims.w - dims.w).max(0)),
+									if anchor.bottom_excluded() + dims.h <= screen_dims.h {
+										anchor.bottom_excluded()
+									} else {
+										(anchor.top() - dims.h).max(0)
+									},
+								)
+									.into(),
+								None => ((screen_dims.w - dims.w) / 2, line_height).into(),
+							};
+							let panel = Rect { top_left, dims };
+							ui.panel(panel);
+							for (index, line) in lines.iter().enumerate() {
+								ui.label(
+									panel.top_left
+										+ DxDy::from((
+											line_height / 4,
+											line_height / 4 + index as i32 * line_height,
+										)),
+									line,
+								);
+							}
+						}
 
-			for coords in level.grid.dims.iter() {
-				let dst = Rect::tile(coords, cell_pixel_side);
-				let sprite = match level.grid.get(coords).unwrap().groud {
-					Ground::Grass => (5, 0),
-					Ground::Water => (6, 0),
-					Ground::Path(_) => (7, 0),
+						if level.game_joever {
+							let jover_sprite = spritesheet.sprite("ui.joever");
+							let dst_dims =
+								Dimensions { w: jover_sprite.dims.w * 8, h: jover_sprite.dims.h * 8 };
+							let centered_dst = Rect {
+								top_left: Coords {
+									x: pixel_buffer_dims.w / 2 - dst_dims.w / 2,
+									y: pixel_buffer_dims.h / 2 - dst_dims.h / 2,
+								},
+								dims: dst_dims,
+							};
+							draw_sprite(
+								&mut pixel_buffer,
+								pixel_buffer_dims,
+								centered_dst,
+								&spritesheet,
+								jover_sprite,
+							);
+							// How many times the player can still go back to the last checkpoint.
+							if let Some(checkpoints) = checkpoints.as_ref().filter(|c| c.rewinds_left > 0)
+							{
+								let icon = Rect {
+									top_left: Coords {
+										x: pixel_buffer_dims.w / 2 - cell_pixel_side,
+										y: centered_dst.bottom_excluded(),
+									},
+									dims: Dimensions::square(cell_pixel_side),
+								};
+								draw_sprite(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									icon,
+									&spritesheet,
+									spritesheet.sprite("ui.rewind"),
+								);
+								draw_number(
+									&mut pixel_buffer,
+									pixel_buffer_dims,
+									Rect {
+										top_left: (icon.right_excluded(), icon.top()).into(),
+										dims: Dimensions { w: cell_pixel_side / 2, h: cell_pixel_side },
+									},
+									&spritesheet,
+									checkpoints.rewinds_left,
+								);
+							}
+						}
+					})
 				};
-				let sprite_rect = Rect::tile(sprite.into(), 8);
-				draw_sprite(
-					&mut pixel_buffer,
-					pixel_buffer_dims,
-					dst,
-					&spritesheet,
-					sprite_rect,
-				);
-				if level.grid.get(coords).unwrap().rocky_path {
-					let sprite_rect = Rect::tile((5, 2).into(), 8);
-					draw_sprite(
+				if let Err(error) = drawn {
+					set_scene_tint(None);
+					error_message = error;
+					screen = Screen::Error;
+				}
+				if screen == Screen::Error {
+					let everything = Rect { top_left: (0, 0).into(), dims: pixel_buffer_dims };
+					draw_rect(
 						&mut pixel_buffer,
 						pixel_buffer_dims,
-						dst,
-						&spritesheet,
-						sprite_rect,
+						everything,
+						clear_color,
 					);
 				}
-				let sprite = match level.grid.get(coords).unwrap().obj {
-					Obj::Empty => None,
-					Obj::Player { .. } => Some((0, 2)),
-					Obj::Goal => Some((1, 2)),
-					Obj::Enemy { variant: Enemy::Basic, .. } => Some((2, 2)),
-					Obj::Enemy { variant: Enemy::Tank, .. } => Some((2, 3)),
-					Obj::Enemy { variant: Enemy::Speeeeed, .. } => Some((2, 4)),
-					Obj::Enemy { variant: Enemy::Stuner, .. } => Some((2, 5)),
-					Obj::Enemy { variant: Enemy::Eater, .. } => Some((2, 6)),
-					Obj::Enemy { variant: Enemy::Protected { direction, protection }, .. } => {
-						Some(protection.sprite(direction))
-					},
-					Obj::Tower { variant: Tower::Basic, .. } => Some((3, 2)),
-					Obj::Tower { variant: Tower::Piercing, .. } => Some((3, 3)),
-					Obj::Tower { variant: Tower::TotalEnergy, .. } => Some((3, 4)),
-					Obj::Tower { variant: Tower::Unabomber, .. } => Some((3, 5)),
-					Obj::Tower { variant: Tower::Pusher, .. } => Some((3, 6)),
-					Obj::Bomb { countdown: 3 } => Some((4, 5)),
-					Obj::Bomb { countdown: 2 } => Some((5, 5)),
-					Obj::Bomb { countdown: 1 } => Some((6, 5)),
-					Obj::Bomb { countdown: 0 } => Some((7, 5)),
-					Obj::Bomb { .. } => unimplemented!(),
-					Obj::Flower { variant: Flower::BlueFlower } => Some((6, 2)),
-					Obj::Flower { variant: Flower::TheOther } => Some((7, 2)),
-					Obj::Flower { variant: Flower::TheOtherOther } => Some((7, 4)),
-					Obj::Rock => Some((8, 2)),
-					Obj::Tree => Some((9, 2)),
-				};
-				if let Some(sprite) = sprite {
-					let sprite_rect = Rect::tile(sprite.into(), 8);
-					draw_sprite(
+
+				if screen == Screen::Title {
+					if ui_input.has_input() {
+						needs_redraw = true;
+						title_idle_since = std::time::Instant::now();
+						if attract_mode {
+							// Any input stops the demo, and the level is ready to be played.
+							level = LevelState::new(&level_data);
+							level_starts += 1;
+							played = replay::Replay::new(&level_file);
+							if checkpoints.is_some() {
+								checkpoints = Some(checkpoint::Checkpoints::new(&level));
+							}
+							ui_input.clear();
+						}
+					}
+					let mut ui = ui::Ui::new(
 						&mut pixel_buffer,
 						pixel_buffer_dims,
-						dst,
 						&spritesheet,
-						sprite_rect,
+						&ui_input,
+						cursor_position,
 					);
-				}
-				if let Obj::Enemy { variant, hp, .. } = &level.grid.get(coords).unwrap().obj {
-					// Draw a life bar
-					let mut dst = Rect::tile(coords, cell_pixel_side);
-					dst.top_left.y += cell_pixel_side / 8;
-					dst.dims.h = cell_pixel_side / 8;
-					dst.top_left.x += cell_pixel_side / 8;
-					dst.dims.w = cell_pixel_side * 6 / 8;
-					draw_rect(&mut pixel_buffer, pixel_buffer_dims, dst, [255, 0, 0, 255]);
-					dst.dims.w = (cell_pixel_side * 6 / 8) * *hp as i32 / variant.hp_max() as i32;
-					draw_rect(&mut pixel_buffer, pixel_buffer_dims, dst, [0, 255, 0, 255]);
-				}
-				if let Obj::Player { stunned: true } | Obj::Tower { stunned: true, .. } =
-					&level.grid.get(coords).unwrap().obj
-				{
-					let mut dst = dst;
-					dst.dims.w /= 4;
-					dst.dims.h /= 4;
-					dst.top_left.x += 6 * cell_pixel_side / 8;
-					draw_rect(
+					ui.dim_background();
+					let line_height = ui.line_height();
+					let screen_dims = ui.screen_dims();
+					ui.big_centered_label(screen_dims.h / 4, &tr("menu.title"));
+					if attract_mode {
+						ui.centered_label(screen_dims.h * 3 / 4, &tr("menu.demo"));
+					} else {
+						let items = [
+							"menu.play",
+							"menu.levels",
+							"menu.watch",
+							"menu.options",
+							"menu.quit",
+						]
+						.map(tr);
+						let list_width = items
+							.iter()
+							.map(|item| ui.text_width(item))
+							.max()
+							.unwrap_or(0) + 2 * line_height;
+						let list_rect = Rect {
+							top_left: ((screen_dims.w - list_width) / 2, screen_dims.h / 2).into(),
+							dims: Dimensions { w: list_width, h: items.len() as i32 * line_height },
+						};
+						let event = ui.list(list_rect, &items, &mut menu_selection);
+						if ui.back() {
+							*control_flow = winit::event_loop::ControlFlow::Exit;
+						} else if let Some(ui::ListEvent::Activated(index)) = event {
+							menu_selection = 0;
+							match index {
+								0 => {
+									screen = Screen::Playing;
+									main_menu = Screen::Paused;
+								},
+								1 => {
+									screen = Screen::LevelSelect;
+									level_files = list_level_files(&mods);
+									share_status.clear();
+								},
+								2 => {
+									screen = Screen::WatchReplay;
+									share_status.clear();
+								},
+								3 => screen = Screen::Settings,
+								_ => *control_flow = winit::event_loop::ControlFlow::Exit,
+							}
+						}
+					}
+					ui_input.clear();
+				} else if screen != Screen::Playing {
+					let mut ui = ui::Ui::new(
 						&mut pixel_buffer,
 						pixel_buffer_dims,
-						dst,
-						[255, 255, 0, 255],
+						&spritesheet,
+						&ui_input,
+						cursor_position,
 					);
+					ui.dim_background();
+					let line_height = ui.line_height();
+					let margin = line_height / 2;
+					let screen_dims = ui.screen_dims();
+					let title = match screen {
+						Screen::Title | Screen::Playing => unreachable!(),
+						Screen::Paused => tr("menu.pause"),
+						Screen::LevelSelect => tr("menu.levels"),
+						Screen::Settings => tr("menu.settings"),
+						Screen::Error => tr("menu.error"),
+						Screen::NameEntry => tr("menu.high_score"),
+						Screen::WatchReplay => tr("menu.watch"),
+						Screen::Crafting => tr("menu.crafting"),
+						Screen::ConfirmQuit => tr("menu.confirm_quit"),
+						Screen::Results => tr(if outcome.is_some_and(|outcome| outcome.won) {
+							"menu.won"
+						} else {
+							"menu.lost"
+						}),
+					};
+					let panel = Rect {
+						top_left: (margin, margin).into(),
+						dims: Dimensions { w: screen_dims.w - 2 * margin, h: screen_dims.h - 2 * margin },
+					};
+					ui.panel(panel);
+					ui.centered_label(panel.top() + margin, &title);
+					let back_text = tr("menu.back");
+					let back_button = Rect {
+						top_left: (
+							panel.left() + margin,
+							panel.bottom_excluded() - margin - line_height,
+						)
+							.into(),
+						dims: Dimensions { w: ui.text_width(&back_text) + line_height, h: line_height },
+					};
+					let list_rect = Rect {
+						top_left: (
+							panel.left() + margin,
+							panel.top() + margin + line_height * 2,
+						)
+							.into(),
+						dims: Dimensions {
+							w: panel.dims.w - 2 * margin,
+							h: back_button.top() - margin - (panel.top() + margin + line_height * 2),
+						},
+					};
+					let back = ui.back() || ui.button(back_button, &back_text);
+					// Restarting the level is in several menus.
+					let mut restart = false;
+					match screen {
+						Screen::Title | Screen::Playing => unreachable!(),
+						Screen::Error => {
+							let lines = ui.wrap_text(&error_message, list_rect.dims.w);
+							for (index, line) in lines.iter().enumerate() {
+								let y = list_rect.top() + index as i32 * line_height;
+								ui.label((list_rect.left(), y).into(), line);
+							}
+							let items = ["menu.back_to_menu", "menu.quit"].map(tr);
+							let items_rect = Rect {
+								top_left: (
+									list_rect.left(),
+									list_rect.top() + (lines.len() as i32 + 1) * line_height,
+								)
+									.into(),
+								dims: Dimensions {
+									w: list_rect.dims.w,
+									h: items.len() as i32 * line_height,
+								},
+							};
+							let event = ui.list(items_rect, &items, &mut menu_selection);
+							if back || matches!(event, Some(ui::ListEvent::Activated(0))) {
+								// The level may have been left in a broken state, it starts over.
+								screen = Screen::Title;
+								main_menu = Screen::Title;
+								menu_selection = 0;
+								title_idle_since = std::time::Instant::now();
+								error_message.clear();
+								level = LevelState::new(&level_data);
+								level_starts += 1;
+								outcome = None;
+								played = replay::Replay::new(&level_file);
+								if checkpoints.is_some() {
+									checkpoints = Some(checkpoint::Checkpoints::new(&level));
+								}
+								input_queue.clear();
+								(merge_offer, pickup_to_confirm) = (None, None);
+							} else if let Some(ui::ListEvent::Activated(_)) = event {
+								*control_flow = winit::event_loop::ControlFlow::Exit;
+							}
+						},
+						Screen::Paused => {
+							let items = [
+								"menu.resume",
+								"menu.restart",
+								"menu.levels",
+								"menu.watch",
+								"menu.settings",
+								"menu.quit",
+							]
+							.map(tr);
+							let event = ui.list(list_rect, &items, &mut menu_selection);
+							if back {
+								screen = Screen::Playing;
+							} else if let Some(ui::ListEvent::Activated(index)) = event {
+								menu_selection = 0;
+								match index {
+									0 => screen = Screen::Playing,
+									1 => restart = true,
+									2 => {
+										screen = Screen::LevelSelect;
+										level_files = list_level_files(&mods);
+										share_status.clear();
+									},
+									3 => {
+										screen = Screen::WatchReplay;
+										share_status.clear();
+									},
+									4 => screen = Screen::Settings,
+									_ if quit_is_confirmed(
+										&config,
+										&level,
+										screen,
+										!bot_mode && spectator.is_none(),
+									) =>
+									{
+										screen_before_quit = screen;
+										screen = Screen::ConfirmQuit;
+									},
+									_ => *control_flow = winit::event_loop::ControlFlow::Exit,
+								}
+							}
+						},
+						Screen::ConfirmQuit => {
+							ui.label(list_rect.top_left, &tr("menu.quit_warning"));
+							let items = ["menu.quit", "menu.keep_playing"].map(tr);
+							let items_rect = Rect {
+								top_left: (list_rect.left(), list_rect.top() + 2 * line_height).into(),
+								dims: Dimensions {
+									w: list_rect.dims.w,
+									h: items.len() as i32 * line_height,
+								},
+							};
+							let event = ui.list(items_rect, &items, &mut menu_selection);
+							if back || matches!(event, Some(ui::ListEvent::Activated(1))) {
+								screen = screen_before_quit;
+								menu_selection = 0;
+							} else if let Some(ui::ListEvent::Activated(_)) = event {
+								*control_flow = winit::event_loop::ControlFlow::Exit;
+							}
+						},
+						Screen::Crafting => {
+							let resources = tr_with(
+								"crafting.resources",
+								&[
+									("wood", &level.inventory.wood),
+									("stone", &level.inventory.stone),
+								],
+							);
+							ui.label(list_rect.top_left, &resources);
+							let items = Recipe::ALL.map(|recipe| {
+								let (wood, stone) = balance::get().craft_cost(recipe);
+								tr_with(
+									&format!("crafting.{}", recipe.name()),
+									&[("wood", &wood), ("stone", &stone)],
+								)
+							});
+							let items_rect = Rect {
+								top_left: (list_rect.left(), list_rect.top() + 2 * line_height).into(),
+								dims: Dimensions {
+									w: list_rect.dims.w,
+									h: items.len() as i32 * line_height,
+								},
+							};
+							let event = ui.list(items_rect, &items, &mut menu_selection);
+							if back {
+								screen = Screen::Playing;
+							} else if let Some(ui::ListEvent::Activated(index)) = event {
+								// Played like the other actions, so that it is recorded.
+								let recipe = Recipe::ALL[index];
+								input_queue.push(DxDy::from((0, 0)), PlayerAction::Craft { recipe });
+								screen = Screen::Playing;
+							}
+						},
+						Screen::NameEntry => {
+							let Some(level_outcome) = &mut outcome else {
+								unreachable!("no name entry without an outcome");
+							};
+							let score_text = tr_with(
+								"results.score",
+								&[
+									("score", &level_outcome.score),
+									("turns", &level_outcome.turns),
+								],
+							);
+							ui.label(list_rect.top_left, &score_text);
+							let name_y = list_rect.top() + 2 * line_height;
+							ui.label((list_rect.left(), name_y).into(), &tr("results.enter_name"));
+							let field = Rect {
+								top_left: (list_rect.left(), name_y + line_height).into(),
+								dims: Dimensions {
+									w: ui.text_width(&"W".repeat(high_scores::MAX_NAME_LENGTH + 1))
+										+ line_height / 2,
+									h: line_height,
+								},
+							};
+							ui.text_field(field, &mut name_entry, high_scores::MAX_NAME_LENGTH);
+							if ui_input.enter {
+								let name = match name_entry.trim() {
+									"" => tr("results.default_name"),
+									name => name.to_string(),
+								};
+								let entry = high_scores::Entry {
+									name: name.clone(),
+									score: level_outcome.score,
+									turns: level_outcome.turns,
+									date: daily::Date::today().to_string(),
+								};
+								level_outcome.rank =
+									high_scores.add(&high_scores::level_key(&level_file), entry);
+								high_scores.last_name = name;
+								high_scores.save();
+								screen = Screen::Results;
+							} else if back && !ui_input.erase {
+								screen = Screen::Results;
+							}
+						},
+						Screen::WatchReplay => {
+							ui.label(list_rect.top_left, &tr("share.paste_replay"));
+							let field = Rect {
+								top_left: (list_rect.left(), list_rect.top() + line_height).into(),
+								dims: Dimensions { w: list_rect.dims.w, h: line_height },
+							};
+							ui.text_field(field, &mut code_entry, share::MAX_CODE_LENGTH);
+							for (index, line) in ui
+								.wrap_text(&share_status, list_rect.dims.w)
+								.iter()
+								.enumerate()
+							{
+								let y = field.bottom_excluded() + (index as i32 + 1) * line_height;
+								ui.label((list_rect.left(), y).into(), line);
+							}
+							if ui_input.enter {
+								match share::replay_from_code(&code_entry, &level_files)
+									.and_then(|replay| Ok((load_level(&replay.level_file)?, replay)))
+								{
+									Ok((new_level_data, replay)) => {
+										screen = Screen::Playing;
+										main_menu = Screen::Paused;
+										menu_selection = 0;
+										level_file = replay.level_file.clone();
+										level_data = new_level_data;
+										level = LevelState::new(&level_data);
+										level_starts += 1;
+										played = replay::Replay::new(&level_file);
+										spectator = Some(spectate::Spectator::new(replay));
+										daily_run = None;
+										outcome = None;
+										recorder = None;
+										checkpoints = None;
+										input_queue.clear();
+										(merge_offer, pickup_to_confirm) = (None, None);
+										pixel_buffer_dims = fit_window_to_level(
+											&window,
+											&mut pixel_buffer,
+											level.grid.dims,
+											cell_pixel_side,
+											&config,
+										);
+									},
+									Err(error) => share_status = error,
+								}
+							} else if back && !ui_input.erase {
+								screen = main_menu;
+								menu_selection = 0;
+							}
+						},
+						Screen::Results => {
+							let Some(level_outcome) = outcome else {
+								unreachable!("no results without an outcome");
+							};
+							let mut lines = vec![tr_with(
+								"results.score",
+								&[
+									("score", &level_outcome.score),
+									("turns", &level_outcome.turns),
+								],
+							)];
+							if let Some(rank) = level_outcome.rank {
+								lines.push(tr_with("results.rank", &[("rank", &rank)]));
+							}
+							if !share_status.is_empty() {
+								lines.push(share_status.clone());
+							}
+							lines.push(String::new());
+							let entries = high_scores.of_level(&high_scores::level_key(&level_file));
+							if entries.is_empty() {
+								lines.push(tr("results.no_scores"));
+							}
+							for (index, entry) in entries.iter().enumerate() {
+								lines.push(format!(
+									"{:>2}. {:<width$} {:>6}  {}  {}",
+									index + 1,
+									entry.name,
+									entry.score,
+									tr_with("results.turns", &[("turns", &entry.turns)]),
+									entry.date,
+									width = high_scores::MAX_NAME_LENGTH,
+								));
+							}
+							for (index, line) in lines.iter().enumerate() {
+								let y = list_rect.top() + index as i32 * line_height;
+								ui.label((list_rect.left(), y).into(), line);
+							}
+							let items = [
+								"menu.restart",
+								"menu.copy_replay",
+								"menu.levels",
+								"menu.quit",
+							]
+							.map(tr);
+							let items_rect = Rect {
+								top_left: (
+									list_rect.left(),
+									list_rect.top() + (lines.len() as i32 + 1) * line_height,
+								)
+									.into(),
+								dims: Dimensions {
+									w: list_rect.dims.w,
+									h: items.len() as i32 * line_height,
+								},
+							};
+							let event = ui.list(items_rect, &items, &mut menu_selection);
+							if back {
+								screen = Screen::Playing;
+							} else if let Some(ui::ListEvent::Activated(index)) = event {
+								menu_selection = 0;
+								match index {
+									0 => restart = true,
+									1 => {
+										share_status = match share::replay_code(&played)
+											.and_then(|code| share::copy_to_clipboard(&code))
+										{
+											Ok(()) => tr("share.copied"),
+											Err(error) => tr_with("share.copy_failed", &[("error", &error)]),
+										};
+										menu_selection = 1;
+									},
+									2 => {
+										screen = Screen::LevelSelect;
+										level_files = list_level_files(&mods);
+										share_status.clear();
+									},
+									_ => *control_flow = winit::event_loop::ControlFlow::Exit,
+								}
+							}
+						},
+						Screen::LevelSelect => {
+							let names: Vec<String> = level_files
+								.iter()
+								.map(|(name, path)| {
+									match high_scores.of_level(&high_scores::level_key(path)).first() {
+										Some(best) => format!(
+											"{name}  {}",
+											tr_with("results.best", &[("score", &best.score)])
+										),
+										None => name.clone(),
+									}
+								})
+								.collect();
+							// The levels, then a line for the result of the copy and paste buttons.
+							let levels_rect = Rect {
+								top_left: list_rect.top_left,
+								dims: Dimensions { w: list_rect.dims.w, h: list_rect.dims.h - line_height },
+							};
+							let event = ui.list(levels_rect, &names, &mut menu_selection);
+							ui.label(
+								(list_rect.left(), levels_rect.bottom_excluded()).into(),
+								&share_status,
+							);
+							let [copy_text, paste_text] = ["menu.copy_level", "menu.paste_level"].map(tr);
+							let copy_button = Rect {
+								top_left: (back_button.right_excluded() + margin, back_button.top()).into(),
+								dims: Dimensions {
+									w: ui.text_width(&copy_text) + line_height,
+									h: line_height,
+								},
+							};
+							let paste_button = Rect {
+								top_left: (copy_button.right_excluded() + margin, back_button.top()).into(),
+								dims: Dimensions {
+									w: ui.text_width(&paste_text) + line_height,
+									h: line_height,
+								},
+							};
+							let copy = ui.button(copy_button, &copy_text) || ui_input.copy;
+							let paste = ui.button(paste_button, &paste_text) || ui_input.paste;
+							if copy {
+								share_status = match level_files.get(menu_selection) {
+									Some((name, path)) => match share::level_text_for_sharing(path)
+										.and_then(|text| share::copy_to_clipboard(&text))
+									{
+										Ok(()) => tr_with("share.level_copied", &[("level", name)]),
+										Err(error) => tr_with("share.copy_failed", &[("error", &error)]),
+									},
+									None => String::new(),
+								};
+							} else if paste {
+								share_status = match share::paste_from_clipboard()
+									.and_then(|text| share::save_pasted_level(&text))
+								{
+									Ok(pasted_file) => {
+										level_files = list_level_files(&mods);
+										menu_selection = level_files
+											.iter()
+											.position(|(_, path)| *path == pasted_file)
+											.unwrap_or(0);
+										tr_with("share.level_pasted", &[("file", &pasted_file)])
+									},
+									Err(error) => tr_with("share.paste_failed", &[("error", &error)]),
+								};
+							} else if back {
+								screen = main_menu;
+								menu_selection = 0;
+							} else if let Some(ui::ListEvent::Activated(index)) = event {
+								let new_level_file = level_files[index].1.clone();
+								match load_level(&new_level_file) {
+									Ok(new_level_data) => {
+										screen = Screen::Playing;
+										main_menu = Screen::Paused;
+										menu_selection = 0;
+										level_file = new_level_file;
+										level_data = new_level_data;
+										level = LevelState::new(&level_data);
+										level_starts += 1;
+										spectator = None;
+										daily_run = None;
+										outcome = None;
+										played = replay::Replay::new(&level_file);
+										share_status.clear();
+										recorder = record_file.as_ref().map(|record_file| {
+											replay::Recorder::new(&level_file, record_file)
+										});
+										checkpoints = (!bot_mode && recorder.is_none())
+											.then(|| checkpoint::Checkpoints::new(&level));
+										input_queue.clear();
+										(merge_offer, pickup_to_confirm) = (None, None);
+										pixel_buffer_dims = fit_window_to_level(
+											&window,
+											&mut pixel_buffer,
+											level.grid.dims,
+											cell_pixel_side,
+											&config,
+										);
+									},
+									Err(error) => {
+										log::error!("Failed to load {new_level_file}: {error}");
+										error_message = error;
+										screen = Screen::Error;
+										menu_selection = 0;
+									},
+								}
+							}
+						},
+						Screen::Settings => {
+							let on_off = |on: bool| tr(if on { "settings.on" } else { "settings.off" });
+							let items = [
+								tr_with("settings.turn_delay", &[("ms", &turn_interval.as_millis())]),
+								tr_with(
+									"settings.enemy_arrows",
+									&[("value", &on_off(config.show_enemy_intents))],
+								),
+								tr_with(
+									"settings.stuner_sight",
+									&[("value", &on_off(config.show_stuner_sight))],
+								),
+								tr_with(
+									"settings.facing_arrows",
+									&[("value", &on_off(config.show_facing))],
+								),
+								tr_with("settings.volume", &[("value", &config.volume)]),
+								tr_with("settings.zoom", &[("value", &config.zoom)]),
+								tr_with("settings.animations", &[("value", &config.animation_speed)]),
+								tr_with(
+									"settings.colorblind",
+									&[("value", &on_off(config.colorblind))],
+								),
+								tr_with(
+									"settings.keys",
+									&[("value", &config.key_bindings.name().to_uppercase())],
+								),
+								if config.key_repeat == 0 {
+									tr_with("settings.key_repeat", &[("value", &tr("settings.off"))])
+								} else {
+									tr_with("settings.key_repeat_ms", &[("ms", &config.key_repeat)])
+								},
+								tr_with(
+									"settings.language",
+									&[("value", &config.language.native_name())],
+								),
+								tr_with(
+									"settings.confirm_quit",
+									&[("value", &on_off(config.confirm_quit))],
+								),
+								if config.scale == 0 {
+									tr_with("settings.scale", &[("value", &tr("settings.auto"))])
+								} else {
+									tr_with(
+										"settings.scale",
+										&[("value", &format!("{}X", config.scale))],
+									)
+								},
+							];
+							let event = ui.list(list_rect, &items, &mut menu_selection);
+							if back {
+								screen = main_menu;
+								menu_selection = 0;
+							}
+							// All the options but the turn delay are saved in the config.
+							let changes_config = matches!(
+								event,
+								Some(ui::ListEvent::Activated(1..) | ui::ListEvent::Adjusted(1.., _))
+							);
+							match event {
+								Some(ui::ListEvent::Adjusted(0, delta)) => {
+									let step = std::time::Duration::from_millis(100);
+									turn_interval = if delta < 0 {
+										turn_interval.saturating_sub(step).max(step)
+									} else {
+										(turn_interval + step).min(std::time::Duration::from_secs(3))
+									};
+								},
+								Some(ui::ListEvent::Activated(1) | ui::ListEvent::Adjusted(1, _)) => {
+									config.show_enemy_intents = !config.show_enemy_intents;
+								},
+								Some(ui::ListEvent::Activated(2) | ui::ListEvent::Adjusted(2, _)) => {
+									config.show_stuner_sight = !config.show_stuner_sight;
+								},
+								Some(ui::ListEvent::Activated(3) | ui::ListEvent::Adjusted(3, _)) => {
+									config.show_facing = !config.show_facing;
+								},
+								Some(ui::ListEvent::Adjusted(4, delta)) => {
+									config.volume = (config.volume as i32 + 10 * delta).clamp(0, 100) as u32;
+								},
+								Some(ui::ListEvent::Adjusted(5, delta)) => {
+									config.zoom = (config.zoom + delta)
+										.clamp(*config::ZOOM_RANGE.start(), *config::ZOOM_RANGE.end());
+									cell_pixel_side = 8 * config.zoom;
+									pixel_buffer_dims = fit_window_to_level(
+										&window,
+										&mut pixel_buffer,
+										level.grid.dims,
+										cell_pixel_side,
+										&config,
+									);
+								},
+								Some(ui::ListEvent::Adjusted(6, delta)) => {
+									config.animation_speed = (config.animation_speed as i32 + 25 * delta)
+										.clamp(
+											*config::ANIMATION_SPEED_RANGE.start() as i32,
+											*config::ANIMATION_SPEED_RANGE.end() as i32,
+										) as u32;
+								},
+								Some(ui::ListEvent::Activated(7) | ui::ListEvent::Adjusted(7, _)) => {
+									config.colorblind = !config.colorblind;
+									COLORBLIND.store(config.colorblind, Ordering::Relaxed);
+								},
+								Some(ui::ListEvent::Adjusted(8, delta)) => {
+									let all = config::KeyBindings::ALL;
+									let index = all
+										.iter()
+										.position(|&key_bindings| key_bindings == config.key_bindings)
+										.unwrap();
+									let new_index = (index as i32 + delta).rem_euclid(all.len() as i32);
+									config.key_bindings = all[new_index as usize];
+								},
+								Some(ui::ListEvent::Adjusted(9, delta)) => {
+									// Off below the shortest delay (which is the step).
+									let max = *config::KEY_REPEAT_RANGE.end() as i32;
+									config.key_repeat =
+										(config.key_repeat as i32 + 50 * delta).clamp(0, max) as u32;
+								},
+								Some(ui::ListEvent::Adjusted(10, delta)) => {
+									let all = locale::Language::ALL;
+									let index = all
+										.iter()
+										.position(|&language| language == config.language)
+										.unwrap();
+									let new_index = (index as i32 + delta).rem_euclid(all.len() as i32);
+									config.language = all[new_index as usize];
+									locale::set_language(config.language);
+								},
+								Some(ui::ListEvent::Activated(11) | ui::ListEvent::Adjusted(11, _)) => {
+									config.confirm_quit = !config.confirm_quit;
+								},
+								Some(ui::ListEvent::Adjusted(12, delta)) => {
+									// Automatic below 1X.
+									let max = *config::SCALE_RANGE.end() as i32;
+									config.scale = (config.scale as i32 + delta).clamp(0, max) as u32;
+									pixel_buffer_dims = fit_window_to_level(
+										&window,
+										&mut pixel_buffer,
+										level.grid.dims,
+										cell_pixel_side,
+										&config,
+									);
+								},
+								_ => {},
+							}
+							if changes_config {
+								config.save();
+							}
+						},
+					}
+					if restart {
+						screen = Screen::Playing;
+						level = LevelState::new(&level_data);
+						level_starts += 1;
+						if let Some(spectator) = &mut spectator {
+							spectator.restart();
+						}
+						recorder = record_file
+							.as_ref()
+							.map(|record_file| replay::Recorder::new(&level_file, record_file));
+						if checkpoints.is_some() {
+							checkpoints = Some(checkpoint::Checkpoints::new(&level));
+						}
+						daily_run = daily_run.take().map(|run| daily::Run::new(run.date));
+						outcome = None;
+						played = replay::Replay::new(&level_file);
+						share_status.clear();
+						input_queue.clear();
+						(merge_offer, pickup_to_confirm) = (None, None);
+					}
+					// Menus react to the input while being drawn, show the result right away.
+					if ui_input.has_input() {
+						needs_redraw = true;
+					}
+					ui_input.clear();
 				}
-			}
 
-			if level.game_joever {
-				let jover_sprite = Rect {
-					top_left: Coords { x: 0, y: 8 },
-					dims: Dimensions { w: 8 * 7, h: 8 },
-				};
-				let dst_dims = Dimensions { w: 8 * 7 * 8, h: 8 * 8 };
-				let centered_dst = Rect {
-					top_left: Coords {
-						x: pixel_buffer_dims.w / 2 - dst_dims.w / 2,
-						y: pixel_buffer_dims.h / 2 - dst_dims.h / 2,
-					},
-					dims: dst_dims,
-				};
-				draw_sprite(
-					&mut pixel_buffer,
-					pixel_buffer_dims,
-					centered_dst,
-					&spritesheet,
-					jover_sprite,
-				);
-			}
+				let now = std::time::Instant::now();
+				if transition
+					.as_ref()
+					.is_some_and(|transition| transition.is_over(now))
+				{
+					transition = None;
+				}
+				if let Some(transition) = &transition {
+					transition.draw(&mut pixel_buffer, pixel_buffer_dims, now);
+				}
 
-			window.request_redraw();
-		},
+				window.request_redraw();
 
-		Event::RedrawRequested(_) => {
-			pixel_buffer.render().unwrap();
-		},
+				// Wait for the next input, unless something has to happen before.
+				let now = std::time::Instant::now();
+				let mut wake_up_times = vec![];
+				if needs_redraw {
+					wake_up_times.push(now);
+				}
+				let timed_turns = bot_mode || auto_turn_mode || spectator.is_some();
+				if attract_mode {
+					let next_demo_step = if level.game_joever {
+						3 * turn_interval
+					} else {
+						turn_interval
+					};
+					wake_up_times.push(last_timed_turn + next_demo_step);
+				} else if screen == Screen::Title {
+					wake_up_times.push(title_idle_since + attract_mode_delay);
+				}
+				if screen == Screen::Playing && !level.game_joever {
+					if fast_forward {
+						wake_up_times.push(now + animation_frame_duration);
+					} else if timed_turns {
+						wake_up_times.push(last_timed_turn + turn_interval);
+					} else if input_queue.front().is_some() {
+						// The next action waiting is played at the next frame.
+						wake_up_times.push(now + animation_frame_duration);
+					}
+					wake_up_times.extend(input_queue.next_repeat());
+				}
+				if !toasts(&level).is_empty() && animation_time_since_turn < TOAST_DURATION {
+					// To hide the toasts.
+					wake_up_times.push(last_turn_drawn.1 + TOAST_DURATION.div_f32(animation_speed));
+				}
+				let something_is_stunned = level.grid.dims.iter().any(|coords| {
+					matches!(
+						level.grid.get(coords).unwrap().obj,
+						Obj::Player { stunned: true } | Obj::Tower { stunned: true, .. }
+					)
+				});
+				if hit_flash_on
+					|| !slides.is_empty()
+					|| !rises.is_empty()
+					|| attack_flash.is_some()
+					|| something_is_stunned
+					|| !particles.is_empty()
+					|| screen_effects.is_active(now)
+					|| transition.is_some()
+				{
+					wake_up_times.push(now + animation_frame_duration);
+				}
+				match wake_up_times.into_iter().min() {
+					Some(wake_up_time) => control_flow.set_wait_until(wake_up_time),
+					None => control_flow.set_wait(),
+				}
+			},
 
-		_ => {},
+			Event::RedrawRequested(_) => {
+				pixel_buffer.render().unwrap();
+			},
+
+			Event::LoopDestroyed => {
+				config.window = window_placement(&window, config.window).or(config.window);
+				config.save();
+			},
+
+			_ => {},
+		}
 	});
 }
@@ -0,0 +1,133 @@
+//! Content packs, in the directories of `mods/` (like `mods/winter/`), found at startup. A mod
+//! can contain:
+//! - `levels/`, level files added to the level select (as `<mod>/<level>`),
+//! - `spritesheet.png` (with `sprites.toml` if the sprites are not where they are in the game's
+//!   spritesheet), replacing the spritesheet of the game,
+//! - `balance.toml`, overriding some numbers of `assets/balance.toml`,
+//! - `sounds/`, a sound pack (there are no sounds yet, it is only reported).
+//!
+//! Mods are applied in the alphabetical order of their directories, so when two mods replace
+//! the same thing the last one wins (prefix the directory names with numbers to order them).
+//! The options given on the command line (like `--balance`) come after all the mods.
+
+use std::path::{Path, PathBuf};
+
+struct Mod {
+	name: String,
+	dir: PathBuf,
+}
+
+pub struct Mods {
+	/// In the order in which they are applied.
+	mods: Vec<Mod>,
+}
+
+impl Mods {
+	pub const DIR: &'static str = "mods";
+
+	/// The mods in the directories of `dir`, none if there is no such directory. Directories
+	/// starting with a `.` are ignored, so a mod can be disabled by renaming it.
+	pub fn scan(dir: &Path) -> Mods {
+		let mut mods: Vec<Mod> = std::fs::read_dir(dir)
+			.map(|entries| {
+				entries
+					.filter_map(|entry| entry.ok())
+					.filter(|entry| entry.path().is_dir())
+					.filter_map(|entry| {
+						let name = entry.file_name().into_string().ok()?;
+						(!name.starts_with('.')).then(|| Mod { name, dir: entry.path() })
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+		mods.sort_by(|a, b| a.name.cmp(&b.name));
+		for mod_ in &mods {
+			log::info!("Mod {} found in {}", mod_.name, mod_.dir.display());
+			if mod_.dir.join("sounds").is_dir() {
+				log::info!(
+					"Mod {} has sounds, there are no sounds in the game yet",
+					mod_.name
+				);
+			}
+		}
+		Mods { mods }
+	}
+
+	/// The level files of the mods, as `(name, path)`, sorted by mod and then by name.
+	pub fn level_files(&self) -> Vec<(String, String)> {
+		let mut level_files = vec![];
+		for mod_ in &self.mods {
+			let mut names: Vec<String> = std::fs::read_dir(mod_.dir.join("levels"))
+				.map(|entries| {
+					entries
+						.filter_map(|entry| entry.ok())
+						.filter(|entry| entry.path().is_file())
+						.filter_map(|entry| entry.file_name().into_string().ok())
+						.collect()
+				})
+				.unwrap_or_default();
+			names.sort();
+			for name in names {
+				let path = mod_.dir.join("levels").join(&name);
+				level_files.push((
+					format!("{}/{name}", mod_.name),
+					path.to_string_lossy().into_owned(),
+				));
+			}
+		}
+		level_files
+	}
+
+	/// The spritesheet replacing the game's one (from the last mod that has one), with its
+	/// atlas if it has one.
+	pub fn spritesheet(&self) -> Option<(PathBuf, Option<PathBuf>)> {
+		self.mods.iter().rev().find_map(|mod_| {
+			let image = mod_.dir.join("spritesheet.png");
+			let atlas = mod_.dir.join("sprites.toml");
+			image
+				.is_file()
+				.then(|| (image, atlas.is_file().then_some(atlas)))
+		})
+	}
+
+	/// The balance files of the mods, in the order in which they are to be applied.
+	pub fn balance_files(&self) -> Vec<PathBuf> {
+		self
+			.mods
+			.iter()
+			.map(|mod_| mod_.dir.join("balance.toml"))
+			.filter(|path| path.is_file())
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn later_mods_win() {
+		let dir = std::env::temp_dir().join("prototype-07-mods-test");
+		let _ = std::fs::remove_dir_all(&dir);
+		for name in ["2_winter", "1_desert", ".disabled"] {
+			std::fs::create_dir_all(dir.join(name).join("levels")).unwrap();
+			std::fs::write(dir.join(name).join("levels/dunes"), "Op |g").unwrap();
+			std::fs::write(dir.join(name).join("spritesheet.png"), "").unwrap();
+			std::fs::write(dir.join(name).join("balance.toml"), "[wall]\nhp = 5").unwrap();
+		}
+		let mods = Mods::scan(&dir);
+		let names: Vec<&str> = mods.mods.iter().map(|mod_| mod_.name.as_str()).collect();
+		assert_eq!(names, ["1_desert", "2_winter"]);
+		let level_names: Vec<String> = mods
+			.level_files()
+			.into_iter()
+			.map(|(name, _)| name)
+			.collect();
+		assert_eq!(level_names, ["1_desert/dunes", "2_winter/dunes"]);
+		let (image, atlas) = mods.spritesheet().unwrap();
+		assert!(image.starts_with(dir.join("2_winter")));
+		assert!(atlas.is_none());
+		assert_eq!(mods.balance_files().len(), 2);
+		assert!(Mods::scan(&dir.join("nothing")).mods.is_empty());
+	}
+}
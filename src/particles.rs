@@ -0,0 +1,179 @@
+//! Particles drawn over the grid to show what happened during a turn (explosions, deaths,
+//! shots, etc.). They only exist in the renderer: they move with the time between frames,
+//! independently of the turns, and have no effect on the game.
+
+use crate::coords::*;
+use crate::*;
+
+struct Particle {
+	/// In pixels, from the top left corner of the grid.
+	x: f32,
+	y: f32,
+	/// In pixels per second.
+	vx: f32,
+	vy: f32,
+	/// Seconds left before the particle disappears.
+	lifetime: f32,
+	initial_lifetime: f32,
+	color: [u8; 3],
+	/// In pixels.
+	size: f32,
+}
+
+/// How particles are emitted from a cell.
+struct Burst {
+	count: u32,
+	/// The particles go in random directions (in radians) within `spread` of `direction`.
+	direction: f32,
+	spread: f32,
+	/// Range of the initial speeds, in cells per second.
+	speed: (f32, f32),
+	/// The longest lifetime of the particles, in seconds.
+	lifetime: f32,
+	colors: &'static [[u8; 3]],
+}
+
+const DEATH: Burst = Burst {
+	count: 12,
+	direction: 0.0,
+	spread: std::f32::consts::PI,
+	speed: (0.3, 1.2),
+	lifetime: 0.6,
+	colors: &[[200, 30, 30], [120, 20, 20], [90, 90, 90]],
+};
+
+const MUZZLE_FLASH: Burst = Burst {
+	count: 6,
+	direction: 0.0,
+	spread: 0.3,
+	speed: (1.5, 3.0),
+	lifetime: 0.15,
+	colors: &[[255, 240, 150], [255, 200, 60]],
+};
+
+const EXPLOSION: Burst = Burst {
+	count: 30,
+	direction: 0.0,
+	spread: std::f32::consts::PI,
+	speed: (0.5, 3.0),
+	lifetime: 0.5,
+	colors: &[[255, 220, 80], [255, 140, 30], [230, 60, 20], [60, 60, 60]],
+};
+
+const GOAL_DAMAGE: Burst = Burst {
+	count: 16,
+	direction: 0.0,
+	spread: std::f32::consts::PI,
+	speed: (0.5, 1.5),
+	lifetime: 0.7,
+	colors: &[[200, 120, 255], [255, 255, 255]],
+};
+
+pub struct Particles {
+	particles: Vec<Particle>,
+	rng: rng::Rng,
+}
+
+impl Particles {
+	pub fn new() -> Particles {
+		Particles { particles: vec![], rng: rng::Rng::with_seed(0) }
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.particles.is_empty()
+	}
+
+	/// A random number in `min..max`.
+	fn random(&mut self, min: f32, max: f32) -> f32 {
+		min + (max - min) * self.rng.below(1000) as f32 / 1000.0
+	}
+
+	/// Emit particles from the center of a cell.
+	fn burst(&mut self, coords: Coords, cell_pixel_side: i32, burst: Burst) {
+		let center = Rect::tile(coords, cell_pixel_side).center();
+		let cell_side = cell_pixel_side as f32;
+		for _ in 0..burst.count {
+			let angle = burst.direction + self.random(-burst.spread, burst.spread);
+			let speed = self.random(burst.speed.0, burst.speed.1) * cell_side;
+			let color = burst.colors[self.rng.below(burst.colors.len() as u32) as usize];
+			let lifetime = self.random(burst.lifetime / 2.0, burst.lifetime);
+			let size = self.random(cell_side / 16.0, cell_side / 8.0);
+			self.particles.push(Particle {
+				x: center.x as f32,
+				y: center.y as f32,
+				vx: angle.cos() * speed,
+				vy: angle.sin() * speed,
+				lifetime,
+				initial_lifetime: lifetime,
+				color,
+				size,
+			});
+		}
+	}
+
+	/// Emit the particles that show what happened during the turn that was just played.
+	pub fn emit_for_turn(&mut self, turn_events: &[TurnEvent], cell_pixel_side: i32) {
+		for event in turn_events {
+			let (coords, burst) = match *event {
+				TurnEvent::Hit(_)
+				| TurnEvent::Slid { .. }
+				| TurnEvent::BossPhase { .. }
+				| TurnEvent::Granted(..) => continue,
+				TurnEvent::EnemyDied(coords) => (coords, DEATH),
+				TurnEvent::Shot { from, to } => {
+					// The muzzle flash goes towards the target.
+					let dd = to - from;
+					let direction = (dd.dy as f32).atan2(dd.dx as f32);
+					(from, Burst { direction, ..MUZZLE_FLASH })
+				},
+				TurnEvent::Explosion(coords) => (coords, EXPLOSION),
+				TurnEvent::GoalDamaged(coords) => (coords, GOAL_DAMAGE),
+			};
+			self.burst(coords, cell_pixel_side, burst);
+		}
+	}
+
+	/// Move the particles by `dt` seconds, and remove the ones that are over.
+	pub fn update(&mut self, dt: f32) {
+		for particle in self.particles.iter_mut() {
+			particle.x += particle.vx * dt;
+			particle.y += particle.vy * dt;
+			// Slow down, as if there was some air.
+			let damping = (1.0 - 3.0 * dt).max(0.0);
+			particle.vx *= damping;
+			particle.vy *= damping;
+			particle.lifetime -= dt;
+		}
+		self.particles.retain(|particle| particle.lifetime > 0.0);
+	}
+
+	/// Particles fade out as they get to the end of their lifetime.
+	pub fn draw(&self, pixel_buffer: &mut pixels::Pixels, pixel_buffer_dims: Dimensions) {
+		for particle in &self.particles {
+			let [r, g, b] = particle.color;
+			let alpha = (255.0 * particle.lifetime / particle.initial_lifetime) as u8;
+			let size = particle.size.max(1.0) as i32;
+			let dst = Rect {
+				top_left: Coords { x: particle.x as i32 - size / 2, y: particle.y as i32 - size / 2 },
+				dims: Dimensions::square(size),
+			};
+			draw_rect(pixel_buffer, pixel_buffer_dims, dst, [r, g, b, alpha]);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn particles_disappear_at_the_end_of_their_lifetime() {
+		let mut particles = Particles::new();
+		particles.emit_for_turn(&[TurnEvent::Explosion((2, 2).into())], 64);
+		assert!(!particles.is_empty());
+		particles.update(0.1);
+		assert!(!particles.is_empty());
+		particles.update(1.0);
+		assert!(particles.is_empty());
+	}
+}
@@ -0,0 +1,278 @@
+//! Turn-based text protocol so that external programs (bots written in any language)
+//! can play the game without a window.
+//!
+//! Each turn the game writes the state of the level as one line of JSON, then reads one
+//! action line, which is one of:
+//! - `move <direction>`
+//! - `tower <direction> [variant]` (the variant defaults to `basic`)
+//! - `attack <direction>`
+//! - `pickup <direction>` (to take back a tower)
+//! - `merge <direction>` (to merge a tower into an identical adjacent tower)
+//! - `reload <direction>` (to refill the ammo of an adjacent tower)
+//! - `wall <direction>`
+//! - `use bomb <direction>` or `use voucher` (to use an item of the inventory)
+//! - `skip`
+//! - `switch` (to control the next unit, in levels with several, which doesn't end the turn)
+//! - `craft tower` or `craft bomb` (in crafting levels, which doesn't end the turn either)
+//! - `quit`
+//!
+//! where `<direction>` is one of `up`, `right`, `down`, `left`.
+//! Invalid action lines are answered with a `{"error": "..."}` line.
+
+use crate::coords::*;
+use crate::*;
+
+use std::io::{BufRead, Write};
+
+fn direction_from_name(name: &str) -> Option<DxDy> {
+	Some(
+		match name {
+			"up" => (0, -1),
+			"right" => (1, 0),
+			"down" => (0, 1),
+			"left" => (-1, 0),
+			_ => return None,
+		}
+		.into(),
+	)
+}
+
+fn direction_to_name(dd: DxDy) -> &'static str {
+	match (dd.dx, dd.dy) {
+		(0, -1) => "up",
+		(1, 0) => "right",
+		(0, 1) => "down",
+		(-1, 0) => "left",
+		_ => panic!("not a direction"),
+	}
+}
+
+/// The inverse of `parse_action`.
+pub fn action_line(dd: DxDy, action: &PlayerAction) -> String {
+	match action {
+		PlayerAction::Move => format!("move {}", direction_to_name(dd)),
+		PlayerAction::PlaceTower { variant } => {
+			format!("tower {} {}", direction_to_name(dd), variant.name())
+		},
+		PlayerAction::PlaceWall => format!("wall {}", direction_to_name(dd)),
+		PlayerAction::Attack => format!("attack {}", direction_to_name(dd)),
+		PlayerAction::PickUpTower => format!("pickup {}", direction_to_name(dd)),
+		PlayerAction::MergeTower => format!("merge {}", direction_to_name(dd)),
+		PlayerAction::Reload => format!("reload {}", direction_to_name(dd)),
+		PlayerAction::UseItem { item: Item::Bomb } => {
+			format!("use bomb {}", direction_to_name(dd))
+		},
+		PlayerAction::UseItem { item } => format!("use {}", item.name()),
+		PlayerAction::SkipTurn => String::from("skip"),
+		PlayerAction::SwitchUnit => String::from("switch"),
+		PlayerAction::Craft { recipe } => format!("craft {}", recipe.name()),
+	}
+}
+
+/// Parse an action line, `Ok(None)` means that the agent quits.
+pub fn parse_action(line: &str) -> Result<Option<(DxDy, PlayerAction)>, String> {
+	let mut words = line.split_whitespace();
+	let direction = |word: Option<&str>| {
+		let word = word.ok_or("missing direction")?;
+		direction_from_name(word).ok_or(format!("unknown direction \"{word}\""))
+	};
+	match words.next() {
+		Some("move") => Ok(Some((direction(words.next())?, PlayerAction::Move))),
+		Some("tower") => {
+			let dd = direction(words.next())?;
+			let variant_name = words.next().unwrap_or("basic");
+			let variant = Tower::from_name(variant_name)
+				.ok_or(format!("unknown tower variant \"{variant_name}\""))?;
+			Ok(Some((dd, PlayerAction::PlaceTower { variant })))
+		},
+		Some("attack") => Ok(Some((direction(words.next())?, PlayerAction::Attack))),
+		Some("pickup") => Ok(Some((direction(words.next())?, PlayerAction::PickUpTower))),
+		Some("merge") => Ok(Some((direction(words.next())?, PlayerAction::MergeTower))),
+		Some("reload") => Ok(Some((direction(words.next())?, PlayerAction::Reload))),
+		Some("wall") => Ok(Some((direction(words.next())?, PlayerAction::PlaceWall))),
+		Some("use") => {
+			let item_name = words.next().ok_or("missing item")?;
+			match Item::from_name(item_name) {
+				Some(Item::Bomb) => Ok(Some((
+					direction(words.next())?,
+					PlayerAction::UseItem { item: Item::Bomb },
+				))),
+				Some(Item::TowerVoucher) => Ok(Some((
+					(0, 0).into(),
+					PlayerAction::UseItem { item: Item::TowerVoucher },
+				))),
+				Some(Item::Heart) => Err(String::from("hearts are used automatically")),
+				Some(Item::Wood | Item::Stone) => Err(String::from("resources are used to craft")),
+				None => Err(format!("unknown item \"{item_name}\"")),
+			}
+		},
+		Some("skip") => Ok(Some(((0, 0).into(), PlayerAction::SkipTurn))),
+		Some("switch") => Ok(Some(((0, 0).into(), PlayerAction::SwitchUnit))),
+		Some("craft") => {
+			let recipe_name = words.next().ok_or("missing recipe")?;
+			let recipe =
+				Recipe::from_name(recipe_name).ok_or(format!("unknown recipe \"{recipe_name}\""))?;
+			Ok(Some(((0, 0).into(), PlayerAction::Craft { recipe })))
+		},
+		Some("quit") => Ok(None),
+		Some(other) => Err(format!("unknown action \"{other}\"")),
+		None => Err(String::from("empty action")),
+	}
+}
+
+/// The state of the level as seen by the agents.
+fn state_json(level: &LevelState) -> serde_json::Value {
+	let tiles: Vec<String> = (0..level.grid.dims.h)
+		.map(|y| {
+			(0..level.grid.dims.w)
+				.map(|x| {
+					let cell = level.grid.get((x, y).into()).unwrap();
+					if level.is_visible((x, y).into()) || !matches!(cell.obj, Obj::Enemy { .. }) {
+						tile_string(cell).iter().collect::<String>()
+					} else {
+						let [ground, _] = tile_string(cell);
+						format!("{ground}-")
+					}
+				})
+				.collect::<Vec<_>>()
+				.join(" ")
+		})
+		.collect();
+	let mut objects = vec![];
+	for coords in level.grid.dims.iter() {
+		if !level.is_visible(coords) {
+			continue;
+		}
+		let (x, y) = (coords.x, coords.y);
+		let object = match &level.grid.get(coords).unwrap().obj {
+			Obj::Player { stunned } => serde_json::json!({
+				"type": "player", "x": x, "y": y, "stunned": stunned,
+				"active": level.player_coords() == Some(coords),
+				"acted": level.units_acted.contains(&coords),
+			}),
+			Obj::Enemy { variant, hp, effects } => {
+				let mut enemy = serde_json::json!({
+					"type": "enemy", "x": x, "y": y, "variant": variant.name(),
+					"hp": hp, "hp_max": variant.hp_max(), "burn": effects.burn,
+					"group": effects.group.map(|member| member.id),
+				});
+				if effects.group.is_some_and(|member| member.leader) {
+					enemy["leader"] = true.into();
+				}
+				if effects.emerging {
+					enemy["emerging"] = true.into();
+				}
+				if let Enemy::Protected { direction, .. } = variant {
+					enemy["direction"] = direction.name().into();
+				}
+				if let Enemy::Burrower { underground } = variant {
+					enemy["underground"] = (*underground > 0).into();
+				}
+				if let Enemy::Boss { phase, .. } = variant {
+					enemy["phase"] = (*phase).into();
+				}
+				if let Some(shield) = variant.shield() {
+					enemy["shield"] = shield.into();
+				}
+				enemy
+			},
+			Obj::Tower { variant, stunned, cooldown, tier, ammo, facing, .. } => serde_json::json!({
+				"type": "tower", "x": x, "y": y, "variant": variant.name(),
+				"stunned": stunned,
+				"range": Some(level.weather().tower_range(variant.range()))
+					.filter(|&range| range != i32::MAX),
+				"cooldown": cooldown,
+				"tier": tier,
+				"ammo": ammo,
+				"facing": facing.name(),
+			}),
+			Obj::Goal { hp } => serde_json::json!({
+				"type": "goal", "x": x, "y": y, "hp": hp, "hp_max": level.goal_hp_max,
+			}),
+			Obj::Bomb { countdown } => serde_json::json!({
+				"type": "bomb", "x": x, "y": y, "countdown": countdown,
+			}),
+			Obj::Key { color } => serde_json::json!({
+				"type": "key", "x": x, "y": y, "color": color.name(),
+			}),
+			Obj::Door { color } => serde_json::json!({
+				"type": "door", "x": x, "y": y, "color": color.name(),
+			}),
+			_ => continue,
+		};
+		objects.push(object);
+	}
+	let pending_spawns: Vec<_> = level
+		.pending_events()
+		.filter_map(|event| match &event.event_type {
+			GameEventType::EnemySpawn(coords, enemy, group) => Some(serde_json::json!({
+				"x": coords.x, "y": coords.y, "variant": enemy.name(),
+				"group": group.map(|member| member.id),
+			})),
+			_ => None,
+		})
+		.collect();
+	let pending_terrain: Vec<_> = level
+		.pending_events()
+		.filter_map(|event| match &event.event_type {
+			GameEventType::TerrainChange(coords, ground) => Some(serde_json::json!({
+				"x": coords.x, "y": coords.y, "ground": ground.name(),
+			})),
+			_ => None,
+		})
+		.collect();
+	let pending_meteors: Vec<_> = level
+		.pending_events()
+		.filter_map(|event| match &event.event_type {
+			GameEventType::Meteor(coords) => Some(serde_json::json!({ "x": coords.x, "y": coords.y })),
+			_ => None,
+		})
+		.collect();
+	serde_json::json!({
+		"turn": level.turn,
+		"weather": level.weather().name(),
+		"state_hash": format!("{:016x}", level.state_hash()),
+		"game_over": level.game_joever,
+		"remaining_towers": level.remaining_towers,
+		"remaining_walls": level.remaining_walls,
+		"lives": level.lives,
+		"inventory": Item::ALL
+			.into_iter()
+			.map(|item| (item.name().to_string(), level.inventory.count(item).into()))
+			.collect::<serde_json::Map<_, _>>(),
+		"keys": level.inventory.keys.iter().map(|color| color.name()).collect::<Vec<_>>(),
+		"width": level.grid.dims.w,
+		"height": level.grid.dims.h,
+		"tiles": tiles,
+		"objects": objects,
+		"pending_spawns": pending_spawns,
+		"pending_terrain": pending_terrain,
+		"pending_meteors": pending_meteors,
+	})
+}
+
+/// Play the level by exchanging lines with an agent until the game is over,
+/// the agent quits or the input ends.
+pub fn run(
+	level: &mut LevelState,
+	input: impl BufRead,
+	mut output: impl Write,
+) -> std::io::Result<()> {
+	writeln!(output, "{}", state_json(level))?;
+	output.flush()?;
+	for line in input.lines() {
+		match parse_action(&line?) {
+			Ok(Some((dd, action))) => {
+				play_turn(level, dd, action);
+				writeln!(output, "{}", state_json(level))?;
+				if level.game_joever {
+					break;
+				}
+			},
+			Ok(None) => break,
+			Err(error) => writeln!(output, "{}", serde_json::json!({ "error": error }))?,
+		}
+		output.flush()?;
+	}
+	output.flush()
+}
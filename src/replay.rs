@@ -0,0 +1,226 @@
+//! Replays: the list of the actions played in a level, along with a hash of the state of the
+//! level after each turn so that any nondeterminism (or change in the game logic) is detected
+//! when the replay is played back.
+//!
+//! A replay file looks like a level file, with a `@level <path>` metadata line giving the
+//! level file, followed by one line per turn: the action (in the format of the `protocol`
+//! module) then `#` and the hash (in hexadecimal) of the state after that turn.
+
+use crate::coords::*;
+use crate::*;
+
+use std::hash::{Hash, Hasher};
+
+/// FNV-1a, which (unlike the std `DefaultHasher`) is guaranteed to give the same hashes
+/// on every platform and with every version of Rust.
+pub struct StableHasher(u64);
+
+impl StableHasher {
+	pub fn new() -> StableHasher {
+		StableHasher(0xcbf29ce484222325)
+	}
+}
+
+impl Hasher for StableHasher {
+	fn finish(&self) -> u64 {
+		self.0
+	}
+	fn write(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.0 ^= *byte as u64;
+			self.0 = self.0.wrapping_mul(0x100000001b3);
+		}
+	}
+	// Integers are hashed in little endian and with a fixed size
+	// so that the hashes don't depend on the platform.
+	fn write_u16(&mut self, i: u16) {
+		self.write(&i.to_le_bytes());
+	}
+	fn write_u32(&mut self, i: u32) {
+		self.write(&i.to_le_bytes());
+	}
+	fn write_u64(&mut self, i: u64) {
+		self.write(&i.to_le_bytes());
+	}
+	fn write_usize(&mut self, i: usize) {
+		self.write_u64(i as u64);
+	}
+	fn write_i16(&mut self, i: i16) {
+		self.write(&i.to_le_bytes());
+	}
+	fn write_i32(&mut self, i: i32) {
+		self.write(&i.to_le_bytes());
+	}
+	fn write_i64(&mut self, i: i64) {
+		self.write(&i.to_le_bytes());
+	}
+	fn write_isize(&mut self, i: isize) {
+		self.write_i64(i as i64);
+	}
+}
+
+impl LevelState {
+	/// A hash of everything in the state of the level that matters for the game logic.
+	pub fn state_hash(&self) -> u64 {
+		let mut hasher = StableHasher::new();
+		self.turn.hash(&mut hasher);
+		self.remaining_towers.hash(&mut hasher);
+		self.remaining_walls.hash(&mut hasher);
+		self.lives.hash(&mut hasher);
+		self.game_joever.hash(&mut hasher);
+		self.won_by_script.hash(&mut hasher);
+		self.grid.hash(&mut hasher);
+		self.events.hash(&mut hasher);
+		self.stats.corpses.hash(&mut hasher);
+		// Sorted, as the iteration order of a `HashMap` changes from a run to the next.
+		let mut path_forks: Vec<(Coords, usize)> =
+			self.stats.path_forks.clone().into_iter().collect();
+		path_forks.sort_by_key(|(coords, _)| (coords.x, coords.y));
+		path_forks.hash(&mut hasher);
+		let mut obstacle_hits: Vec<(Coords, u32)> =
+			self.stats.obstacle_hits.clone().into_iter().collect();
+		obstacle_hits.sort_by_key(|(coords, _)| (coords.x, coords.y));
+		obstacle_hits.hash(&mut hasher);
+		self.inventory.hash(&mut hasher);
+		self.active_unit.hash(&mut hasher);
+		self.units_acted.hash(&mut hasher);
+		self.stumps.hash(&mut hasher);
+		hasher.finish()
+	}
+}
+
+pub struct ReplayTurn {
+	pub dd: DxDy,
+	pub action: PlayerAction,
+	/// The hash of the state of the level after this turn.
+	pub state_hash: u64,
+}
+
+pub struct Replay {
+	pub level_file: String,
+	pub turns: Vec<ReplayTurn>,
+}
+
+impl Replay {
+	pub fn new(level_file: &str) -> Replay {
+		Replay { level_file: String::from(level_file), turns: vec![] }
+	}
+
+	/// Play a turn and record it.
+	pub fn play_and_record(&mut self, level: &mut LevelState, dd: DxDy, action: PlayerAction) {
+		play_turn(level, dd, action.clone());
+		self
+			.turns
+			.push(ReplayTurn { dd, action, state_hash: level.state_hash() });
+	}
+
+	pub fn to_text(&self) -> String {
+		let mut text = format!("@level {}\n", self.level_file);
+		for turn in self.turns.iter() {
+			let action = protocol::action_line(turn.dd, &turn.action);
+			text.push_str(&format!("{action} #{:016x}\n", turn.state_hash));
+		}
+		text
+	}
+
+	pub fn parse(text: &str) -> Result<Replay, String> {
+		let mut level_file = None;
+		let mut turns = vec![];
+		for (line_index, line) in text.lines().enumerate() {
+			let line_number = line_index + 1;
+			if line.is_empty() || line.starts_with('~') {
+				continue;
+			} else if let Some(path) = line.strip_prefix("@level ") {
+				level_file = Some(String::from(path.trim()));
+			} else {
+				let (action, state_hash) = line
+					.split_once('#')
+					.ok_or(format!("line {line_number}: missing state hash"))?;
+				let (dd, action) = protocol::parse_action(action)
+					.map_err(|error| format!("line {line_number}: {error}"))?
+					.ok_or(format!("line {line_number}: can't quit in a replay"))?;
+				let state_hash = u64::from_str_radix(state_hash.trim(), 16)
+					.map_err(|error| format!("line {line_number}: bad state hash: {error}"))?;
+				turns.push(ReplayTurn { dd, action, state_hash });
+			}
+		}
+		let level_file = level_file.ok_or("missing @level line")?;
+		Ok(Replay { level_file, turns })
+	}
+}
+
+/// Records the turns played in a replay file (which is updated after every turn).
+pub struct Recorder {
+	replay: Replay,
+	replay_file: String,
+}
+
+impl Recorder {
+	pub fn new(level_file: &str, replay_file: &str) -> Recorder {
+		Recorder {
+			replay: Replay::new(level_file),
+			replay_file: String::from(replay_file),
+		}
+	}
+
+	pub fn play_and_record(&mut self, level: &mut LevelState, dd: DxDy, action: PlayerAction) {
+		self.replay.play_and_record(level, dd, action);
+		if let Err(error) = fs::write(&self.replay_file, self.replay.to_text()) {
+			log::error!(
+				"Failed to write the replay file {}: {error}",
+				self.replay_file
+			);
+		}
+	}
+}
+
+/// Play back the next turn of a replay, checking that the state of the level is the same
+/// as when it was recorded.
+pub fn play_back_turn(level: &mut LevelState, turn: &ReplayTurn) -> Result<(), String> {
+	let turn_number = level.turn;
+	play_turn(level, turn.dd, turn.action.clone());
+	let state_hash = level.state_hash();
+	if state_hash == turn.state_hash {
+		Ok(())
+	} else {
+		Err(format!(
+			"replay desync after turn {turn_number}: expected state hash {:016x}, got {state_hash:016x}",
+			turn.state_hash
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn recorded_replay_plays_back_without_desync() {
+		let level_data = load_level("levels/level_ZA.pr7").unwrap();
+		let mut level = LevelState::new(&level_data);
+		let mut replay = Replay::new("levels/level_ZA.pr7");
+		let mut rng = rng::Rng::with_seed(7);
+		for _ in 0..30 {
+			let (dd, action) = bot::choose_action(&level, &mut rng);
+			replay.play_and_record(&mut level, dd, action);
+		}
+
+		let replay = Replay::parse(&replay.to_text()).unwrap();
+		let mut level = LevelState::new(&load_level(&replay.level_file).unwrap());
+		for turn in replay.turns.iter() {
+			play_back_turn(&mut level, turn).unwrap();
+		}
+	}
+
+	#[test]
+	fn tampered_replay_is_detected() {
+		let level_data = load_level("levels/level_ZA.pr7").unwrap();
+		let mut level = LevelState::new(&level_data);
+		let mut replay = Replay::new("levels/level_ZA.pr7");
+		replay.play_and_record(&mut level, (-1, 0).into(), PlayerAction::Move);
+		replay.turns[0].action = PlayerAction::SkipTurn;
+
+		let mut level = LevelState::new(&level_data);
+		assert!(play_back_turn(&mut level, &replay.turns[0]).is_err());
+	}
+}
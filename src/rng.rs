@@ -0,0 +1,32 @@
+//! A small deterministic pseudo-random number generator, so that everything random
+//! can be reproduced from a seed (and there is no need for a dependency).
+
+#[derive(Clone)]
+pub struct Rng {
+	state: u64,
+}
+
+impl Rng {
+	pub fn with_seed(seed: u64) -> Rng {
+		// Scramble the seed (splitmix64) so that close seeds give unrelated sequences
+		// and so that the state is never zero (which xorshift can't escape).
+		let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+		z ^= z >> 31;
+		Rng { state: if z == 0 { 1 } else { z } }
+	}
+
+	/// Xorshift64*, see https://en.wikipedia.org/wiki/Xorshift
+	pub fn next_u64(&mut self) -> u64 {
+		self.state ^= self.state >> 12;
+		self.state ^= self.state << 25;
+		self.state ^= self.state >> 27;
+		self.state.wrapping_mul(0x2545f4914f6cdd1d)
+	}
+
+	/// A number in `0..n` (`n` must not be zero).
+	pub fn below(&mut self, n: u32) -> u32 {
+		(self.next_u64() % n as u64) as u32
+	}
+}
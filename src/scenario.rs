@@ -0,0 +1,1472 @@
+//! Test support for scripted scenarios: a level is written inline (in the level file format),
+//! actions are played (in the format of the `protocol` module), and then assertions are made
+//! about the content of the grid.
+//!
+//! Tiles can be refered to by their `(x, y)` coords or by their name (`'a'` or `"spawn"`).
+
+use crate::coords::*;
+use crate::*;
+
+pub struct Scenario {
+	pub level: LevelState,
+	named_tiles: HashMap<String, Coords>,
+}
+
+/// Something that refers to a tile of the level of a scenario.
+pub trait Place {
+	fn coords(&self, scenario: &Scenario) -> Coords;
+}
+
+impl Place for &str {
+	fn coords(&self, scenario: &Scenario) -> Coords {
+		*scenario
+			.named_tiles
+			.get(*self)
+			.unwrap_or_else(|| panic!("no tile named ?{self} in the scenario"))
+	}
+}
+
+impl Place for char {
+	fn coords(&self, scenario: &Scenario) -> Coords {
+		self.to_string().as_str().coords(scenario)
+	}
+}
+
+impl Place for Coords {
+	fn coords(&self, _scenario: &Scenario) -> Coords {
+		*self
+	}
+}
+
+impl Place for (i32, i32) {
+	fn coords(&self, _scenario: &Scenario) -> Coords {
+		(*self).into()
+	}
+}
+
+impl Scenario {
+	/// The level text may be indented, every line is trimmed.
+	pub fn new(level_text: &str) -> Scenario {
+		let level_text: Vec<&str> = level_text
+			.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty())
+			.collect();
+		let level_data = parse_level(&level_text.join("\n")).unwrap();
+		Scenario {
+			level: LevelState::new(&level_data),
+			named_tiles: level_data.named_tiles,
+		}
+	}
+
+	/// Play one turn per action.
+	pub fn play(&mut self, actions: &[&str]) -> &mut Scenario {
+		for action in actions {
+			let (dd, action) = protocol::parse_action(action)
+				.unwrap_or_else(|error| panic!("bad action in scenario: {error}"))
+				.expect("can't quit in a scenario");
+			play_turn(&mut self.level, dd, action);
+		}
+		self
+	}
+
+	pub fn skip_turns(&mut self, turns: usize) -> &mut Scenario {
+		self.play(&vec!["skip"; turns])
+	}
+
+	pub fn obj(&self, place: impl Place) -> &Obj {
+		&self.level.grid.get(place.coords(self)).unwrap().obj
+	}
+
+	/// Assert that the object at the given place is the one given by its level format character.
+	#[track_caller]
+	pub fn assert_obj(&self, place: impl Place, expected: char) -> &Scenario {
+		let coords = place.coords(self);
+		let [_, actual] = tile_string(self.level.grid.get(coords).unwrap());
+		assert_eq!(actual, expected, "unexpected object at {coords}");
+		self
+	}
+
+	/// Assert that the ground at the given place is the one given by its level format character.
+	#[track_caller]
+	pub fn assert_ground(&self, place: impl Place, expected: char) -> &Scenario {
+		let coords = place.coords(self);
+		let [actual, _] = tile_string(self.level.grid.get(coords).unwrap());
+		assert_eq!(actual, expected, "unexpected ground at {coords}");
+		self
+	}
+
+	#[track_caller]
+	pub fn assert_enemy(
+		&self,
+		place: impl Place,
+		expected_name: &str,
+		expected_hp: u32,
+	) -> &Scenario {
+		let coords = place.coords(self);
+		match self.obj(coords) {
+			Obj::Enemy { variant, hp, .. } => {
+				assert_eq!(
+					variant.name(),
+					expected_name,
+					"unexpected enemy at {coords}"
+				);
+				assert_eq!(*hp, expected_hp, "unexpected hp of the enemy at {coords}");
+			},
+			_ => panic!("expected an enemy at {coords}"),
+		}
+		self
+	}
+
+	#[track_caller]
+	pub fn assert_bomb(&self, place: impl Place, expected_countdown: u32) -> &Scenario {
+		let coords = place.coords(self);
+		match self.obj(coords) {
+			Obj::Bomb { countdown } => {
+				assert_eq!(
+					*countdown, expected_countdown,
+					"unexpected countdown at {coords}"
+				)
+			},
+			_ => panic!("expected a bomb at {coords}"),
+		}
+		self
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Note: path tiles that are not connected to the goal have no distance to it,
+	// so enemies standing on them never move, which is handy to test towers.
+
+	#[test]
+	fn tank_walks_along_the_path() {
+		let mut scenario = Scenario::new(
+			"
+			|W |- |- ?a |- |g
+			O- O- Ot O- O- O-
+			@tile a |-
+			",
+		);
+		scenario.skip_turns(3);
+		// The tower only saw the tank once, when it was right above it.
+		scenario
+			.assert_enemy('a', "tank", 8)
+			.assert_obj((0, 0), '-');
+	}
+
+	#[test]
+	fn tiles_can_have_long_names_given_anywhere() {
+		let mut scenario = Scenario::new(
+			"
+			@event spawn basic entrance 1
+			@event spawn tank exit 1
+			Op ?entrance |- |- |- |g
+			@tile entrance |-
+			@name exit 4 0
+			",
+		);
+		scenario.skip_turns(1);
+		scenario
+			.assert_enemy("entrance", "basic", Enemy::Basic.hp_max())
+			.assert_enemy("exit", "tank", Enemy::Tank.hp_max());
+	}
+
+	#[test]
+	fn prefabs_are_stamped_and_files_included() {
+		// Prefabs are looked for next to the file that stamps them.
+		let dir = std::env::temp_dir().join("prototype-07-include-test");
+		fs::create_dir_all(dir.join("prefabs")).unwrap();
+		fs::copy("levels/prefabs/gauntlet", dir.join("prefabs/gauntlet")).unwrap();
+		let include_file = dir.join("waves");
+		fs::write(
+			&include_file,
+			"@prefab gauntlet corner\n@event spawn basic entrance 1\n",
+		)
+		.unwrap();
+		let mut scenario = Scenario::new(&format!(
+			"
+			Op O- O- O- O- O-
+			?entrance |- |- |- |- |g
+			O- O- O- O- O- O-
+			@name corner 1 0
+			@include {}
+			",
+			include_file.display()
+		));
+		scenario
+			.assert_obj((1, 0), 'r')
+			.assert_obj((2, 2), 't')
+			.assert_obj((1, 1), '-');
+		scenario.skip_turns(1);
+		scenario.assert_enemy("entrance", "basic", Enemy::Basic.hp_max());
+
+		let include_itself = format!("@include {}", include_file.display());
+		fs::write(&include_file, &include_itself).unwrap();
+		assert!(parse_level(&format!("Op |g\n{include_itself}")).is_err());
+	}
+
+	#[test]
+	fn levels_can_be_mirrored_and_rotated() {
+		let scenario = Scenario::new(
+			"
+			Op |) |- |g
+			@transform mirror_x
+			",
+		);
+		scenario.assert_obj((0, 0), 'g').assert_obj((3, 0), 'p');
+		assert!(matches!(
+			scenario.level.grid.get((2, 0).into()).unwrap().obj,
+			Obj::Enemy { variant: Enemy::Protected { direction: Direction::West, .. }, .. }
+		));
+
+		let dir = std::env::temp_dir().join("prototype-07-variant-test");
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(
+			dir.join("base"),
+			"Op ?a |g\n@tile a |-\n@event spawn basic a 2",
+		)
+		.unwrap();
+		let variant = parse_level_in("@include base\n@transform rot90", &dir).unwrap();
+		assert!(variant.init_grid.dims == Dimensions { w: 1, h: 3 });
+		assert!(matches!(
+			variant.init_grid.get((0, 2).into()).unwrap().obj,
+			Obj::Goal { .. }
+		));
+		assert!(variant.named_tiles["a"] == Coords { x: 0, y: 1 });
+		assert!(matches!(
+			variant.init_events[0].event_type,
+			GameEventType::EnemySpawn(Coords { x: 0, y: 1 }, Enemy::Basic, None)
+		));
+		fs::write(dir.join("itself"), "@include itself").unwrap();
+		assert!(parse_level_in("@include itself", &dir).is_err());
+	}
+
+	#[test]
+	fn enemies_spawning_at_night_are_tougher() {
+		let mut scenario = Scenario::new(
+			"
+			Op ?a |- |- |- |- |- |g
+			@tile a |-
+			@day_night 2 2 3
+			@event spawn basic a 1
+			@event spawn basic a 2
+			",
+		);
+		let hp_max = Enemy::Basic.hp_max();
+		assert!(scenario.level.scene_tint() == Some(DayNight::DUSK_TINT));
+		scenario.skip_turns(2);
+		assert!(scenario.level.is_night());
+		assert!(scenario.level.scene_tint() == Some(DayNight::NIGHT_TINT));
+		scenario
+			.assert_enemy('a', "basic", hp_max + 3)
+			.assert_enemy((2, 0), "basic", hp_max);
+		scenario.skip_turns(2);
+		assert!(!scenario.level.is_night());
+	}
+
+	#[test]
+	fn player_pushes_a_chain_of_rocks() {
+		let mut scenario = Scenario::new("O- Op Or Or O- Og");
+		scenario.play(&["move right"]);
+		scenario
+			.assert_obj((2, 0), 'p')
+			.assert_obj((3, 0), 'r')
+			.assert_obj((4, 0), 'r');
+		// The chain is now blocked by the goal.
+		scenario.play(&["move right"]);
+		scenario
+			.assert_obj((2, 0), 'p')
+			.assert_obj((3, 0), 'r')
+			.assert_obj((4, 0), 'r');
+	}
+
+	#[test]
+	fn chain_pushes_are_recorded_for_the_slide_animation() {
+		let mut scenario = Scenario::new("O- Op Or Or O- Og");
+		scenario.play(&["move right"]);
+		let events = &scenario.level.stats.turn_events;
+		assert!(events.contains(&TurnEvent::Slid { from: (2, 0).into(), to: (3, 0).into() }));
+		assert!(events.contains(&TurnEvent::Slid { from: (3, 0).into(), to: (4, 0).into() }));
+		let slides = slides(&scenario.level);
+		assert_eq!(slides.len(), 2);
+	}
+
+	#[test]
+	fn player_picks_up_and_uses_items() {
+		let mut scenario = Scenario::new(
+			"
+			Op Oo O$ O- Og
+			@max_towers 0
+			",
+		);
+		scenario.play(&["move right", "move right"]);
+		assert_eq!(scenario.level.inventory.bombs, 1);
+		assert_eq!(scenario.level.inventory.tower_vouchers, 1);
+		scenario.play(&["use voucher", "use bomb left"]);
+		scenario.assert_bomb((1, 0), 2);
+		assert_eq!(scenario.level.remaining_towers, Some(1));
+	}
+
+	#[test]
+	fn heart_brings_the_player_back() {
+		let mut scenario = Scenario::new("Op O+ Ob O- Og");
+		scenario.play(&["move right"]);
+		assert_eq!(scenario.level.inventory.hearts, 1);
+		scenario.skip_turns(3);
+		// Killed by the bomb, but the heart saved the day.
+		scenario.assert_obj((2, 0), '-').assert_obj((1, 0), 'p');
+		assert_eq!(scenario.level.inventory.hearts, 0);
+	}
+
+	#[test]
+	fn player_attacks_an_adjacent_enemy() {
+		let mut scenario = Scenario::new("Op |Z O- Og");
+		scenario.play(&["attack right"]);
+		scenario.assert_enemy((1, 0), "speeeeed", 1);
+		scenario.play(&["attack right"]);
+		scenario.assert_obj((1, 0), '-');
+		assert_eq!(scenario.level.stats.player_kills, 1);
+	}
+
+	#[test]
+	fn player_picks_up_a_tower_and_places_it_again() {
+		let mut scenario = Scenario::new("Op Ot");
+		scenario.level.remaining_towers = Some(0);
+		scenario.play(&["pickup right"]);
+		scenario.assert_obj((1, 0), '-');
+		assert_eq!(scenario.level.remaining_towers, Some(1));
+		scenario.play(&["tower right"]);
+		scenario.assert_obj((1, 0), 't');
+		assert_eq!(scenario.level.remaining_towers, Some(0));
+	}
+
+	#[test]
+	fn merged_towers_deal_double_damage() {
+		let mut scenario = Scenario::new(
+			"
+			Op Ot Ot O- Og
+			O- O- O- O- O-
+			O- O- |W O- O-
+			",
+		);
+		scenario.play(&["merge right"]);
+		scenario.assert_obj((1, 0), '-');
+		scenario.assert_enemy((2, 2), "tank", 7);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((2, 2), "tank", 5);
+	}
+
+	#[test]
+	fn goal_survives_enemies_while_it_has_hp() {
+		let mut scenario = Scenario::new(
+			"
+			Op O- O- O-
+			|e |Z |g O-
+			@goal_hp 2
+			",
+		);
+		scenario.skip_turns(1);
+		assert!(!scenario.level.game_joever);
+		assert!(matches!(
+			scenario.level.grid.get((2, 1).into()).unwrap().obj,
+			Obj::Goal { hp: 1 }
+		));
+		scenario.skip_turns(1);
+		assert!(scenario.level.game_joever);
+	}
+
+	#[test]
+	fn lives_are_lost_when_the_goal_is_destroyed() {
+		let mut scenario = Scenario::new(
+			"
+			Op O- O- O-
+			|e |Z |g O-
+			@lives 2
+			",
+		);
+		scenario.skip_turns(1);
+		assert!(!scenario.level.game_joever);
+		assert_eq!(scenario.level.lives, Some(1));
+		scenario.assert_obj((2, 1), 'g');
+		scenario.skip_turns(1);
+		assert!(scenario.level.game_joever);
+		assert_eq!(scenario.level.lives, Some(0));
+	}
+
+	#[test]
+	fn enemy_moves_are_predicted_without_playing() {
+		let scenario = Scenario::new("Op O- O- O-\n|Z |- |- |g");
+		let hash = scenario.level.state_hash();
+		let moves = predict_enemy_moves(&scenario.level);
+		assert_eq!(
+			moves,
+			[
+				(Coords { x: 0, y: 1 }, Coords { x: 1, y: 1 }),
+				(Coords { x: 1, y: 1 }, Coords { x: 2, y: 1 })
+			]
+		);
+		assert_eq!(scenario.level.state_hash(), hash);
+	}
+
+	#[test]
+	fn inspected_cell_describes_the_protection_of_enemies() {
+		let scenario = Scenario::new("|{ |- |g");
+		assert_eq!(
+			cell_description(&scenario.level, (0, 0).into()),
+			[
+				"path: 2 from the goal",
+				"protected sides hp 4/4",
+				"facing east",
+				"protected from north south",
+			]
+		);
+	}
+
+	#[test]
+	fn explosions_and_goal_damage_are_reported() {
+		let mut scenario = Scenario::new(
+			"
+			Op Ob O- O-
+			|e |- |g O-
+			@goal_hp 2
+			",
+		);
+		let mut events = vec![];
+		for _ in 0..4 {
+			scenario.skip_turns(1);
+			events.extend(scenario.level.stats.turn_events.iter().copied());
+		}
+		assert!(events.contains(&TurnEvent::GoalDamaged((2, 1).into())));
+		assert!(events.contains(&TurnEvent::Explosion((1, 0).into())));
+	}
+
+	#[test]
+	fn enemies_hit_during_the_turn_are_recorded() {
+		let mut scenario = Scenario::new("Op Ot O- |e |- |- |- |g");
+		// The enemy moves before the tower shoots it.
+		scenario.skip_turns(1);
+		assert!(scenario
+			.level
+			.stats
+			.turn_events
+			.contains(&TurnEvent::Hit((4, 0).into())));
+		scenario.skip_turns(1);
+		assert!(scenario
+			.level
+			.stats
+			.turn_events
+			.contains(&TurnEvent::Hit((5, 0).into())));
+		assert!(!scenario
+			.level
+			.stats
+			.turn_events
+			.contains(&TurnEvent::Hit((4, 0).into())));
+	}
+
+	#[test]
+	fn pusher_tower_pushes_enemies_around_its_target() {
+		let mut scenario = Scenario::new("Oy O- |e |e |- O- Og");
+		scenario.skip_turns(1);
+		scenario
+			.assert_enemy((2, 0), "basic", 4)
+			.assert_obj((3, 0), '-')
+			.assert_enemy((4, 0), "basic", 5);
+	}
+
+	#[test]
+	fn protected_enemy_is_shielded_on_its_sides() {
+		// Facing east, so protected from the north and the south.
+		let mut scenario = Scenario::new(
+			"
+			O- O- Ot O- Og
+			O- O- O- O- O-
+			O- O- |{ O- O-
+			",
+		);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((2, 2), "protected_sides", 4);
+	}
+
+	#[test]
+	fn protected_enemy_is_hurt_from_the_front() {
+		let mut scenario = Scenario::new(
+			"
+			O- O- Ot O- Og
+			O- O- O- O- O-
+			Ot O- |{ O- O-
+			",
+		);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((2, 2), "protected_sides", 3);
+	}
+
+	#[test]
+	fn broodmother_spawns_a_minion_behind_itself() {
+		let mut scenario = Scenario::new("|- |M |- |- |- |- |- |- |- |- |- |g");
+		let minions = |scenario: &Scenario| {
+			let grid = &scenario.level.grid;
+			grid
+				.dims
+				.iter()
+				.filter(|&coords| {
+					matches!(
+						grid.get(coords).unwrap().obj,
+						Obj::Enemy { variant: Enemy::Basic, .. }
+					)
+				})
+				.count()
+		};
+		scenario.skip_turns(BROODMOTHER_SPAWN_PERIOD as usize - 1);
+		// It only moved every other turn.
+		scenario.assert_obj((3, 0), 'M').assert_obj((2, 0), '-');
+		assert_eq!(minions(&scenario), 0);
+		scenario.skip_turns(1);
+		scenario
+			.assert_obj((3, 0), 'M')
+			.assert_enemy((2, 0), "basic", 5)
+			.assert_obj((4, 0), '-');
+		scenario.skip_turns(BROODMOTHER_SPAWN_PERIOD as usize - 1);
+		assert_eq!(minions(&scenario), 1);
+		scenario.skip_turns(1);
+		assert_eq!(scenario.level.turn, 2 * BROODMOTHER_SPAWN_PERIOD);
+		assert_eq!(minions(&scenario), 2);
+	}
+
+	#[test]
+	fn enemies_killed_leave_corpses() {
+		let mut scenario = Scenario::new("Ot |Z O- Og");
+		scenario.skip_turns(3);
+		scenario.assert_obj((1, 0), '-');
+		assert_eq!(scenario.level.stats.corpses.len(), 1);
+	}
+
+	#[test]
+	fn necromancer_revives_a_corpse_instead_of_moving() {
+		let mut scenario = Scenario::new("|N |- |- |- |- |g");
+		scenario
+			.level
+			.stats
+			.corpses
+			.push(((2, 0).into(), Enemy::Tank));
+		scenario.skip_turns(1);
+		scenario
+			.assert_obj((0, 0), 'N')
+			.assert_enemy((2, 0), "tank", 4);
+		assert!(scenario.level.stats.corpses.is_empty());
+	}
+
+	#[test]
+	fn burrower_digs_under_a_tower() {
+		let mut scenario = Scenario::new(
+			"
+			|B |t |- |- |g
+			Ot O- O- O- O-
+			",
+		);
+		scenario.skip_turns(1);
+		// Underground, so the tower below doesn't shoot it.
+		scenario
+			.assert_enemy((0, 0), "burrower", 4)
+			.assert_obj((1, 0), 't');
+		scenario.skip_turns(1);
+		scenario.assert_enemy((0, 0), "burrower", 4);
+		scenario.skip_turns(1);
+		// Back on the surface, where it gets shot.
+		scenario
+			.assert_obj((0, 0), '-')
+			.assert_obj((1, 0), 't')
+			.assert_enemy((2, 0), "burrower", 3);
+	}
+
+	#[test]
+	fn paver_paves_its_way_to_the_path() {
+		let mut scenario = Scenario::new("|P O- O- |- |g");
+		scenario.skip_turns(1);
+		scenario.assert_ground((1, 0), '|').assert_obj((1, 0), 'P');
+		scenario.skip_turns(1);
+		scenario.assert_ground((2, 0), '|').assert_obj((2, 0), 'P');
+		// Now connected to the path, it just walks along it.
+		scenario.skip_turns(1);
+		scenario.assert_obj((3, 0), 'P');
+	}
+
+	#[test]
+	fn sniper_shoots_diagonally_and_reloads() {
+		let mut scenario = Scenario::new(
+			"
+			Os O- O- O- Og
+			O- O- O- O- O-
+			O- O- |W O- O-
+			",
+		);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((2, 2), "tank", 6);
+		scenario.skip_turns(2);
+		scenario.assert_enemy((2, 2), "tank", 6);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((2, 2), "tank", 3);
+	}
+
+	#[test]
+	fn enemy_steps_on_a_mine_placed_by_a_miner() {
+		let mut scenario = Scenario::new(
+			"
+			|W |- |- |- |- |g
+			O- O- O- Om O- O-
+			",
+		);
+		scenario.skip_turns(1);
+		scenario.assert_obj((3, 0), '*');
+		scenario.skip_turns(2);
+		scenario.assert_enemy((3, 0), "tank", 4);
+	}
+
+	#[test]
+	fn magnet_pulls_the_closest_enemy_back() {
+		let mut scenario = Scenario::new("On |- |- |e |- |W O- Og");
+		scenario.skip_turns(1);
+		scenario
+			.assert_enemy((2, 0), "basic", 5)
+			.assert_obj((3, 0), '-')
+			.assert_enemy((5, 0), "tank", 9);
+		// Not pulled into the tower.
+		scenario.skip_turns(2);
+		scenario.assert_enemy((1, 0), "basic", 5);
+	}
+
+	#[test]
+	fn enemies_go_around_walls() {
+		let mut scenario = Scenario::new(
+			"
+			|e |- |- |g
+			|- Op O- |-
+			|- |- |- |-
+			",
+		);
+		scenario.play(&["wall up"]);
+		scenario.assert_obj((1, 0), '#');
+		scenario.assert_enemy((0, 1), "basic", 5);
+	}
+
+	#[test]
+	fn enemies_take_turns_on_the_branches_of_the_path() {
+		let mut scenario = Scenario::new(
+			"
+			?a |- |-
+			|- O- |-
+			|- |- |g
+			Op O- O-
+			@tile a |-
+			@event spawn basic a 1
+			@event spawn basic a 2
+			",
+		);
+		scenario.skip_turns(2);
+		scenario.assert_enemy((1, 0), "basic", 5);
+		scenario.assert_enemy('a', "basic", 5);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((2, 0), "basic", 5);
+		scenario.assert_enemy((0, 1), "basic", 5);
+	}
+
+	#[test]
+	fn enemies_break_walls_in_their_way() {
+		let mut scenario = Scenario::new("|e |# |g Op");
+		scenario.skip_turns(2);
+		scenario.assert_obj((1, 0), '#');
+		scenario.skip_turns(1);
+		scenario.assert_obj((1, 0), '-');
+		scenario.skip_turns(1);
+		scenario.assert_obj((1, 0), 'e');
+	}
+
+	#[test]
+	fn protected_enemies_face_the_way_they_walk() {
+		let mut scenario = Scenario::new(
+			"
+			?a O- O-
+			|- |- |g
+			Op O- O-
+			@tile a |-
+			@event spawn protected_front a 1
+			",
+		);
+		let facing = |scenario: &Scenario, coords: (i32, i32)| match scenario.obj(coords) {
+			Obj::Enemy { variant: Enemy::Protected { direction, .. }, .. } => direction.name(),
+			_ => panic!("expected a protected enemy at {coords:?}"),
+		};
+		scenario.skip_turns(1);
+		assert_eq!(facing(&scenario, (0, 0)), "south");
+		scenario.skip_turns(2);
+		assert_eq!(facing(&scenario, (1, 1)), "east");
+	}
+
+	#[test]
+	fn pushes_are_limited_by_the_weight_of_the_line() {
+		let mut scenario = Scenario::new("Op Or Or O- |g");
+		scenario.play(&["move right"]);
+		scenario.assert_obj((1, 0), 'p').assert_obj((3, 0), 'r');
+
+		let mut scenario = Scenario::new("Op Or Or Or O- |g");
+		scenario.play(&["move right"]);
+		scenario.assert_obj((0, 0), 'p').assert_obj((1, 0), 'r');
+		scenario.assert_obj((4, 0), '-');
+
+		let mut scenario = Scenario::new("|e |r |r |r |- |g Op");
+		scenario.skip_turns(1);
+		scenario.assert_obj((0, 0), 'e').assert_obj((4, 0), '-');
+
+		let mut balance = balance::Balance::compiled_in();
+		balance.set_value("push_strength.player", "6").unwrap();
+		balance::set(balance);
+		let mut scenario = Scenario::new("Op Or Or Or O- |g");
+		scenario.play(&["move right"]);
+		scenario.assert_obj((1, 0), 'p').assert_obj((4, 0), 'r');
+		balance::set(balance::Balance::compiled_in());
+	}
+
+	#[test]
+	fn towers_run_out_of_ammo_until_reloaded() {
+		let mut balance = balance::Balance::compiled_in();
+		balance.set_value("tower_ammo.basic", "2").unwrap();
+		balance::set(balance);
+		let mut scenario = Scenario::new("Op Ot |e |- |- |- |- |- |- |g");
+		scenario.skip_turns(3);
+		scenario.assert_enemy((5, 0), "basic", 3);
+		assert!(matches!(
+			scenario.obj((1, 0)),
+			Obj::Tower { ammo: Some(0), .. }
+		));
+		scenario.play(&["reload right"]);
+		scenario.assert_enemy((6, 0), "basic", 2);
+		assert!(matches!(
+			scenario.obj((1, 0)),
+			Obj::Tower { ammo: Some(1), .. }
+		));
+		balance::set(balance::Balance::compiled_in());
+	}
+
+	#[test]
+	fn cannons_only_shoot_the_way_they_were_placed() {
+		// Placed facing north, it doesn't see the enemy passing by on its right.
+		let mut scenario = Scenario::new(
+			"
+			O- O- |e
+			O- O- |-
+			O- Op |-
+			O- O- |g
+			",
+		);
+		scenario.play(&["tower up cannon"]);
+		assert!(matches!(
+			scenario.obj((1, 1)),
+			Obj::Tower { facing: Direction::North, .. }
+		));
+		scenario.assert_enemy((2, 1), "basic", 5);
+		// Placed facing east, it shoots it for double damage.
+		let mut scenario = Scenario::new(
+			"
+			O- O- |e
+			Op O- |-
+			O- O- |-
+			O- O- |g
+			",
+		);
+		scenario.play(&["tower right cannon"]);
+		scenario.assert_enemy((2, 1), "basic", 3);
+	}
+
+	#[test]
+	fn basic_tower_hits_knock_enemies_back() {
+		let mut scenario = Scenario::new("Op Ot |e |- |- |- |- |g");
+		scenario.skip_turns(2);
+		scenario.assert_enemy((4, 0), "basic", 3);
+
+		let mut scenario = Scenario::new("Op Ot |e |- |- |- |- |g\n@knockback on");
+		scenario.skip_turns(2);
+		scenario.assert_enemy((2, 0), "basic", 3);
+		assert!(parse_level("Op |g\n@knockback maybe").is_err());
+	}
+
+	#[test]
+	fn flamethrowers_set_enemies_on_fire() {
+		let mut scenario = Scenario::new(
+			"
+			|e |- |- |- Oh
+			O- O- O- |- O-
+			Op O- O- |g O-
+			",
+		);
+		let burn = |scenario: &Scenario, coords: (i32, i32)| match scenario.obj(coords) {
+			Obj::Enemy { effects, .. } => effects.burn,
+			_ => panic!("expected an enemy at {coords:?}"),
+		};
+		scenario.skip_turns(2);
+		scenario.assert_enemy((2, 0), "basic", 5);
+		assert_eq!(burn(&scenario, (2, 0)), 3);
+		// Hit again while burning, the fire is rekindled but not stacked.
+		scenario.skip_turns(1);
+		scenario.assert_enemy((3, 0), "basic", 4);
+		assert_eq!(burn(&scenario, (3, 0)), 3);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((3, 1), "basic", 3);
+		assert_eq!(burn(&scenario, (3, 1)), 2);
+	}
+
+	#[test]
+	fn shields_absorb_hits_and_regenerate() {
+		let mut scenario = Scenario::new("Op Ot |D |- |- |- |- |- |- |- |g");
+		let shield = |scenario: &Scenario, coords: (i32, i32)| match scenario.obj(coords) {
+			Obj::Enemy { variant, .. } => variant.shield().unwrap(),
+			_ => panic!("expected a shielded enemy at {coords:?}"),
+		};
+		scenario.skip_turns(3);
+		scenario.assert_enemy((5, 0), "shielded", 4);
+		assert_eq!(shield(&scenario, (5, 0)), 0);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((6, 0), "shielded", 3);
+		// Out of range, the shield is back after two turns without damage.
+		scenario.skip_turns(1);
+		assert_eq!(shield(&scenario, (7, 0)), 0);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((8, 0), "shielded", 3);
+		assert_eq!(shield(&scenario, (8, 0)), 3);
+	}
+
+	#[test]
+	fn groups_follow_their_leader_and_lose_its_buff() {
+		let mut balance = balance::Balance::compiled_in();
+		balance.set_value("player.attack_damage", "5").unwrap();
+		balance::set(balance);
+		let mut scenario = Scenario::new(
+			"?a |- |- |- |- |g\n\
+			O- Op O- O- O- O-\n\
+			@tile a |-\n\
+			@event group a 1 basic basic basic",
+		);
+		scenario.skip_turns(2);
+		scenario.assert_enemy((1, 0), "basic", 5);
+		scenario.assert_enemy((0, 0), "basic", 6);
+		scenario.play(&["attack up"]);
+		scenario.assert_enemy((1, 0), "basic", 5);
+		scenario.assert_enemy((0, 0), "basic", 5);
+		assert!(parse_level("?a |g\n@tile a |-\n@event group a 1").is_err());
+		balance::set(balance::Balance::compiled_in());
+	}
+
+	#[test]
+	fn bosses_change_behavior_as_they_lose_hp() {
+		let mut balance = balance::Balance::compiled_in();
+		balance.set_value("enemy_hp.boss", "10").unwrap();
+		balance.set_value("boss_phase.stun", "10").unwrap();
+		balance.set_value("boss_phase.fast", "8").unwrap();
+		balance.set_value("boss_phase.summon", "0").unwrap();
+		balance::set(balance);
+		let mut scenario = Scenario::new(
+			"|- |X |- |- |- |- |- |- |g\n\
+			O- Op O- O- O- O- O- O- O-",
+		);
+		scenario.play(&["attack up"]);
+		scenario.assert_enemy((2, 0), "boss", 8);
+		let events = &scenario.level.stats.turn_events;
+		let phases: Vec<BossBehavior> = events
+			.iter()
+			.filter_map(|event| match *event {
+				TurnEvent::BossPhase { behavior, .. } => Some(behavior),
+				_ => None,
+			})
+			.collect();
+		assert!(phases == [BossBehavior::Stun, BossBehavior::Fast]);
+		// It stuns the player that steps in its sight, and now moves two tiles per turn.
+		scenario.play(&["move right"]);
+		assert!(matches!(
+			scenario.obj((2, 1)),
+			Obj::Player { stunned: true }
+		));
+		scenario.assert_enemy((4, 0), "boss", 8);
+		balance::set(balance::Balance::compiled_in());
+	}
+
+	#[test]
+	fn the_weather_changes_the_rules() {
+		let mut scenario = Scenario::new("Op |e |- |- |- |- |g\n@weather rain");
+		scenario.skip_turns(1);
+		scenario.assert_obj((2, 0), 'e');
+		scenario.skip_turns(1);
+		scenario.assert_obj((2, 0), 'e');
+		scenario.skip_turns(1);
+		scenario.assert_obj((3, 0), 'e');
+
+		let mut scenario = Scenario::new(
+			"Op Ob O- x- O-\n\
+			|e |- |- |- |g\n\
+			@weather wind east",
+		);
+		scenario.skip_turns(1);
+		scenario.assert_obj((1, 0), '-').assert_obj((2, 0), 'b');
+		// Bombs don't go into the water.
+		scenario.skip_turns(1);
+		scenario.assert_obj((2, 0), 'b');
+
+		let scenario = Scenario::new("Ot O- O- O- O- O- O-\n@weather fog");
+		let level = &scenario.level;
+		let tiles = tower_range_tiles(
+			&level.grid,
+			(0, 0).into(),
+			&Tower::Basic,
+			Direction::East,
+			level.weather(),
+		);
+		assert_eq!(tiles.len(), weather::FOG_TOWER_RANGE as usize);
+		assert!(parse_level("Op |g\n@weather wind").is_err());
+	}
+
+	#[test]
+	fn spike_traps_strike_every_other_turn() {
+		let mut scenario = Scenario::new("Op |e ^- ^- |- |- |- |g");
+		// Down during the first turn, so the enemy steps on them unharmed.
+		scenario.skip_turns(1);
+		scenario.assert_enemy((2, 0), "basic", 5);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((3, 0), "basic", 2);
+		scenario.skip_turns(2);
+		scenario.assert_enemy((5, 0), "basic", 2);
+
+		let mut scenario = Scenario::new(
+			"Op Or ^- O- O- O- O-\n\
+			|e |- |- |- |- |- |g",
+		);
+		scenario.skip_turns(1);
+		scenario.play(&["move right"]);
+		scenario.assert_obj((1, 0), 'p').assert_obj((2, 0), '-');
+		assert_eq!(
+			tile_string(scenario.level.grid.get((2, 0).into()).unwrap()),
+			['^', '-']
+		);
+		// The player doesn't survive them either.
+		scenario.play(&["move right"]);
+		scenario.assert_obj((2, 0), 'p');
+		scenario.skip_turns(1);
+		assert!(scenario.level.player_coords().is_none());
+	}
+
+	#[test]
+	fn conveyor_belts_carry_what_is_on_them() {
+		let mut scenario = Scenario::new(
+			">p >r >- O- O- O-\n\
+			|e <- |- |- |- |g",
+		);
+		assert_eq!(
+			tile_string(scenario.level.grid.get((1, 1).into()).unwrap()),
+			['<', '-']
+		);
+		// The rock moves first, which makes room for the player.
+		scenario.skip_turns(1);
+		scenario
+			.assert_obj((0, 0), '-')
+			.assert_obj((1, 0), 'p')
+			.assert_obj((2, 0), 'r');
+		// The enemy stepped on the belt, which brought it back.
+		scenario.assert_enemy((0, 1), "basic", 5);
+		scenario.skip_turns(1);
+		scenario.assert_obj((2, 0), 'p').assert_obj((3, 0), 'r');
+		// The rock is off the belt, and blocks the player.
+		scenario.skip_turns(1);
+		scenario.assert_obj((2, 0), 'p').assert_obj((3, 0), 'r');
+		assert!(scenario
+			.level
+			.player_coords()
+			.is_some_and(|coords| coords == (2, 0).into()));
+	}
+
+	#[test]
+	fn conveyor_belts_keep_enemies_on_the_path() {
+		let mut scenario = Scenario::new(
+			"Op O- O- O- O-\n\
+			|e A- |- |- |g",
+		);
+		scenario.skip_turns(1);
+		// The grass above the belt is empty, but the enemy isn't carried off the path.
+		scenario.assert_obj((1, 0), '-');
+		scenario.assert_enemy((1, 1), "basic", 5);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((2, 1), "basic", 5);
+	}
+
+	#[test]
+	fn turn_phases_happen_in_order() {
+		let position = |phase| {
+			TURN_PHASES
+				.iter()
+				.position(|&other| other == phase)
+				.unwrap()
+		};
+		let before = |first, then| assert!(position(first) < position(then), "{first:?} {then:?}");
+		// The towers shoot the enemies where they moved, and then what is left is carried.
+		before(TurnPhase::EnemiesMove, TurnPhase::GoalsDamaged);
+		before(TurnPhase::GoalsDamaged, TurnPhase::Towers);
+		before(TurnPhase::Towers, TurnPhase::Conveyors);
+		before(TurnPhase::Conveyors, TurnPhase::Spikes);
+		// The enemies spawned by the events are still emerging during the next turn.
+		before(TurnPhase::EnemiesEmerge, TurnPhase::NextTurn);
+		before(TurnPhase::NextTurn, TurnPhase::Events);
+		// Doesn't compile once a phase is added, so that it is added here and then checked to
+		// be in `TURN_PHASES` (a phase that isn't there never happens).
+		let number = |phase| match phase {
+			TurnPhase::Burns => 0,
+			TurnPhase::EnemiesMove => 1,
+			TurnPhase::GoalsDamaged => 2,
+			TurnPhase::Bombs => 3,
+			TurnPhase::Wind => 4,
+			TurnPhase::Flowers => 5,
+			TurnPhase::Towers => 6,
+			TurnPhase::Conveyors => 7,
+			TurnPhase::Spikes => 8,
+			TurnPhase::Lava => 9,
+			TurnPhase::Shields => 10,
+			TurnPhase::GroupLeaders => 11,
+			TurnPhase::BossPhases => 12,
+			TurnPhase::EnemiesEmerge => 13,
+			TurnPhase::NextTurn => 14,
+			TurnPhase::Events => 15,
+			TurnPhase::TreesRegrow => 16,
+		};
+		let mut numbers: Vec<usize> = TURN_PHASES.into_iter().map(number).collect();
+		numbers.sort();
+		assert_eq!(numbers, (0..17).collect::<Vec<usize>>());
+
+		// The enemy burns to death before it gets to step onto the goal.
+		let mut scenario = Scenario::new(
+			"
+			Op O- O-
+			|e |- |g
+			@lives 2
+			",
+		);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((1, 1), "basic", 5);
+		if let Obj::Enemy { hp, effects, .. } =
+			&mut scenario.level.grid.get_mut((1, 1).into()).unwrap().obj
+		{
+			*hp = balance::get().burn_damage;
+			effects.burn = 1;
+		}
+		scenario.skip_turns(1);
+		scenario.assert_obj((1, 1), '-').assert_obj((2, 1), 'g');
+		assert_eq!(scenario.level.lives, Some(2));
+	}
+
+	#[test]
+	fn enemies_come_out_of_portals_out_of_reach() {
+		let mut scenario = Scenario::new(
+			"Op Ot Ot O- O- O-\n\
+			*- |- |- |- |- |g\n\
+			@name A 0 1\n\
+			@event spawn basic A 1",
+		);
+		assert_eq!(
+			tile_string(scenario.level.grid.get((0, 1).into()).unwrap()),
+			['*', '-']
+		);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((0, 1), "basic", 5);
+		// Still coming out of the portal when it passes the first tower.
+		scenario.skip_turns(1);
+		scenario.assert_enemy((1, 1), "basic", 5);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((2, 1), "basic", 4);
+
+		// The spawn tiles of the events are portals even without `*`.
+		let level_data = parse_level("Op ?A |- |g\n@event spawn basic A 3").unwrap();
+		assert!(level_data.init_grid.get((1, 0).into()).unwrap().portal);
+	}
+
+	#[test]
+	fn lava_burns_what_is_pushed_into_it() {
+		let mut scenario = Scenario::new("Oy O- |e |e %- O- Og");
+		scenario.skip_turns(1);
+		scenario
+			.assert_enemy((2, 0), "basic", 4)
+			.assert_enemy((4, 0), "basic", 3);
+		scenario.skip_turns(2);
+		scenario.assert_obj((4, 0), '-');
+		assert_eq!(scenario.level.stats.lava_kills, 1);
+
+		// Rocks are destroyed, bombs explode, and the player can't walk on it.
+		let mut scenario = Scenario::new(
+			"Op Or Ob %- O- Og\n\
+			O- O- O- |e O- O-",
+		);
+		scenario.play(&["move right"]);
+		scenario
+			.assert_obj((2, 0), 'r')
+			.assert_obj((3, 0), '-')
+			.assert_ground((3, 0), '%')
+			.assert_enemy((3, 1), "basic", 1);
+		scenario.play(&["move right", "move right"]);
+		scenario.assert_obj((2, 0), 'p').assert_obj((3, 0), '-');
+	}
+
+	#[test]
+	fn keys_open_the_doors_of_their_color() {
+		let level = "Op OK O= O- O-\n\
+			|e |- |- |- |g\n\
+			@name D 2 1\n\
+			@door blue D";
+		let mut scenario = Scenario::new(level);
+		scenario.play(&["move right"]);
+		assert!(scenario.level.inventory.keys == [KeyColor::Red]);
+		// The blue door is in the way of the enemy.
+		scenario.assert_enemy((0, 1), "basic", 5);
+		scenario.play(&["move right"]);
+		scenario.assert_obj((1, 0), 'p').assert_obj((2, 0), '-');
+		assert!(scenario.level.inventory.keys.is_empty());
+		// Without a blue key, the player can't go through either.
+		scenario.play(&["move down", "move right"]);
+		scenario.assert_obj((1, 1), 'p').assert_obj((2, 1), '=');
+		scenario.assert_enemy((0, 1), "basic", 5);
+
+		assert!(parse_level(&level.replace("blue", "purple")).is_err());
+	}
+
+	#[test]
+	fn terrain_events_open_new_paths() {
+		let level = "Op O- Or O- O-\n\
+			|e ?a |- |- |g\n\
+			@event terrain ?a path 2\n\
+			@name b 2 0\n\
+			@event terrain b water 1\n\
+			@goal_hp 5";
+		let mut scenario = Scenario::new(level);
+		scenario.skip_turns(1);
+		scenario.assert_enemy((0, 1), "basic", 5);
+		// The rock would sink, so the water waits for it to be moved.
+		scenario.assert_ground((2, 0), 'O');
+		scenario.skip_turns(1);
+		scenario.assert_ground((1, 1), '|');
+		scenario.skip_turns(1);
+		scenario.assert_enemy((1, 1), "basic", 5);
+		scenario.play(&["move right", "move right"]);
+		scenario.assert_obj((2, 0), 'p').assert_ground((2, 0), 'O');
+		scenario.play(&["move right"]);
+		scenario.assert_obj((4, 0), 'r').assert_ground((2, 0), 'x');
+
+		assert!(parse_level(&level.replace("path", "sand")).is_err());
+	}
+
+	#[test]
+	fn meteors_destroy_what_is_on_their_tile() {
+		let level = "Op O- O- O-\n\
+			|e |- |- |g\n\
+			@event meteor 2 7";
+		let meteor = |scenario: &Scenario| match scenario.level.events.last().unwrap().event_type {
+			GameEventType::Meteor(coords) => coords,
+			_ => unreachable!(),
+		};
+		let mut scenario = Scenario::new(level);
+		let coords = meteor(&scenario);
+		assert!(coords == meteor(&Scenario::new(level)));
+		scenario.level.grid.get_mut(coords).unwrap().obj = Obj::Rock;
+		scenario.skip_turns(1);
+		// Telegraphed a turn in advance.
+		assert_eq!(scenario.level.pending_events().count(), 1);
+		scenario.skip_turns(1);
+		scenario.assert_obj(coords, '-');
+		assert!(scenario.level.grid.get(coords).unwrap().rocky_path);
+		assert!(scenario
+			.level
+			.stats
+			.turn_events
+			.contains(&TurnEvent::Explosion(coords)));
+	}
+
+	#[test]
+	fn meteor_kills_score_like_other_kills() {
+		let mut scenario = Scenario::new(
+			"Op ?A |e |- |- |g\n\
+			@event spawn basic A 5\n\
+			@event meteor 2 2",
+		);
+		let coords = match scenario.level.events.last().unwrap().event_type {
+			GameEventType::Meteor(coords) => coords,
+			_ => unreachable!(),
+		};
+		// Where the enemy walks on the turn the meteor falls.
+		assert_eq!(coords, (4, 0).into());
+		scenario.skip_turns(1);
+		let score = high_scores::score(&scenario.level);
+		scenario.skip_turns(1);
+		assert_eq!(scenario.level.stats.meteor_kills, 1);
+		assert_eq!(high_scores::score(&scenario.level), score + 10);
+	}
+
+	#[test]
+	fn grant_events_give_towers_and_items() {
+		let level = "Op O- O- O-\n\
+			|e |- |- |g\n\
+			@max_towers 0\n\
+			@event grant towers 2 1\n\
+			@event grant bomb 2";
+		let mut scenario = Scenario::new(level);
+		scenario.skip_turns(1);
+		assert_eq!(scenario.level.remaining_towers, Some(2));
+		assert_eq!(toasts(&scenario.level), ["+2 towers to place"]);
+		scenario.skip_turns(1);
+		assert_eq!(scenario.level.inventory.bombs, 1);
+		assert_eq!(toasts(&scenario.level), ["+1 bomb"]);
+		scenario.skip_turns(1);
+		assert!(toasts(&scenario.level).is_empty());
+
+		assert!(parse_level(&level.replace("bomb", "gold")).is_err());
+		assert!(parse_level(&level.replace("bomb 2", "bomb")).is_err());
+	}
+
+	#[test]
+	fn conditional_events_wait_for_their_condition() {
+		let level = "Op O- O- O- O-\n\
+			|e |- |- |- |g\n\
+			@name B 0 1\n\
+			@event when enemies_below 1 spawn tank B 0\n\
+			@event when goal_hp_below 80% grant bomb 0\n\
+			@goal_hp 4";
+		let mut scenario = Scenario::new(level);
+		scenario.skip_turns(3);
+		scenario.assert_enemy((3, 1), "basic", 5);
+		assert!(scenario.level.pending_events().next().is_none());
+		assert!(!scenario.level.is_won());
+		// The enemy damages the goal, which leaves no enemies and the goal at 75% of its HP.
+		scenario.skip_turns(1);
+		assert!(matches!(
+			scenario.obj((0, 1)),
+			Obj::Enemy { variant: Enemy::Tank, .. }
+		));
+		assert_eq!(scenario.level.inventory.bombs, 1);
+
+		assert!(parse_level(&level.replace("enemies_below", "enemies_above")).is_err());
+	}
+
+	#[test]
+	fn rocks_pushed_into_water_make_stepping_stones() {
+		let mut scenario = Scenario::new("Op Or x- x- |g");
+		scenario.play(&["move right"]);
+		scenario.assert_obj((1, 0), 'p').assert_obj((2, 0), '-');
+		scenario
+			.assert_ground((2, 0), '0')
+			.assert_ground((3, 0), 'x');
+		// The player can walk on it, but not further into the water.
+		scenario.play(&["move right", "move right"]);
+		scenario.assert_obj((2, 0), 'p').assert_ground((3, 0), 'x');
+	}
+
+	#[test]
+	fn trees_are_chopped_down_and_grow_back() {
+		let mut scenario = Scenario::new("Op OT O- |g\n@tree_regrowth 2");
+		scenario.play(&["move right"]);
+		assert!(matches!(scenario.obj((1, 0)), Obj::Tree { chopped: true }));
+		scenario.play(&["move right"]);
+		scenario.assert_obj((0, 0), 'p').assert_obj((1, 0), 'o');
+		// It doesn't grow back under the player.
+		scenario.play(&["move right"]);
+		scenario.assert_obj((1, 0), 'p');
+		assert_eq!(scenario.level.inventory.bombs, 1);
+		scenario.play(&["move left"]);
+		scenario.assert_obj((1, 0), 'T');
+		assert!(matches!(scenario.obj((1, 0)), Obj::Tree { chopped: false }));
+	}
+
+	#[test]
+	fn resources_are_gathered_and_crafted() {
+		let mut scenario = Scenario::new(
+			"
+			Op Ow Ow Oc Or |g
+			OT O- O- O- O- O-
+			@crafting
+			",
+		);
+		assert_eq!(scenario.level.remaining_towers, Some(0));
+		scenario.play(&["move right", "move right", "move right", "attack right"]);
+		scenario.assert_obj((4, 0), 'c');
+		let inventory = &scenario.level.inventory;
+		assert_eq!((inventory.wood, inventory.stone), (2, 1));
+		scenario.play(&["craft tower", "craft bomb"]);
+		assert_eq!(scenario.level.turn, 4);
+		assert_eq!(scenario.level.remaining_towers, Some(1));
+		let inventory = &scenario.level.inventory;
+		assert_eq!(
+			(inventory.wood, inventory.stone, inventory.bombs),
+			(0, 0, 0)
+		);
+
+		// Trees give wood instead of bombs.
+		scenario.play(&[
+			"move left",
+			"move left",
+			"move down",
+			"move left",
+			"move left",
+		]);
+		scenario.assert_obj((0, 1), 'w');
+	}
+
+	#[test]
+	fn enemies_break_obstacles_they_cant_push() {
+		for level in ["|e |r |r |g Op", "|e |T |- |g Op"] {
+			let mut scenario = Scenario::new(level);
+			scenario.skip_turns(3);
+			scenario.assert_obj((0, 0), 'e');
+			scenario.skip_turns(1);
+			scenario.assert_obj((1, 0), '-');
+			scenario.skip_turns(1);
+			scenario.assert_obj((1, 0), 'e');
+		}
+	}
+
+	#[test]
+	fn bomb_explodes_after_its_countdown() {
+		let mut scenario = Scenario::new("|W Ob |e O- Og");
+		scenario.skip_turns(3);
+		scenario
+			.assert_bomb((1, 0), 0)
+			.assert_enemy((0, 0), "tank", 9)
+			.assert_enemy((2, 0), "basic", 5);
+		scenario.skip_turns(1);
+		scenario
+			.assert_obj((1, 0), '-')
+			.assert_enemy((0, 0), "tank", 5)
+			.assert_enemy((2, 0), "basic", 1);
+	}
+
+	#[test]
+	fn bombs_explode_in_chain() {
+		let mut scenario = Scenario::new("|W Ob Ob |e O- Og");
+		// The second bomb would take longer to explode on its own.
+		scenario.level.grid.get_mut((2, 0).into()).unwrap().obj = Obj::Bomb { countdown: 9 };
+		scenario.skip_turns(4);
+		scenario
+			.assert_obj((1, 0), '-')
+			.assert_obj((2, 0), '-')
+			.assert_enemy((0, 0), "tank", 5)
+			.assert_enemy((3, 0), "basic", 1);
+	}
+
+	#[test]
+	fn bombs_spare_towers_without_friendly_fire() {
+		let mut scenario = Scenario::new(
+			"
+			Ot Ob O- Og
+			@friendly_fire off
+			",
+		);
+		scenario.skip_turns(4);
+		scenario.assert_obj((1, 0), '-').assert_obj((0, 0), 't');
+	}
+
+	#[test]
+	fn unabomber_drops_a_bomb_in_front_of_its_target() {
+		let mut scenario = Scenario::new("Od O- O- |W O- Og");
+		scenario.skip_turns(1);
+		scenario
+			.assert_bomb((2, 0), 3)
+			.assert_enemy((3, 0), "tank", 9);
+		scenario.skip_turns(4);
+		// The tower immediately drops another bomb.
+		scenario
+			.assert_bomb((2, 0), 3)
+			.assert_enemy((3, 0), "tank", 5);
+	}
+
+	#[test]
+	fn each_unit_acts_once_per_turn() {
+		let mut scenario = Scenario::new(
+			"
+			Op O- O- Op
+			O- O- O- O-
+			|e |- |- |g
+			",
+		);
+		scenario.play(&["move right"]);
+		assert_eq!(scenario.level.turn, 0);
+		scenario.assert_obj((1, 0), 'p').assert_obj((0, 2), 'e');
+		assert_eq!(scenario.level.player_coords(), Some((3, 0).into()));
+		scenario.play(&["move down"]);
+		assert_eq!(scenario.level.turn, 1);
+		scenario.assert_obj((3, 1), 'p').assert_obj((1, 2), 'e');
+
+		// Switching to the second unit doesn't take a turn.
+		scenario.play(&["switch"]);
+		assert_eq!(scenario.level.turn, 1);
+		assert_eq!(scenario.level.player_coords(), Some((3, 1).into()));
+		scenario.play(&["move left"]);
+		scenario.assert_obj((2, 1), 'p').assert_obj((1, 2), 'e');
+		scenario.play(&["skip"]);
+		assert_eq!(scenario.level.turn, 2);
+		assert_eq!(scenario.level.actions, 5);
+		scenario.assert_obj((1, 0), 'p').assert_obj((2, 1), 'p');
+	}
+
+	#[test]
+	fn levels_are_lost_when_out_of_turns() {
+		let mut scenario = Scenario::new(
+			"
+			Op O- O-
+			?a |- |g
+			@event spawn basic a 5
+			@max_turns 3
+			",
+		);
+		assert_eq!(scenario.level.turns_left(), Some(3));
+		scenario.skip_turns(2);
+		assert!(!scenario.level.game_joever);
+		assert_eq!(scenario.level.turns_left(), Some(1));
+		scenario.skip_turns(1);
+		assert!(scenario.level.game_joever);
+		assert!(!scenario.level.is_won());
+
+		let mut scenario = Scenario::new("Op O- |g\n@max_turns 1");
+		scenario.skip_turns(1);
+		assert!(scenario.level.is_won());
+	}
+
+	#[test]
+	fn the_window_title_shows_the_level_and_the_turn() {
+		let title = |level_data: &LevelData, level: &LevelState, screen| {
+			window_title(level_data, "./levels/marsh_maze", level, screen)
+		};
+		let level_data = parse_level("Op O- |g\n@title  Marsh maze ").unwrap();
+		let mut level = LevelState::new(&level_data);
+		play_turn(&mut level, (0, 0).into(), PlayerAction::SkipTurn);
+		play_turn(&mut level, (0, 0).into(), PlayerAction::SkipTurn);
+		assert_eq!(
+			title(&level_data, &level, Screen::Playing),
+			"Prototype 7 — Marsh maze — turn 2"
+		);
+		assert_eq!(title(&level_data, &level, Screen::Title), "Prototype 7");
+		let level_data = parse_level("Op |g").unwrap();
+		assert_eq!(
+			title(&level_data, &LevelState::new(&level_data), Screen::Paused),
+			"Prototype 7 — marsh_maze — turn 0"
+		);
+	}
+
+	#[test]
+	fn bad_levels_are_reported_instead_of_crashing() {
+		assert_eq!(
+			parse_level("Op Q- |g").err().unwrap(),
+			"Line 1: Unknown ground 'Q-'"
+		);
+		assert_eq!(
+			parse_level("Op ?a |g\n@event spawn dragon a 3")
+				.err()
+				.unwrap(),
+			"Line 2: Cannot spawn dragon, there is no such enemy"
+		);
+		assert!(parse_level("Op ?a |g\n@event spawn basic b 3").is_err());
+		assert!(parse_level("Op |g\n@max_towers lots").is_err());
+		assert!(parse_level("?a ?a |g").is_err());
+		assert!(parse_level("Op |g\n@name a 5 0").is_err());
+		assert!(catch_panic(|| panic!("oops")).is_err_and(|error| error.contains("oops")));
+	}
+}
@@ -0,0 +1,133 @@
+//! Feedback effects on the whole screen, triggered by what happened during a turn:
+//! the screen shakes when a bomb explodes or the goal is damaged, and the animations freeze
+//! for a moment when an enemy is killed (hit-stop). They only affect the rendering,
+//! the game itself doesn't wait for them.
+
+use crate::coords::*;
+use crate::*;
+
+use std::time::{Duration, Instant};
+
+const EXPLOSION_SHAKE: Shake = Shake { strength: 1.0 / 8.0, duration: Duration::from_millis(250) };
+const GOAL_DAMAGE_SHAKE: Shake =
+	Shake { strength: 1.0 / 5.0, duration: Duration::from_millis(350) };
+const HIT_STOP_DURATION: Duration = Duration::from_millis(80);
+
+#[derive(Clone, Copy)]
+struct Shake {
+	/// The largest offset of the screen, in cells.
+	strength: f32,
+	duration: Duration,
+}
+
+pub struct ScreenEffects {
+	/// The current shake and when it started.
+	shake: Option<(Shake, Instant)>,
+	hit_stop_end: Option<Instant>,
+	rng: rng::Rng,
+}
+
+impl ScreenEffects {
+	pub fn new() -> ScreenEffects {
+		ScreenEffects { shake: None, hit_stop_end: None, rng: rng::Rng::with_seed(0) }
+	}
+
+	/// Start the effects triggered by the events of the turn that was just played.
+	pub fn react_to_turn(&mut self, turn_events: &[TurnEvent], now: Instant) {
+		for event in turn_events {
+			match event {
+				TurnEvent::Explosion(_) => self.start_shake(EXPLOSION_SHAKE, now),
+				TurnEvent::GoalDamaged(_) | TurnEvent::BossPhase { .. } => {
+					self.start_shake(GOAL_DAMAGE_SHAKE, now)
+				},
+				TurnEvent::EnemyDied(_) => self.hit_stop_end = Some(now + HIT_STOP_DURATION),
+				TurnEvent::Hit(_)
+				| TurnEvent::Shot { .. }
+				| TurnEvent::Slid { .. }
+				| TurnEvent::Granted(..) => {},
+			}
+		}
+	}
+
+	/// A shake doesn't replace a stronger one.
+	fn start_shake(&mut self, shake: Shake, now: Instant) {
+		let current_strength = self.current_shake_strength(now);
+		if shake.strength >= current_strength {
+			self.shake = Some((shake, now));
+		}
+	}
+
+	/// The shake strength (in cells) fades out during the shake.
+	fn current_shake_strength(&self, now: Instant) -> f32 {
+		self.shake.map_or(0.0, |(shake, start)| {
+			let progress = (now - start).as_secs_f32() / shake.duration.as_secs_f32();
+			shake.strength * (1.0 - progress).max(0.0)
+		})
+	}
+
+	/// By how many pixels the game is moved for this frame.
+	pub fn shake_offset(&mut self, now: Instant, cell_pixel_side: i32) -> DxDy {
+		let amplitude = (self.current_shake_strength(now) * cell_pixel_side as f32) as i32;
+		if amplitude == 0 {
+			self.shake = None;
+			return DxDy { dx: 0, dy: 0 };
+		}
+		let mut random_offset = || self.rng.below(2 * amplitude as u32 + 1) as i32 - amplitude;
+		DxDy { dx: random_offset(), dy: random_offset() }
+	}
+
+	/// During a hit-stop, animations are frozen.
+	pub fn is_hit_stopped(&self, now: Instant) -> bool {
+		self.hit_stop_end.is_some_and(|end| now < end)
+	}
+
+	/// Whether frames need to be drawn for the effects to play out.
+	pub fn is_active(&self, now: Instant) -> bool {
+		self.current_shake_strength(now) > 0.0 || self.is_hit_stopped(now)
+	}
+}
+
+/// Move everything drawn in the pixel buffer by `offset`, the uncovered pixels are filled
+/// with `fill_color`.
+pub fn shift_frame(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	offset: DxDy,
+	fill_color: [u8; 4],
+) {
+	if offset == (DxDy { dx: 0, dy: 0 }) {
+		return;
+	}
+	let frame = pixel_buffer.frame_mut();
+	let original = frame.to_vec();
+	for coords in pixel_buffer_dims.iter() {
+		let source = coords - offset;
+		let color = match pixel_buffer_dims.index_of_coords(source) {
+			Some(index) => &original[(index * 4)..(index * 4 + 4)],
+			None => &fill_color,
+		};
+		let index = pixel_buffer_dims.index_of_coords(coords).unwrap() * 4;
+		frame[index..(index + 4)].copy_from_slice(color);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn shakes_fade_out_and_kills_freeze_the_animations_for_a_moment() {
+		let mut effects = ScreenEffects::new();
+		let start = Instant::now();
+		effects.react_to_turn(&[TurnEvent::Explosion((0, 0).into())], start);
+		assert!(effects.is_active(start));
+		assert!(!effects.is_hit_stopped(start));
+		let later = start + EXPLOSION_SHAKE.duration;
+		assert!(effects.shake_offset(later, 64) == DxDy { dx: 0, dy: 0 });
+		assert!(!effects.is_active(later));
+
+		effects.react_to_turn(&[TurnEvent::EnemyDied((0, 0).into())], later);
+		assert!(effects.is_hit_stopped(later));
+		assert!(!effects.is_hit_stopped(later + HIT_STOP_DURATION));
+	}
+}
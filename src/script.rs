@@ -0,0 +1,323 @@
+//! Level scripts (`@script <file>`, in Rhai), for scripted tutorials and custom win conditions.
+//! The functions of a script named after the hooks are called when things happen in the level:
+//! - `on_turn_start(state)` at the start of every turn (turn 0 included),
+//! - `on_enemy_killed(state, enemy, x, y)` for every enemy killed (`enemy` is its name),
+//! - `on_goal_damaged(state, hp)` every time an enemy reaches a goal (`hp` is what it has left),
+//! - `on_boss_phase(state, behavior, x, y)` when a boss enters a phase (`behavior` is what the
+//!   phase gives it, see `[boss_phase]` in `assets/balance.toml`).
+//!
+//! `state` is a map with `turn`, `enemies` (the number of enemies on the level), `lives`,
+//! `towers` (that can still be placed) and `player_x`/`player_y` (-1 when there is no such
+//! thing). The scripts can only act on the level through these functions:
+//! - `spawn_enemy(name, x, y)` spawns an enemy on an empty path tile,
+//! - `place(object, x, y)` puts an object (its character in the level format, like `"r"`) on
+//!   an empty tile, the player and the goals can't be placed,
+//! - `message(text)` shows a message over the level until the next one, `clear_message()`,
+//! - `win()` and `lose()` end the level.
+//!
+//! The level is also won as usual, once all its events happened and there are no enemies left.
+//!
+//! Scripts can't do anything else (like reading files), and they are stopped if they run for
+//! too long. Errors while running a hook are logged and the hook is skipped.
+
+use crate::coords::*;
+use crate::*;
+
+use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use std::cell::RefCell;
+
+pub struct Script {
+	ast: AST,
+}
+
+/// What the functions of the API ask for, done once the hook returns.
+enum Command {
+	SpawnEnemy(Enemy, Coords),
+	Place(Obj, Coords),
+	Message(Option<String>),
+	Win,
+	Lose,
+}
+
+thread_local! {
+	static ENGINE: Engine = engine();
+	static COMMANDS: RefCell<Vec<Command>> = const { RefCell::new(vec![]) };
+}
+
+fn push(command: Command) {
+	COMMANDS.with(|commands| commands.borrow_mut().push(command));
+}
+
+fn coords(x: i64, y: i64) -> Coords {
+	Coords { x: x as i32, y: y as i32 }
+}
+
+fn engine() -> Engine {
+	let mut engine = Engine::new();
+	engine
+		.set_max_operations(100_000)
+		.set_max_call_levels(32)
+		.set_max_string_size(10_000)
+		.set_max_array_size(10_000)
+		.set_max_map_size(1_000);
+	// Without it, `import` would read scripts from anywhere on the disk.
+	engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+	engine.on_print(|text| log::info!("Script: {text}"));
+	engine.on_debug(|text, _, _| log::debug!("Script: {text}"));
+	engine.register_fn(
+		"spawn_enemy",
+		|name: &str, x: i64, y: i64| -> Result<(), Box<EvalAltResult>> {
+			let enemy = Enemy::from_name(name).ok_or(format!("no enemy named {name}"))?;
+			push(Command::SpawnEnemy(enemy, coords(x, y)));
+			Ok(())
+		},
+	);
+	engine.register_fn(
+		"place",
+		|object: &str, x: i64, y: i64| -> Result<(), Box<EvalAltResult>> {
+			let cell = parse_tile_text(&format!("O{object}"))?;
+			if matches!(cell.obj, Obj::Player { .. } | Obj::Goal { .. } | Obj::Empty) {
+				return Err(format!("{object} can't be placed by a script").into());
+			}
+			push(Command::Place(cell.obj, coords(x, y)));
+			Ok(())
+		},
+	);
+	engine.register_fn("message", |text: &str| {
+		push(Command::Message(Some(text.to_string())))
+	});
+	engine.register_fn("clear_message", || push(Command::Message(None)));
+	engine.register_fn("win", || push(Command::Win));
+	engine.register_fn("lose", || push(Command::Lose));
+	engine
+}
+
+/// What the hooks are given about the level.
+fn state_map(level: &LevelState) -> Map {
+	let enemies = level
+		.grid
+		.dims
+		.iter()
+		.filter(|&coords| matches!(level.grid.get(coords).unwrap().obj, Obj::Enemy { .. }))
+		.count();
+	let player = level.player_coords();
+	let mut state = Map::new();
+	state.insert("turn".into(), (level.turn as i64).into());
+	state.insert("enemies".into(), (enemies as i64).into());
+	state.insert(
+		"lives".into(),
+		level.lives.map_or(-1, |lives| lives as i64).into(),
+	);
+	state.insert(
+		"towers".into(),
+		level
+			.remaining_towers
+			.map_or(-1, |towers| towers as i64)
+			.into(),
+	);
+	state.insert(
+		"player_x".into(),
+		player.map_or(-1, |coords| coords.x as i64).into(),
+	);
+	state.insert(
+		"player_y".into(),
+		player.map_or(-1, |coords| coords.y as i64).into(),
+	);
+	state
+}
+
+impl Script {
+	pub fn compile(text: &str) -> Result<Script, String> {
+		let ast = ENGINE
+			.with(|engine| engine.compile(text))
+			.map_err(|error| error.to_string())?;
+		Ok(Script { ast })
+	}
+
+	fn has_hook(&self, hook: &str) -> bool {
+		self
+			.ast
+			.iter_functions()
+			.any(|function| function.name == hook)
+	}
+
+	/// Call a hook (if the script has it) and do what it asked for.
+	fn call(&self, level: &mut LevelState, hook: &str, args: impl rhai::FuncArgs) {
+		if !self.has_hook(hook) {
+			return;
+		}
+		let result =
+			ENGINE.with(|engine| engine.call_fn::<Dynamic>(&mut Scope::new(), &self.ast, hook, args));
+		let commands = COMMANDS.with(|commands| std::mem::take(&mut *commands.borrow_mut()));
+		if let Err(error) = result {
+			log::warn!("Error in the {hook} hook of the script: {error}");
+			return;
+		}
+		for command in commands {
+			apply(level, command);
+		}
+	}
+
+	pub fn on_turn_start(&self, level: &mut LevelState) {
+		let state = state_map(level);
+		self.call(level, "on_turn_start", (state,));
+	}
+
+	/// Call the hooks for what happened during the turn that was just played, and then
+	/// `on_turn_start` for the next one.
+	pub fn after_turn(&self, level: &mut LevelState) {
+		for (coords, enemy) in level.stats.turn_kills.clone() {
+			let state = state_map(level);
+			let args = (
+				state,
+				enemy.name().to_string(),
+				coords.x as i64,
+				coords.y as i64,
+			);
+			self.call(level, "on_enemy_killed", args);
+		}
+		let goals_damaged: Vec<Coords> = level
+			.stats
+			.turn_events
+			.iter()
+			.filter_map(|event| match *event {
+				TurnEvent::GoalDamaged(coords) => Some(coords),
+				_ => None,
+			})
+			.collect();
+		for coords in goals_damaged {
+			let hp = match level.grid.get(coords).unwrap().obj {
+				Obj::Goal { hp } => hp as i64,
+				_ => 0,
+			};
+			let state = state_map(level);
+			self.call(level, "on_goal_damaged", (state, hp));
+		}
+		let boss_phases: Vec<(Coords, BossBehavior)> = level
+			.stats
+			.turn_events
+			.iter()
+			.filter_map(|event| match *event {
+				TurnEvent::BossPhase { at, behavior } => Some((at, behavior)),
+				_ => None,
+			})
+			.collect();
+		for (coords, behavior) in boss_phases {
+			let state = state_map(level);
+			let args = (
+				state,
+				behavior.name().to_string(),
+				coords.x as i64,
+				coords.y as i64,
+			);
+			self.call(level, "on_boss_phase", args);
+		}
+		// Not while some units have yet to act.
+		if !level.game_joever && !level.is_won() && level.units_acted.is_empty() {
+			self.on_turn_start(level);
+		}
+	}
+}
+
+fn apply(level: &mut LevelState, command: Command) {
+	match command {
+		Command::SpawnEnemy(enemy, coords) => {
+			if let Some(cell) = level.grid.get_mut(coords) {
+				if matches!(cell.groud, Ground::Path(_)) && matches!(cell.obj, Obj::Empty) {
+					cell.obj = Obj::new_enemy(enemy);
+				}
+			}
+		},
+		Command::Place(obj, coords) => {
+			if let Some(cell) = level.grid.get_mut(coords) {
+				if matches!(cell.obj, Obj::Empty) && !cell.groud.is_liquid() {
+					cell.obj = obj;
+				}
+			}
+		},
+		Command::Message(message) => level.message = message,
+		Command::Win => level.won_by_script = true,
+		Command::Lose => level.game_joever = true,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn hooks_can_act_on_the_level() {
+		let script = Script::compile(
+			r#"
+			fn on_turn_start(state) {
+				if state.turn == 0 {
+					message("Place a tower next to the path");
+				} else if state.turn == 2 {
+					spawn_enemy("basic", 1, 0);
+					place("r", 0, 1);
+				}
+			}
+			fn on_enemy_killed(state, enemy, x, y) {
+				message(`${enemy} killed at ${x}, ${y}`);
+				win();
+			}
+			"#,
+		)
+		.unwrap();
+		let mut level_data = parse_level(
+			"Op ?A |- |- |- |- |- |- |- |g\n\
+			O- O- Ot Ot Ot Ot Ot O- O- O-\n\
+			@tile A |-\n\
+			@event spawn basic A 50",
+		)
+		.unwrap();
+		level_data.script = Some(std::rc::Rc::new(script));
+		let mut level = LevelState::new(&level_data);
+		assert_eq!(
+			level.message.as_deref(),
+			Some("Place a tower next to the path")
+		);
+		for _ in 0..2 {
+			play_turn(&mut level, (0, 0).into(), PlayerAction::SkipTurn);
+		}
+		assert!(matches!(
+			level.grid.get((1, 0).into()).unwrap().obj,
+			Obj::Enemy { .. }
+		));
+		assert!(matches!(
+			level.grid.get((0, 1).into()).unwrap().obj,
+			Obj::Rock
+		));
+		assert!(!level.is_won());
+		while level.message.as_deref() == Some("Place a tower next to the path") {
+			play_turn(&mut level, (0, 0).into(), PlayerAction::SkipTurn);
+		}
+		assert_eq!(level.message.as_deref(), Some("basic killed at 6, 0"));
+		assert!(level.is_won());
+
+		assert!(Script::compile("fn on_turn_start(state) {").is_err());
+		let endless = Script::compile("fn on_turn_start(state) { loop {} }").unwrap();
+		level_data.script = Some(std::rc::Rc::new(endless));
+		LevelState::new(&level_data);
+	}
+
+	#[test]
+	fn scripts_cant_import_files() {
+		let path = std::env::temp_dir().join(format!("prototype-07-import-{}", std::process::id()));
+		fs::write(
+			path.with_extension("rhai"),
+			"export const TEXT = \"imported\";",
+		)
+		.unwrap();
+		let script = Script::compile(&format!(
+			"fn on_turn_start(state) {{ import {:?} as imported; message(imported::TEXT); }}",
+			path.display().to_string()
+		))
+		.unwrap();
+		let mut level_data = parse_level("Op |g").unwrap();
+		level_data.script = Some(std::rc::Rc::new(script));
+		let level = LevelState::new(&level_data);
+		fs::remove_file(path.with_extension("rhai")).unwrap();
+		assert_eq!(level.message, None);
+	}
+}
@@ -0,0 +1,353 @@
+//! Share codes: replays as short strings that can be copied to the clipboard and pasted
+//! elsewhere, to share the solution of a level without sending files.
+//!
+//! A replay code is `P7R1` followed by, in base64 (URL-safe, without padding):
+//! - the level file (its length in a byte, then the path as it is in the level select),
+//! - a hash of the text of the level file, so that a replay of another version of the level
+//!   is rejected instead of desyncing,
+//! - the hash of the state of the level at the end of the replay,
+//! - one byte per turn (the action and its direction), followed by the name of the tower or
+//!   item for the actions that have one (except basic towers).
+//!
+//! There is no seed: the game logic has no randomness. The hashes of the states after each
+//! turn (see `replay`) are not in the code, they are computed when it is decoded by playing
+//! the replay, and only the last one is checked.
+//!
+//! Levels are shared as their text, to be pasted in a chat message as is. The files that a
+//! level includes are put in the text, and pasted levels are saved in `levels/` as
+//! `shared_<hash>` (the same level pasted twice is the same file).
+
+use crate::coords::*;
+use crate::*;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+const REPLAY_PREFIX: &str = "P7R1";
+/// The longest code that can be pasted in the window.
+pub const MAX_CODE_LENGTH: usize = 4000;
+
+/// The actions, in the high bits of the byte of a turn (`tower` is a basic tower, the most
+/// common, the other towers are a `tower_of` followed by the name of the tower).
+const ACTIONS: [&str; 12] = [
+	"skip", "move", "tower", "wall", "attack", "pickup", "merge", "use", "tower_of", "switch",
+	"craft", "reload",
+];
+/// The directions, in the 3 low bits of the byte of a turn (0 for none).
+const DIRECTIONS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+/// The hash of the text of a level file, stable across platforms.
+fn level_text_hash(text: &str) -> u64 {
+	use std::hash::Hasher;
+	let mut hasher = replay::StableHasher::new();
+	hasher.write(text.as_bytes());
+	hasher.finish()
+}
+
+fn push_name(bytes: &mut Vec<u8>, name: &str) {
+	bytes.push(name.len() as u8);
+	bytes.extend_from_slice(name.as_bytes());
+}
+
+/// Reads the bytes of a code, with an error for codes cut short.
+struct Reader<'a> {
+	bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+	fn take(&mut self, count: usize) -> Result<&[u8], String> {
+		if self.bytes.len() < count {
+			return Err(tr("share.truncated"));
+		}
+		let (taken, rest) = self.bytes.split_at(count);
+		self.bytes = rest;
+		Ok(taken)
+	}
+
+	fn byte(&mut self) -> Result<u8, String> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn u64(&mut self) -> Result<u64, String> {
+		Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+	}
+
+	fn name(&mut self) -> Result<String, String> {
+		let length = self.byte()? as usize;
+		String::from_utf8(self.take(length)?.to_vec()).map_err(|_| tr("share.invalid"))
+	}
+}
+
+fn encode_turn(bytes: &mut Vec<u8>, dd: DxDy, action: &PlayerAction) {
+	let (kind, name) = match action {
+		PlayerAction::SkipTurn => ("skip", None),
+		PlayerAction::SwitchUnit => ("switch", None),
+		PlayerAction::Move => ("move", None),
+		PlayerAction::PlaceTower { variant: Tower::Basic } => ("tower", None),
+		PlayerAction::PlaceTower { variant } => ("tower_of", Some(variant.name())),
+		PlayerAction::PlaceWall => ("wall", None),
+		PlayerAction::Attack => ("attack", None),
+		PlayerAction::PickUpTower => ("pickup", None),
+		PlayerAction::MergeTower => ("merge", None),
+		PlayerAction::Reload => ("reload", None),
+		PlayerAction::UseItem { item } => ("use", Some(item.name())),
+		PlayerAction::Craft { recipe } => ("craft", Some(recipe.name())),
+	};
+	let kind = ACTIONS.iter().position(|&name| name == kind).unwrap() as u8;
+	let direction = DIRECTIONS
+		.iter()
+		.position(|&(dx, dy)| dd.dx == dx && dd.dy == dy)
+		.map_or(0, |index| index as u8 + 1);
+	bytes.push(kind << 3 | direction);
+	if let Some(name) = name {
+		push_name(bytes, name);
+	}
+}
+
+fn decode_turn(reader: &mut Reader) -> Result<(DxDy, PlayerAction), String> {
+	let byte = reader.byte()?;
+	let dd: DxDy = match byte & 0b111 {
+		0 => (0, 0).into(),
+		direction => (*DIRECTIONS
+			.get(direction as usize - 1)
+			.ok_or(tr("share.invalid"))?)
+		.into(),
+	};
+	let kind = ACTIONS
+		.get((byte >> 3) as usize)
+		.ok_or(tr("share.invalid"))?;
+	let action = match *kind {
+		"skip" => PlayerAction::SkipTurn,
+		"move" => PlayerAction::Move,
+		"tower" => PlayerAction::PlaceTower { variant: Tower::Basic },
+		"tower_of" => PlayerAction::PlaceTower {
+			variant: Tower::from_name(&reader.name()?).ok_or(tr("share.invalid"))?,
+		},
+		"wall" => PlayerAction::PlaceWall,
+		"attack" => PlayerAction::Attack,
+		"pickup" => PlayerAction::PickUpTower,
+		"merge" => PlayerAction::MergeTower,
+		"reload" => PlayerAction::Reload,
+		"switch" => PlayerAction::SwitchUnit,
+		"craft" => PlayerAction::Craft {
+			recipe: Recipe::from_name(&reader.name()?).ok_or(tr("share.invalid"))?,
+		},
+		_ => PlayerAction::UseItem {
+			item: Item::from_name(&reader.name()?).ok_or(tr("share.invalid"))?,
+		},
+	};
+	Ok((dd, action))
+}
+
+/// The code of a replay, which needs the level file to hash it.
+pub fn replay_code(replay: &replay::Replay) -> Result<String, String> {
+	let level_text = fs::read_to_string(&replay.level_file)
+		.map_err(|error| format!("{}: {error}", replay.level_file))?;
+	let level_key = high_scores::level_key(&replay.level_file);
+	let mut bytes = vec![];
+	push_name(&mut bytes, &level_key);
+	bytes.extend_from_slice(&level_text_hash(&level_text).to_le_bytes());
+	let final_hash = replay.turns.last().map_or(0, |turn| turn.state_hash);
+	bytes.extend_from_slice(&final_hash.to_le_bytes());
+	for turn in &replay.turns {
+		encode_turn(&mut bytes, turn.dd, &turn.action);
+	}
+	Ok(format!("{REPLAY_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes)))
+}
+
+/// The replay of a code, played to check that it ends as it did when the code was made (the
+/// level it refers to has to be one of `level_files`, unchanged).
+pub fn replay_from_code(
+	code: &str,
+	level_files: &[(String, String)],
+) -> Result<replay::Replay, String> {
+	let encoded = code
+		.trim()
+		.strip_prefix(REPLAY_PREFIX)
+		.ok_or(tr("share.not_a_replay"))?;
+	let bytes = URL_SAFE_NO_PAD
+		.decode(encoded)
+		.map_err(|_| tr("share.invalid"))?;
+	let mut reader = Reader { bytes: &bytes };
+	let level_key = reader.name()?;
+	let level_hash = reader.u64()?;
+	let final_hash = reader.u64()?;
+	let unknown_level = || tr_with("share.unknown_level", &[("level", &level_key)]);
+	// The key comes from whoever made the code, it must not lead outside of the levels.
+	let key_path = std::path::Path::new(&level_key);
+	if key_path.is_absolute()
+		|| key_path
+			.components()
+			.any(|component| component == std::path::Component::ParentDir)
+	{
+		return Err(unknown_level());
+	}
+	let level_file = level_files
+		.iter()
+		.map(|(_, path)| path)
+		.find(|path| high_scores::level_key(path) == level_key)
+		.ok_or_else(unknown_level)?;
+	let level_text = fs::read_to_string(level_file).map_err(|_| unknown_level())?;
+	if level_text_hash(&level_text) != level_hash {
+		return Err(tr_with("share.level_changed", &[("level", &level_key)]));
+	}
+	let level_data = parse_level_in(&level_text, level_dir(level_file))?;
+	let mut level = LevelState::new(&level_data);
+	let mut replay = replay::Replay::new(level_file);
+	while !reader.bytes.is_empty() {
+		let (dd, action) = decode_turn(&mut reader)?;
+		replay.play_and_record(&mut level, dd, action);
+	}
+	if replay.turns.last().map_or(0, |turn| turn.state_hash) != final_hash {
+		return Err(tr_with("share.desync", &[("level", &level_key)]));
+	}
+	Ok(replay)
+}
+
+/// The text of a level file to share it, with the files it includes in it. Levels with a
+/// script or prefabs can't be shared this way.
+pub fn level_text_for_sharing(level_file: &str) -> Result<String, String> {
+	let text = inline_includes(std::path::Path::new(level_file), 0)?;
+	let name = high_scores::level_key(level_file);
+	Ok(format!("~ {name}\n{}", text.trim_end()))
+}
+
+fn inline_includes(file: &std::path::Path, depth: u32) -> Result<String, String> {
+	if depth > MetadataContext::MAX_INCLUDE_DEPTH {
+		return Err(tr("error.include_depth"));
+	}
+	let text = fs::read_to_string(file).map_err(|error| {
+		tr_with(
+			"error.file_unreadable",
+			&[("file", &file.display()), ("error", &error)],
+		)
+	})?;
+	let dir = file.parent().unwrap_or(std::path::Path::new(""));
+	let mut inlined = String::new();
+	for line in text.lines() {
+		let mut words = line.split_whitespace();
+		match (words.next(), words.next()) {
+			(Some("@include"), Some(included)) => {
+				inlined += inline_includes(&dir.join(included), depth + 1)?.trim_end();
+			},
+			(Some("@script" | "@prefab"), Some(other_file)) => {
+				return Err(tr_with("share.level_needs_file", &[("file", &other_file)]));
+			},
+			_ => inlined += line,
+		}
+		inlined.push('\n');
+	}
+	Ok(inlined)
+}
+
+/// Save a pasted level in `levels/` (after checking it), returns its file.
+pub fn save_pasted_level(text: &str) -> Result<String, String> {
+	let text = text.trim().replace("\r\n", "\n") + "\n";
+	parse_level_in(&text, std::path::Path::new("levels"))?;
+	let level_file = format!("./levels/shared_{:08x}", level_text_hash(&text) as u32);
+	fs::write(&level_file, &text).map_err(|error| format!("{level_file}: {error}"))?;
+	Ok(level_file)
+}
+
+/// Put a text in the clipboard of the system.
+pub fn copy_to_clipboard(text: &str) -> Result<(), String> {
+	arboard::Clipboard::new()
+		.and_then(|mut clipboard| clipboard.set_text(text))
+		.map_err(|error| error.to_string())
+}
+
+/// The text in the clipboard of the system.
+pub fn paste_from_clipboard() -> Result<String, String> {
+	arboard::Clipboard::new()
+		.and_then(|mut clipboard| clipboard.get_text())
+		.map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn replays_survive_their_codes() {
+		let level_data = load_level("levels/level_ZA.pr7").unwrap();
+		let mut level = LevelState::new(&level_data);
+		let mut replay = replay::Replay::new("./levels/level_ZA.pr7");
+		let mut rng = rng::Rng::with_seed(3);
+		for _ in 0..40 {
+			let (dd, action) = bot::choose_action(&level, &mut rng);
+			replay.play_and_record(&mut level, dd, action);
+		}
+		replay.play_and_record(
+			&mut level,
+			(1, 0).into(),
+			PlayerAction::PlaceTower { variant: Tower::Sniper },
+		);
+		let code = replay_code(&replay).unwrap();
+		assert!(code.len() < 120, "{code}");
+
+		let level_files = [(
+			"level_ZA.pr7".to_string(),
+			"./levels/level_ZA.pr7".to_string(),
+		)];
+		let decoded = replay_from_code(&code, &level_files).unwrap();
+		assert_eq!(decoded.level_file, "./levels/level_ZA.pr7");
+		assert_eq!(decoded.to_text(), replay.to_text());
+		assert!(replay_from_code(&code, &[]).is_err());
+
+		let mut bytes = URL_SAFE_NO_PAD
+			.decode(&code[REPLAY_PREFIX.len()..])
+			.unwrap();
+		bytes.pop();
+		let truncated = format!("{REPLAY_PREFIX}{}", URL_SAFE_NO_PAD.encode(&bytes));
+		assert!(replay_from_code(&truncated, &level_files).is_err());
+		assert!(replay_from_code("hello", &level_files).is_err());
+	}
+
+	#[test]
+	fn codes_only_refer_to_known_levels() {
+		let level_text = fs::read_to_string("levels/level_ZA.pr7").unwrap();
+		for level_key in [
+			"../levels/level_ZA.pr7",
+			"/etc/passwd",
+			"levels/../levels/level_ZA.pr7",
+		] {
+			let mut bytes = vec![];
+			push_name(&mut bytes, level_key);
+			bytes.extend_from_slice(&level_text_hash(&level_text).to_le_bytes());
+			bytes.extend_from_slice(&0u64.to_le_bytes());
+			let code = format!("{REPLAY_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes));
+			let level_files = [(level_key.to_string(), level_key.to_string())];
+			assert_eq!(
+				replay_from_code(&code, &level_files).err(),
+				Some(tr_with("share.unknown_level", &[("level", &level_key)]))
+			);
+		}
+	}
+
+	#[test]
+	fn shared_levels_have_their_includes() {
+		let dir = std::env::temp_dir().join("prototype-07-share-test");
+		fs::create_dir_all(dir.join("waves")).unwrap();
+		fs::write(
+			dir.join("base"),
+			"Op ?a |g\n@tile a |-\n@include waves/first",
+		)
+		.unwrap();
+		fs::write(dir.join("waves/first"), "@event spawn basic a 2\n").unwrap();
+		fs::write(
+			dir.join("variant"),
+			"@include base\n@transform rot90\n@lives 3",
+		)
+		.unwrap();
+		let variant_file = dir.join("variant").display().to_string();
+		let text = level_text_for_sharing(&variant_file).unwrap();
+		assert!(!text.contains("@include"), "{text}");
+		let shared = parse_level(&text).unwrap();
+		let original = load_level(&variant_file).unwrap();
+		assert!(shared.init_grid.dims == original.init_grid.dims);
+		assert!(shared.named_tiles["a"] == original.named_tiles["a"]);
+		assert_eq!(shared.init_events.len(), 1);
+		assert_eq!(shared.lives, Some(3));
+		assert!(level_text_for_sharing("levels/test_script").is_err());
+	}
+}
@@ -0,0 +1,99 @@
+//! Batch simulation of many headless games of a level played by the bot,
+//! to help balancing levels (enemy hp, tower counts, etc.).
+
+use crate::rng::Rng;
+use crate::*;
+
+use std::io::Write;
+
+/// Games that last longer than that are considered lost.
+pub const MAX_TURNS: u32 = 1000;
+
+const TOWERS: [Tower; 10] = [
+	Tower::Basic,
+	Tower::Piercing,
+	Tower::TotalEnergy,
+	Tower::Unabomber,
+	Tower::Pusher,
+	Tower::Sniper,
+	Tower::Miner,
+	Tower::Magnet,
+	Tower::Cannon,
+	Tower::Flamethrower,
+];
+
+struct GameResult {
+	won: bool,
+	turns: u32,
+	/// Lives left at the end of the game (0 for levels without lives).
+	lives: u32,
+	stats: Stats,
+}
+
+fn simulate_game(level_data: &LevelData, seed: u64) -> GameResult {
+	let mut level = LevelState::new(level_data);
+	let mut rng = Rng::with_seed(seed);
+	while !level.game_joever && !level.is_won() && level.turn < MAX_TURNS {
+		let (dd, action) = bot::choose_action(&level, &mut rng);
+		play_turn(&mut level, dd, action);
+	}
+	GameResult {
+		won: level.is_won(),
+		turns: level.turn,
+		lives: level.lives.unwrap_or(0),
+		stats: level.stats,
+	}
+}
+
+/// The header line of the CSV report written by `run`.
+pub fn write_header(mut output: impl Write) -> std::io::Result<()> {
+	write!(output, "level,games,win_rate,average_turns,average_lives")?;
+	for tower in TOWERS.iter() {
+		write!(output, ",kills_{}", tower.name())?;
+	}
+	writeln!(output, ",kills_bomb,kills_player")
+}
+
+/// Simulate `games` games of the level (with consecutive seeds starting at `first_seed`)
+/// and write a line of CSV report (see `write_header`)
+/// with the win rate, the average number of turns, the average number of lives left
+/// and the average kill counts per game.
+pub fn run(
+	level_name: &str,
+	level_data: &LevelData,
+	games: u32,
+	first_seed: u64,
+	mut output: impl Write,
+) -> std::io::Result<()> {
+	let results: Vec<GameResult> = (0..games as u64)
+		.map(|index| simulate_game(level_data, first_seed + index))
+		.collect();
+	let games = games.max(1) as f64;
+	let wins = results.iter().filter(|result| result.won).count();
+	let turns: u32 = results.iter().map(|result| result.turns).sum();
+	let lives: u32 = results.iter().map(|result| result.lives).sum();
+
+	write!(
+		output,
+		"{level_name},{},{:.3},{:.2},{:.2}",
+		results.len(),
+		wins as f64 / games,
+		turns as f64 / games,
+		lives as f64 / games
+	)?;
+	for tower in TOWERS.iter() {
+		let kills: u32 = results
+			.iter()
+			.map(|result| result.stats.tower_kills.get(tower).copied().unwrap_or(0))
+			.sum();
+		write!(output, ",{:.2}", kills as f64 / games)?;
+	}
+	let bomb_kills: u32 = results.iter().map(|result| result.stats.bomb_kills).sum();
+	let player_kills: u32 = results.iter().map(|result| result.stats.player_kills).sum();
+	writeln!(
+		output,
+		",{:.2},{:.2}",
+		bomb_kills as f64 / games,
+		player_kills as f64 / games
+	)
+}
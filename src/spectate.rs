@@ -0,0 +1,140 @@
+//! Spectating a replay (`--replay`, `--watch` or the watch screen): the turns are played back
+//! on a timer, and can be paused (Space), played one at a time forward (`.`) and backward (`,`)
+//! and sped up or slowed down (`+`/`-`), while the last turns are shown in a log.
+//!
+//! Going back a turn restores the last snapshot before it and plays the turns after the
+//! snapshot again, which gives the same states as the first time since the game logic is
+//! deterministic.
+
+use crate::coords::*;
+use crate::*;
+
+/// A snapshot of the level is taken every that many turns.
+const SNAPSHOT_PERIOD: usize = 10;
+/// How many turns are shown in the log.
+pub const LOG_LINES: usize = 6;
+
+pub struct Spectator {
+	pub replay: replay::Replay,
+	/// The index of the next turn to play back.
+	pub next_turn: usize,
+	pub paused: bool,
+	/// The level before the turns `0`, `SNAPSHOT_PERIOD`, `2 * SNAPSHOT_PERIOD`, etc.
+	snapshots: Vec<LevelState>,
+	/// A line per turn played back.
+	pub log: Vec<String>,
+}
+
+impl Spectator {
+	pub fn new(replay: replay::Replay) -> Spectator {
+		Spectator {
+			replay,
+			next_turn: 0,
+			paused: false,
+			snapshots: vec![],
+			log: vec![],
+		}
+	}
+
+	/// Play back the next turn, returns it (`None` at the end of the replay). A desync is
+	/// an error.
+	pub fn step_forward(
+		&mut self,
+		level: &mut LevelState,
+	) -> Result<Option<(DxDy, PlayerAction)>, String> {
+		let Some(turn) = self.replay.turns.get(self.next_turn) else {
+			return Ok(None);
+		};
+		if self.next_turn.is_multiple_of(SNAPSHOT_PERIOD)
+			&& self.snapshots.len() == self.next_turn / SNAPSHOT_PERIOD
+		{
+			self.snapshots.push(level.clone());
+		}
+		let turn_number = level.turn;
+		catch_panic(|| replay::play_back_turn(level, turn)).and_then(|result| result)?;
+		self
+			.log
+			.push(log_line(level, turn_number, turn.dd, &turn.action));
+		self.next_turn += 1;
+		Ok(Some((turn.dd, turn.action.clone())))
+	}
+
+	/// Go back to before the last turn played back, `false` if there is none.
+	pub fn step_back(&mut self, level: &mut LevelState) -> bool {
+		let Some(target) = self.next_turn.checked_sub(1) else {
+			return false;
+		};
+		let snapshot = target / SNAPSHOT_PERIOD;
+		*level = self.snapshots[snapshot].clone();
+		self.next_turn = snapshot * SNAPSHOT_PERIOD;
+		self.log.truncate(self.next_turn);
+		while self.next_turn < target {
+			// These turns were played back without error already.
+			let _ = self.step_forward(level);
+		}
+		true
+	}
+
+	/// To call when the level starts over.
+	pub fn restart(&mut self) {
+		self.next_turn = 0;
+		self.log.clear();
+	}
+}
+
+/// The line of the log for a turn (`turn` being its number before it was played).
+fn log_line(level: &LevelState, turn: u32, dd: DxDy, action: &PlayerAction) -> String {
+	let events = &level.stats.turn_events;
+	let count = |is_it: fn(&TurnEvent) -> bool| events.iter().filter(|&event| is_it(event)).count();
+	let mut line = tr_with(
+		"spectate.log_line",
+		&[
+			("turn", &turn),
+			("action", &protocol::action_line(dd, action)),
+		],
+	);
+	let kills = count(|event| matches!(event, TurnEvent::EnemyDied(_)));
+	if kills > 0 {
+		line += &tr_with("spectate.kills", &[("count", &kills)]);
+	}
+	if count(|event| matches!(event, TurnEvent::GoalDamaged(_))) > 0 {
+		line += &tr("spectate.goal_damaged");
+	}
+	line
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn stepping_back_gives_the_same_states() {
+		let level_data = load_level("levels/level_ZA.pr7").unwrap();
+		let mut level = LevelState::new(&level_data);
+		let mut replay = replay::Replay::new("levels/level_ZA.pr7");
+		let mut rng = rng::Rng::with_seed(5);
+		for _ in 0..25 {
+			let (dd, action) = bot::choose_action(&level, &mut rng);
+			replay.play_and_record(&mut level, dd, action);
+		}
+		let hashes: Vec<u64> = replay.turns.iter().map(|turn| turn.state_hash).collect();
+
+		let mut level = LevelState::new(&level_data);
+		let mut spectator = Spectator::new(replay);
+		assert!(!spectator.step_back(&mut level));
+		while spectator.step_forward(&mut level).unwrap().is_some() {}
+		assert_eq!(spectator.next_turn, 25);
+		assert_eq!(spectator.log.len(), 25);
+		for turn in (0..25).rev() {
+			assert!(spectator.step_back(&mut level));
+			assert_eq!(spectator.next_turn, turn);
+			assert_eq!(spectator.log.len(), turn);
+			if turn > 0 {
+				assert_eq!(level.state_hash(), hashes[turn - 1]);
+			}
+		}
+		assert!(level.state_hash() == LevelState::new(&level_data).state_hash());
+		assert!(spectator.step_forward(&mut level).unwrap().is_some());
+		assert_eq!(level.state_hash(), hashes[0]);
+	}
+}
@@ -0,0 +1,285 @@
+//! The spritesheet image and its atlas (`assets/sprites.toml`) that maps sprite names
+//! (like `"enemy.tank"` or `"ui.joever"`) to where the sprites are in the image,
+//! so that the image can be reorganized without touching the code.
+
+use crate::coords::*;
+
+use std::collections::HashMap;
+
+pub struct Spritesheet {
+	/// The RGBA pixels of the image, row by row. The image is decoded once at load time,
+	/// so that drawing (which reads a pixel per pixel drawn) only has to index them.
+	pixels: Vec<[u8; 4]>,
+	pub dims: Dimensions,
+	sprites: HashMap<String, Rect>,
+}
+
+impl Spritesheet {
+	/// The spritesheet and atlas embedded in the executable.
+	pub fn load() -> Spritesheet {
+		match Spritesheet::decode(
+			include_bytes!("../assets/spritesheet.png"),
+			include_str!("../assets/sprites.toml"),
+		) {
+			Ok(spritesheet) => spritesheet,
+			Err(error) => panic!("invalid embedded spritesheet: {error}"),
+		}
+	}
+
+	/// A spritesheet from files (like the ones of a mod), with the embedded atlas if there is
+	/// no atlas file.
+	pub fn load_files(
+		image_path: &std::path::Path,
+		atlas_path: Option<&std::path::Path>,
+	) -> Result<Spritesheet, String> {
+		let image =
+			std::fs::read(image_path).map_err(|error| format!("{}: {error}", image_path.display()))?;
+		let atlas = match atlas_path {
+			Some(atlas_path) => std::fs::read_to_string(atlas_path)
+				.map_err(|error| format!("{}: {error}", atlas_path.display()))?,
+			None => include_str!("../assets/sprites.toml").to_string(),
+		};
+		let spritesheet = Spritesheet::decode(&image, &atlas)?;
+		// Sprites outside of the image would make drawing them panic.
+		if let Some((name, _)) = spritesheet.sprites.iter().find(|(_, rect)| {
+			rect.top_left.x + rect.dims.w > spritesheet.dims.w
+				|| rect.top_left.y + rect.dims.h > spritesheet.dims.h
+		}) {
+			return Err(format!(
+				"{}: the sprite {name} is outside of the image",
+				image_path.display()
+			));
+		}
+		Ok(spritesheet)
+	}
+
+	fn decode(image: &[u8], atlas: &str) -> Result<Spritesheet, String> {
+		let image = image::load_from_memory(image).map_err(|error| error.to_string())?;
+		let sprites = parse_atlas(atlas).map_err(|error| format!("invalid sprite atlas: {error}"))?;
+		let image = image.to_rgba8();
+		let dims = Dimensions { w: image.width() as i32, h: image.height() as i32 };
+		let pixels = image.pixels().map(|pixel| pixel.0).collect();
+		Ok(Spritesheet { pixels, dims, sprites })
+	}
+
+	/// The color of a pixel of the image.
+	pub fn pixel(&self, coords: Coords) -> [u8; 4] {
+		self.pixels[(coords.y * self.dims.w + coords.x) as usize]
+	}
+
+	/// Panics if the atlas has no sprite with that name.
+	pub fn sprite(&self, name: &str) -> Rect {
+		match self.sprites.get(name) {
+			Some(rect) => *rect,
+			None => panic!("no sprite named \"{name}\" in the atlas"),
+		}
+	}
+
+	/// The RGBA bytes of a sprite scaled up `scale` times (for the window icon), and their size.
+	pub fn sprite_pixels(&self, name: &str, scale: i32) -> (Vec<u8>, Dimensions) {
+		let rect = self.sprite(name);
+		let dims = Dimensions { w: rect.dims.w * scale, h: rect.dims.h * scale };
+		let mut bytes = Vec::with_capacity((dims.w * dims.h * 4) as usize);
+		for y in 0..dims.h {
+			for x in 0..dims.w {
+				bytes.extend(self.pixel(rect.top_left + DxDy::from((x / scale, y / scale))));
+			}
+		}
+		(bytes, dims)
+	}
+
+	/// For sprites that are a row of same-sized parts (like the autotile variants of a ground
+	/// or the glyphs of the font), the part at `index` which is `part_width` pixels wide.
+	pub fn sprite_part(&self, name: &str, index: i32, part_width: i32) -> Rect {
+		let row = self.sprite(name);
+		Rect {
+			top_left: row.top_left + DxDy::from((index * part_width, 0)),
+			dims: Dimensions { w: part_width, h: row.dims.h },
+		}
+	}
+}
+
+/// Tables are flattened into dotted names, and a sprite is either `[col, row]` (an 8x8 tile)
+/// or `[x, y, w, h]` (in pixels).
+fn parse_atlas(text: &str) -> Result<HashMap<String, Rect>, String> {
+	fn add_table(
+		sprites: &mut HashMap<String, Rect>,
+		prefix: &str,
+		table: &toml::Table,
+	) -> Result<(), String> {
+		for (key, value) in table {
+			let name = if prefix.is_empty() {
+				key.clone()
+			} else {
+				format!("{prefix}.{key}")
+			};
+			match value {
+				toml::Value::Table(table) => add_table(sprites, &name, table)?,
+				toml::Value::Array(array) => {
+					let numbers = array
+						.iter()
+						.map(|value| value.as_integer().map(|number| number as i32))
+						.collect::<Option<Vec<_>>>()
+						.ok_or(format!("sprite \"{name}\" is not a list of integers"))?;
+					let rect = match numbers[..] {
+						[col, row] => Rect::tile((col, row).into(), 8),
+						[x, y, w, h] => Rect { top_left: (x, y).into(), dims: Dimensions { w, h } },
+						_ => return Err(format!("sprite \"{name}\" should have 2 or 4 numbers")),
+					};
+					sprites.insert(name, rect);
+				},
+				_ => return Err(format!("sprite \"{name}\" is not a list of integers")),
+			}
+		}
+		Ok(())
+	}
+
+	let table: toml::Table = text
+		.parse()
+		.map_err(|error: toml::de::Error| error.to_string())?;
+	let mut sprites = HashMap::new();
+	add_table(&mut sprites, "", &table)?;
+	Ok(sprites)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::*;
+
+	fn xywh(rect: Rect) -> (i32, i32, i32, i32) {
+		(rect.left(), rect.top(), rect.dims.w, rect.dims.h)
+	}
+
+	#[test]
+	fn atlas_tables_are_flattened_into_dotted_names() {
+		let sprites = parse_atlas("[enemy]\ntank = [2, 3]\n[ui]\njoever = [0, 8, 56, 8]").unwrap();
+		assert_eq!(xywh(sprites["enemy.tank"]), (16, 24, 8, 8));
+		assert_eq!(xywh(sprites["ui.joever"]), (0, 8, 56, 8));
+		assert!(parse_atlas("[enemy]\ntank = [2, 3, 4]").is_err());
+	}
+
+	#[test]
+	fn sprites_named_by_the_game_are_in_the_atlas() {
+		let spritesheet = Spritesheet::load();
+		let protections = [
+			Protection::Sides,
+			Protection::FullStack,
+			Protection::UniqueFront,
+			Protection::UniqueBack,
+			Protection::ThreeFront,
+			Protection::ThreeBack,
+		];
+		for protection in protections {
+			for direction in Direction::ALL {
+				let enemy = Enemy::Protected { direction, protection };
+				spritesheet.sprite(&enemy.sprite_name());
+			}
+		}
+		for item in Item::ALL {
+			spritesheet.sprite(&item.sprite_name());
+		}
+		for priority in TargetPriority::ALL {
+			spritesheet.sprite(priority.sprite_name());
+		}
+		for character in FONT_SYMBOLS.chars().chain('A'..='Z').chain('0'..='9') {
+			assert!(glyph_sprite(&spritesheet, character).is_some());
+		}
+	}
+
+	#[test]
+	fn colorblind_markers_are_distinct_glyphs_of_the_font() {
+		let spritesheet = Spritesheet::load();
+		let enemies = [
+			"basic",
+			"tank",
+			"protected_sides",
+			"speeeeed",
+			"stun",
+			"eat",
+			"broodmother",
+			"necromancer",
+			"burrower",
+			"paver",
+			"shielded",
+			"boss",
+		]
+		.map(|name| Enemy::from_name(name).unwrap().marker());
+		let towers = [
+			"basic",
+			"piercing",
+			"total_energy",
+			"unabomber",
+			"pusher",
+			"sniper",
+			"miner",
+			"magnet",
+			"cannon",
+			"flamethrower",
+		]
+		.map(|name| Tower::from_name(name).unwrap().marker());
+		for markers in [&enemies[..], &towers[..]] {
+			for (index, marker) in markers.iter().enumerate() {
+				assert!(glyph_sprite(&spritesheet, *marker).is_some());
+				assert!(
+					!markers[..index].contains(marker),
+					"marker {marker} is used twice"
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn every_sprite_of_the_atlas_is_in_the_image() {
+		let spritesheet = Spritesheet::load();
+		let image_rect = Rect { top_left: (0, 0).into(), dims: spritesheet.dims };
+		for (name, rect) in &spritesheet.sprites {
+			assert!(
+				image_rect.contains(rect.top_left)
+					&& rect.right_excluded() <= image_rect.right_excluded()
+					&& rect.bottom_excluded() <= image_rect.bottom_excluded(),
+				"sprite \"{name}\" is out of the image"
+			);
+		}
+	}
+
+	/// Checks that drawing a sprite at a large zoom level is faster through `Spritesheet::pixel`
+	/// than by reading the decoded image through `DynamicImage::get_pixel` (as it used to be).
+	/// Run with `cargo test --release sprite_reading_benchmark -- --ignored`.
+	#[test]
+	#[ignore = "benchmark"]
+	fn sprite_reading_benchmark() {
+		use image::GenericImageView;
+
+		let image = image::load_from_memory(include_bytes!("../assets/spritesheet.png")).unwrap();
+		let spritesheet = Spritesheet::load();
+		let sprite = spritesheet.sprite("enemy.tank");
+		// A whole 1920x1080 screen of sprites, each drawn 64 times bigger than in the image.
+		let dst_dims = Dimensions::square(8 * 64);
+		let sprites_per_frame = (1920 / dst_dims.w + 1) * (1080 / dst_dims.h + 1);
+		let frames = 10;
+		let mut frame = vec![0; (dst_dims.w * dst_dims.h) as usize * 4];
+		let mut draw = |read_pixel: &dyn Fn(Coords) -> [u8; 4]| {
+			let start = std::time::Instant::now();
+			for _ in 0..(frames * sprites_per_frame) {
+				for coords in dst_dims.iter() {
+					let coords_in_sprite = Coords {
+						x: sprite.left() + coords.x * sprite.dims.w / dst_dims.w,
+						y: sprite.top() + coords.y * sprite.dims.h / dst_dims.h,
+					};
+					let color = read_pixel(coords_in_sprite);
+					let index = (coords.y * dst_dims.w + coords.x) as usize * 4;
+					frame[index..(index + 4)].copy_from_slice(&color);
+				}
+			}
+			std::hint::black_box(&frame);
+			start.elapsed() / frames as u32
+		};
+		let with_get_pixel = draw(&|coords| image.get_pixel(coords.x as u32, coords.y as u32).0);
+		let with_decoded_pixels = draw(&|coords| spritesheet.pixel(coords));
+		assert!(
+			with_decoded_pixels < with_get_pixel,
+			"per frame: {with_get_pixel:?} with get_pixel, {with_decoded_pixels:?} decoded"
+		);
+	}
+}
@@ -0,0 +1,125 @@
+//! Visual themes of the levels (`@theme <name>`), they recolor the ground and the scenery
+//! (trees, rocks and the leaves of the flowers) by swapping the colors of the spritesheet for
+//! others while the sprites are drawn, so that levels can look different without new sprites.
+
+/// Pairs of a color of the spritesheet and the color it is replaced with.
+type Palette = &'static [([u8; 3], [u8; 3])];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Theme {
+	/// The colors of the spritesheet.
+	#[default]
+	Meadow,
+	Desert,
+	Snow,
+	Swamp,
+}
+
+// The colors of the spritesheet that themes replace.
+const GRASS: [u8; 3] = [79, 199, 46];
+const GRASS_LIGHT: [u8; 3] = [105, 207, 76];
+const WATER: [u8; 3] = [41, 157, 230];
+const WATER_FOAM: [u8; 3] = [216, 240, 255];
+const WATER_SHORE: [u8; 3] = [112, 168, 224];
+const WATER_LIGHT: [u8; 3] = [126, 190, 230];
+const PATH: [u8; 3] = [199, 143, 46];
+const PATH_DARK: [u8; 3] = [184, 130, 44];
+const PATH_EDGE: [u8; 3] = [140, 112, 72];
+const PATH_EDGE_LIGHT: [u8; 3] = [168, 140, 96];
+const LEAVES: [u8; 3] = [57, 139, 54];
+const LEAVES_DARK: [u8; 3] = [46, 110, 43];
+
+const DESERT: Palette = &[
+	(GRASS, [222, 196, 120]),
+	(GRASS_LIGHT, [235, 212, 140]),
+	(PATH, [190, 120, 70]),
+	(PATH_DARK, [175, 110, 62]),
+	(PATH_EDGE, [130, 80, 50]),
+	(PATH_EDGE_LIGHT, [160, 100, 64]),
+	(LEAVES, [110, 150, 60]),
+	(LEAVES_DARK, [90, 125, 45]),
+];
+
+const SNOW: Palette = &[
+	(GRASS, [235, 240, 250]),
+	(GRASS_LIGHT, [250, 252, 255]),
+	(WATER, [150, 200, 230]),
+	(WATER_FOAM, [255, 255, 255]),
+	(WATER_SHORE, [200, 220, 240]),
+	(WATER_LIGHT, [180, 215, 240]),
+	(PATH, [165, 160, 165]),
+	(PATH_DARK, [150, 145, 150]),
+	(PATH_EDGE, [120, 115, 125]),
+	(PATH_EDGE_LIGHT, [190, 190, 200]),
+	(LEAVES, [40, 90, 70]),
+	(LEAVES_DARK, [30, 70, 55]),
+];
+
+const SWAMP: Palette = &[
+	(GRASS, [80, 110, 50]),
+	(GRASS_LIGHT, [95, 125, 60]),
+	(WATER, [60, 100, 70]),
+	(WATER_FOAM, [150, 170, 120]),
+	(WATER_SHORE, [80, 120, 80]),
+	(WATER_LIGHT, [90, 130, 90]),
+	(PATH, [110, 90, 50]),
+	(PATH_DARK, [100, 80, 45]),
+	(PATH_EDGE, [70, 55, 35]),
+	(PATH_EDGE_LIGHT, [90, 72, 45]),
+	(LEAVES, [60, 90, 40]),
+	(LEAVES_DARK, [45, 70, 30]),
+];
+
+impl Theme {
+	pub fn from_name(name: &str) -> Option<Theme> {
+		match name {
+			"meadow" => Some(Theme::Meadow),
+			"desert" => Some(Theme::Desert),
+			"snow" => Some(Theme::Snow),
+			"swamp" => Some(Theme::Swamp),
+			_ => None,
+		}
+	}
+
+	fn palette(self) -> Palette {
+		match self {
+			Theme::Meadow => &[],
+			Theme::Desert => DESERT,
+			Theme::Snow => SNOW,
+			Theme::Swamp => SWAMP,
+		}
+	}
+
+	/// The color to draw instead of a color of the spritesheet.
+	pub fn recolor(self, color: [u8; 4]) -> [u8; 4] {
+		let [r, g, b, a] = color;
+		match self.palette().iter().find(|(from, _)| *from == [r, g, b]) {
+			Some((_, [r, g, b])) => [*r, *g, *b, a],
+			None => color,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn themes_only_recolor_the_colors_of_their_palette() {
+		assert_eq!(
+			Theme::Meadow.recolor([79, 199, 46, 255]),
+			[79, 199, 46, 255]
+		);
+		assert_eq!(
+			Theme::Snow.recolor([79, 199, 46, 255]),
+			[235, 240, 250, 255]
+		);
+		assert_eq!(Theme::Snow.recolor([1, 2, 3, 255]), [1, 2, 3, 255]);
+		for theme in [Theme::Desert, Theme::Snow, Theme::Swamp] {
+			let palette = theme.palette();
+			for (index, (from, _)) in palette.iter().enumerate() {
+				assert!(!palette[..index].iter().any(|(other, _)| other == from));
+			}
+		}
+	}
+}
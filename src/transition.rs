@@ -0,0 +1,71 @@
+//! Fades through black when the game switches to another state (another level, the game
+//! over, a menu, etc.), so that the switch doesn't happen abruptly. The switch itself is
+//! instantaneous: the last frame drawn before it is kept and fades out, and then the new
+//! state fades in.
+
+use crate::coords::*;
+use crate::*;
+
+use std::time::{Duration, Instant};
+
+pub struct Transition {
+	/// The frame that was on screen before the switch.
+	previous_frame: Vec<u8>,
+	start: Instant,
+	duration: Duration,
+}
+
+impl Transition {
+	/// Start a transition from what is currently in the pixel buffer.
+	pub fn start(pixel_buffer: &pixels::Pixels, duration: Duration, now: Instant) -> Transition {
+		Transition {
+			previous_frame: pixel_buffer.frame().to_vec(),
+			start: now,
+			duration,
+		}
+	}
+
+	pub fn is_over(&self, now: Instant) -> bool {
+		now >= self.start + self.duration
+	}
+
+	/// Draw over the frame of the new state: during the first half the previous frame fades
+	/// out, during the second half the new frame fades in.
+	pub fn draw(
+		&self,
+		pixel_buffer: &mut pixels::Pixels,
+		pixel_buffer_dims: Dimensions,
+		now: Instant,
+	) {
+		let progress = ((now - self.start).as_secs_f32() / self.duration.as_secs_f32()).min(1.0);
+		let fading_out = progress < 0.5;
+		// If the window was resized by the switch, the previous frame doesn't fit anymore
+		// and the new state only fades in.
+		if fading_out && self.previous_frame.len() == pixel_buffer.frame().len() {
+			pixel_buffer
+				.frame_mut()
+				.copy_from_slice(&self.previous_frame);
+		}
+		let darkness = fade_darkness(progress);
+		let screen = Rect { top_left: (0, 0).into(), dims: pixel_buffer_dims };
+		draw_rect(pixel_buffer, pixel_buffer_dims, screen, [0, 0, 0, darkness]);
+	}
+}
+
+/// The alpha of the black drawn over the screen, fully black in the middle of the transition.
+fn fade_darkness(progress: f32) -> u8 {
+	(255.0 * (1.0 - (2.0 * progress - 1.0).abs())) as u8
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fades_are_black_in_the_middle_and_clear_at_both_ends() {
+		assert_eq!(fade_darkness(0.0), 0);
+		assert_eq!(fade_darkness(0.5), 255);
+		assert_eq!(fade_darkness(1.0), 0);
+		assert!(fade_darkness(0.25) > 100 && fade_darkness(0.75) > 100);
+	}
+}
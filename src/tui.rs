@@ -0,0 +1,326 @@
+//! Terminal frontend (`--tui`): the grid is drawn with colored Unicode characters and the
+//! keys are read from the terminal, so that the game can be played over SSH or without a GPU.
+//! It plays the same `LevelState` as the window, only the rendering and the input differ.
+//!
+//! Arrows move, and an action key followed by an arrow does the action in that direction
+//! (terminals don't reliably report Ctrl or Shift with the arrows): `t` tower, `n` cannon
+//! (facing that way), `w` wall, `a` attack, `x` pick up a tower, `m` merge towers, `r` reload a
+//! tower and `b` bomb. `v` uses a tower voucher, space skips the turn, Tab controls the next unit
+//! (in levels with several), Enter closes the tutorial hint and `q` (or Escape) quits.
+
+use crate::coords::*;
+use crate::*;
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::Write;
+
+/// What a key press does.
+#[derive(PartialEq, Eq)]
+enum Command {
+	Play(DxDy, PlayerAction),
+	/// The action will be done in the direction of the next arrow.
+	Prepare(PlayerAction),
+	/// Close the tutorial hint being shown.
+	DismissHint,
+	Quit,
+	Nothing,
+}
+
+fn key_command(key: KeyEvent, prepared: Option<&PlayerAction>) -> Command {
+	let direction = match key.code {
+		KeyCode::Up => Some((0, -1)),
+		KeyCode::Right => Some((1, 0)),
+		KeyCode::Down => Some((0, 1)),
+		KeyCode::Left => Some((-1, 0)),
+		_ => None,
+	};
+	if let Some(dd) = direction {
+		let action = prepared.cloned().unwrap_or(PlayerAction::Move);
+		return Command::Play(dd.into(), action);
+	}
+	match key.code {
+		KeyCode::Char('t') => Command::Prepare(PlayerAction::PlaceTower { variant: Tower::Basic }),
+		KeyCode::Char('n') => Command::Prepare(PlayerAction::PlaceTower { variant: Tower::Cannon }),
+		KeyCode::Char('w') => Command::Prepare(PlayerAction::PlaceWall),
+		KeyCode::Char('a') => Command::Prepare(PlayerAction::Attack),
+		KeyCode::Char('x') => Command::Prepare(PlayerAction::PickUpTower),
+		KeyCode::Char('m') => Command::Prepare(PlayerAction::MergeTower),
+		KeyCode::Char('r') => Command::Prepare(PlayerAction::Reload),
+		KeyCode::Char('b') => Command::Prepare(PlayerAction::UseItem { item: Item::Bomb }),
+		KeyCode::Char('v') => Command::Play(
+			(0, 0).into(),
+			PlayerAction::UseItem { item: Item::TowerVoucher },
+		),
+		KeyCode::Char(' ') | KeyCode::Char('.') => {
+			Command::Play((0, 0).into(), PlayerAction::SkipTurn)
+		},
+		KeyCode::Tab => Command::Play((0, 0).into(), PlayerAction::SwitchUnit),
+		KeyCode::Char('c') => {
+			Command::Play((0, 0).into(), PlayerAction::Craft { recipe: Recipe::Tower })
+		},
+		KeyCode::Char('o') => {
+			Command::Play((0, 0).into(), PlayerAction::Craft { recipe: Recipe::Bomb })
+		},
+		KeyCode::Enter => Command::DismissHint,
+		KeyCode::Char('q') | KeyCode::Esc => Command::Quit,
+		_ => Command::Nothing,
+	}
+}
+
+/// The character, its color and the background color of a cell.
+fn cell_glyph(level: &LevelState, coords: Coords) -> (char, Color, Color) {
+	let cell = level.grid.get(coords).unwrap();
+	let visible = level.is_visible(coords);
+	let (r, g, b) = match cell.groud {
+		Ground::Grass => (60, 140, 40),
+		Ground::Path(_) => (170, 120, 50),
+		Ground::Water => (40, 110, 200),
+		Ground::Lava => (200, 70, 20),
+	};
+	// The fog of war darkens the ground.
+	let background = if visible {
+		Color::Rgb { r, g, b }
+	} else {
+		Color::Rgb { r: r / 3, g: g / 3, b: b / 3 }
+	};
+	let white = Color::Rgb { r: 255, g: 255, b: 255 };
+	let [_, obj_char] = tile_string(cell);
+	let (character, color) = match &cell.obj {
+		Obj::Enemy { .. } if !visible => (' ', white),
+		Obj::Empty if cell.conveyor.is_some() => {
+			let character = match cell.conveyor.unwrap() {
+				Direction::North => '↑',
+				Direction::East => '→',
+				Direction::South => '↓',
+				Direction::West => '←',
+			};
+			(character, Color::Rgb { r: 200, g: 200, b: 210 })
+		},
+		Obj::Empty if cell.portal => ('Ω', Color::Rgb { r: 190, g: 120, b: 255 }),
+		Obj::Empty if cell.spikes => {
+			let character = if level.spikes_armed() { '▲' } else { '△' };
+			(character, Color::Rgb { r: 200, g: 200, b: 210 })
+		},
+		Obj::Empty => match (&cell.groud, cell.rocky_path) {
+			(Ground::Water, _) => ('≈', Color::Rgb { r: 150, g: 200, b: 255 }),
+			(Ground::Lava, _) => ('≈', Color::Rgb { r: 255, g: 200, b: 80 }),
+			(_, true) => ('∴', Color::Rgb { r: 90, g: 90, b: 90 }),
+			(Ground::Path(_), false) => ('·', Color::Rgb { r: 120, g: 80, b: 30 }),
+			(Ground::Grass, false) => (' ', white),
+		},
+		// With several units, the one controlled is in yellow and the ones done with the turn
+		// are grayed out.
+		Obj::Player { .. } if level.units_acted.contains(&coords) => {
+			('☺', Color::Rgb { r: 140, g: 140, b: 140 })
+		},
+		Obj::Player { .. }
+			if level.unit_coords().len() > 1 && level.player_coords() == Some(coords) =>
+		{
+			('☺', Color::Rgb { r: 255, g: 230, b: 0 })
+		},
+		Obj::Player { .. } => ('☺', white),
+		Obj::Goal { .. } => ('⌂', Color::Rgb { r: 200, g: 120, b: 255 }),
+		Obj::Enemy { .. } => (obj_char, Color::Rgb { r: 230, g: 30, b: 30 }),
+		Obj::Tower { .. } => (obj_char, Color::Rgb { r: 30, g: 230, b: 230 }),
+		Obj::Rock => ('●', Color::Rgb { r: 130, g: 125, b: 135 }),
+		Obj::Tree { chopped: false } => ('♣', Color::Rgb { r: 20, g: 80, b: 20 }),
+		Obj::Tree { chopped: true } => ('♣', Color::Rgb { r: 120, g: 110, b: 60 }),
+		Obj::Flower { .. } => ('✿', Color::Rgb { r: 120, g: 110, b: 255 }),
+		Obj::Mine => ('✱', Color::Rgb { r: 60, g: 60, b: 60 }),
+		Obj::Wall { .. } => ('█', Color::Rgb { r: 110, g: 100, b: 90 }),
+		Obj::Bomb { countdown } => (
+			char::from_digit(*countdown, 10).unwrap_or('b'),
+			Color::Rgb { r: 255, g: 60, b: 0 },
+		),
+		Obj::Item { item: Item::Heart } => ('♥', Color::Rgb { r: 255, g: 80, b: 120 }),
+		Obj::Item { .. } => (obj_char, Color::Rgb { r: 255, g: 220, b: 60 }),
+		Obj::Key { color } | Obj::Door { color } => {
+			let [r, g, b] = color.tint();
+			(obj_char, Color::Rgb { r, g, b })
+		},
+	};
+	(character, color, background)
+}
+
+fn status_line(level: &LevelState) -> String {
+	let mut parts = vec![tr_with("tui.turn", &[("turn", &level.turn)])];
+	if let Some(towers) = level.remaining_towers {
+		parts.push(tr_with("tui.towers", &[("count", &towers)]));
+	}
+	if let Some(walls) = level.remaining_walls {
+		parts.push(tr_with("tui.walls", &[("count", &walls)]));
+	}
+	if let Some(weather) = level.weather().description() {
+		parts.push(weather);
+	}
+	if let Some(lives) = level.lives {
+		parts.push(tr_with("tui.lives", &[("count", &lives)]));
+	}
+	let units = level.unit_coords().len();
+	if units > 1 {
+		let to_act = level.units_to_act().len();
+		parts.push(tr_with(
+			"tui.units",
+			&[("count", &to_act), ("units", &units)],
+		));
+	}
+	if let Some(turns_left) = level.turns_left() {
+		parts.push(tr_with("hud.turns_left", &[("count", &turns_left)]));
+	}
+	for item in Item::ALL {
+		let count = level.inventory.count(item);
+		if count > 0 {
+			parts.push(format!("{} {count}", tr(&format!("item.{}", item.name()))));
+		}
+	}
+	parts.extend(toasts(level));
+	for color in &level.inventory.keys {
+		let color = tr(&format!("key_color.{}", color.name()));
+		parts.push(tr_with("inspect.key", &[("color", &color)]));
+	}
+	parts.join("  ")
+}
+
+fn draw(
+	output: &mut impl Write,
+	level: &LevelState,
+	prepared: Option<&PlayerAction>,
+) -> std::io::Result<()> {
+	queue!(
+		output,
+		cursor::MoveTo(0, 0),
+		terminal::Clear(terminal::ClearType::All)
+	)?;
+	for y in 0..level.grid.dims.h {
+		queue!(output, cursor::MoveTo(0, y as u16))?;
+		for x in 0..level.grid.dims.w {
+			let (character, color, background) = cell_glyph(level, (x, y).into());
+			// Cells are two columns wide to look roughly square.
+			queue!(
+				output,
+				SetBackgroundColor(background),
+				SetForegroundColor(color),
+				Print(format!("{character} ")),
+			)?;
+		}
+		queue!(output, ResetColor)?;
+	}
+	let mut lines = vec![status_line(level)];
+	lines.extend(level.message.clone());
+	if let Some(hint) = level.shown_hints.front() {
+		lines.push(format!("{} {}", hint.text, tr("hint.dismiss")));
+	}
+	if let Some(action) = prepared {
+		let name = protocol::action_line((0, -1).into(), action);
+		let name = name
+			.split_whitespace()
+			.next()
+			.unwrap_or_default()
+			.to_string();
+		lines.push(tr_with("tui.direction", &[("action", &name)]));
+	} else if level.game_joever {
+		lines.push(tr("tui.game_over"));
+	} else {
+		lines.push(tr("tui.help"));
+	}
+	for (index, line) in lines.iter().enumerate() {
+		queue!(
+			output,
+			cursor::MoveTo(0, (level.grid.dims.h + 1 + index as i32) as u16),
+			Print(line),
+		)?;
+	}
+	output.flush()
+}
+
+/// Restores the terminal when dropped, even if the game panics.
+struct RawTerminal;
+
+impl RawTerminal {
+	fn enter() -> std::io::Result<RawTerminal> {
+		terminal::enable_raw_mode()?;
+		execute!(
+			std::io::stdout(),
+			terminal::EnterAlternateScreen,
+			cursor::Hide
+		)?;
+		Ok(RawTerminal)
+	}
+}
+
+impl Drop for RawTerminal {
+	fn drop(&mut self) {
+		let _ = execute!(
+			std::io::stdout(),
+			cursor::Show,
+			terminal::LeaveAlternateScreen
+		);
+		let _ = terminal::disable_raw_mode();
+	}
+}
+
+/// Play the level in the terminal until the game is over or the player quits.
+pub fn run(level: &mut LevelState) -> std::io::Result<()> {
+	let _raw_terminal = RawTerminal::enter()?;
+	let mut output = std::io::stdout().lock();
+	let mut prepared: Option<PlayerAction> = None;
+	loop {
+		draw(&mut output, level, prepared.as_ref())?;
+		let Event::Key(key) = crossterm::event::read()? else {
+			continue;
+		};
+		if key.kind == KeyEventKind::Release {
+			continue;
+		}
+		if level.game_joever {
+			return Ok(());
+		}
+		match key_command(key, prepared.as_ref()) {
+			Command::Play(dd, action) => {
+				prepared = None;
+				play_turn(level, dd, action);
+			},
+			Command::Prepare(action) => prepared = Some(action),
+			Command::DismissHint => hints::dismiss(level),
+			Command::Quit => return Ok(()),
+			Command::Nothing => prepared = None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crossterm::event::KeyModifiers;
+
+	fn press(code: KeyCode) -> KeyEvent {
+		KeyEvent::new(code, KeyModifiers::NONE)
+	}
+
+	#[test]
+	fn action_keys_apply_to_the_next_arrow() {
+		assert!(
+			key_command(press(KeyCode::Left), None)
+				== Command::Play((-1, 0).into(), PlayerAction::Move)
+		);
+		let Command::Prepare(action) = key_command(press(KeyCode::Char('w')), None) else {
+			panic!("w should prepare a wall");
+		};
+		assert!(
+			key_command(press(KeyCode::Up), Some(&action))
+				== Command::Play((0, -1).into(), PlayerAction::PlaceWall)
+		);
+		assert!(key_command(press(KeyCode::Char('q')), None) == Command::Quit);
+	}
+
+	#[test]
+	fn enemies_in_the_fog_are_not_shown() {
+		let level_data = parse_level("Op O- O- O- O- |e |g\n@fog 1").unwrap();
+		let level = LevelState::new(&level_data);
+		assert!(cell_glyph(&level, (0, 0).into()).0 == '☺');
+		assert!(cell_glyph(&level, (5, 0).into()).0 == ' ');
+	}
+}
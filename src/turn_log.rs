@@ -0,0 +1,112 @@
+//! The turn log (`--log-turns <file>`): one line of JSON per turn played, with the action, what
+//! happened during the turn (shots, hits, deaths, explosions, enemy steps, etc.), the enemies
+//! left and the hash of the state, so that two runs that desync can be diffed turn by turn.
+
+use crate::coords::*;
+use crate::*;
+
+use std::io::Write;
+
+pub struct TurnLog {
+	output: std::io::BufWriter<fs::File>,
+}
+
+fn coords_json(coords: Coords) -> serde_json::Value {
+	serde_json::json!([coords.x, coords.y])
+}
+
+fn event_json(event: &TurnEvent) -> serde_json::Value {
+	match *event {
+		TurnEvent::Hit(coords) => serde_json::json!({"type": "hit", "at": coords_json(coords)}),
+		TurnEvent::EnemyDied(coords) => {
+			serde_json::json!({"type": "enemy_died", "at": coords_json(coords)})
+		},
+		TurnEvent::Shot { from, to } => serde_json::json!({
+			"type": "shot", "from": coords_json(from), "to": coords_json(to),
+		}),
+		TurnEvent::Explosion(coords) => {
+			serde_json::json!({"type": "explosion", "at": coords_json(coords)})
+		},
+		TurnEvent::GoalDamaged(coords) => {
+			serde_json::json!({"type": "goal_damaged", "at": coords_json(coords)})
+		},
+		TurnEvent::Slid { from, to } => serde_json::json!({
+			"type": "slid", "from": coords_json(from), "to": coords_json(to),
+		}),
+		TurnEvent::BossPhase { at, behavior } => serde_json::json!({
+			"type": "boss_phase", "at": coords_json(at), "behavior": behavior.name(),
+		}),
+		TurnEvent::Granted(grant, count) => serde_json::json!({
+			"type": "granted",
+			"what": match grant {
+				Grant::Towers => "towers",
+				Grant::Item(item) => item.name(),
+			},
+			"count": count,
+		}),
+	}
+}
+
+/// The line logged for a turn, `turn` being the number of the turn before it was played.
+fn turn_json(level: &LevelState, turn: u32, dd: DxDy, action: &PlayerAction) -> serde_json::Value {
+	let enemies: Vec<serde_json::Value> = level
+		.grid
+		.dims
+		.iter()
+		.filter_map(|coords| match &level.grid.get(coords).unwrap().obj {
+			Obj::Enemy { variant, hp, .. } => Some(serde_json::json!({
+				"variant": variant.name(), "at": coords_json(coords), "hp": hp,
+			})),
+			_ => None,
+		})
+		.collect();
+	serde_json::json!({
+		"turn": turn,
+		"action": protocol::action_line(dd, action),
+		"events": level.stats.turn_events.iter().map(event_json).collect::<Vec<_>>(),
+		"enemy_moves": level
+			.stats
+			.enemy_moves
+			.iter()
+			.map(|&(from, to)| [coords_json(from), coords_json(to)])
+			.collect::<Vec<_>>(),
+		"enemies": enemies,
+		"game_over": level.game_joever,
+		"state_hash": format!("{:016x}", level.state_hash()),
+	})
+}
+
+impl TurnLog {
+	pub fn create(path: &str) -> std::io::Result<TurnLog> {
+		Ok(TurnLog { output: std::io::BufWriter::new(fs::File::create(path)?) })
+	}
+
+	/// Log the turn that was just played (`turn` is the number of the turn before it).
+	pub fn log(&mut self, level: &LevelState, turn: u32, dd: DxDy, action: &PlayerAction) {
+		let line = turn_json(level, turn, dd, action);
+		// Flushed every turn so that the log is complete even if the game crashes.
+		let result = writeln!(self.output, "{line}").and_then(|()| self.output.flush());
+		if let Err(error) = result {
+			log::error!("Failed to write the turn log: {error}");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn turn_log_lines_tell_what_happened() {
+		let level_data = parse_level("Op Ot |e |- |g").unwrap();
+		let mut level = LevelState::new(&level_data);
+		let turn = level.turn;
+		play_turn(&mut level, (0, 0).into(), PlayerAction::SkipTurn);
+		let line = turn_json(&level, turn, (0, 0).into(), &PlayerAction::SkipTurn);
+		assert_eq!(line["turn"], 0);
+		assert_eq!(line["action"], "skip");
+		assert_eq!(line["enemy_moves"], serde_json::json!([[[2, 0], [3, 0]]]));
+		assert_eq!(line["events"][0]["type"], "shot");
+		assert_eq!(line["enemies"][0]["at"], serde_json::json!([3, 0]));
+	}
+}
@@ -0,0 +1,334 @@
+//! A small immediate-mode UI toolkit, drawn with the same primitives as the game
+//! (`draw_rect` and the pixel font of the spritesheet).
+//!
+//! Every frame, a screen calls the widget methods of a `Ui` (panels, labels, buttons, lists)
+//! which draw the widgets and report what the player did with them, based on the keyboard
+//! and mouse input gathered since the previous frame in a `UiInput`.
+
+use crate::coords::*;
+use crate::*;
+
+use winit::event::VirtualKeyCode;
+
+const TEXT_COLOR: [u8; 4] = [230, 230, 230, 255];
+const SELECTED_TEXT_COLOR: [u8; 4] = [255, 220, 60, 255];
+const PANEL_COLOR: [u8; 4] = [30, 30, 40, 255];
+const BORDER_COLOR: [u8; 4] = [120, 120, 150, 255];
+const SELECTED_BACKGROUND_COLOR: [u8; 4] = [60, 60, 90, 255];
+
+/// The keyboard and mouse input gathered since the last frame.
+#[derive(Default)]
+pub struct UiInput {
+	pub up: bool,
+	pub down: bool,
+	pub left: bool,
+	pub right: bool,
+	pub confirm: bool,
+	pub back: bool,
+	pub clicked: bool,
+	/// The mouse only selects what it hovers when it moves, so that it doesn't fight
+	/// with the keyboard.
+	pub cursor_moved: bool,
+	/// The characters typed, for text fields.
+	pub typed: String,
+	/// Backspace, which is also `back` outside of text fields.
+	pub erase: bool,
+	/// Enter, which is also `confirm` (but Space is typed in text fields).
+	pub enter: bool,
+	/// Ctrl+C and Ctrl+V.
+	pub copy: bool,
+	pub paste: bool,
+}
+
+impl UiInput {
+	pub fn press_key(&mut self, key: VirtualKeyCode) {
+		match key {
+			VirtualKeyCode::Up => self.up = true,
+			VirtualKeyCode::Down => self.down = true,
+			VirtualKeyCode::Left => self.left = true,
+			VirtualKeyCode::Right => self.right = true,
+			VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+				self.confirm = true;
+				self.enter = true;
+			},
+			VirtualKeyCode::Space => self.confirm = true,
+			VirtualKeyCode::Escape => self.back = true,
+			VirtualKeyCode::Back => {
+				self.back = true;
+				self.erase = true;
+			},
+			_ => {},
+		}
+	}
+
+	/// A key pressed with Ctrl.
+	pub fn press_shortcut(&mut self, key: VirtualKeyCode) {
+		match key {
+			VirtualKeyCode::C => self.copy = true,
+			VirtualKeyCode::V => self.paste = true,
+			_ => {},
+		}
+	}
+
+	pub fn has_input(&self) -> bool {
+		self.up
+			|| self.down
+			|| self.left
+			|| self.right
+			|| self.confirm
+			|| self.back
+			|| self.clicked
+			|| self.cursor_moved
+			|| !self.typed.is_empty()
+			|| self.copy
+			|| self.paste
+	}
+
+	/// To call at the end of every frame, once the widgets had a chance to see the input.
+	pub fn clear(&mut self) {
+		*self = UiInput::default();
+	}
+}
+
+/// What happened to a list this frame.
+pub enum ListEvent {
+	/// The item was clicked or confirmed with the keyboard.
+	Activated(usize),
+	/// The left (-1) or right (+1) key was pressed while the item was selected.
+	Adjusted(usize, i32),
+}
+
+pub struct Ui<'a> {
+	pixel_buffer: &'a mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	spritesheet: &'a Spritesheet,
+	input: &'a UiInput,
+	cursor: Option<Coords>,
+	/// Each pixel of the font is a square of that many pixels.
+	scale: i32,
+}
+
+impl<'a> Ui<'a> {
+	pub fn new(
+		pixel_buffer: &'a mut pixels::Pixels,
+		pixel_buffer_dims: Dimensions,
+		spritesheet: &'a Spritesheet,
+		input: &'a UiInput,
+		cursor: Option<Coords>,
+	) -> Ui<'a> {
+		let scale = (pixel_buffer_dims.h / 160).clamp(2, 4);
+		Ui {
+			pixel_buffer,
+			pixel_buffer_dims,
+			spritesheet,
+			input,
+			cursor,
+			scale,
+		}
+	}
+
+	pub fn screen_dims(&self) -> Dimensions {
+		self.pixel_buffer_dims
+	}
+
+	pub fn text_width(&self, text: &str) -> i32 {
+		text.chars().count() as i32 * 4 * self.scale
+	}
+
+	/// Split a text in lines that are at most `width` pixels wide (except for words that are
+	/// wider by themselves).
+	pub fn wrap_text(&self, text: &str, width: i32) -> Vec<String> {
+		let mut lines: Vec<String> = vec![];
+		for word in text.split_whitespace() {
+			match lines.last_mut() {
+				Some(line) if self.text_width(&format!("{line} {word}")) <= width => {
+					line.push(' ');
+					line.push_str(word);
+				},
+				_ => lines.push(word.to_string()),
+			}
+		}
+		lines
+	}
+
+	/// The height of a line of text (with some room around it).
+	pub fn line_height(&self) -> i32 {
+		10 * self.scale
+	}
+
+	/// Darken everything that was drawn before, to make the UI stand out.
+	pub fn dim_background(&mut self) {
+		let screen = Rect { top_left: (0, 0).into(), dims: self.pixel_buffer_dims };
+		darken_rect(self.pixel_buffer, self.pixel_buffer_dims, screen);
+	}
+
+	pub fn panel(&mut self, rect: Rect) {
+		draw_rect(
+			self.pixel_buffer,
+			self.pixel_buffer_dims,
+			rect,
+			BORDER_COLOR,
+		);
+		let inside = Rect {
+			top_left: rect.top_left + DxDy::from((self.scale, self.scale)),
+			dims: Dimensions { w: rect.dims.w - 2 * self.scale, h: rect.dims.h - 2 * self.scale },
+		};
+		draw_rect(
+			self.pixel_buffer,
+			self.pixel_buffer_dims,
+			inside,
+			PANEL_COLOR,
+		);
+	}
+
+	pub fn label(&mut self, top_left: Coords, text: &str) {
+		draw_text(
+			self.pixel_buffer,
+			self.pixel_buffer_dims,
+			top_left,
+			self.scale,
+			self.spritesheet,
+			text,
+			TEXT_COLOR,
+		);
+	}
+
+	/// A label horizontally centered in the screen.
+	pub fn centered_label(&mut self, y: i32, text: &str) {
+		let x = (self.pixel_buffer_dims.w - self.text_width(text)) / 2;
+		self.label((x, y).into(), text);
+	}
+
+	/// A label twice as big as the others, horizontally centered in the screen.
+	pub fn big_centered_label(&mut self, y: i32, text: &str) {
+		let x = (self.pixel_buffer_dims.w - 2 * self.text_width(text)) / 2;
+		draw_text(
+			self.pixel_buffer,
+			self.pixel_buffer_dims,
+			(x, y).into(),
+			2 * self.scale,
+			self.spritesheet,
+			text,
+			SELECTED_TEXT_COLOR,
+		);
+	}
+
+	/// Returns `true` if the button was clicked.
+	pub fn button(&mut self, rect: Rect, text: &str) -> bool {
+		let hovered = self.cursor.is_some_and(|cursor| rect.contains(cursor));
+		self.panel(rect);
+		let text_top_left = Coords {
+			x: rect.left() + (rect.dims.w - self.text_width(text)) / 2,
+			y: rect.top() + (rect.dims.h - 8 * self.scale) / 2 + self.scale,
+		};
+		let color = if hovered {
+			SELECTED_TEXT_COLOR
+		} else {
+			TEXT_COLOR
+		};
+		draw_text(
+			self.pixel_buffer,
+			self.pixel_buffer_dims,
+			text_top_left,
+			self.scale,
+			self.spritesheet,
+			text,
+			color,
+		);
+		hovered && self.input.clicked
+	}
+
+	/// A vertical list of items in which one is selected, with the up and down keys or by
+	/// hovering it with the mouse. The list scrolls if it doesn't fit in `rect`.
+	pub fn list(&mut self, rect: Rect, items: &[String], selected: &mut usize) -> Option<ListEvent> {
+		if items.is_empty() {
+			return None;
+		}
+		if self.input.up {
+			*selected = selected.checked_sub(1).unwrap_or(items.len() - 1);
+		}
+		if self.input.down {
+			*selected = (*selected + 1) % items.len();
+		}
+		*selected = (*selected).min(items.len() - 1);
+		let line_height = self.line_height();
+		let visible_count = ((rect.dims.h / line_height) as usize).max(1);
+		let first_visible = (*selected + 1).saturating_sub(visible_count);
+		let mut event = None;
+		for (row, index) in (first_visible..items.len()).take(visible_count).enumerate() {
+			let item_rect = Rect {
+				top_left: rect.top_left + DxDy::from((0, row as i32 * line_height)),
+				dims: Dimensions { w: rect.dims.w, h: line_height },
+			};
+			if self.cursor.is_some_and(|cursor| item_rect.contains(cursor)) {
+				if self.input.cursor_moved {
+					*selected = index;
+				}
+				if self.input.clicked {
+					*selected = index;
+					event = Some(ListEvent::Activated(index));
+				}
+			}
+			let is_selected = index == *selected;
+			if is_selected {
+				draw_rect(
+					self.pixel_buffer,
+					self.pixel_buffer_dims,
+					item_rect,
+					SELECTED_BACKGROUND_COLOR,
+				);
+			}
+			draw_text(
+				self.pixel_buffer,
+				self.pixel_buffer_dims,
+				item_rect.top_left + DxDy::from((2 * self.scale, 2 * self.scale)),
+				self.scale,
+				self.spritesheet,
+				&items[index],
+				if is_selected {
+					SELECTED_TEXT_COLOR
+				} else {
+					TEXT_COLOR
+				},
+			);
+		}
+		if self.input.confirm {
+			event = Some(ListEvent::Activated(*selected));
+		} else if self.input.left {
+			event = Some(ListEvent::Adjusted(*selected, -1));
+		} else if self.input.right {
+			event = Some(ListEvent::Adjusted(*selected, 1));
+		}
+		event
+	}
+
+	/// A one-line text field, edited with the characters typed and Backspace (the end of the
+	/// text is shown if it is too long to fit).
+	pub fn text_field(&mut self, rect: Rect, text: &mut String, max_length: usize) {
+		for character in self.input.typed.chars() {
+			if text.chars().count() < max_length {
+				text.push(character);
+			}
+		}
+		if self.input.erase {
+			text.pop();
+		}
+		self.panel(rect);
+		let padding = self.line_height() / 4;
+		let fitting = ((rect.dims.w - 2 * padding) / self.text_width("_")).max(1) as usize;
+		let shown: String = format!("{text}_")
+			.chars()
+			.rev()
+			.take(fitting)
+			.collect::<Vec<char>>()
+			.into_iter()
+			.rev()
+			.collect();
+		self.label(rect.top_left + DxDy::from((padding, padding)), &shown);
+	}
+
+	/// Was the back key (Escape) pressed?
+	pub fn back(&self) -> bool {
+		self.input.back
+	}
+}
@@ -0,0 +1,164 @@
+//! Checks of a level file beyond what is needed to load it (the `validate` subcommand): a level
+//! can load fine and still be unplayable, with no player, a path that leads nowhere or enemies
+//! spawning in the grass. All the problems found are reported, with their line when they have
+//! one.
+
+use crate::coords::*;
+use crate::*;
+
+/// The problems of a level file, empty if it is fine (`dir` is where the files it refers to are).
+pub fn validate_level(level_raw_data: &str, dir: &std::path::Path) -> Vec<String> {
+	// Variants are only checked as far as loading them goes, the level they are a variant of
+	// can be validated by itself.
+	if variant_base(level_raw_data).is_some() {
+		return parse_level_in(level_raw_data, dir)
+			.err()
+			.into_iter()
+			.collect();
+	}
+	// Nothing else can be checked without the grid.
+	let (grid, mut named_tiles) = match parse_grid(level_raw_data) {
+		Ok(parsed) => parsed,
+		Err(error) => return vec![error],
+	};
+	let row_lines: Vec<usize> = grid_lines(level_raw_data)
+		.map(|(line_index, _)| line_index)
+		.collect();
+	let mut problems = parse_tile_names(level_raw_data, grid.dims, &mut named_tiles);
+
+	let mut level_data = LevelData::new(grid);
+	let mut spawns = vec![];
+	let mut max_towers_line = None;
+	for (line_index, line) in level_raw_data.split('\n').enumerate() {
+		let Some(line) = line.strip_prefix('@') else {
+			continue;
+		};
+		let events_before = level_data.init_events.len();
+		let context = MetadataContext { named_tiles: &named_tiles, dir, include_depth: 0 };
+		if let Err(error) = parse_metadata(&mut level_data, line, &context) {
+			problems.push(at_line(line_index, error));
+			continue;
+		}
+		if line.starts_with("max_towers") {
+			max_towers_line = Some(line_index);
+		}
+		for event in &level_data.init_events[events_before..] {
+			if let GameEventType::EnemySpawn(coords, _, _) = event.event_type {
+				spawns.push((line_index, coords, event.turn));
+			}
+		}
+	}
+
+	let mut grid = level_data.init_grid.clone();
+	let coords_with = |is_it: fn(&Obj) -> bool| -> Vec<Coords> {
+		grid
+			.dims
+			.iter()
+			.filter(|&coords| is_it(&grid.get(coords).unwrap().obj))
+			.collect()
+	};
+	if coords_with(|obj| matches!(obj, Obj::Player { .. })).is_empty() {
+		problems.push(tr("validate.no_player"));
+	}
+	if coords_with(|obj| matches!(obj, Obj::Goal { .. })).is_empty() {
+		problems.push(tr("validate.no_goal"));
+	} else {
+		// The paths behind the doors lead somewhere once they are opened.
+		for coords in grid.dims.iter() {
+			let cell = grid.get_mut(coords).unwrap();
+			if matches!(cell.obj, Obj::Door { .. }) {
+				cell.obj = Obj::Empty;
+			}
+		}
+		compute_distance(&mut grid);
+		for coords in grid.dims.iter() {
+			if matches!(grid.get(coords).unwrap().groud, Ground::Path(-1)) {
+				problems.push(at_line(
+					row_lines[coords.y as usize],
+					tr_with("validate.path_to_nowhere", &[("coords", &coords)]),
+				));
+			}
+		}
+	}
+
+	for (line_index, coords, turn) in spawns {
+		if !matches!(grid.get(coords).unwrap().groud, Ground::Path(dist) if dist >= 0) {
+			problems.push(at_line(
+				line_index,
+				tr_with("validate.spawn_off_path", &[("coords", &coords)]),
+			));
+		}
+		if let Some(max_turns) = level_data.max_turns.filter(|&max_turns| turn >= max_turns) {
+			problems.push(at_line(
+				line_index,
+				tr_with(
+					"validate.spawn_after_max_turns",
+					&[("turn", &turn), ("max_turns", &max_turns)],
+				),
+			));
+		}
+	}
+
+	if let (Some(max_towers), Some(line_index)) = (level_data.max_towers, max_towers_line) {
+		let room = grid
+			.dims
+			.iter()
+			.filter(|&coords| {
+				let cell = grid.get(coords).unwrap();
+				matches!(cell.obj, Obj::Empty) && !cell.groud.is_liquid() && !cell.rocky_path
+			})
+			.count();
+		if max_towers as usize > room {
+			problems.push(at_line(
+				line_index,
+				tr_with(
+					"validate.too_many_towers",
+					&[("count", &max_towers), ("room", &room)],
+				),
+			));
+		}
+	}
+	problems
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn problems(level_raw_data: &str) -> Vec<String> {
+		validate_level(level_raw_data, std::path::Path::new("levels"))
+	}
+
+	#[test]
+	fn all_the_problems_of_a_level_are_reported() {
+		assert!(problems("Op ?A |- |g\n@tile A |-\n@event spawn basic A 3").is_empty());
+
+		let found = problems(
+			"Op ?A |- |g\n\
+			|- O- O- Op\n\
+			@event spawn basic A 3\n\
+			@event spawn dragon A 4\n\
+			@max_towers 10\n\
+			@max_turns 3",
+		);
+		assert_eq!(
+			found,
+			[
+				"Line 4: Cannot spawn dragon, there is no such enemy",
+				"Line 2: The path at (0, 1) doesn't lead to a goal",
+				"Line 3: Enemies spawn at (1, 0), which is not on a path to a goal",
+				"Line 3: Enemies spawn at turn 3, but the level is lost after 3 turns",
+				"Line 5: @max_towers is 10 but only 5 tiles can hold a tower",
+			]
+		);
+
+		assert_eq!(
+			problems("O- O-\nO- O-"),
+			["There is no player", "There is no goal"]
+		);
+		assert_eq!(
+			problems("Op |g\nO-"),
+			["Line 2: This row has 1 tiles instead of 2"]
+		);
+	}
+}
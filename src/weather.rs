@@ -0,0 +1,238 @@
+//! Weather (`@weather <weather>`), which changes the rules of the turns while it lasts: in the
+//! rain the enemies only move every other turn, in the fog the towers don't shoot farther than
+//! `FOG_TOWER_RANGE` tiles, and the wind pushes the bombs one tile per turn the way it blows.
+//! `@weather random N` draws a new weather every N turns instead, from a seed so that a level
+//! has the same weather every time it is played.
+//!
+//! It is drawn over the level as rain drops, a haze or gusts of wind.
+
+use crate::coords::*;
+use crate::*;
+
+/// How far towers shoot in the fog (towers with a shorter range keep it).
+pub const FOG_TOWER_RANGE: i32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Weather {
+	#[default]
+	Clear,
+	Rain,
+	Fog,
+	/// Blowing towards that direction.
+	Wind(Direction),
+}
+
+impl Weather {
+	pub fn name(self) -> &'static str {
+		match self {
+			Weather::Clear => "clear",
+			Weather::Rain => "rain",
+			Weather::Fog => "fog",
+			Weather::Wind(_) => "wind",
+		}
+	}
+
+	/// How it is shown to the player, `None` for clear weather.
+	pub fn description(self) -> Option<String> {
+		match self {
+			Weather::Clear => None,
+			Weather::Wind(direction) => {
+				let direction = tr(&format!("direction.{}", direction.name()));
+				Some(tr_with("weather.wind", &[("direction", &direction)]))
+			},
+			_ => Some(tr(&format!("weather.{}", self.name()))),
+		}
+	}
+
+	/// The range of a tower with that range in this weather.
+	pub fn tower_range(self, range: i32) -> i32 {
+		match self {
+			Weather::Fog => range.min(FOG_TOWER_RANGE),
+			_ => range,
+		}
+	}
+
+	/// Do the enemies move during that turn?
+	pub fn enemies_move(self, turn: u32) -> bool {
+		self != Weather::Rain || turn.is_multiple_of(2)
+	}
+}
+
+/// The weather of a level over the turns.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Forecast {
+	Fixed(Weather),
+	/// A weather drawn from the seed every `period` turns.
+	Random {
+		period: u32,
+		seed: u64,
+	},
+}
+
+impl Default for Forecast {
+	fn default() -> Forecast {
+		Forecast::Fixed(Weather::Clear)
+	}
+}
+
+impl Forecast {
+	/// `clear`, `rain`, `fog`, `wind <direction>` or `random <period> [seed]`.
+	pub fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Forecast, String> {
+		let name = words.next().unwrap_or_default();
+		let invalid = || tr_with("error.weather", &[("name", &name)]);
+		Ok(match name {
+			"clear" => Forecast::Fixed(Weather::Clear),
+			"rain" => Forecast::Fixed(Weather::Rain),
+			"fog" => Forecast::Fixed(Weather::Fog),
+			"wind" => {
+				let direction = words.next().unwrap_or_default();
+				Forecast::Fixed(Weather::Wind(
+					Direction::from_name(direction).ok_or_else(invalid)?,
+				))
+			},
+			"random" => {
+				let period = words.next().and_then(|period| period.parse().ok());
+				let seed = match words.next() {
+					Some(seed) => seed.parse().map_err(|_| invalid())?,
+					None => 0,
+				};
+				Forecast::Random {
+					period: period.filter(|&period| period > 0).ok_or_else(invalid)?,
+					seed,
+				}
+			},
+			_ => return Err(invalid()),
+		})
+	}
+
+	/// The weather during that turn.
+	pub fn weather(self, turn: u32) -> Weather {
+		match self {
+			Forecast::Fixed(weather) => weather,
+			Forecast::Random { period, seed } => {
+				let mut rng = rng::Rng::with_seed(seed ^ (turn / period) as u64);
+				match rng.below(4) {
+					0 => Weather::Clear,
+					1 => Weather::Rain,
+					2 => Weather::Fog,
+					_ => Weather::Wind(Direction::ALL[rng.below(4) as usize]),
+				}
+			},
+		}
+	}
+}
+
+/// The wind pushes the bombs one tile downwind, onto empty tiles that are not water nor lava
+/// (the ones most downwind move first, so that a line of bombs moves as one).
+pub fn wind_blows(grid: &mut Grid<Cell>, stats: &mut Stats, direction: Direction) {
+	let dd = direction.step();
+	let mut bombs: Vec<Coords> = grid
+		.dims
+		.iter()
+		.filter(|&coords| matches!(grid.get(coords).unwrap().obj, Obj::Bomb { .. }))
+		.collect();
+	bombs.sort_by_key(|coords| Reverse(coords.x * dd.dx + coords.y * dd.dy));
+	for coords in bombs {
+		let dst_coords = coords + dd;
+		let can_move = grid
+			.get(dst_coords)
+			.is_some_and(|cell| matches!(cell.obj, Obj::Empty) && !cell.groud.is_liquid());
+		if can_move {
+			grid.get_mut(dst_coords).unwrap().obj =
+				std::mem::replace(&mut grid.get_mut(coords).unwrap().obj, Obj::Empty);
+			stats
+				.turn_events
+				.push(TurnEvent::Slid { from: coords, to: dst_coords });
+		}
+	}
+}
+
+/// Draw the weather over the level (of `level_dims` pixels), `time` being in seconds.
+pub fn draw_overlay(
+	pixel_buffer: &mut pixels::Pixels,
+	pixel_buffer_dims: Dimensions,
+	level_dims: Dimensions,
+	cell_pixel_side: i32,
+	weather: Weather,
+	time: f32,
+) {
+	if level_dims.w <= 0 || level_dims.h <= 0 {
+		return;
+	}
+	let cells = (level_dims.w * level_dims.h / (cell_pixel_side * cell_pixel_side).max(1)) as u32;
+	// The same streaks every frame, moved along by the time, and wrapped around the level.
+	let mut rng = rng::Rng::with_seed(0);
+	let mut streak = |speed: DxDy| {
+		let distance = time * cell_pixel_side as f32;
+		let x = rng.below(level_dims.w as u32) as f32 + speed.dx as f32 * distance;
+		let y = rng.below(level_dims.h as u32) as f32 + speed.dy as f32 * distance;
+		Coords {
+			x: (x as i32).rem_euclid(level_dims.w),
+			y: (y as i32).rem_euclid(level_dims.h),
+		}
+	};
+	let thickness = (cell_pixel_side / 16).max(1);
+	match weather {
+		Weather::Clear => {},
+		Weather::Fog => draw_rect(
+			pixel_buffer,
+			pixel_buffer_dims,
+			Rect { top_left: Coords { x: 0, y: 0 }, dims: level_dims },
+			[220, 225, 235, 110],
+		),
+		Weather::Rain => {
+			for _ in 0..cells * 2 {
+				let top_left = streak(DxDy { dx: 1, dy: 6 });
+				let dims = Dimensions { w: thickness, h: cell_pixel_side / 3 };
+				draw_rect(
+					pixel_buffer,
+					pixel_buffer_dims,
+					Rect { top_left, dims },
+					[190, 210, 255, 120],
+				);
+			}
+		},
+		Weather::Wind(direction) => {
+			let dd = direction.step();
+			for _ in 0..cells / 2 {
+				let top_left = streak(DxDy { dx: dd.dx * 4, dy: dd.dy * 4 });
+				let dims = if dd.dx != 0 {
+					Dimensions { w: cell_pixel_side / 2, h: thickness }
+				} else {
+					Dimensions { w: thickness, h: cell_pixel_side / 2 }
+				};
+				draw_rect(
+					pixel_buffer,
+					pixel_buffer_dims,
+					Rect { top_left, dims },
+					[255, 255, 255, 90],
+				);
+			}
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn forecasts_are_parsed_and_random_ones_are_reproducible() {
+		let parse = |text: &str| Forecast::parse(text.split_whitespace());
+		assert!(parse("rain").unwrap() == Forecast::Fixed(Weather::Rain));
+		assert!(parse("wind north").unwrap() == Forecast::Fixed(Weather::Wind(Direction::North)));
+		assert!(parse("wind").is_err());
+		assert!(parse("random 0").is_err());
+		assert!(parse("snow").is_err());
+		let forecast = parse("random 5 42").unwrap();
+		let weathers: Vec<Weather> = (0..100).map(|turn| forecast.weather(turn)).collect();
+		assert!(
+			weathers
+				== (0..100)
+					.map(|turn| forecast.weather(turn))
+					.collect::<Vec<_>>()
+		);
+		assert!((0..100).all(|turn| weathers[turn] == weathers[turn / 5 * 5]));
+		assert!(weathers.iter().any(|&weather| weather != weathers[0]));
+	}
+}